@@ -0,0 +1,49 @@
+//! How much a read through `SimpleAsyncReader` costs on its own, polled
+//! directly or through a boxed future, from memory so storage doesn't
+//! drown it out. `cargo bench --features alloc-stats` also counts heap
+//! allocations per read.
+
+use async_std::io::ReadExt;
+use std::{path::Path, time::Instant};
+use surviving::{bench::FutureOnly, Position, SimpleAsyncReader, SimpleRead, TracingReader};
+
+/// How much each case reads
+const TOTAL: u64 = 1 << 30;
+
+async fn run<R: SimpleRead + Send + 'static>(name: &str, chunk: usize, inner: R) {
+    let mut reader = SimpleAsyncReader::new(inner, Position::new(Path::new("bench")));
+    let mut buf = vec![0u8; chunk];
+    let mut reads = 0u64;
+    #[cfg(feature = "alloc-stats")]
+    let before = surviving::alloc::stats().allocations;
+    let start = Instant::now();
+    while reader.read(&mut buf).await.unwrap() > 0 {
+        reads += 1;
+    }
+    let elapsed = start.elapsed();
+    print!(
+        "{:>6} {:>4}K: {:>7} reads, {:>7.1} ns/read, {:>6.2} GiB/s",
+        name,
+        chunk >> 10,
+        reads,
+        elapsed.as_nanos() as f64 / reads as f64,
+        TOTAL as f64 / (1u64 << 30) as f64 / elapsed.as_secs_f64()
+    );
+    #[cfg(feature = "alloc-stats")]
+    print!(
+        ", {:.2} allocations/read",
+        (surviving::alloc::stats().allocations - before) as f64 / reads as f64
+    );
+    println!();
+}
+
+fn main() {
+    async_std::task::block_on(async {
+        for chunk in [4 << 10, 64 << 10, 256 << 10] {
+            let source = || futures::io::AsyncReadExt::take(futures::io::repeat(0), TOTAL);
+            let position = Position::new(Path::new("bench"));
+            run("polled", chunk, TracingReader::new(source(), position)).await;
+            run("boxed", chunk, FutureOnly(source())).await;
+        }
+    });
+}
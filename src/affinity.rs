@@ -0,0 +1,165 @@
+//! Pinning the process to a set of CPUs or a NUMA node, and lowering its
+//! priority.
+//!
+//! Affinity, memory policy and priority are inherited by threads spawned afterwards, so
+//! applying them before the async runtime starts its workers pins every
+//! hashing thread — and, through first-touch and the memory policy, the
+//! buffers they allocate.
+
+use color_eyre::eyre::{self, eyre};
+use std::str::FromStr;
+
+/// A set of CPU indices, written like `0-3,8,10-11`
+#[derive(Debug, Clone)]
+pub struct CpuList(pub Vec<usize>);
+
+impl FromStr for CpuList {
+    type Err = eyre::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut cpus = Vec::new();
+        for part in s.trim().split(',').filter(|p| !p.is_empty()) {
+            let parse = |n: &str| {
+                n.trim()
+                    .parse::<usize>()
+                    .map_err(|_| eyre!("{:?} is not a CPU number", n))
+            };
+            match part.split_once('-') {
+                Some((start, end)) => {
+                    let (start, end) = (parse(start)?, parse(end)?);
+                    if start > end {
+                        return Err(eyre!("CPU range {:?} is backwards", part));
+                    }
+                    cpus.extend(start..=end);
+                }
+                None => cpus.push(parse(part)?),
+            }
+        }
+        if cpus.is_empty() {
+            return Err(eyre!("CPU list is empty"));
+        }
+        cpus.sort_unstable();
+        cpus.dedup();
+        Ok(Self(cpus))
+    }
+}
+
+/// Restricts the calling thread (and threads it spawns later) to `cpus`
+#[cfg(target_os = "linux")]
+pub fn pin_to_cpus(cpus: &CpuList) -> Result<(), eyre::Error> {
+    unsafe {
+        let mut set: libc::cpu_set_t = std::mem::zeroed();
+        for &cpu in &cpus.0 {
+            if cpu >= libc::CPU_SETSIZE as usize {
+                return Err(eyre!("CPU {} is out of range", cpu));
+            }
+            libc::CPU_SET(cpu, &mut set);
+        }
+        if libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &set) != 0 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+    }
+    Ok(())
+}
+
+/// The CPUs the calling thread may run on
+#[cfg(target_os = "linux")]
+pub fn allowed_cpus() -> Result<CpuList, eyre::Error> {
+    unsafe {
+        let mut set: libc::cpu_set_t = std::mem::zeroed();
+        if libc::sched_getaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &mut set) != 0 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+        let cpus: Vec<usize> = (0..libc::CPU_SETSIZE as usize)
+            .filter(|&cpu| libc::CPU_ISSET(cpu, &set))
+            .collect();
+        Ok(CpuList(cpus))
+    }
+}
+
+/// Pins the calling thread to the CPUs of NUMA node `node`, and makes it
+/// prefer that node's memory for allocations.
+#[cfg(target_os = "linux")]
+pub fn pin_to_numa_node(node: usize) -> Result<(), eyre::Error> {
+    const MPOL_PREFERRED: libc::c_int = 1;
+    const MAX_NODES: usize = 1024;
+    if node >= MAX_NODES {
+        return Err(eyre!("NUMA node {} is out of range", node));
+    }
+
+    let cpulist = std::fs::read_to_string(format!("/sys/devices/system/node/node{}/cpulist", node))
+        .map_err(|e| eyre!("can't read the CPUs of NUMA node {}: {}", node, e))?;
+    pin_to_cpus(&cpulist.parse()?)?;
+
+    let mut mask = [0 as libc::c_ulong; MAX_NODES / libc::c_ulong::BITS as usize];
+    let bits = libc::c_ulong::BITS as usize;
+    mask[node / bits] |= 1 << (node % bits);
+    let res = unsafe {
+        libc::syscall(
+            libc::SYS_set_mempolicy,
+            MPOL_PREFERRED,
+            mask.as_ptr(),
+            MAX_NODES as libc::c_ulong,
+        )
+    };
+    if res != 0 {
+        return Err(eyre!(
+            "can't set memory policy for NUMA node {}: {}",
+            node,
+            std::io::Error::last_os_error()
+        ));
+    }
+    Ok(())
+}
+
+/// Makes the calling thread (and threads it spawns later) the last to get
+/// CPU time and disk time: the lowest nice value, and the idle I/O class.
+#[cfg(target_os = "linux")]
+pub fn idle_priority() -> Result<(), eyre::Error> {
+    const IOPRIO_WHO_PROCESS: libc::c_int = 1;
+    const IOPRIO_CLASS_IDLE: libc::c_int = 3;
+    const IOPRIO_CLASS_SHIFT: libc::c_int = 13;
+
+    // `who` 0 is the calling thread, which is what threads inherit from
+    if unsafe { libc::setpriority(libc::PRIO_PROCESS, 0, 19) } != 0 {
+        return Err(eyre!(
+            "can't lower CPU priority: {}",
+            std::io::Error::last_os_error()
+        ));
+    }
+    let res = unsafe {
+        libc::syscall(
+            libc::SYS_ioprio_set,
+            IOPRIO_WHO_PROCESS,
+            0,
+            IOPRIO_CLASS_IDLE << IOPRIO_CLASS_SHIFT,
+        )
+    };
+    if res != 0 {
+        return Err(eyre!(
+            "can't lower I/O priority: {}",
+            std::io::Error::last_os_error()
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn pin_to_cpus(_cpus: &CpuList) -> Result<(), eyre::Error> {
+    Err(eyre!("--cpu-list is only supported on Linux"))
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn allowed_cpus() -> Result<CpuList, eyre::Error> {
+    Err(eyre!("CPU affinity is only supported on Linux"))
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn pin_to_numa_node(_node: usize) -> Result<(), eyre::Error> {
+    Err(eyre!("--numa-node is only supported on Linux"))
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn idle_priority() -> Result<(), eyre::Error> {
+    Err(eyre!("--idle-priority is only supported on Linux"))
+}
@@ -0,0 +1,388 @@
+//! The hash functions file contents can be fed into.
+
+use color_eyre::eyre::{self, eyre};
+use sha3::Digest;
+use std::{fmt, str::FromStr};
+
+/// Something that incrementally consumes file contents
+pub trait Update: Send {
+    fn update(&mut self, data: &[u8]);
+
+    /// Called once everything was fed, for hashers that can fail
+    fn finish(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Update for sha3::Sha3_256 {
+    fn update(&mut self, data: &[u8]) {
+        sha3::Digest::update(self, data)
+    }
+}
+
+impl Update for crate::blake3::Parallel {
+    fn update(&mut self, data: &[u8]) {
+        crate::blake3::Parallel::update(self, data)
+    }
+}
+
+impl Update for crc32fast::Hasher {
+    fn update(&mut self, data: &[u8]) {
+        crc32fast::Hasher::update(self, data)
+    }
+}
+
+/// Which digest to compute for each file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Algorithm {
+    Sha3_256,
+    Sha3_512,
+    Sha256,
+    Sha512,
+    Blake3,
+    /// eDonkey2000's chunked MD4
+    Ed2k,
+    /// Tiger Tree Hash
+    Tth,
+    /// `ext:COMMAND`, run for each file by [`crate::external`]. Only ever
+    /// parsed from the command line, never from a manifest.
+    External(&'static str),
+}
+
+impl Algorithm {
+    /// Every algorithm built in, in the order candidates are reported
+    pub const BUILT_IN: [Self; 7] = [
+        Self::Sha3_256,
+        Self::Sha3_512,
+        Self::Sha256,
+        Self::Sha512,
+        Self::Blake3,
+        Self::Ed2k,
+        Self::Tth,
+    ];
+
+    /// How many bytes its digests have, which an external command doesn't
+    /// say up front
+    pub fn digest_len(self) -> Option<usize> {
+        match self {
+            Self::Sha3_256 | Self::Sha256 | Self::Blake3 => Some(32),
+            Self::Sha3_512 | Self::Sha512 => Some(64),
+            Self::Ed2k => Some(16),
+            Self::Tth => Some(24),
+            Self::External(_) => None,
+        }
+    }
+
+    /// The built-in algorithms that could have made a digest of `len` bytes
+    pub fn candidates(len: usize) -> Vec<Self> {
+        Self::BUILT_IN
+            .iter()
+            .copied()
+            .filter(|a| a.digest_len() == Some(len))
+            .collect()
+    }
+
+    /// The name written next to digests in manifests
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Sha3_256 => "SHA3-256",
+            Self::Sha3_512 => "SHA3-512",
+            Self::Sha256 => "SHA-256",
+            Self::Sha512 => "SHA-512",
+            Self::Blake3 => "BLAKE3",
+            Self::Ed2k => "ED2K",
+            Self::Tth => "TTH",
+            Self::External(spec) => spec,
+        }
+    }
+
+    /// A hasher keyed with `key`, for the algorithms that have a keyed mode
+    pub fn keyed_hasher(self, key: &[u8]) -> Result<Hasher, eyre::Error> {
+        crate::hmac::hasher(self, key)
+    }
+
+    pub fn hasher(self) -> Hasher {
+        match self {
+            Self::Sha3_256 => Hasher::Sha3_256(Default::default()),
+            Self::Sha3_512 => Hasher::Sha3_512(Default::default()),
+            Self::Sha256 => Hasher::Sha256(Default::default()),
+            Self::Sha512 => Hasher::Sha512(Default::default()),
+            Self::Blake3 => Hasher::Blake3(Default::default()),
+            Self::Ed2k => Hasher::Ed2k(Default::default()),
+            Self::Tth => Hasher::Tth(Default::default()),
+            Self::External(spec) => {
+                Hasher::External(crate::external::Process::new(&spec["ext:".len()..]))
+            }
+        }
+    }
+
+    /// Parses `--algo`, which unlike [`FromStr`] also takes `ext:COMMAND`
+    pub fn parse_cli(s: &str) -> Result<Self, eyre::Error> {
+        match s.strip_prefix("ext:") {
+            Some("") => Err(eyre!("`ext:` needs a command")),
+            // parsed once per run, so the command can live as long as it
+            Some(_) => Ok(Self::External(Box::leak(s.to_string().into_boxed_str()))),
+            None => s.parse(),
+        }
+    }
+
+    /// Parses a comma-separated `--algo`, where an `ext:COMMAND` takes the
+    /// rest of the list, commas and all
+    pub fn parse_cli_list(s: &str) -> Result<Vec<Self>, eyre::Error> {
+        let mut algorithms = Vec::new();
+        let mut rest = s;
+        loop {
+            if rest.starts_with("ext:") {
+                algorithms.push(Self::parse_cli(rest)?);
+                break;
+            }
+            let (name, more) = match rest.split_once(',') {
+                Some((name, more)) => (name, Some(more)),
+                None => (rest, None),
+            };
+            algorithms.push(Self::parse_cli(name)?);
+            match more {
+                Some(more) => rest = more,
+                None => break,
+            }
+        }
+        Ok(algorithms)
+    }
+}
+
+impl fmt::Display for Algorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Algorithm {
+    type Err = eyre::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "sha3-256" | "sha3" => Ok(Self::Sha3_256),
+            "sha3-512" => Ok(Self::Sha3_512),
+            "sha-256" | "sha256" => Ok(Self::Sha256),
+            "sha-512" | "sha512" => Ok(Self::Sha512),
+            "blake3" => Ok(Self::Blake3),
+            "ed2k" => Ok(Self::Ed2k),
+            "tth" => Ok(Self::Tth),
+            _ => Err(eyre!(
+                "expected `sha3-256`, `sha3-512`, `sha256`, `sha512`, `blake3`, \
+                 `ed2k` or `tth`, got {:?}",
+                s
+            )),
+        }
+    }
+}
+
+/// A hasher for any of the supported algorithms
+// there's one of these per file being hashed, boxing buys nothing
+#[allow(clippy::large_enum_variant)]
+pub enum Hasher {
+    Sha3_256(sha3::Sha3_256),
+    Sha3_512(sha3::Sha3_512),
+    Sha256(sha2::Sha256),
+    Sha512(sha2::Sha512),
+    Blake3(crate::blake3::Blake3),
+    Ed2k(crate::ed2k::Ed2k),
+    Tth(crate::tth::Tth),
+    External(crate::external::Process),
+    Hmac(Box<crate::hmac::Hmac>),
+}
+
+impl Update for Hasher {
+    fn update(&mut self, data: &[u8]) {
+        match self {
+            Self::Sha3_256(h) => Update::update(h, data),
+            Self::Sha3_512(h) => sha3::Digest::update(h, data),
+            Self::Sha256(h) => Update::update(h, data),
+            Self::Sha512(h) => sha2::Digest::update(h, data),
+            Self::Blake3(h) => h.update(data),
+            Self::Ed2k(h) => h.update(data),
+            Self::Tth(h) => h.update(data),
+            Self::External(h) => h.update(data),
+            Self::Hmac(h) => h.update(data),
+        }
+    }
+
+    fn finish(&mut self) -> std::io::Result<()> {
+        match self {
+            Self::External(h) => h.finish(),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl Hasher {
+    pub fn finalize(self) -> Vec<u8> {
+        match self {
+            Self::Sha3_256(h) => h.finalize().to_vec(),
+            Self::Sha3_512(h) => h.finalize().to_vec(),
+            Self::Sha256(h) => h.finalize().to_vec(),
+            Self::Sha512(h) => h.finalize().to_vec(),
+            Self::Blake3(h) => h.finalize(),
+            Self::Ed2k(h) => h.finalize(),
+            Self::Tth(h) => h.finalize(),
+            Self::External(h) => h.finalize(),
+            Self::Hmac(h) => h.finalize(),
+        }
+    }
+
+    /// A copy of the state so far, which an external command can't give
+    pub(crate) fn try_clone(&self) -> Option<Self> {
+        Some(match self {
+            Self::Sha3_256(h) => Self::Sha3_256(h.clone()),
+            Self::Sha3_512(h) => Self::Sha3_512(h.clone()),
+            Self::Sha256(h) => Self::Sha256(h.clone()),
+            Self::Sha512(h) => Self::Sha512(h.clone()),
+            Self::Blake3(h) => Self::Blake3(h.clone()),
+            Self::Ed2k(h) => Self::Ed2k(h.clone()),
+            Self::Tth(h) => Self::Tth(h.clone()),
+            Self::External(_) => return None,
+            Self::Hmac(h) => Self::Hmac(Box::new(h.try_clone()?)),
+        })
+    }
+
+    /// The state so far as bytes, for the algorithms that can pick up from
+    /// it, unkeyed
+    pub fn serialize(&self) -> Option<Vec<u8>> {
+        use sha3::digest::common::hazmat::SerializableState;
+
+        Some(match self {
+            Self::Sha3_256(h) => h.serialize().to_vec(),
+            Self::Sha3_512(h) => h.serialize().to_vec(),
+            Self::Sha256(h) => h.serialize().to_vec(),
+            Self::Sha512(h) => h.serialize().to_vec(),
+            Self::Blake3(h) => h.serialize()?,
+            _ => return None,
+        })
+    }
+
+    /// A hasher for `algorithm` back where [`Hasher::serialize`] left it
+    pub fn deserialize(algorithm: Algorithm, bytes: &[u8]) -> Option<Self> {
+        use sha3::digest::common::hazmat::SerializableState;
+        use std::convert::TryFrom;
+
+        fn restore<S: SerializableState>(bytes: &[u8]) -> Option<S> {
+            S::deserialize(&TryFrom::try_from(bytes).ok()?).ok()
+        }
+        Some(match algorithm {
+            Algorithm::Sha3_256 => Self::Sha3_256(restore(bytes)?),
+            Algorithm::Sha3_512 => Self::Sha3_512(restore(bytes)?),
+            Algorithm::Sha256 => Self::Sha256(restore(bytes)?),
+            Algorithm::Sha512 => Self::Sha512(restore(bytes)?),
+            Algorithm::Blake3 => Self::Blake3(crate::blake3::Blake3::deserialize(bytes)?),
+            _ => return None,
+        })
+    }
+}
+
+/// Wraps a hasher, keeping the digest of everything fed so far as each of a
+/// list of offsets is reached. The rest of the stream is unaffected, so a
+/// chunked upload can be checked one chunk at a time against a single read
+/// of the source.
+pub struct Checkpoints {
+    hasher: Hasher,
+    /// Offsets still to reach, furthest first
+    pending: Vec<u64>,
+    fed: u64,
+    reached: Vec<(u64, Vec<u8>)>,
+}
+
+impl Checkpoints {
+    pub fn new(hasher: Hasher, offsets: impl IntoIterator<Item = u64>) -> Self {
+        let mut pending: Vec<u64> = offsets.into_iter().filter(|&o| o > 0).collect();
+        pending.sort_unstable_by(|a, b| b.cmp(a));
+        pending.dedup();
+        Self {
+            hasher,
+            pending,
+            fed: 0,
+            reached: Vec::new(),
+        }
+    }
+
+    /// The digest of the whole stream, and every checkpoint reached
+    pub fn finalize(self) -> (Vec<u8>, Vec<(u64, Vec<u8>)>) {
+        (self.hasher.finalize(), self.reached)
+    }
+}
+
+impl Update for Checkpoints {
+    fn update(&mut self, mut data: &[u8]) {
+        while let Some(&next) = self.pending.last() {
+            let until = next - self.fed;
+            if until > data.len() as u64 {
+                break;
+            }
+            let (before, after) = data.split_at(until as usize);
+            self.hasher.update(before);
+            self.fed = next;
+            if let Some(hasher) = self.hasher.try_clone() {
+                self.reached.push((next, hasher.finalize()));
+            }
+            self.pending.pop();
+            data = after;
+        }
+        self.fed += data.len() as u64;
+        self.hasher.update(data);
+    }
+
+    fn finish(&mut self) -> std::io::Result<()> {
+        self.hasher.finish()
+    }
+}
+
+/// Feeds several hashers at once, to find out which algorithm made a digest
+/// with a single read
+pub struct Several(Vec<(Algorithm, Hasher)>);
+
+impl Several {
+    /// Keyed with `key`, if given, leaving out algorithms that can't use it
+    pub fn new(algorithms: &[Algorithm], key: Option<&[u8]>) -> Self {
+        Self(
+            algorithms
+                .iter()
+                .filter_map(|&a| match key {
+                    Some(key) => a.keyed_hasher(key).ok().map(|h| (a, h)),
+                    None => Some((a, a.hasher())),
+                })
+                .collect(),
+        )
+    }
+
+    pub fn finalize(self) -> Vec<(Algorithm, Vec<u8>)> {
+        self.0
+            .into_iter()
+            .map(|(algorithm, hasher)| (algorithm, hasher.finalize()))
+            .collect()
+    }
+}
+
+impl Update for Several {
+    fn update(&mut self, data: &[u8]) {
+        for (_, hasher) in &mut self.0 {
+            hasher.update(data);
+        }
+    }
+
+    fn finish(&mut self) -> std::io::Result<()> {
+        self.0
+            .iter_mut()
+            .try_for_each(|(_, hasher)| hasher.finish())
+    }
+}
+
+impl Update for sha2::Sha256 {
+    fn update(&mut self, data: &[u8]) {
+        sha2::Digest::update(self, data)
+    }
+}
+
+impl Update for Box<dyn sha2::digest::DynDigest + Send> {
+    fn update(&mut self, data: &[u8]) {
+        sha2::digest::DynDigest::update(&mut **self, data)
+    }
+}
@@ -0,0 +1,1072 @@
+//! Hashing what's inside tar and zip archives, for `--archive`, as they're
+//! read. What's read is fed to the archive's own hasher and to a parser
+//! that hashes each member in turn, so nothing is extracted, and the
+//! archive is read once, through everything else a read goes through.
+//!
+//! Zip archives are read front to back, by the header before each member,
+//! rather than through the central directory at the end, which can't be
+//! reached without seeking.
+
+use crate::algo::{Hasher, Update};
+use flate2::{Decompress, FlushDecompress, Status};
+use std::{
+    io,
+    path::{Path, PathBuf},
+};
+
+/// How much is inflated at a time
+const INFLATED: usize = 64 * 1024;
+
+/// What an archive is, going by its name
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    Tar,
+    TarGz,
+    Zip,
+}
+
+impl Kind {
+    /// What `path`'s extension says it is, if it's an archive
+    pub fn of(path: &Path) -> Option<Self> {
+        let name = path.file_name()?.to_str()?.to_ascii_lowercase();
+        if name.ends_with(".tar") {
+            Some(Self::Tar)
+        } else if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+            Some(Self::TarGz)
+        } else if name.ends_with(".zip") {
+            Some(Self::Zip)
+        } else {
+            None
+        }
+    }
+}
+
+/// Where a member is reported: `archive.tar!path/inside`
+pub fn member_path(archive: &Path, name: &str) -> PathBuf {
+    let mut path = archive.as_os_str().to_owned();
+    path.push("!");
+    path.push(name);
+    path.into()
+}
+
+/// A member that was hashed
+#[derive(Debug, Clone)]
+pub struct Member {
+    /// As the archive names it
+    pub name: String,
+    pub hash: Vec<u8>,
+    pub size: u64,
+}
+
+impl Member {
+    /// As a file of its own, for the writer
+    pub fn into_hashed(self) -> crate::Hashed {
+        crate::Hashed {
+            hash: self.hash,
+            checkpoints: Vec::new(),
+            leaves: Vec::new(),
+            unreadable: Vec::new(),
+            size: self.size,
+            truncated: None,
+            unstable: false,
+            disagreement: None,
+            elapsed: Default::default(),
+            report: Default::default(),
+            timings: Default::default(),
+            audit: None,
+            members: Vec::new(),
+            also: Vec::new(),
+        }
+    }
+}
+
+/// Hashes an archive, and each member in it. Parsing errors are held until
+/// [`Update::finish`], which fails with the first of them.
+pub struct Members {
+    hasher: Hasher,
+    gzip: Option<Gunzip>,
+    parser: Parser,
+    sink: Sink,
+    error: Option<String>,
+}
+
+impl Members {
+    /// Members of a `kind` archive, each hashed with a copy of `hasher` as
+    /// it is now. That's `None` for algorithms whose state can't be copied.
+    pub fn new(kind: Kind, hasher: Hasher) -> Option<Self> {
+        let fresh = hasher.try_clone()?;
+        Some(Self {
+            hasher,
+            gzip: (kind == Kind::TarGz).then(Gunzip::new),
+            parser: match kind {
+                Kind::Zip => Parser::Zip(Zip::new()),
+                Kind::Tar | Kind::TarGz => Parser::Tar(Tar::new()),
+            },
+            sink: Sink {
+                fresh,
+                done: Vec::new(),
+            },
+            error: None,
+        })
+    }
+
+    /// The digest of the archive itself, and its members, in the order it
+    /// holds them
+    pub fn finalize(self) -> (Vec<u8>, Vec<Member>) {
+        (self.hasher.finalize(), self.sink.done)
+    }
+}
+
+impl Update for Members {
+    fn update(&mut self, data: &[u8]) {
+        self.hasher.update(data);
+        if self.error.is_some() {
+            return;
+        }
+        let Self {
+            gzip, parser, sink, ..
+        } = self;
+        let res = match gzip {
+            Some(gzip) => gzip.feed(data, &mut |inflated| parser.feed(inflated, sink)),
+            None => parser.feed(data, sink),
+        };
+        if let Err(e) = res {
+            self.error = Some(e);
+        }
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.hasher.finish()?;
+        let whole = self.gzip.as_ref().is_none_or(Gunzip::finished) && self.parser.finished();
+        let error = match self.error.take() {
+            Some(e) => Some(e),
+            None if !whole => Some("the archive ends early".to_string()),
+            None => None,
+        };
+        match error {
+            Some(e) => Err(io::Error::new(io::ErrorKind::InvalidData, e)),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Where members go as they're found
+struct Sink {
+    /// Copied for each member
+    fresh: Hasher,
+    done: Vec<Member>,
+}
+
+impl Sink {
+    fn open(&self, name: String) -> Box<Open> {
+        Box::new(Open {
+            name,
+            hasher: self.fresh.try_clone().expect("checked in `Members::new`"),
+            crc: crc32fast::Hasher::new(),
+            size: 0,
+        })
+    }
+}
+
+/// A member being hashed
+struct Open {
+    name: String,
+    hasher: Hasher,
+    /// For zip archives, which have one to check
+    crc: crc32fast::Hasher,
+    size: u64,
+}
+
+impl Open {
+    fn update(&mut self, data: &[u8]) {
+        self.hasher.update(data);
+        self.crc.update(data);
+        self.size += data.len() as u64;
+    }
+
+    fn close(self, sink: &mut Sink) {
+        sink.done.push(Member {
+            name: self.name,
+            hash: self.hasher.finalize(),
+            size: self.size,
+        });
+    }
+}
+
+enum Parser {
+    Tar(Tar),
+    Zip(Zip),
+}
+
+impl Parser {
+    fn feed(&mut self, data: &[u8], sink: &mut Sink) -> Result<(), String> {
+        match self {
+            Self::Tar(tar) => tar.feed(data, sink),
+            Self::Zip(zip) => zip.feed(data, sink),
+        }
+    }
+
+    /// Whether it stopped between members, or at the end
+    fn finished(&self) -> bool {
+        match self {
+            Self::Tar(tar) => tar.finished(),
+            Self::Zip(zip) => zip.finished(),
+        }
+    }
+}
+
+/// Inflates what it can of `input` into `out`, handing what comes out to
+/// `sink`, and returns how much of `input` was used and whether the stream
+/// ended there
+fn inflate(
+    state: &mut Decompress,
+    mut input: &[u8],
+    out: &mut [u8],
+    sink: &mut dyn FnMut(&[u8]) -> Result<(), String>,
+) -> Result<(usize, bool), String> {
+    let mut used = 0;
+    loop {
+        let (before_in, before_out) = (state.total_in(), state.total_out());
+        let status = state
+            .decompress(input, out, FlushDecompress::None)
+            .map_err(|e| format!("can't inflate: {}", e))?;
+        let consumed = (state.total_in() - before_in) as usize;
+        let produced = (state.total_out() - before_out) as usize;
+        input = &input[consumed..];
+        used += consumed;
+        if produced > 0 {
+            sink(&out[..produced])?;
+        }
+        match status {
+            Status::StreamEnd => return Ok((used, true)),
+            // there's more of what was taken in still to come out
+            _ if produced == out.len() => {}
+            _ if input.is_empty() => return Ok((used, false)),
+            _ if consumed == 0 && produced == 0 => {
+                return Err("the compressed data is corrupt".to_string())
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Takes what's missing of the first `want` bytes into `buf`, returning
+/// whether it has them all now
+fn fill(buf: &mut Vec<u8>, want: usize, data: &mut &[u8]) -> bool {
+    let n = want.saturating_sub(buf.len()).min(data.len());
+    buf.extend_from_slice(&data[..n]);
+    *data = &data[n..];
+    buf.len() >= want
+}
+
+fn u16_at(bytes: &[u8], at: usize) -> u16 {
+    u16::from_le_bytes([bytes[at], bytes[at + 1]])
+}
+
+fn u32_at(bytes: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes([bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]])
+}
+
+fn u64_at(bytes: &[u8], at: usize) -> u64 {
+    let mut le = [0; 8];
+    le.copy_from_slice(&bytes[at..at + 8]);
+    u64::from_le_bytes(le)
+}
+
+/// Gzip, as a series of members, each a header, a deflate stream and a
+/// trailer with the CRC and size of what it inflates to
+struct Gunzip {
+    state: Gzip,
+    inflate: Decompress,
+    out: Vec<u8>,
+    crc: crc32fast::Hasher,
+    len: u64,
+    /// Whether a whole member was read
+    any: bool,
+}
+
+enum Gzip {
+    Header(Vec<u8>),
+    Body,
+    Trailer(Vec<u8>),
+}
+
+impl Gunzip {
+    fn new() -> Self {
+        Self {
+            state: Gzip::Header(Vec::new()),
+            inflate: Decompress::new(false),
+            out: vec![0; INFLATED],
+            crc: crc32fast::Hasher::new(),
+            len: 0,
+            any: false,
+        }
+    }
+
+    fn feed(
+        &mut self,
+        mut data: &[u8],
+        next: &mut dyn FnMut(&[u8]) -> Result<(), String>,
+    ) -> Result<(), String> {
+        while !data.is_empty() {
+            match &mut self.state {
+                Gzip::Header(buf) => {
+                    // a byte at a time, as where it ends depends on what's
+                    // in it
+                    buf.push(data[0]);
+                    data = &data[1..];
+                    if gzip_header(buf)?.is_some() {
+                        self.state = Gzip::Body;
+                    }
+                }
+                Gzip::Body => {
+                    let (crc, len) = (&mut self.crc, &mut self.len);
+                    let (used, ended) =
+                        inflate(&mut self.inflate, data, &mut self.out, &mut |inflated| {
+                            crc.update(inflated);
+                            *len += inflated.len() as u64;
+                            next(inflated)
+                        })?;
+                    data = &data[used..];
+                    if ended {
+                        self.state = Gzip::Trailer(Vec::new());
+                    }
+                }
+                Gzip::Trailer(buf) => {
+                    if !fill(buf, 8, &mut data) {
+                        continue;
+                    }
+                    let crc = std::mem::replace(&mut self.crc, crc32fast::Hasher::new());
+                    if u32_at(buf, 0) != crc.finalize() || u32_at(buf, 4) != self.len as u32 {
+                        return Err("the gzip checksum doesn't match".to_string());
+                    }
+                    // another member may follow, continuing the stream
+                    self.state = Gzip::Header(Vec::new());
+                    self.inflate = Decompress::new(false);
+                    self.len = 0;
+                    self.any = true;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn finished(&self) -> bool {
+        self.any && matches!(&self.state, Gzip::Header(buf) if buf.is_empty())
+    }
+}
+
+/// How long the gzip header `buf` starts with is, once it's all there
+fn gzip_header(buf: &[u8]) -> Result<Option<usize>, String> {
+    const FHCRC: u8 = 2;
+    const FEXTRA: u8 = 4;
+    const FNAME: u8 = 8;
+    const FCOMMENT: u8 = 16;
+
+    if buf.len() >= 2 && buf[..2] != [0x1f, 0x8b] {
+        return Err("not gzip-compressed".to_string());
+    }
+    if buf.len() < 10 {
+        return Ok(None);
+    }
+    if buf[2] != 8 {
+        return Err(format!("unknown gzip compression method {}", buf[2]));
+    }
+    let flags = buf[3];
+    let mut len = 10;
+    if flags & FEXTRA != 0 {
+        if buf.len() < len + 2 {
+            return Ok(None);
+        }
+        len += 2 + u16_at(buf, len) as usize;
+    }
+    for flag in [FNAME, FCOMMENT] {
+        if flags & flag != 0 {
+            match buf
+                .get(len..)
+                .and_then(|rest| rest.iter().position(|&b| b == 0))
+            {
+                Some(end) => len += end + 1,
+                None => return Ok(None),
+            }
+        }
+    }
+    if flags & FHCRC != 0 {
+        len += 2;
+    }
+    Ok((buf.len() >= len).then_some(len))
+}
+
+/// A tar archive: 512-byte blocks, each member a header block then its
+/// data, padded to a whole block
+struct Tar {
+    state: TarState,
+    /// From a GNU long name or a pax header, for the member that follows
+    name: Option<String>,
+}
+
+enum TarState {
+    Header(Vec<u8>),
+    /// An entry's data, then the padding after it
+    Data {
+        left: u64,
+        padding: u64,
+        content: Content,
+    },
+    /// Past the block of zeros the archive ends with
+    End,
+}
+
+/// What an entry's data is
+enum Content {
+    Member(Box<Open>),
+    /// A GNU long name
+    Name(Vec<u8>),
+    /// A pax header's records
+    Pax(Vec<u8>),
+    /// Directories, links and the like, which have nothing to hash
+    Skip,
+}
+
+const BLOCK: usize = 512;
+
+impl Tar {
+    fn new() -> Self {
+        Self {
+            state: TarState::Header(Vec::new()),
+            name: None,
+        }
+    }
+
+    fn feed(&mut self, mut data: &[u8], sink: &mut Sink) -> Result<(), String> {
+        while !data.is_empty() {
+            match &mut self.state {
+                TarState::Header(buf) => {
+                    if fill(buf, BLOCK, &mut data) {
+                        let header = std::mem::take(buf);
+                        self.start(&header, sink)?;
+                    }
+                }
+                TarState::Data {
+                    left,
+                    padding,
+                    content,
+                } => {
+                    if *left > 0 {
+                        let n = (*left).min(data.len() as u64) as usize;
+                        match content {
+                            Content::Member(open) => open.update(&data[..n]),
+                            Content::Name(bytes) | Content::Pax(bytes) => {
+                                bytes.extend_from_slice(&data[..n])
+                            }
+                            Content::Skip => {}
+                        }
+                        *left -= n as u64;
+                        data = &data[n..];
+                    } else {
+                        let n = (*padding).min(data.len() as u64) as usize;
+                        *padding -= n as u64;
+                        data = &data[n..];
+                    }
+                    self.settle(sink);
+                }
+                // whatever follows isn't part of the archive
+                TarState::End => return Ok(()),
+            }
+        }
+        Ok(())
+    }
+
+    fn start(&mut self, header: &[u8], sink: &mut Sink) -> Result<(), String> {
+        if header.iter().all(|&b| b == 0) {
+            self.state = TarState::End;
+            return Ok(());
+        }
+        let sum: u64 = header
+            .iter()
+            .enumerate()
+            .map(|(i, &b)| if (148..156).contains(&i) { b' ' } else { b } as u64)
+            .sum();
+        if number(&header[148..156]) != Some(sum) {
+            return Err("not a tar archive, or a corrupt one".to_string());
+        }
+        let size = number(&header[124..136])
+            .ok_or_else(|| "a tar header has a corrupt size".to_string())?;
+        let content = match header[156] {
+            b'L' => Content::Name(Vec::new()),
+            b'x' => Content::Pax(Vec::new()),
+            kind => {
+                let name = match self.name.take() {
+                    Some(name) => name,
+                    None => {
+                        let name = text(&header[..100]);
+                        let prefix = text(&header[345..500]);
+                        if &header[257..262] == b"ustar" && !prefix.is_empty() {
+                            format!("{}/{}", prefix, name)
+                        } else {
+                            name
+                        }
+                    }
+                };
+                match kind {
+                    b'0' | b'\0' | b'7' => Content::Member(sink.open(name)),
+                    _ => Content::Skip,
+                }
+            }
+        };
+        self.state = TarState::Data {
+            left: size,
+            padding: (BLOCK as u64 - size % BLOCK as u64) % BLOCK as u64,
+            content,
+        };
+        self.settle(sink);
+        Ok(())
+    }
+
+    /// Closes the entry whose data and padding have all gone by
+    fn settle(&mut self, sink: &mut Sink) {
+        if !matches!(
+            self.state,
+            TarState::Data {
+                left: 0,
+                padding: 0,
+                ..
+            }
+        ) {
+            return;
+        }
+        if let TarState::Data { content, .. } =
+            std::mem::replace(&mut self.state, TarState::Header(Vec::new()))
+        {
+            match content {
+                Content::Member(open) => open.close(sink),
+                Content::Name(bytes) => self.name = Some(text(&bytes)),
+                Content::Pax(records) => {
+                    if let Some(path) = pax_path(&records) {
+                        self.name = Some(path);
+                    }
+                }
+                Content::Skip => {}
+            }
+        }
+    }
+
+    fn finished(&self) -> bool {
+        match &self.state {
+            TarState::Header(buf) => buf.is_empty(),
+            TarState::Data { .. } => false,
+            TarState::End => true,
+        }
+    }
+}
+
+/// A header field's number, in octal or, when its top bit is set, in
+/// big-endian base 256
+fn number(field: &[u8]) -> Option<u64> {
+    if field.first().is_some_and(|&b| b & 0x80 != 0) {
+        let mut n: u64 = (field[0] & 0x7f) as u64;
+        for &b in &field[1..] {
+            n = n.checked_mul(256)? | b as u64;
+        }
+        return Some(n);
+    }
+    let digits = std::str::from_utf8(field).ok()?;
+    let digits = digits.trim_matches(|c| c == ' ' || c == '\0');
+    u64::from_str_radix(digits, 8).ok()
+}
+
+/// A NUL-terminated field, as text
+fn text(field: &[u8]) -> String {
+    let end = field.iter().position(|&b| b == 0).unwrap_or(field.len());
+    String::from_utf8_lossy(&field[..end]).into_owned()
+}
+
+/// The `path` in pax `records`, each `LENGTH key=value\n`
+fn pax_path(mut records: &[u8]) -> Option<String> {
+    let mut path = None;
+    while !records.is_empty() {
+        let space = records.iter().position(|&b| b == b' ')?;
+        let len: usize = std::str::from_utf8(&records[..space]).ok()?.parse().ok()?;
+        let record = records.get(space + 1..len)?;
+        let record = record.strip_suffix(b"\n").unwrap_or(record);
+        if let Some(value) = record.strip_prefix(b"path=") {
+            path = Some(String::from_utf8_lossy(value).into_owned());
+        }
+        records = &records[len..];
+    }
+    path
+}
+
+/// A zip archive, read as a series of local headers, each followed by a
+/// member's data and, when the header couldn't say how long that was, a
+/// data descriptor
+struct Zip {
+    state: ZipState,
+    out: Vec<u8>,
+}
+
+enum ZipState {
+    Header(Vec<u8>),
+    Stored {
+        left: u64,
+        entry: Entry,
+    },
+    Deflated {
+        /// Unless a data descriptor follows, which deflate's own end marker
+        /// has to do without
+        left: Option<u64>,
+        inflate: Box<Decompress>,
+        entry: Entry,
+    },
+    Descriptor {
+        buf: Vec<u8>,
+        entry: Entry,
+    },
+    /// At the central directory, which only lists the members again
+    End,
+}
+
+struct Entry {
+    name: String,
+    /// `None` for directories
+    open: Option<Box<Open>>,
+    crc: u32,
+    /// Whether a data descriptor follows the data
+    descriptor: bool,
+    /// Whether the sizes are 64-bit
+    zip64: bool,
+}
+
+impl Entry {
+    fn update(&mut self, data: &[u8]) {
+        if let Some(open) = &mut self.open {
+            open.update(data);
+        }
+    }
+
+    fn close(self, crc: u32, sink: &mut Sink) -> Result<(), String> {
+        if let Some(open) = self.open {
+            if open.crc.clone().finalize() != crc {
+                return Err(format!("{}: the CRC doesn't match", self.name));
+            }
+            open.close(sink);
+        }
+        Ok(())
+    }
+}
+
+const LOCAL: u32 = 0x04034b50;
+const DESCRIPTOR: u32 = 0x08074b50;
+const CENTRAL: u32 = 0x02014b50;
+const END: u32 = 0x06054b50;
+const ZIP64_END: u32 = 0x06064b50;
+
+/// How long the record `buf` starts with is, as far as what's in it tells
+fn header_len(buf: &[u8]) -> usize {
+    match buf.len() {
+        len if len < 4 => 4,
+        _ if u32_at(buf, 0) != LOCAL => 4,
+        len if len < 30 => 30,
+        _ => 30 + u16_at(buf, 26) as usize + u16_at(buf, 28) as usize,
+    }
+}
+
+/// How long the data descriptor `buf` starts with is, as far as what's in
+/// it tells
+fn descriptor_len(buf: &[u8], zip64: bool) -> usize {
+    let sizes = if zip64 { 16 } else { 8 };
+    match buf.len() {
+        len if len < 4 => 4,
+        _ if u32_at(buf, 0) == DESCRIPTOR => 8 + sizes,
+        _ => 4 + sizes,
+    }
+}
+
+impl Zip {
+    fn new() -> Self {
+        Self {
+            state: ZipState::Header(Vec::new()),
+            out: vec![0; INFLATED],
+        }
+    }
+
+    fn feed(&mut self, mut data: &[u8], sink: &mut Sink) -> Result<(), String> {
+        while !data.is_empty() {
+            match &mut self.state {
+                ZipState::Header(buf) => {
+                    // how long a header is only shows as it comes in
+                    let want = header_len(buf);
+                    if fill(buf, want, &mut data) && header_len(buf) == buf.len() {
+                        let header = std::mem::take(buf);
+                        self.start(&header, sink)?;
+                    }
+                }
+                ZipState::Stored { left, entry } => {
+                    let n = (*left).min(data.len() as u64) as usize;
+                    entry.update(&data[..n]);
+                    *left -= n as u64;
+                    data = &data[n..];
+                    if *left == 0 {
+                        self.after_data(sink)?;
+                    }
+                }
+                ZipState::Deflated {
+                    left,
+                    inflate: state,
+                    entry,
+                } => {
+                    let input = match left {
+                        Some(left) => &data[..(*left).min(data.len() as u64) as usize],
+                        None => data,
+                    };
+                    let (used, ended) = inflate(state, input, &mut self.out, &mut |inflated| {
+                        entry.update(inflated);
+                        Ok(())
+                    })?;
+                    data = &data[used..];
+                    if let Some(left) = left {
+                        *left -= used as u64;
+                        if ended != (*left == 0) {
+                            return Err(format!(
+                                "{}: the compressed size doesn't match the data",
+                                entry.name
+                            ));
+                        }
+                    }
+                    if ended {
+                        self.after_data(sink)?;
+                    }
+                }
+                ZipState::Descriptor { buf, entry } => {
+                    let want = descriptor_len(buf, entry.zip64);
+                    if fill(buf, want, &mut data) && descriptor_len(buf, entry.zip64) == buf.len() {
+                        // after the signature, which is optional
+                        let crc = match u32_at(buf, 0) {
+                            DESCRIPTOR => u32_at(buf, 4),
+                            crc => crc,
+                        };
+                        if let ZipState::Descriptor { entry, .. } =
+                            std::mem::replace(&mut self.state, ZipState::Header(Vec::new()))
+                        {
+                            entry.close(crc, sink)?;
+                        }
+                    }
+                }
+                // the rest only lists what was read already
+                ZipState::End => return Ok(()),
+            }
+        }
+        Ok(())
+    }
+
+    /// Starts on the record whose header is `header`
+    fn start(&mut self, header: &[u8], sink: &mut Sink) -> Result<(), String> {
+        match u32_at(header, 0) {
+            LOCAL => {}
+            CENTRAL | END | ZIP64_END => {
+                self.state = ZipState::End;
+                return Ok(());
+            }
+            _ => return Err("not a zip archive, or a corrupt one".to_string()),
+        }
+        let flags = u16_at(header, 6);
+        let method = u16_at(header, 8);
+        let crc = u32_at(header, 14);
+        let mut compressed = u32_at(header, 18) as u64;
+        let mut len = u32_at(header, 22) as u64;
+        let name_len = u16_at(header, 26) as usize;
+        let name = String::from_utf8_lossy(&header[30..30 + name_len]).into_owned();
+        if flags & 1 != 0 {
+            return Err(format!("{}: encrypted members can't be hashed", name));
+        }
+
+        // the zip64 extra field has the sizes too large for the header, in
+        // this order
+        let mut zip64 = false;
+        let mut extra = &header[30 + name_len..];
+        while extra.len() >= 4 {
+            let (id, size) = (u16_at(extra, 0), u16_at(extra, 2) as usize);
+            let field = extra.get(4..4 + size).unwrap_or(&[]);
+            if id == 1 {
+                zip64 = true;
+                let mut at = 0;
+                for value in [&mut len, &mut compressed] {
+                    if *value == u32::MAX as u64 && field.len() >= at + 8 {
+                        *value = u64_at(field, at);
+                        at += 8;
+                    }
+                }
+            }
+            extra = extra.get(4 + size..).unwrap_or(&[]);
+        }
+
+        let descriptor = flags & 8 != 0;
+        let is_dir = name.ends_with('/');
+        let entry = Entry {
+            open: (!is_dir).then(|| sink.open(name.clone())),
+            name,
+            crc,
+            descriptor,
+            zip64,
+        };
+        self.state = match method {
+            0 if descriptor && !is_dir => {
+                return Err(format!(
+                    "{}: stored without its size up front, which can't be read as it streams",
+                    entry.name
+                ))
+            }
+            0 => ZipState::Stored {
+                left: compressed,
+                entry,
+            },
+            8 => ZipState::Deflated {
+                left: (!descriptor).then_some(compressed),
+                inflate: Box::new(Decompress::new(false)),
+                entry,
+            },
+            _ => {
+                return Err(format!(
+                    "{}: compression method {} isn't supported",
+                    entry.name, method
+                ))
+            }
+        };
+        if matches!(self.state, ZipState::Stored { left: 0, .. }) {
+            self.after_data(sink)?;
+        }
+        Ok(())
+    }
+
+    /// Moves past a member whose data has all gone by
+    fn after_data(&mut self, sink: &mut Sink) -> Result<(), String> {
+        let entry = match std::mem::replace(&mut self.state, ZipState::Header(Vec::new())) {
+            ZipState::Stored { entry, .. } | ZipState::Deflated { entry, .. } => entry,
+            _ => unreachable!("only called after a member's data"),
+        };
+        if entry.descriptor {
+            self.state = ZipState::Descriptor {
+                buf: Vec::new(),
+                entry,
+            };
+            return Ok(());
+        }
+        let crc = entry.crc;
+        entry.close(crc, sink)
+    }
+
+    fn finished(&self) -> bool {
+        matches!(self.state, ZipState::End)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::algo::Algorithm;
+    use std::io::Write;
+
+    fn digest(data: &[u8]) -> Vec<u8> {
+        let mut hasher = Algorithm::Sha256.hasher();
+        hasher.update(data);
+        hasher.finalize()
+    }
+
+    /// Feeds `archive` a few bytes at a time, as reads would, returning its
+    /// members by name, digest and size
+    fn members(kind: Kind, archive: &[u8]) -> io::Result<Vec<(String, Vec<u8>, u64)>> {
+        let mut members = Members::new(kind, Algorithm::Sha256.hasher()).unwrap();
+        for chunk in archive.chunks(7) {
+            members.update(chunk);
+        }
+        members.finish()?;
+        let (whole, members) = members.finalize();
+        assert_eq!(whole, digest(archive));
+        Ok(members
+            .into_iter()
+            .map(|m| (m.name, m.hash, m.size))
+            .collect())
+    }
+
+    fn member(name: &str, data: &[u8]) -> (String, Vec<u8>, u64) {
+        (name.to_string(), digest(data), data.len() as u64)
+    }
+
+    /// A ustar header, then `data` padded to a whole block
+    fn tar_entry(name: &str, prefix: &str, kind: u8, data: &[u8]) -> Vec<u8> {
+        let mut header = vec![0; BLOCK];
+        header[..name.len()].copy_from_slice(name.as_bytes());
+        header[124..136].copy_from_slice(format!("{:011o}\0", data.len()).as_bytes());
+        header[156] = kind;
+        header[257..263].copy_from_slice(b"ustar\0");
+        header[345..345 + prefix.len()].copy_from_slice(prefix.as_bytes());
+        header[148..156].fill(b' ');
+        let sum: u32 = header.iter().map(|&b| b as u32).sum();
+        header[148..156].copy_from_slice(format!("{:06o}\0 ", sum).as_bytes());
+        header.extend_from_slice(data);
+        header.resize(header.len().div_ceil(BLOCK) * BLOCK, 0);
+        header
+    }
+
+    fn tar() -> Vec<u8> {
+        let long = "long/".repeat(30) + "name";
+        let pax = "17 path=pax/path\n";
+        [
+            tar_entry("dir/", "", b'5', b""),
+            tar_entry("a.txt", "", b'0', b"hello"),
+            tar_entry("b.txt", "deep/prefix", b'0', &[7; 1000]),
+            tar_entry("././@LongLink", "", b'L', format!("{}\0", long).as_bytes()),
+            tar_entry("truncated", "", b'0', b"long"),
+            tar_entry("PaxHeader", "", b'x', pax.as_bytes()),
+            tar_entry("ignored", "", b'0', b""),
+            vec![0; 2 * BLOCK],
+        ]
+        .concat()
+    }
+
+    fn tar_members() -> Vec<(String, Vec<u8>, u64)> {
+        vec![
+            member("a.txt", b"hello"),
+            member("deep/prefix/b.txt", &[7; 1000]),
+            member(&("long/".repeat(30) + "name"), b"long"),
+            member("pax/path", b""),
+        ]
+    }
+
+    #[test]
+    fn hashes_tar_members() {
+        assert_eq!(members(Kind::Tar, &tar()).unwrap(), tar_members());
+    }
+
+    #[test]
+    fn hashes_gzipped_tar_members() {
+        // split across two gzip members, the second with a name in its header
+        let tar = tar();
+        let (first, second) = tar.split_at(1500);
+        let mut archive = Vec::new();
+        for (part, name) in [(first, None), (second, Some("part"))] {
+            let mut builder = flate2::GzBuilder::new();
+            if let Some(name) = name {
+                builder = builder.filename(name);
+            }
+            let mut gz = builder.write(Vec::new(), flate2::Compression::default());
+            gz.write_all(part).unwrap();
+            archive.extend(gz.finish().unwrap());
+        }
+        assert_eq!(members(Kind::TarGz, &archive).unwrap(), tar_members());
+
+        let last = archive.len() - 1;
+        archive[last] ^= 1;
+        let e = members(Kind::TarGz, &archive).unwrap_err();
+        assert!(e.to_string().contains("checksum"), "{}", e);
+    }
+
+    #[test]
+    fn a_cut_short_archive_fails() {
+        let tar = tar();
+        let e = members(Kind::Tar, &tar[..BLOCK + 3]).unwrap_err();
+        assert_eq!(e.to_string(), "the archive ends early");
+    }
+
+    /// A local header, then `data` as stored or deflated, with a data
+    /// descriptor after it if `descriptor`
+    fn zip_entry(name: &str, data: &[u8], deflate: bool, descriptor: bool) -> Vec<u8> {
+        let compressed = if deflate {
+            let mut encoder =
+                flate2::write::DeflateEncoder::new(Vec::new(), flate2::Compression::default());
+            encoder.write_all(data).unwrap();
+            encoder.finish().unwrap()
+        } else {
+            data.to_vec()
+        };
+        let crc = crc32fast::hash(data);
+        let (flags, method): (u16, u16) = (if descriptor { 8 } else { 0 }, deflate as u16 * 8);
+        let (crc_field, sizes) = if descriptor {
+            (0, [0, 0])
+        } else {
+            (crc, [compressed.len() as u32, data.len() as u32])
+        };
+        let mut entry = Vec::new();
+        entry.extend(LOCAL.to_le_bytes());
+        entry.extend(20u16.to_le_bytes());
+        entry.extend(flags.to_le_bytes());
+        entry.extend(method.to_le_bytes());
+        entry.extend([0; 4]);
+        entry.extend(crc_field.to_le_bytes());
+        entry.extend(sizes[0].to_le_bytes());
+        entry.extend(sizes[1].to_le_bytes());
+        entry.extend((name.len() as u16).to_le_bytes());
+        entry.extend(0u16.to_le_bytes());
+        entry.extend(name.as_bytes());
+        entry.extend(&compressed);
+        if descriptor {
+            entry.extend(DESCRIPTOR.to_le_bytes());
+            entry.extend(crc.to_le_bytes());
+            entry.extend((compressed.len() as u32).to_le_bytes());
+            entry.extend((data.len() as u32).to_le_bytes());
+        }
+        entry
+    }
+
+    #[test]
+    fn hashes_zip_members() {
+        let text = b"some text, some text, some text".repeat(100);
+        let archive = [
+            zip_entry("dir/", b"", false, false),
+            zip_entry("stored.bin", &[1, 2, 3], false, false),
+            zip_entry("deflated.txt", &text, true, false),
+            zip_entry("streamed.txt", &text[..50], true, true),
+            // where the central directory starts, and the rest goes unread
+            CENTRAL.to_le_bytes().to_vec(),
+            vec![0xff; 40],
+        ]
+        .concat();
+        assert_eq!(
+            members(Kind::Zip, &archive).unwrap(),
+            [
+                member("stored.bin", &[1, 2, 3]),
+                member("deflated.txt", &text),
+                member("streamed.txt", &text[..50]),
+            ]
+        );
+    }
+
+    #[test]
+    fn zip_members_must_match_their_crc() {
+        let mut archive = zip_entry("a", b"abc", false, false);
+        let at = archive.len() - 1;
+        archive[at] = b'x';
+        archive.extend(END.to_le_bytes());
+        let e = members(Kind::Zip, &archive).unwrap_err();
+        assert_eq!(e.to_string(), "a: the CRC doesn't match");
+    }
+
+    #[test]
+    fn reads_header_fields() {
+        assert_eq!(number(b"0000644\0"), Some(0o644));
+        assert_eq!(number(b"     17 "), Some(0o17));
+        assert_eq!(number(&[0x80, 0, 0, 1, 0]), Some(256));
+        assert_eq!(number(b"9"), None);
+        assert_eq!(text(b"name\0junk"), "name");
+        assert_eq!(
+            pax_path(b"18 path=a/b/c.txt\n"),
+            Some("a/b/c.txt".to_string())
+        );
+        assert_eq!(pax_path(b"99 path=short\n"), None);
+
+        let named = [&[0x1f, 0x8b, 8, 8, 0, 0, 0, 0, 0, 3][..], b"name\0"].concat();
+        assert_eq!(gzip_header(&named[..10]), Ok(None));
+        assert_eq!(gzip_header(&named), Ok(Some(15)));
+        assert!(gzip_header(b"PK").is_err());
+    }
+
+    #[test]
+    fn names_members_after_their_archive() {
+        assert_eq!(
+            member_path(Path::new("dir/a.tar.gz"), "inside/b"),
+            Path::new("dir/a.tar.gz!inside/b")
+        );
+        assert_eq!(Kind::of(Path::new("A.TGZ")), Some(Kind::TarGz));
+        assert_eq!(Kind::of(Path::new("a.tar.zst")), None);
+    }
+}
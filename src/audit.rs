@@ -0,0 +1,105 @@
+//! Owners and permissions, for a basic security audit alongside the hashes:
+//! world-writable files, setuid and setgid binaries, and anything whose
+//! owner or mode changed since an earlier run.
+
+use crate::filter::MIN_DIGEST_LEN;
+use color_eyre::eyre::{self, eyre};
+use std::{
+    collections::HashMap,
+    fmt::{self, Write as _},
+    path::{Path, PathBuf},
+};
+
+/// Who owns a file and what it allows
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Audit {
+    pub uid: u32,
+    pub gid: u32,
+    /// Permission bits, including setuid, setgid and sticky
+    pub mode: u32,
+}
+
+impl Audit {
+    #[cfg(unix)]
+    pub fn of(metadata: &std::fs::Metadata) -> Option<Self> {
+        use std::os::unix::fs::MetadataExt;
+
+        Some(Self {
+            uid: metadata.uid(),
+            gid: metadata.gid(),
+            mode: metadata.mode() & 0o7777,
+        })
+    }
+
+    #[cfg(not(unix))]
+    pub fn of(_metadata: &std::fs::Metadata) -> Option<Self> {
+        None
+    }
+
+    /// The `owner=` and `mode=` words, then any anomalies, each with a
+    /// leading space
+    pub fn words(&self, previous: Option<&Audit>) -> String {
+        let mut words = format!(" {}", self);
+        if self.mode & 0o002 != 0 {
+            words += " world-writable";
+        }
+        if self.mode & 0o4000 != 0 {
+            words += " setuid";
+        }
+        if self.mode & 0o2000 != 0 {
+            words += " setgid";
+        }
+        if let Some(previous) = previous {
+            if (previous.uid, previous.gid) != (self.uid, self.gid) {
+                write!(words, " owner-changed={}:{}", previous.uid, previous.gid).unwrap();
+            }
+            if previous.mode != self.mode {
+                write!(words, " mode-changed={:04o}", previous.mode).unwrap();
+            }
+        }
+        words
+    }
+}
+
+impl fmt::Display for Audit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "owner={}:{} mode={:04o}", self.uid, self.gid, self.mode)
+    }
+}
+
+/// Reads owners and modes back from an earlier `--audit` run's output.
+/// Lines without them, like error lines, are skipped.
+pub async fn load(path: &Path) -> Result<HashMap<PathBuf, Audit>, eyre::Error> {
+    let text = async_std::fs::read_to_string(path).await?;
+    let mut audits = HashMap::new();
+    for (i, line) in text.lines().enumerate() {
+        let bad = || eyre!("{}:{}: malformed owner or mode", path.display(), i + 1);
+        let (owner, mode) = match (word(line, "owner="), word(line, "mode=")) {
+            (Some(owner), Some(mode)) => (owner, mode),
+            _ => continue,
+        };
+        let (uid, gid) = owner.split_once(':').ok_or_else(bad)?;
+        let audit = Audit {
+            uid: uid.parse().map_err(|_| bad())?,
+            gid: gid.parse().map_err(|_| bad())?,
+            mode: u32::from_str_radix(mode, 8).map_err(|_| bad())?,
+        };
+        // the digest follows the path, which may itself contain spaces
+        let digest = line
+            .match_indices(' ')
+            .find(|&(i, _)| {
+                line[i + 1..]
+                    .split(' ')
+                    .next()
+                    .is_some_and(|w| w.len() >= MIN_DIGEST_LEN && crate::unhex(w).is_some())
+            })
+            .ok_or_else(bad)?;
+        audits.insert(PathBuf::from(&line[..digest.0]), audit);
+    }
+    Ok(audits)
+}
+
+/// The value of the first ` key=value` word in `line`
+fn word<'a>(line: &'a str, key: &str) -> Option<&'a str> {
+    line.split(' ').find_map(|w| w.strip_prefix(key))
+}
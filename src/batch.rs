@@ -0,0 +1,38 @@
+//! Sizing the batches of small files a worker stats and opens at once, for
+//! `--batch-small-files`. Where every open waits on a round trip, as on
+//! NFS or SMB, hashing a small file is mostly waiting, and a batch waits
+//! for all of its files together; where opens are cheap, batching only
+//! takes files from other workers, so batches shrink back to one.
+
+use std::time::Duration;
+
+/// A batch grows while statting it took at least this share of the time
+/// hashing it did
+const WAITING: u32 = 4;
+
+pub struct Batcher {
+    limit: usize,
+    size: usize,
+}
+
+impl Batcher {
+    /// Batches of at most `limit` files, starting from one
+    pub fn new(limit: usize) -> Self {
+        Self { limit, size: 1 }
+    }
+
+    /// How many files to take for the next batch
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Resizes the next batch from how long the last one took to stat,
+    /// all at once, and then to hash
+    pub fn observe(&mut self, stat: Duration, hash: Duration) {
+        self.size = if stat * WAITING >= hash {
+            (self.size * 2).min(self.limit)
+        } else {
+            (self.size / 2).max(1)
+        };
+    }
+}
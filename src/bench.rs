@@ -0,0 +1,207 @@
+//! Throughput of the read paths and hashers, for `surviving bench`, from
+//! synthetic data in memory so storage doesn't drown out what's measured.
+//!
+//! The same bytes are read with a plain read loop, then through
+//! [`SimpleAsyncReader`] as files are, once polled directly and once with
+//! a boxed future per read, so what the adapter's state machine costs
+//! shows up as the gap to the plain loop.
+
+use crate::{
+    algo::{Algorithm, Update},
+    Position, SimpleAsyncReader, SimpleRead, TracingReader,
+};
+use async_trait::async_trait;
+use color_eyre::eyre::{self, eyre};
+use futures::io::{AsyncRead, AsyncReadExt, Cursor};
+use std::{
+    fmt, io,
+    path::Path,
+    str::FromStr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+/// How the data is read
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reader {
+    /// Straight from the source, with no adapter
+    Raw,
+    /// Through [`SimpleAsyncReader`], which polls the source directly
+    Polled,
+    /// Through [`SimpleAsyncReader`], boxing a future for every read
+    Boxed,
+}
+
+impl Reader {
+    pub const ALL: [Self; 3] = [Self::Raw, Self::Polled, Self::Boxed];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Raw => "raw",
+            Self::Polled => "polled",
+            Self::Boxed => "boxed",
+        }
+    }
+}
+
+/// What's done with the data once it's read
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Work {
+    /// Nothing, to measure reading alone
+    Read,
+    Hash(Algorithm),
+}
+
+impl fmt::Display for Work {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Read => f.write_str("none"),
+            Self::Hash(algorithm) => f.write_str(algorithm.name()),
+        }
+    }
+}
+
+/// `none` to only read, or a built-in algorithm
+impl FromStr for Work {
+    type Err = eyre::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "none" {
+            return Ok(Self::Read);
+        }
+        s.parse().map(Self::Hash).map_err(|_| {
+            eyre!(
+                "expected `none` or a built-in algorithm, like `sha256`, got {:?}",
+                s
+            )
+        })
+    }
+}
+
+/// What to measure
+#[derive(Debug, Clone)]
+pub struct Options {
+    /// Bytes read in each case
+    pub size: usize,
+    pub work: Vec<Work>,
+    pub buffer_sizes: Vec<usize>,
+    /// Times each case is run, the fastest one counting
+    pub rounds: usize,
+}
+
+/// How one case went
+#[derive(Debug, Clone)]
+pub struct Measurement {
+    pub reader: Reader,
+    pub work: Work,
+    pub buffer_size: usize,
+    pub reads: u64,
+    /// The fastest round's
+    pub elapsed: Duration,
+}
+
+impl Measurement {
+    pub fn bytes_per_sec(&self, size: usize) -> f64 {
+        size as f64 / self.elapsed.as_secs_f64()
+    }
+}
+
+/// The data every case reads, the same on every run
+fn data(size: usize) -> Arc<[u8]> {
+    (0..size).map(|i| (i % 251) as u8).collect()
+}
+
+/// Runs every case, in the order of `options`, each reader after the other
+pub async fn run(options: &Options) -> Result<Vec<Measurement>, eyre::Error> {
+    let data = data(options.size);
+    let mut measurements = Vec::new();
+    for &work in &options.work {
+        for &buffer_size in &options.buffer_sizes {
+            for reader in Reader::ALL {
+                let mut best: Option<(u64, Duration)> = None;
+                for _ in 0..options.rounds.max(1) {
+                    let (reads, elapsed) = case(reader, work, buffer_size, data.clone()).await?;
+                    if best.is_none_or(|(_, best)| elapsed < best) {
+                        best = Some((reads, elapsed));
+                    }
+                }
+                let (reads, elapsed) = best.unwrap();
+                tracing::debug!(reader = reader.name(), %work, buffer_size, ?elapsed, "measured");
+                measurements.push(Measurement {
+                    reader,
+                    work,
+                    buffer_size,
+                    reads,
+                    elapsed,
+                });
+            }
+        }
+    }
+    Ok(measurements)
+}
+
+/// A source that only has [`SimpleRead::simple_read`], so every read is a
+/// boxed future. Public for `benches/read_path.rs`.
+#[doc(hidden)]
+pub struct FutureOnly<R>(pub R);
+
+#[async_trait]
+impl<R: AsyncRead + Send + Unpin> SimpleRead for FutureOnly<R> {
+    async fn simple_read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf).await
+    }
+}
+
+/// Reads all of `data` once, returning how many reads it took and how
+/// long
+async fn case(
+    reader: Reader,
+    work: Work,
+    buffer_size: usize,
+    data: Arc<[u8]>,
+) -> Result<(u64, Duration), eyre::Error> {
+    let source = Cursor::new(data);
+    let position = || Position::new(Path::new("bench"));
+    match reader {
+        Reader::Raw => read_all(source, work, buffer_size).await,
+        Reader::Polled => {
+            let inner = TracingReader::new(source, position());
+            let reader = SimpleAsyncReader::with_capacity(inner, position(), buffer_size);
+            read_all(reader, work, buffer_size).await
+        }
+        Reader::Boxed => {
+            let reader =
+                SimpleAsyncReader::with_capacity(FutureOnly(source), position(), buffer_size);
+            read_all(reader, work, buffer_size).await
+        }
+    }
+}
+
+async fn read_all(
+    mut reader: impl AsyncRead + Unpin,
+    work: Work,
+    buffer_size: usize,
+) -> Result<(u64, Duration), eyre::Error> {
+    let mut hasher = match work {
+        Work::Read => None,
+        Work::Hash(algorithm) => Some(algorithm.hasher()),
+    };
+    let mut buf = vec![0; buffer_size];
+    let mut reads = 0;
+    let started = Instant::now();
+    loop {
+        let n = reader.read(&mut buf).await?;
+        if n == 0 {
+            break;
+        }
+        reads += 1;
+        if let Some(hasher) = &mut hasher {
+            hasher.update(&buf[..n]);
+        }
+    }
+    // finishing is part of hashing, for the tree hashes most of all
+    if let Some(hasher) = hasher {
+        std::hint::black_box(hasher.finalize());
+    }
+    Ok((reads, started.elapsed()))
+}
@@ -0,0 +1,132 @@
+//! BLAKE2b (RFC 7693), unkeyed, for minisign signatures, which are made
+//! over a file's BLAKE2b-512 digest and keep a BLAKE2b-256 checksum of
+//! their keys.
+
+use std::convert::TryInto;
+
+const BLOCK_LEN: usize = 128;
+
+const IV: [u64; 8] = [
+    0x6A09E667F3BCC908,
+    0xBB67AE8584CAA73B,
+    0x3C6EF372FE94F82B,
+    0xA54FF53A5F1D36F1,
+    0x510E527FADE682D1,
+    0x9B05688C2B3E6C1F,
+    0x1F83D9ABFB41BD6B,
+    0x5BE0CD19137E2179,
+];
+
+const SIGMA: [[usize; 16]; 10] = [
+    [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15],
+    [14, 10, 4, 8, 9, 15, 13, 6, 1, 12, 0, 2, 11, 7, 5, 3],
+    [11, 8, 12, 0, 5, 2, 15, 13, 10, 14, 3, 6, 7, 1, 9, 4],
+    [7, 9, 3, 1, 13, 12, 11, 14, 2, 6, 5, 10, 4, 0, 15, 8],
+    [9, 0, 5, 7, 2, 4, 10, 15, 14, 1, 11, 12, 6, 8, 3, 13],
+    [2, 12, 6, 10, 0, 11, 8, 3, 4, 13, 7, 5, 15, 14, 1, 9],
+    [12, 5, 1, 15, 14, 13, 4, 10, 0, 7, 6, 3, 9, 2, 8, 11],
+    [13, 11, 7, 14, 12, 1, 3, 9, 5, 0, 15, 4, 8, 6, 2, 10],
+    [6, 15, 14, 9, 11, 3, 0, 8, 12, 2, 13, 7, 1, 4, 10, 5],
+    [10, 2, 8, 4, 7, 6, 1, 5, 15, 11, 9, 14, 3, 12, 13, 0],
+];
+
+#[derive(Clone)]
+pub struct Blake2b {
+    h: [u64; 8],
+    /// Bytes compressed so far
+    count: u128,
+    /// Held back until more comes or the end, since the last block is
+    /// compressed differently
+    block: [u8; BLOCK_LEN],
+    len: usize,
+    out_len: usize,
+}
+
+impl Blake2b {
+    /// For digests of `out_len` bytes, from 1 to 64
+    pub fn new(out_len: usize) -> Self {
+        assert!((1..=64).contains(&out_len));
+        let mut h = IV;
+        h[0] ^= 0x01010000 ^ out_len as u64;
+        Self {
+            h,
+            count: 0,
+            block: [0; BLOCK_LEN],
+            len: 0,
+            out_len,
+        }
+    }
+
+    pub fn update(&mut self, mut data: &[u8]) {
+        while !data.is_empty() {
+            if self.len == BLOCK_LEN {
+                self.count += BLOCK_LEN as u128;
+                let block = self.block;
+                self.compress(&block, false);
+                self.len = 0;
+            }
+            let n = (BLOCK_LEN - self.len).min(data.len());
+            self.block[self.len..self.len + n].copy_from_slice(&data[..n]);
+            self.len += n;
+            data = &data[n..];
+        }
+    }
+
+    pub fn finalize(mut self) -> Vec<u8> {
+        self.count += self.len as u128;
+        let mut block = self.block;
+        block[self.len..].fill(0);
+        self.compress(&block, true);
+        let mut out: Vec<u8> = self.h.iter().flat_map(|w| w.to_le_bytes()).collect();
+        out.truncate(self.out_len);
+        out
+    }
+
+    fn compress(&mut self, block: &[u8; BLOCK_LEN], last: bool) {
+        let mut m = [0u64; 16];
+        for (word, bytes) in m.iter_mut().zip(block.chunks_exact(8)) {
+            *word = u64::from_le_bytes(bytes.try_into().unwrap());
+        }
+        let mut v = [0u64; 16];
+        v[..8].copy_from_slice(&self.h);
+        v[8..].copy_from_slice(&IV);
+        v[12] ^= self.count as u64;
+        v[13] ^= (self.count >> 64) as u64;
+        if last {
+            v[14] = !v[14];
+        }
+        for round in 0..12 {
+            let s = &SIGMA[round % 10];
+            g(&mut v, 0, 4, 8, 12, m[s[0]], m[s[1]]);
+            g(&mut v, 1, 5, 9, 13, m[s[2]], m[s[3]]);
+            g(&mut v, 2, 6, 10, 14, m[s[4]], m[s[5]]);
+            g(&mut v, 3, 7, 11, 15, m[s[6]], m[s[7]]);
+            g(&mut v, 0, 5, 10, 15, m[s[8]], m[s[9]]);
+            g(&mut v, 1, 6, 11, 12, m[s[10]], m[s[11]]);
+            g(&mut v, 2, 7, 8, 13, m[s[12]], m[s[13]]);
+            g(&mut v, 3, 4, 9, 14, m[s[14]], m[s[15]]);
+        }
+        for i in 0..8 {
+            self.h[i] ^= v[i] ^ v[i + 8];
+        }
+    }
+}
+
+/// The mixing function, on four words of the state
+fn g(v: &mut [u64; 16], a: usize, b: usize, c: usize, d: usize, x: u64, y: u64) {
+    v[a] = v[a].wrapping_add(v[b]).wrapping_add(x);
+    v[d] = (v[d] ^ v[a]).rotate_right(32);
+    v[c] = v[c].wrapping_add(v[d]);
+    v[b] = (v[b] ^ v[c]).rotate_right(24);
+    v[a] = v[a].wrapping_add(v[b]).wrapping_add(y);
+    v[d] = (v[d] ^ v[a]).rotate_right(16);
+    v[c] = v[c].wrapping_add(v[d]);
+    v[b] = (v[b] ^ v[c]).rotate_right(63);
+}
+
+/// The `out_len`-byte digest of `data`
+pub fn digest(out_len: usize, data: &[u8]) -> Vec<u8> {
+    let mut hasher = Blake2b::new(out_len);
+    hasher.update(data);
+    hasher.finalize()
+}
@@ -0,0 +1,519 @@
+//! BLAKE3, following the reference implementation: a binary tree of 1 KiB
+//! chunks, each compressed 64 bytes at a time. Its keyed mode is the same
+//! tree, starting from the key instead of the IV.
+//!
+//! [`Parallel`] splits the input into segments that are subtrees of their
+//! own, hashes several at once, and merges them as it would single chunks.
+
+use crate::positioned::PositionedReader;
+use std::io;
+
+/// Size of the tree's leaves
+const CHUNK_LEN: usize = 1024;
+const BLOCK_LEN: usize = 64;
+
+const CHUNK_START: u32 = 1 << 0;
+const CHUNK_END: u32 = 1 << 1;
+const PARENT: u32 = 1 << 2;
+const ROOT: u32 = 1 << 3;
+const KEYED_HASH: u32 = 1 << 4;
+
+/// How many bytes [`Blake3::serialize`] takes before the stack: the chunk's
+/// chaining value, counter and block, and how far into it and the chunk
+const SERIALIZED_LEN: usize = 32 + 8 + BLOCK_LEN + 2;
+
+/// How long keys are in keyed mode
+pub const KEY_LEN: usize = 32;
+
+const IV: [u32; 8] = [
+    0x6A09E667, 0xBB67AE85, 0x3C6EF372, 0xA54FF53A, 0x510E527F, 0x9B05688C, 0x1F83D9AB, 0x5BE0CD19,
+];
+
+const MSG_PERMUTATION: [usize; 16] = [2, 6, 3, 10, 7, 0, 4, 13, 1, 11, 12, 5, 9, 14, 15, 8];
+
+fn g(state: &mut [u32; 16], a: usize, b: usize, c: usize, d: usize, mx: u32, my: u32) {
+    state[a] = state[a].wrapping_add(state[b]).wrapping_add(mx);
+    state[d] = (state[d] ^ state[a]).rotate_right(16);
+    state[c] = state[c].wrapping_add(state[d]);
+    state[b] = (state[b] ^ state[c]).rotate_right(12);
+    state[a] = state[a].wrapping_add(state[b]).wrapping_add(my);
+    state[d] = (state[d] ^ state[a]).rotate_right(8);
+    state[c] = state[c].wrapping_add(state[d]);
+    state[b] = (state[b] ^ state[c]).rotate_right(7);
+}
+
+fn round(state: &mut [u32; 16], m: &[u32; 16]) {
+    // columns
+    g(state, 0, 4, 8, 12, m[0], m[1]);
+    g(state, 1, 5, 9, 13, m[2], m[3]);
+    g(state, 2, 6, 10, 14, m[4], m[5]);
+    g(state, 3, 7, 11, 15, m[6], m[7]);
+    // diagonals
+    g(state, 0, 5, 10, 15, m[8], m[9]);
+    g(state, 1, 6, 11, 12, m[10], m[11]);
+    g(state, 2, 7, 8, 13, m[12], m[13]);
+    g(state, 3, 4, 9, 14, m[14], m[15]);
+}
+
+fn compress(
+    chaining_value: &[u32; 8],
+    block: &[u32; 16],
+    counter: u64,
+    block_len: u32,
+    flags: u32,
+) -> [u32; 16] {
+    let mut state = [
+        chaining_value[0],
+        chaining_value[1],
+        chaining_value[2],
+        chaining_value[3],
+        chaining_value[4],
+        chaining_value[5],
+        chaining_value[6],
+        chaining_value[7],
+        IV[0],
+        IV[1],
+        IV[2],
+        IV[3],
+        counter as u32,
+        (counter >> 32) as u32,
+        block_len,
+        flags,
+    ];
+    let mut block = *block;
+    for i in 0..7 {
+        round(&mut state, &block);
+        if i < 6 {
+            let mut permuted = [0; 16];
+            for (to, &from) in permuted.iter_mut().zip(MSG_PERMUTATION.iter()) {
+                *to = block[from];
+            }
+            block = permuted;
+        }
+    }
+    for i in 0..8 {
+        state[i] ^= state[i + 8];
+        state[i + 8] ^= chaining_value[i];
+    }
+    state
+}
+
+fn first_8(words: [u32; 16]) -> [u32; 8] {
+    let mut out = [0; 8];
+    out.copy_from_slice(&words[..8]);
+    out
+}
+
+fn words(bytes: &[u8; BLOCK_LEN]) -> [u32; 16] {
+    let mut words = [0; 16];
+    for (word, bytes) in words.iter_mut().zip(bytes.chunks_exact(4)) {
+        *word = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+    }
+    words
+}
+
+/// The last compression of a node, which is done differently for the root
+struct Output {
+    input: [u32; 8],
+    block: [u32; 16],
+    counter: u64,
+    block_len: u32,
+    flags: u32,
+}
+
+impl Output {
+    fn chaining_value(&self) -> [u32; 8] {
+        first_8(compress(
+            &self.input,
+            &self.block,
+            self.counter,
+            self.block_len,
+            self.flags,
+        ))
+    }
+
+    fn root(&self) -> Vec<u8> {
+        let words = compress(
+            &self.input,
+            &self.block,
+            0,
+            self.block_len,
+            self.flags | ROOT,
+        );
+        words[..8].iter().flat_map(|w| w.to_le_bytes()).collect()
+    }
+}
+
+fn parent(left: [u32; 8], right: [u32; 8], key: [u32; 8], flags: u32) -> Output {
+    let mut block = [0; 16];
+    block[..8].copy_from_slice(&left);
+    block[8..].copy_from_slice(&right);
+    Output {
+        input: key,
+        block,
+        counter: 0,
+        block_len: BLOCK_LEN as u32,
+        flags: PARENT | flags,
+    }
+}
+
+#[derive(Clone)]
+struct Chunk {
+    chaining_value: [u32; 8],
+    counter: u64,
+    block: [u8; BLOCK_LEN],
+    block_len: usize,
+    blocks_compressed: usize,
+    /// The mode's flags, set on every compression
+    flags: u32,
+}
+
+impl Chunk {
+    fn new(key: [u32; 8], counter: u64, flags: u32) -> Self {
+        Self {
+            chaining_value: key,
+            counter,
+            block: [0; BLOCK_LEN],
+            block_len: 0,
+            blocks_compressed: 0,
+            flags,
+        }
+    }
+
+    fn len(&self) -> usize {
+        BLOCK_LEN * self.blocks_compressed + self.block_len
+    }
+
+    fn start_flag(&self) -> u32 {
+        let start = if self.blocks_compressed == 0 {
+            CHUNK_START
+        } else {
+            0
+        };
+        start | self.flags
+    }
+
+    fn update(&mut self, mut data: &[u8]) {
+        while !data.is_empty() {
+            // the last block is kept back, it's compressed with CHUNK_END
+            if self.block_len == BLOCK_LEN {
+                self.chaining_value = first_8(compress(
+                    &self.chaining_value,
+                    &words(&self.block),
+                    self.counter,
+                    BLOCK_LEN as u32,
+                    self.start_flag(),
+                ));
+                self.blocks_compressed += 1;
+                self.block = [0; BLOCK_LEN];
+                self.block_len = 0;
+            }
+            let take = (BLOCK_LEN - self.block_len).min(data.len());
+            self.block[self.block_len..self.block_len + take].copy_from_slice(&data[..take]);
+            self.block_len += take;
+            data = &data[take..];
+        }
+    }
+
+    fn output(&self) -> Output {
+        Output {
+            input: self.chaining_value,
+            block: words(&self.block),
+            counter: self.counter,
+            block_len: self.block_len as u32,
+            flags: self.start_flag() | CHUNK_END,
+        }
+    }
+}
+
+/// Incremental BLAKE3 hasher, with 32-byte output
+#[derive(Clone)]
+pub struct Blake3 {
+    chunk: Chunk,
+    /// Chaining values of the complete subtrees so far, largest first
+    stack: Vec<[u32; 8]>,
+    /// The IV, or the key in keyed mode
+    key: [u32; 8],
+    flags: u32,
+}
+
+impl Default for Blake3 {
+    fn default() -> Self {
+        Self::with_key(IV, 0)
+    }
+}
+
+impl Blake3 {
+    /// A hasher in keyed mode, a MAC in its own right
+    pub fn keyed(key: &[u8; KEY_LEN]) -> Self {
+        let mut words = [0; 8];
+        for (word, bytes) in words.iter_mut().zip(key.chunks_exact(4)) {
+            *word = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        }
+        Self::with_key(words, KEYED_HASH)
+    }
+
+    fn with_key(key: [u32; 8], flags: u32) -> Self {
+        Self {
+            chunk: Chunk::new(key, 0, flags),
+            stack: Vec::new(),
+            key,
+            flags,
+        }
+    }
+
+    pub fn update(&mut self, mut data: &[u8]) {
+        while !data.is_empty() {
+            // a full chunk is only finished once more input shows it isn't
+            // the root
+            if self.chunk.len() == CHUNK_LEN {
+                let mut cv = self.chunk.output().chaining_value();
+                let mut total = self.chunk.counter + 1;
+                // merge every subtree this chunk completes
+                while total & 1 == 0 {
+                    cv = parent(self.stack.pop().unwrap(), cv, self.key, self.flags)
+                        .chaining_value();
+                    total >>= 1;
+                }
+                self.stack.push(cv);
+                self.chunk = Chunk::new(self.key, self.chunk.counter + 1, self.flags);
+            }
+            let take = (CHUNK_LEN - self.chunk.len()).min(data.len());
+            self.chunk.update(&data[..take]);
+            data = &data[take..];
+        }
+    }
+
+    pub fn finalize(self) -> Vec<u8> {
+        self.output().root()
+    }
+
+    /// The state so far, to pick up from with [`Blake3::deserialize`]. Not
+    /// in keyed mode, where it would give the key away.
+    pub fn serialize(&self) -> Option<Vec<u8>> {
+        if self.flags & KEYED_HASH != 0 {
+            return None;
+        }
+        let chunk = &self.chunk;
+        let mut bytes = Vec::with_capacity(SERIALIZED_LEN + self.stack.len() * 32);
+        let push = |bytes: &mut Vec<u8>, cv: &[u32; 8]| {
+            cv.iter()
+                .for_each(|w| bytes.extend_from_slice(&w.to_le_bytes()));
+        };
+        push(&mut bytes, &chunk.chaining_value);
+        bytes.extend_from_slice(&chunk.counter.to_le_bytes());
+        bytes.extend_from_slice(&chunk.block);
+        bytes.push(chunk.block_len as u8);
+        bytes.push(chunk.blocks_compressed as u8);
+        for cv in &self.stack {
+            push(&mut bytes, cv);
+        }
+        Some(bytes)
+    }
+
+    /// A hasher back where [`Blake3::serialize`] left it, unless `bytes`
+    /// can't be a state it saved
+    pub fn deserialize(bytes: &[u8]) -> Option<Self> {
+        let cv = |bytes: &[u8]| {
+            let mut cv = [0; 8];
+            for (word, bytes) in cv.iter_mut().zip(bytes.chunks_exact(4)) {
+                *word = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+            }
+            cv
+        };
+        if bytes.len() < SERIALIZED_LEN || !(bytes.len() - SERIALIZED_LEN).is_multiple_of(32) {
+            return None;
+        }
+        let (head, stack) = bytes.split_at(SERIALIZED_LEN);
+        let mut counter = [0; 8];
+        counter.copy_from_slice(&head[32..40]);
+        let mut chunk = Chunk::new(IV, u64::from_le_bytes(counter), 0);
+        chunk.chaining_value = cv(&head[..32]);
+        chunk.block.copy_from_slice(&head[40..40 + BLOCK_LEN]);
+        chunk.block_len = head[40 + BLOCK_LEN] as usize;
+        chunk.blocks_compressed = head[41 + BLOCK_LEN] as usize;
+        let stack: Vec<_> = stack.chunks_exact(32).map(cv).collect();
+        // a block is only compressed once the next one starts, and there's
+        // a subtree on the stack for each bit set in the chunk count
+        let valid = chunk.block_len <= BLOCK_LEN
+            && chunk.blocks_compressed < CHUNK_LEN / BLOCK_LEN
+            && stack.len() == chunk.counter.count_ones() as usize;
+        valid.then_some(Self {
+            chunk,
+            stack,
+            key: IV,
+            flags: 0,
+        })
+    }
+
+    /// The last compression of whatever was fed, as the root or not
+    fn output(&self) -> Output {
+        let mut output = self.chunk.output();
+        for &left in self.stack.iter().rev() {
+            output = parent(left, output.chaining_value(), self.key, self.flags);
+        }
+        output
+    }
+
+    /// The chaining value of the subtree over `data`, a whole segment that
+    /// starts at chunk `counter` and isn't the end of the input
+    fn subtree(&self, counter: u64, data: &[u8]) -> [u32; 8] {
+        debug_assert_eq!(data.len(), SEGMENT_LEN);
+        let mut hasher = Self {
+            chunk: Chunk::new(self.key, counter, self.flags),
+            stack: Vec::new(),
+            key: self.key,
+            flags: self.flags,
+        };
+        hasher.update(data);
+        hasher.output().chaining_value()
+    }
+
+    /// Takes a segment [`Blake3::subtree`] hashed, merging it as `update`
+    /// would have merged its last chunk
+    fn push_subtree(&mut self, mut cv: [u32; 8]) {
+        debug_assert_eq!(self.chunk.len(), 0);
+        let chunks = (SEGMENT_LEN / CHUNK_LEN) as u64;
+        let counter = self.chunk.counter + chunks;
+        // the merges within the segment were done by the subtree itself
+        let mut total = counter / chunks;
+        while total & 1 == 0 {
+            cv = parent(self.stack.pop().unwrap(), cv, self.key, self.flags).chaining_value();
+            total >>= 1;
+        }
+        self.stack.push(cv);
+        self.chunk = Chunk::new(self.key, counter, self.flags);
+    }
+}
+
+/// How much of the input [`Parallel`] hashes as one subtree, a power of two
+/// times [`CHUNK_LEN`]
+pub const SEGMENT_LEN: usize = 4 << 20;
+
+/// Hashes up to `threads` segments at once, keeping as many buffered. The
+/// digest is the same as [`Blake3`]'s.
+pub struct Parallel {
+    hasher: Blake3,
+    threads: usize,
+    pending: Vec<u8>,
+}
+
+impl Parallel {
+    pub fn new(hasher: Blake3, threads: usize) -> Self {
+        Self {
+            hasher,
+            threads,
+            pending: Vec::new(),
+        }
+    }
+
+    pub fn update(&mut self, mut data: &[u8]) {
+        let batch_len = self.threads * SEGMENT_LEN;
+        while !data.is_empty() {
+            // a full batch is only hashed once more input shows it doesn't
+            // hold the root
+            if self.pending.len() == batch_len {
+                self.hash_segments(self.threads);
+                self.pending.clear();
+            }
+            let take = (batch_len - self.pending.len()).min(data.len());
+            self.pending.extend_from_slice(&data[..take]);
+            data = &data[take..];
+        }
+    }
+
+    pub fn finalize(mut self) -> Vec<u8> {
+        // the last segment goes through the hasher, whole or not, since the
+        // root is in it
+        let segments = self.pending.len().saturating_sub(1) / SEGMENT_LEN;
+        self.hash_segments(segments);
+        self.hasher.update(&self.pending[segments * SEGMENT_LEN..]);
+        self.hasher.finalize()
+    }
+
+    /// Reads the rest of `reader` and hashes it, instead of being fed, each
+    /// thread reading its own segment with positioned reads straight into
+    /// the buffer it hashes. Fails if the file ends early.
+    pub fn read_from(mut self, reader: &PositionedReader) -> io::Result<Vec<u8>> {
+        debug_assert!(self.pending.is_empty());
+        let batch_len = self.threads * SEGMENT_LEN;
+        let (mut offset, end) = (reader.offset(), reader.offset() + reader.remaining());
+        self.pending
+            .resize(batch_len.min(reader.remaining() as usize), 0);
+        // as with `update`, a full batch only goes whole if more follows it
+        while end - offset > batch_len as u64 {
+            self.read_segments(reader, offset, batch_len, self.threads)?;
+            offset += batch_len as u64;
+        }
+        let tail = (end - offset) as usize;
+        let segments = tail.saturating_sub(1) / SEGMENT_LEN;
+        self.read_segments(reader, offset, tail, segments)?;
+        self.hasher
+            .update(&self.pending[segments * SEGMENT_LEN..tail]);
+        Ok(self.hasher.finalize())
+    }
+
+    /// Reads `len` bytes from `offset` into the buffer, a segment per
+    /// thread, with the first `hashed` segments hashed by the thread that
+    /// read each, and merges those in order
+    fn read_segments(
+        &mut self,
+        reader: &PositionedReader,
+        offset: u64,
+        len: usize,
+        hashed: usize,
+    ) -> io::Result<()> {
+        let (hasher, pending) = (&self.hasher, &mut self.pending);
+        let counter = hasher.chunk.counter;
+        let chunks = (SEGMENT_LEN / CHUNK_LEN) as u64;
+        let cvs = std::thread::scope(|scope| {
+            let handles: Vec<_> = pending[..len]
+                .chunks_mut(SEGMENT_LEN)
+                .enumerate()
+                .map(|(i, segment)| {
+                    let start = offset + (i * SEGMENT_LEN) as u64;
+                    let mut reader = reader.range(start..start + segment.len() as u64);
+                    scope.spawn(move || {
+                        if reader.fill(segment)? < segment.len() {
+                            return Err(io::Error::new(
+                                io::ErrorKind::UnexpectedEof,
+                                "the file got shorter while it was read",
+                            ));
+                        }
+                        Ok((i < hashed)
+                            .then(|| hasher.subtree(counter + i as u64 * chunks, segment)))
+                    })
+                })
+                .collect();
+            handles
+                .into_iter()
+                .map(|h| h.join().unwrap())
+                .collect::<io::Result<Vec<_>>>()
+        })?;
+        for cv in cvs.into_iter().flatten() {
+            self.hasher.push_subtree(cv);
+        }
+        Ok(())
+    }
+
+    /// Hashes the first `segments` segments buffered, each on its own
+    /// thread, and merges them in order
+    fn hash_segments(&mut self, segments: usize) {
+        let hasher = &self.hasher;
+        let counter = hasher.chunk.counter;
+        let chunks = (SEGMENT_LEN / CHUNK_LEN) as u64;
+        let cvs: Vec<_> = std::thread::scope(|scope| {
+            let handles: Vec<_> = self.pending[..segments * SEGMENT_LEN]
+                .chunks(SEGMENT_LEN)
+                .enumerate()
+                .map(|(i, segment)| {
+                    scope.spawn(move || hasher.subtree(counter + i as u64 * chunks, segment))
+                })
+                .collect();
+            handles.into_iter().map(|h| h.join().unwrap()).collect()
+        });
+        for cv in cvs {
+            self.hasher.push_subtree(cv);
+        }
+    }
+}
@@ -0,0 +1,142 @@
+//! A compact, serializable Bloom filter of digests.
+
+use color_eyre::eyre::{self, eyre};
+use sha3::Digest;
+use std::{
+    convert::TryInto,
+    io::{Read, Write},
+};
+
+const MAGIC: &[u8; 8] = b"SVBLOOM1";
+
+/// A set of digests with no false negatives and a tunable false positive rate
+pub struct Bloom {
+    words: Vec<u64>,
+    num_bits: u64,
+    num_hashes: u32,
+}
+
+impl Bloom {
+    /// Sizes a filter for `n` digests at a false positive rate of `fpr`
+    pub fn with_capacity(n: usize, fpr: f64) -> Self {
+        let n = n.max(1) as f64;
+        let ln2 = std::f64::consts::LN_2;
+        let num_bits = (-(n * fpr.ln()) / (ln2 * ln2)).ceil().max(64.0) as u64;
+        let num_hashes = ((num_bits as f64 / n) * ln2).round().max(1.0) as u32;
+        Self {
+            words: vec![0; num_bits.div_ceil(64) as usize],
+            num_bits,
+            num_hashes,
+        }
+    }
+
+    pub fn insert(&mut self, digest: &[u8]) {
+        for bit in self.bits(digest).collect::<Vec<_>>() {
+            self.words[(bit / 64) as usize] |= 1 << (bit % 64);
+        }
+    }
+
+    /// Returns false if `digest` was definitely never inserted
+    pub fn contains(&self, digest: &[u8]) -> bool {
+        self.bits(digest)
+            .all(|bit| self.words[(bit / 64) as usize] & (1 << (bit % 64)) != 0)
+    }
+
+    /// Derives the bit indices for a digest by double hashing. Digests are
+    /// already uniformly distributed, so their leading bytes serve as the two
+    /// base hashes; anything shorter than 16 bytes is stretched first.
+    fn bits(&self, digest: &[u8]) -> impl Iterator<Item = u64> + '_ {
+        let mut key = [0u8; 16];
+        if digest.len() >= key.len() {
+            key.copy_from_slice(&digest[..16]);
+        } else {
+            key.copy_from_slice(&sha3::Sha3_256::digest(digest)[..16]);
+        }
+        let h1 = u64::from_le_bytes(key[..8].try_into().unwrap());
+        let h2 = u64::from_le_bytes(key[8..].try_into().unwrap()) | 1;
+        (0..self.num_hashes as u64)
+            .map(move |i| h1.wrapping_add(i.wrapping_mul(h2)) % self.num_bits)
+    }
+
+    pub fn write_to(&self, w: &mut impl Write) -> Result<(), eyre::Error> {
+        w.write_all(MAGIC)?;
+        w.write_all(&self.num_hashes.to_le_bytes())?;
+        w.write_all(&self.num_bits.to_le_bytes())?;
+        for word in &self.words {
+            w.write_all(&word.to_le_bytes())?;
+        }
+        Ok(())
+    }
+
+    pub fn read_from(r: &mut impl Read) -> Result<Self, eyre::Error> {
+        let mut magic = [0u8; 8];
+        r.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(eyre!("not a surviving bloom filter"));
+        }
+
+        let mut num_hashes = [0u8; 4];
+        r.read_exact(&mut num_hashes)?;
+        let num_hashes = u32::from_le_bytes(num_hashes);
+        let mut num_bits = [0u8; 8];
+        r.read_exact(&mut num_bits)?;
+        let num_bits = u64::from_le_bytes(num_bits);
+        let corrupt = || eyre!("bloom filter header is corrupt");
+        if num_hashes == 0 || num_bits == 0 {
+            return Err(corrupt());
+        }
+
+        // read as it comes rather than sized from the header, which could
+        // ask for any amount, and checked against it after
+        let len = num_bits.div_ceil(64).checked_mul(8).ok_or_else(corrupt)?;
+        let mut bytes = Vec::new();
+        r.take(len.saturating_add(1)).read_to_end(&mut bytes)?;
+        if bytes.len() as u64 != len {
+            return Err(corrupt());
+        }
+        let words = bytes
+            .chunks_exact(8)
+            .map(|word| u64::from_le_bytes(word.try_into().unwrap()))
+            .collect();
+        Ok(Self {
+            words,
+            num_bits,
+            num_hashes,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn written(bloom: &Bloom) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        bloom.write_to(&mut bytes).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn round_trips() {
+        let mut bloom = Bloom::with_capacity(100, 0.01);
+        bloom.insert(&[1; 32]);
+        let read = Bloom::read_from(&mut &written(&bloom)[..]).unwrap();
+        assert!(read.contains(&[1; 32]));
+        assert!(!read.contains(&[2; 32]));
+    }
+
+    #[test]
+    fn truncated_is_corrupt() {
+        let bytes = written(&Bloom::with_capacity(100, 0.01));
+        let e = Bloom::read_from(&mut &bytes[..bytes.len() - 8])
+            .err()
+            .unwrap();
+        assert_eq!(e.to_string(), "bloom filter header is corrupt");
+
+        // a header asking for far more than there is
+        let mut bytes = bytes[..20].to_vec();
+        bytes[12..20].copy_from_slice(&u64::MAX.to_le_bytes());
+        let e = Bloom::read_from(&mut &bytes[..]).err().unwrap();
+        assert_eq!(e.to_string(), "bloom filter header is corrupt");
+    }
+}
@@ -0,0 +1,224 @@
+//! Limits on how much a single run may do, so a scheduled scan stops within
+//! its maintenance window rather than running into the next day.
+//!
+//! Limits are checked before each file starts: once one is reached, files
+//! in flight are finished and no new ones are started. What wasn't hashed
+//! can be saved as leftovers, which the next run picks up instead of its
+//! inputs. Files failing past `--max-errors` stop a run the same way, as
+//! that many failures usually mean it can't get anywhere as it is, and so
+//! do an interrupt and, with `--fail-fast`, the first failure.
+
+use color_eyre::eyre::{self, eyre};
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// How much a run may do, from `--max-runtime`, `--max-bytes`,
+/// `--max-files` and `--max-errors`
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Limits {
+    pub runtime: Option<Duration>,
+    /// Bytes, counted by the size of each file started
+    pub bytes: Option<u64>,
+    pub files: Option<usize>,
+    /// Files that may fail, any more stopping the run
+    pub errors: Option<u64>,
+    /// Whether the first failure stops the run, abandoning files in
+    /// flight too
+    pub fail_fast: bool,
+}
+
+/// How many files may fail, as a count or a percentage of the inputs
+#[derive(Debug, Clone, Copy)]
+pub enum MaxErrors {
+    Count(u64),
+    Percent(f64),
+}
+
+impl FromStr for MaxErrors {
+    type Err = eyre::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let bad = || {
+            eyre!(
+                "expected a count like `100` or a percentage like `5%`, got {:?}",
+                s
+            )
+        };
+        match s.strip_suffix('%') {
+            Some(percent) => match percent.trim().parse::<f64>() {
+                Ok(p) if (0.0..=100.0).contains(&p) => Ok(Self::Percent(p)),
+                _ => Err(bad()),
+            },
+            None => s.parse().map(Self::Count).map_err(|_| bad()),
+        }
+    }
+}
+
+impl MaxErrors {
+    /// As a count, for a run of `files` inputs
+    pub fn of(self, files: usize) -> u64 {
+        match self {
+            Self::Count(n) => n,
+            Self::Percent(p) => (files as f64 * p / 100.0) as u64,
+        }
+    }
+}
+
+/// The limit a run stopped at
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Exceeded {
+    MaxRuntime,
+    MaxBytes,
+    MaxFiles,
+    MaxErrors,
+    FailFast,
+    Interrupted,
+}
+
+impl fmt::Display for Exceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::MaxRuntime => "--max-runtime",
+            Self::MaxBytes => "--max-bytes",
+            Self::MaxFiles => "--max-files",
+            Self::MaxErrors => "--max-errors",
+            Self::FailFast => "--fail-fast",
+            Self::Interrupted => "an interrupt",
+        })
+    }
+}
+
+struct Spent {
+    files: usize,
+    bytes: u64,
+    errors: u64,
+    exceeded: Option<Exceeded>,
+}
+
+/// What a run has spent of its limits, shared by every worker
+pub struct Budget {
+    limits: Limits,
+    started: Instant,
+    spent: Mutex<Spent>,
+}
+
+impl Budget {
+    pub fn new(limits: Limits) -> Self {
+        Self {
+            limits,
+            started: Instant::now(),
+            spent: Mutex::new(Spent {
+                files: 0,
+                bytes: 0,
+                errors: 0,
+                exceeded: None,
+            }),
+        }
+    }
+
+    /// Whether a file of `size` bytes may start, counting it if so. Once
+    /// this says no, it says no to every file after it, so the run stops
+    /// at one point rather than skipping around it.
+    pub fn admit(&self, size: u64) -> bool {
+        let mut spent = self.spent.lock().unwrap();
+        if spent.exceeded.is_some() {
+            return false;
+        }
+        let exceeded = if crate::cancel::interrupted() {
+            Some(Exceeded::Interrupted)
+        } else if self
+            .limits
+            .runtime
+            .is_some_and(|max| self.started.elapsed() >= max)
+        {
+            Some(Exceeded::MaxRuntime)
+        } else if self.limits.files.is_some_and(|max| spent.files >= max) {
+            Some(Exceeded::MaxFiles)
+        } else if self.limits.bytes.is_some_and(|max| spent.bytes >= max) {
+            // the last file in may take it past, but a file larger than the
+            // limit still gets hashed, so every run gets somewhere
+            Some(Exceeded::MaxBytes)
+        } else {
+            None
+        };
+        if let Some(exceeded) = exceeded {
+            tracing::warn!(limit = %exceeded, files = spent.files, bytes = spent.bytes, "limit reached, not starting more files");
+            spent.exceeded = Some(exceeded);
+            return false;
+        }
+        spent.files += 1;
+        spent.bytes += size;
+        true
+    }
+
+    /// Counts a file that failed, stopping the run once more have than
+    /// `--max-errors` allows, or right away with `--fail-fast`
+    pub fn failed(&self) {
+        let mut spent = self.spent.lock().unwrap();
+        spent.errors += 1;
+        if spent.exceeded.is_some() {
+            return;
+        }
+        if self.limits.fail_fast {
+            tracing::warn!(limit = %Exceeded::FailFast, "a file failed, abandoning the rest");
+            spent.exceeded = Some(Exceeded::FailFast);
+            crate::cancel::fail();
+        } else if self.limits.errors.is_some_and(|max| spent.errors > max) {
+            tracing::warn!(limit = %Exceeded::MaxErrors, errors = spent.errors, "too many files failed, not starting more");
+            spent.exceeded = Some(Exceeded::MaxErrors);
+        }
+    }
+
+    /// Notes a file abandoned in flight, so an interrupt that came after
+    /// every file had started still stops the run
+    pub fn abandoned(&self) {
+        let mut spent = self.spent.lock().unwrap();
+        if spent.exceeded.is_none() && crate::cancel::interrupted() {
+            spent.exceeded = Some(Exceeded::Interrupted);
+        }
+    }
+
+    /// How many files failed so far
+    pub fn errors(&self) -> u64 {
+        self.spent.lock().unwrap().errors
+    }
+
+    /// The limit the run stopped at, if it did
+    pub fn exceeded(&self) -> Option<Exceeded> {
+        self.spent.lock().unwrap().exceeded
+    }
+}
+
+/// The files a stopped run didn't get to, for the next one
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Leftovers {
+    pub stopped_at: Exceeded,
+    pub paths: Vec<PathBuf>,
+}
+
+/// The leftovers at `path`, if an earlier run left any
+pub async fn load(path: &Path) -> Result<Option<Leftovers>, eyre::Error> {
+    match async_std::fs::read(path).await {
+        Ok(text) => serde_json::from_slice(&text)
+            .map(Some)
+            .map_err(|e| eyre!("in {}: {}", path.display(), e)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(eyre!("can't read {}: {}", path.display(), e)),
+    }
+}
+
+/// Writes `leftovers` to `path`, replacing any from an earlier run
+pub async fn save(path: &Path, leftovers: &Leftovers) -> Result<(), eyre::Error> {
+    let mut partial = path.as_os_str().to_owned();
+    partial.push(".partial");
+    async_std::fs::write(&partial, serde_json::to_vec_pretty(leftovers)?).await?;
+    async_std::fs::rename(&partial, path).await?;
+    Ok(())
+}
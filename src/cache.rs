@@ -0,0 +1,519 @@
+//! Digests kept between runs, for `--cache`, so files that haven't changed
+//! since they were last hashed aren't read again.
+//!
+//! A file counts as unchanged while its device, inode, size, ctime and,
+//! where the filesystem has one, inode generation all stay the same.
+//! Unlike mtime, ctime can't be set from userspace, so a write that puts
+//! the old mtime back still shows. The generation (btrfs, XFS, ext4) tells
+//! a new file apart from an old one whose inode number it reused. Linux
+//! keeps its change counters to itself, and they move on a touch as well,
+//! so a touched file is read again, and its entry simply refreshed.
+//!
+//! The digests live in a sidecar file, or with `--cache xattr` in an
+//! extended attribute of each file, which travels with it. Setting one
+//! moves the file's ctime, so those are only checked against its size and
+//! mtime, which whoever can write the file can put back.
+
+use color_eyre::eyre::{self, eyre};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::Mutex,
+};
+
+/// Where `--cache` keeps digests
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Store {
+    /// In an extended attribute of each file
+    Xattr,
+    /// All in one file
+    Sidecar(PathBuf),
+}
+
+/// `xattr`, or `sidecar:PATH`, or a path on its own for a sidecar
+impl FromStr for Store {
+    type Err = eyre::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.strip_prefix("sidecar:") {
+            _ if s == "xattr" => Ok(Self::Xattr),
+            Some("") => Err(eyre!("`sidecar:` needs a path")),
+            Some(path) => Ok(Self::Sidecar(path.into())),
+            None => Ok(Self::Sidecar(s.into())),
+        }
+    }
+}
+
+/// What has to stay the same for a cached digest to still hold
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Stamp {
+    pub dev: u64,
+    pub ino: u64,
+    pub size: u64,
+    /// In nanoseconds since the Unix epoch
+    pub ctime: i128,
+    /// Likewise, for the extended attribute, which can't go by the ctime.
+    /// Missing from caches written before it was kept.
+    #[serde(default)]
+    pub mtime: i128,
+    pub generation: Option<u64>,
+}
+
+impl Stamp {
+    /// The stamp of the file at `path` as it is now, following symlinks
+    #[cfg(unix)]
+    pub async fn of(path: &Path) -> std::io::Result<Self> {
+        use std::os::unix::fs::MetadataExt;
+
+        let path = path.to_owned();
+        crate::rt::spawn_blocking(move || {
+            let metadata = std::fs::metadata(&path)?;
+            Ok(Self {
+                dev: metadata.dev(),
+                ino: metadata.ino(),
+                size: metadata.len(),
+                ctime: i128::from(metadata.ctime()) * 1_000_000_000
+                    + i128::from(metadata.ctime_nsec()),
+                mtime: i128::from(metadata.mtime()) * 1_000_000_000
+                    + i128::from(metadata.mtime_nsec()),
+                generation: generation(&path),
+            })
+        })
+        .await
+    }
+
+    #[cfg(not(unix))]
+    pub async fn of(_path: &Path) -> std::io::Result<Self> {
+        Err(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            "--cache needs inode numbers and ctimes",
+        ))
+    }
+}
+
+/// The inode generation, where the filesystem keeps one
+#[cfg(target_os = "linux")]
+fn generation(path: &Path) -> Option<u64> {
+    use std::os::unix::io::AsRawFd;
+
+    // _IOR('v', 1, long)
+    const FS_IOC_GETVERSION: libc::c_ulong = 0x8008_7601;
+    let file = std::fs::File::open(path).ok()?;
+    let mut generation: libc::c_long = 0;
+    // SAFETY: the descriptor is open for the duration of the call, and the
+    // ioctl writes a single long
+    let ret = unsafe { libc::ioctl(file.as_raw_fd(), FS_IOC_GETVERSION, &mut generation) };
+    (ret == 0).then_some(generation as u64)
+}
+
+#[cfg(all(unix, not(target_os = "linux")))]
+fn generation(_path: &Path) -> Option<u64> {
+    None
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Entry {
+    stamp: Stamp,
+    /// In lowercase hex
+    digest: String,
+}
+
+/// The cache file as it's written
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Saved {
+    algorithm: String,
+    /// By absolute path
+    files: HashMap<PathBuf, Entry>,
+}
+
+pub struct Cache {
+    store: Backend,
+    /// Names the extended attribute, and which sidecar entries still hold
+    algorithm: String,
+    /// Off with `--no-cache-read`, where every file is hashed and only
+    /// stored
+    read: bool,
+}
+
+enum Backend {
+    Sidecar { path: PathBuf, saved: Mutex<Saved> },
+    Xattr,
+}
+
+/// The extended attribute's value
+#[derive(Debug, Serialize, Deserialize)]
+struct Attribute {
+    size: u64,
+    /// In nanoseconds since the Unix epoch
+    mtime: i128,
+    /// In lowercase hex
+    digest: String,
+}
+
+impl Cache {
+    /// The cache in `store`, for digests made with `algorithm`. A sidecar
+    /// that's missing, or was made with another algorithm, starts empty.
+    pub async fn load(store: &Store, algorithm: &str) -> Result<Self, eyre::Error> {
+        let store = match store {
+            Store::Xattr if !xattr::SUPPORTED => {
+                return Err(eyre!("--cache xattr is only supported on Linux and macOS"))
+            }
+            Store::Xattr => Backend::Xattr,
+            Store::Sidecar(path) => Backend::Sidecar {
+                path: path.clone(),
+                saved: Mutex::new(Self::load_sidecar(path, algorithm).await?),
+            },
+        };
+        Ok(Self {
+            store,
+            algorithm: algorithm.to_owned(),
+            read: true,
+        })
+    }
+
+    async fn load_sidecar(path: &Path, algorithm: &str) -> Result<Saved, eyre::Error> {
+        let saved = match async_std::fs::read_to_string(path).await {
+            Ok(json) => serde_json::from_str::<Saved>(&json)
+                .map_err(|e| eyre!("in {}: {}", path.display(), e))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Default::default(),
+            Err(e) => return Err(eyre!("can't read {}: {}", path.display(), e)),
+        };
+        Ok(if saved.algorithm == algorithm {
+            saved
+        } else {
+            Saved {
+                algorithm: algorithm.to_owned(),
+                files: HashMap::new(),
+            }
+        })
+    }
+
+    /// Stops the cache from being looked in, for `--no-cache-read`, while
+    /// still keeping what's hashed
+    pub fn write_only(mut self) -> Self {
+        self.read = false;
+        self
+    }
+
+    /// The name of the extended attribute digests are kept in
+    fn attribute(&self) -> String {
+        format!("user.surviving.{}", self.algorithm.to_ascii_lowercase())
+    }
+
+    /// The digest cached for `path`, if it was made while it had `stamp`
+    pub async fn get(&self, path: &Path, stamp: &Stamp) -> Option<Vec<u8>> {
+        if !self.read {
+            return None;
+        }
+        let (key, saved) = match &self.store {
+            Backend::Sidecar { saved, .. } => (std::path::absolute(path).ok()?, saved),
+            Backend::Xattr => {
+                let (path, name) = (path.to_owned(), self.attribute());
+                let value = crate::rt::spawn_blocking(move || xattr::get(&path, &name))
+                    .await
+                    .ok()??;
+                let attribute: Attribute = serde_json::from_slice(&value).ok()?;
+                if (attribute.size, attribute.mtime) != (stamp.size, stamp.mtime) {
+                    return None;
+                }
+                return crate::unhex(&attribute.digest);
+            }
+        };
+        let saved = saved.lock().unwrap();
+        saved
+            .files
+            .get(&key)
+            .filter(|entry| entry.stamp == *stamp)
+            .and_then(|entry| crate::unhex(&entry.digest))
+    }
+
+    /// Keeps `hash` for `path`, as it was when it had `stamp`. A file whose
+    /// attribute can't be set, because it's read-only or its filesystem
+    /// has none, just isn't cached.
+    pub async fn insert(&self, path: &Path, stamp: Stamp, hash: &[u8]) {
+        let digest = crate::hex(hash);
+        match &self.store {
+            Backend::Sidecar { saved, .. } => {
+                if let Ok(key) = std::path::absolute(path) {
+                    saved
+                        .lock()
+                        .unwrap()
+                        .files
+                        .insert(key, Entry { stamp, digest });
+                }
+            }
+            Backend::Xattr => {
+                let attribute = Attribute {
+                    size: stamp.size,
+                    mtime: stamp.mtime,
+                    digest,
+                };
+                let value = serde_json::to_vec(&attribute).unwrap();
+                let (file, name) = (path.to_owned(), self.attribute());
+                let set = crate::rt::spawn_blocking(move || xattr::set(&file, &name, &value));
+                if let Err(e) = set.await {
+                    tracing::debug!(path = %path.display(), error = %e, "can't cache in an extended attribute");
+                }
+            }
+        }
+    }
+
+    /// Writes a sidecar back, replacing the file in one go so a run that's
+    /// interrupted leaves the old one
+    pub async fn save(&self) -> Result<(), eyre::Error> {
+        let (path, saved) = match &self.store {
+            Backend::Sidecar { path, saved } => (path, saved),
+            // set as each file was hashed
+            Backend::Xattr => return Ok(()),
+        };
+        let json = serde_json::to_string(&*saved.lock().unwrap())?;
+        let mut partial = path.clone().into_os_string();
+        partial.push(".partial");
+        async_std::fs::write(&partial, json)
+            .await
+            .map_err(|e| eyre!("can't write {}: {}", path.display(), e))?;
+        async_std::fs::rename(&partial, path).await?;
+        Ok(())
+    }
+}
+
+/// Extended attributes, which Linux and macOS have the same calls for, but
+/// for macOS's extra arguments
+mod xattr {
+    use std::{io, path::Path};
+
+    pub const SUPPORTED: bool = cfg!(any(target_os = "linux", target_os = "macos"));
+
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    fn c_string(s: impl AsRef<std::ffi::OsStr>) -> io::Result<std::ffi::CString> {
+        use std::os::unix::ffi::OsStrExt;
+
+        std::ffi::CString::new(s.as_ref().as_bytes())
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "a NUL in the path"))
+    }
+
+    /// The attribute `name` of `path`, if it has one
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    pub fn get(path: &Path, name: &str) -> io::Result<Option<Vec<u8>>> {
+        #[cfg(target_os = "linux")]
+        const MISSING: i32 = libc::ENODATA;
+        #[cfg(target_os = "macos")]
+        const MISSING: i32 = libc::ENOATTR;
+
+        let (path, name) = (c_string(path)?, c_string(name)?);
+        let mut value = vec![0u8; 256];
+        loop {
+            // SAFETY: both strings are NUL-terminated, and the call writes
+            // no more than `value.len()` bytes into it
+            let n = unsafe {
+                #[cfg(target_os = "linux")]
+                let n = libc::getxattr(
+                    path.as_ptr(),
+                    name.as_ptr(),
+                    value.as_mut_ptr().cast(),
+                    value.len(),
+                );
+                #[cfg(target_os = "macos")]
+                let n = libc::getxattr(
+                    path.as_ptr(),
+                    name.as_ptr(),
+                    value.as_mut_ptr().cast(),
+                    value.len(),
+                    0,
+                    0,
+                );
+                n
+            };
+            if n >= 0 {
+                value.truncate(n as usize);
+                return Ok(Some(value));
+            }
+            let e = io::Error::last_os_error();
+            match e.raw_os_error() {
+                Some(errno) if errno == MISSING => return Ok(None),
+                // ours are far smaller, whatever this is isn't one
+                Some(libc::ERANGE) if value.len() < 64 << 10 => value.resize(value.len() * 4, 0),
+                _ => return Err(e),
+            }
+        }
+    }
+
+    /// Sets the attribute `name` of `path` to `value`
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    pub fn set(path: &Path, name: &str, value: &[u8]) -> io::Result<()> {
+        let (path, name) = (c_string(path)?, c_string(name)?);
+        // SAFETY: both strings are NUL-terminated, and the call reads
+        // `value.len()` bytes from it
+        let ret = unsafe {
+            #[cfg(target_os = "linux")]
+            let ret = libc::setxattr(
+                path.as_ptr(),
+                name.as_ptr(),
+                value.as_ptr().cast(),
+                value.len(),
+                0,
+            );
+            #[cfg(target_os = "macos")]
+            let ret = libc::setxattr(
+                path.as_ptr(),
+                name.as_ptr(),
+                value.as_ptr().cast(),
+                value.len(),
+                0,
+                0,
+            );
+            ret
+        };
+        if ret == 0 {
+            Ok(())
+        } else {
+            Err(io::Error::last_os_error())
+        }
+    }
+
+    #[cfg(not(any(target_os = "linux", target_os = "macos")))]
+    pub fn get(_path: &Path, _name: &str) -> io::Result<Option<Vec<u8>>> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+
+    #[cfg(not(any(target_os = "linux", target_os = "macos")))]
+    pub fn set(_path: &Path, _name: &str, _value: &[u8]) -> io::Result<()> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, SystemTime};
+
+    /// An empty directory of its own for each test
+    fn scratch(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("surviving-cache-{}-{}", std::process::id(), name));
+        std::fs::remove_dir_all(&dir).ok();
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// Moves the mtime of `path` without changing what's in it
+    fn set_mtime(path: &Path, secs: u64) {
+        let file = std::fs::File::options().write(true).open(path).unwrap();
+        file.set_modified(SystemTime::UNIX_EPOCH + Duration::from_secs(secs))
+            .unwrap();
+    }
+
+    #[test]
+    fn stores_parse() {
+        assert_eq!("xattr".parse::<Store>().unwrap(), Store::Xattr);
+        assert_eq!(
+            "sidecar:/var/cache/a.json".parse::<Store>().unwrap(),
+            Store::Sidecar("/var/cache/a.json".into())
+        );
+        assert_eq!(
+            "a.json".parse::<Store>().unwrap(),
+            Store::Sidecar("a.json".into())
+        );
+        assert!("sidecar:".parse::<Store>().is_err());
+    }
+
+    // stamps need inode numbers and ctimes
+    #[cfg(unix)]
+    #[test]
+    fn sidecar_keeps_digests_until_files_change() {
+        async_std::task::block_on(async {
+            let dir = scratch("sidecar");
+            let (file, store) = (dir.join("file"), Store::Sidecar(dir.join("cache.json")));
+            std::fs::write(&file, b"hello").unwrap();
+
+            let cache = Cache::load(&store, "SHA3-256").await.unwrap();
+            let stamp = Stamp::of(&file).await.unwrap();
+            assert_eq!(cache.get(&file, &stamp).await, None);
+            cache.insert(&file, stamp, &[1, 2, 3]).await;
+            cache.save().await.unwrap();
+
+            let cache = Cache::load(&store, "SHA3-256").await.unwrap();
+            let stamp = Stamp::of(&file).await.unwrap();
+            assert_eq!(cache.get(&file, &stamp).await, Some(vec![1, 2, 3]));
+            // made with another algorithm, which doesn't count
+            let other = Cache::load(&store, "BLAKE3").await.unwrap();
+            assert_eq!(other.get(&file, &stamp).await, None);
+
+            std::fs::write(&file, b"hello, world").unwrap();
+            let changed = Stamp::of(&file).await.unwrap();
+            assert_eq!(cache.get(&file, &changed).await, None);
+            std::fs::remove_dir_all(&dir).ok();
+        })
+    }
+
+    #[test]
+    fn sidecars_from_before_mtimes_still_load() {
+        async_std::task::block_on(async {
+            let dir = scratch("old");
+            let store = Store::Sidecar(dir.join("cache.json"));
+            let json = r#"{"algorithm":"SHA3-256","files":{"/a":{"stamp":{"dev":1,"ino":2,"size":3,"ctime":4,"generation":null},"digest":"0102"}}}"#;
+            std::fs::write(dir.join("cache.json"), json).unwrap();
+            Cache::load(&store, "SHA3-256").await.unwrap();
+            std::fs::remove_dir_all(&dir).ok();
+        })
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn reads_can_be_turned_off() {
+        async_std::task::block_on(async {
+            let dir = scratch("write-only");
+            let (file, store) = (dir.join("file"), Store::Sidecar(dir.join("cache.json")));
+            std::fs::write(&file, b"hello").unwrap();
+            let stamp = Stamp::of(&file).await.unwrap();
+
+            let cache = Cache::load(&store, "SHA3-256").await.unwrap().write_only();
+            cache.insert(&file, stamp, &[1, 2, 3]).await;
+            assert_eq!(cache.get(&file, &stamp).await, None);
+            cache.save().await.unwrap();
+
+            let cache = Cache::load(&store, "SHA3-256").await.unwrap();
+            assert_eq!(cache.get(&file, &stamp).await, Some(vec![1, 2, 3]));
+            std::fs::remove_dir_all(&dir).ok();
+        })
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    #[test]
+    fn xattrs_keep_digests_until_files_change() {
+        async_std::task::block_on(async {
+            let dir = scratch("xattr");
+            let file = dir.join("file");
+            std::fs::write(&file, b"hello").unwrap();
+            if let Err(e) = xattr::set(&file, "user.surviving.probe", b"") {
+                // tmpfs only has user attributes on newer kernels
+                eprintln!("skipped, no extended attributes here: {}", e);
+                return;
+            }
+
+            let cache = Cache::load(&Store::Xattr, "SHA3-256").await.unwrap();
+            let stamp = Stamp::of(&file).await.unwrap();
+            assert_eq!(cache.get(&file, &stamp).await, None);
+            cache.insert(&file, stamp, &[1, 2, 3]).await;
+            // setting the attribute moved the ctime, which isn't checked
+            let stamp = Stamp::of(&file).await.unwrap();
+            assert_eq!(cache.get(&file, &stamp).await, Some(vec![1, 2, 3]));
+            let other = Cache::load(&Store::Xattr, "BLAKE3").await.unwrap();
+            assert_eq!(other.get(&file, &stamp).await, None);
+            let write_only = Cache::load(&Store::Xattr, "SHA3-256")
+                .await
+                .unwrap()
+                .write_only();
+            assert_eq!(write_only.get(&file, &stamp).await, None);
+
+            // the same size, written at another time
+            std::fs::write(&file, b"HELLO").unwrap();
+            set_mtime(&file, 1_000_000_000);
+            let changed = Stamp::of(&file).await.unwrap();
+            assert_eq!(cache.get(&file, &changed).await, None);
+            std::fs::remove_dir_all(&dir).ok();
+        })
+    }
+}
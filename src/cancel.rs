@@ -0,0 +1,228 @@
+//! Stopping a run cleanly when it's interrupted, and giving up on files
+//! that take too long, for `--timeout`. The first SIGINT or SIGTERM stops
+//! new files from starting and abandons those in flight, so what was
+//! hashed is still written out whole; a second one exits at once. Both
+//! print what was in flight first, so whichever read was stuck, and on
+//! which mount, shows. A third exits even if printing that is stuck too.
+//! `--fail-fast` abandons files in flight the same way.
+
+use color_eyre::eyre;
+use futures::future::Either;
+use std::{
+    fmt,
+    future::Future,
+    sync::{
+        atomic::{AtomicBool, AtomicI32, AtomicU8, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+/// How often files in flight check whether they're abandoned
+const POLL: Duration = Duration::from_millis(100);
+
+/// Why files in flight are abandoned: not at all, on an interrupt, or on
+/// the first failure with `--fail-fast`
+static REASON: AtomicU8 = AtomicU8::new(NONE);
+const NONE: u8 = 0;
+const INTERRUPT: u8 = 1;
+const FAILURE: u8 = 2;
+
+/// Whether a [`Guard`] is watching for cancellation
+static INSTALLED: AtomicBool = AtomicBool::new(false);
+
+/// How many interrupts came since the guard was installed
+static INTERRUPTS: AtomicUsize = AtomicUsize::new(0);
+
+/// Where the signal handler tells the thread that reports them, or -1
+static NOTIFY: AtomicI32 = AtomicI32::new(-1);
+
+type Status = Arc<dyn Fn() -> String + Send + Sync>;
+
+/// What's printed on an interrupt
+static STATUS: Mutex<Option<Status>> = Mutex::new(None);
+
+/// Why a file in flight was abandoned
+#[derive(Debug)]
+pub struct Cancelled;
+
+impl fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("cancelled")
+    }
+}
+
+impl std::error::Error for Cancelled {}
+
+/// Why a file was given up on with `--timeout`
+#[derive(Debug)]
+pub struct TimedOut {
+    pub after: Duration,
+}
+
+impl fmt::Display for TimedOut {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "timed out after {:.3}s", self.after.as_secs_f64())
+    }
+}
+
+impl std::error::Error for TimedOut {}
+
+/// Catches SIGINT and SIGTERM for as long as it's kept, putting back what
+/// they did before once dropped. Off Unix, signals are left alone, and
+/// interrupts stop the process as usual.
+pub struct Guard(());
+
+impl Guard {
+    pub fn install() -> Self {
+        REASON.store(NONE, Ordering::SeqCst);
+        INTERRUPTS.store(0, Ordering::SeqCst);
+        #[cfg(unix)]
+        {
+            // without the thread, a second interrupt exits without a word
+            if let Err(e) = spawn_reporter() {
+                tracing::debug!(error = %e, "can't report interrupts");
+            }
+            unsafe {
+                let handler = handle as extern "C" fn(libc::c_int) as libc::sighandler_t;
+                libc::signal(libc::SIGINT, handler);
+                libc::signal(libc::SIGTERM, handler);
+            }
+        }
+        INSTALLED.store(true, Ordering::SeqCst);
+        Self(())
+    }
+
+    /// Prints what `status` says on every interrupt, with what's in flight
+    pub fn report(&self, status: impl Fn() -> String + Send + Sync + 'static) {
+        *STATUS.lock().unwrap() = Some(Arc::new(status));
+    }
+}
+
+impl Drop for Guard {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        unsafe {
+            libc::signal(libc::SIGINT, libc::SIG_DFL);
+            libc::signal(libc::SIGTERM, libc::SIG_DFL);
+            // the reporter stops once it reads the end of the pipe
+            let fd = NOTIFY.swap(-1, Ordering::SeqCst);
+            if fd >= 0 {
+                libc::close(fd);
+            }
+        }
+        STATUS.lock().unwrap().take();
+        INSTALLED.store(false, Ordering::SeqCst);
+    }
+}
+
+#[cfg(unix)]
+extern "C" fn handle(_signal: libc::c_int) {
+    // only what's async-signal-safe: atomics, write and _exit
+    REASON.store(INTERRUPT, Ordering::SeqCst);
+    let before = INTERRUPTS.fetch_add(1, Ordering::SeqCst);
+    let fd = NOTIFY.load(Ordering::SeqCst);
+    // the reporter exits on the second, unless it's stuck
+    if before >= 2 || (before == 1 && fd < 0) {
+        unsafe { libc::_exit(130) };
+    }
+    if fd >= 0 {
+        unsafe { libc::write(fd, b"!".as_ptr() as *const libc::c_void, 1) };
+    }
+}
+
+/// Starts the thread that prints the status on each interrupt, and exits
+/// on the second, which the signal handler itself can't safely do
+#[cfg(unix)]
+fn spawn_reporter() -> std::io::Result<()> {
+    let mut fds = [0; 2];
+    if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) } < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    let [read, write] = fds;
+    let old = NOTIFY.swap(write, Ordering::SeqCst);
+    if old >= 0 {
+        unsafe { libc::close(old) };
+    }
+    std::thread::Builder::new()
+        .name("interrupts".to_string())
+        .spawn(move || {
+            let mut byte = 0u8;
+            loop {
+                let n = unsafe { libc::read(read, &mut byte as *mut u8 as *mut libc::c_void, 1) };
+                if n < 0
+                    && std::io::Error::last_os_error().kind() == std::io::ErrorKind::Interrupted
+                {
+                    continue;
+                }
+                if n <= 0 {
+                    break;
+                }
+                let aborting = INTERRUPTS.load(Ordering::SeqCst) > 1;
+                let status = STATUS.lock().unwrap().clone();
+                let status = status.map(|status| status()).unwrap_or_default();
+                if aborting {
+                    eprint!("interrupted again, exiting now\n{}", status);
+                    unsafe { libc::_exit(130) };
+                }
+                eprint!(
+                    "interrupted, stopping; interrupt again to exit now\n{}",
+                    status
+                );
+            }
+            unsafe { libc::close(read) };
+        })?;
+    Ok(())
+}
+
+/// Abandons the files in flight for `--fail-fast`, unless the run was
+/// interrupted already
+pub fn fail() {
+    REASON
+        .compare_exchange(NONE, FAILURE, Ordering::SeqCst, Ordering::SeqCst)
+        .ok();
+}
+
+/// Whether the files in flight are abandoned, for whatever reason
+pub fn requested() -> bool {
+    REASON.load(Ordering::SeqCst) != NONE
+}
+
+/// Whether the run was interrupted
+pub fn interrupted() -> bool {
+    REASON.load(Ordering::SeqCst) == INTERRUPT
+}
+
+/// Runs `future` until it's done, or until it's been running for `timeout`
+/// or files in flight are abandoned, which it fails with [`TimedOut`] or
+/// [`Cancelled`]
+pub async fn bounded<T>(
+    future: impl Future<Output = Result<T, eyre::Error>>,
+    timeout: Option<Duration>,
+) -> Result<T, eyre::Error> {
+    let watching = INSTALLED.load(Ordering::SeqCst);
+    if timeout.is_none() && !watching {
+        return future.await;
+    }
+    let started = Instant::now();
+    let stop = async {
+        loop {
+            if requested() {
+                return eyre::Error::from(Cancelled);
+            }
+            let left = match timeout {
+                Some(after) => match after.checked_sub(started.elapsed()) {
+                    Some(left) if !left.is_zero() => left,
+                    _ => return eyre::Error::from(TimedOut { after }),
+                },
+                None => POLL,
+            };
+            crate::rt::sleep(if watching { left.min(POLL) } else { left }).await;
+        }
+    };
+    futures::pin_mut!(future, stop);
+    match futures::future::select(future, stop).await {
+        Either::Left((res, _)) => res,
+        Either::Right((e, _)) => Err(e),
+    }
+}
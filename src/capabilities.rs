@@ -0,0 +1,174 @@
+//! What this build can do, for `surviving capabilities`, so wrappers can
+//! find out without parsing `--help`.
+//!
+//! Names are listed as the command line takes them. Platform-specific
+//! features are all listed, with whether this binary has them.
+
+use serde::Serialize;
+use std::fmt::Write as _;
+
+#[derive(Debug, Serialize)]
+pub struct Capabilities {
+    pub version: &'static str,
+    pub algorithms: Vec<Algorithm>,
+    /// Whether `--algo ext:COMMAND` works, which it does wherever `sh` does
+    pub external_algorithms: bool,
+    pub encodings: Vec<&'static str>,
+    /// What `--format` takes
+    pub output_formats: Vec<&'static str>,
+    /// What `--check-format` and `sums convert` take
+    pub manifest_formats: Vec<&'static str>,
+    pub io: Vec<Feature>,
+    /// Cargo features this binary was built with, or without
+    pub features: Vec<Feature>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Algorithm {
+    pub name: &'static str,
+    /// In bytes
+    pub digest_len: Option<usize>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Feature {
+    pub name: &'static str,
+    pub available: bool,
+    pub description: &'static str,
+}
+
+const fn feature(name: &'static str, available: bool, description: &'static str) -> Feature {
+    Feature {
+        name,
+        available,
+        description,
+    }
+}
+
+/// The capabilities of the running binary
+pub fn detect() -> Capabilities {
+    let linux = cfg!(target_os = "linux");
+    let unix = cfg!(unix);
+    Capabilities {
+        version: env!("CARGO_PKG_VERSION"),
+        algorithms: crate::algo::Algorithm::BUILT_IN
+            .iter()
+            .map(|a| Algorithm {
+                name: a.name(),
+                digest_len: a.digest_len(),
+            })
+            .collect(),
+        external_algorithms: unix,
+        encodings: crate::digest::Encoding::ALL
+            .iter()
+            .map(|e| e.name())
+            .collect(),
+        output_formats: crate::output::Format::ALL
+            .iter()
+            .map(|f| f.name())
+            .collect(),
+        manifest_formats: crate::sums::Format::ALL.iter().map(|f| f.name()).collect(),
+        io: vec![
+            feature("files", true, "regular files, read asynchronously"),
+            feature("stdin", true, "`-`, or no inputs at all"),
+            feature(
+                "urls",
+                true,
+                "http:// and https:// inputs, fetched as they're hashed",
+            ),
+            feature("noatime", linux, "--noatime opens with O_NOATIME"),
+            feature(
+                "lock",
+                unix,
+                "--changing-files lock holds a shared flock while reading",
+            ),
+            feature(
+                "drop-cache",
+                linux,
+                "--drop-cache evicts each file from the page cache first",
+            ),
+            feature(
+                "hash-threads",
+                true,
+                "--hash-threads hashes on threads apart from the reads",
+            ),
+            feature(
+                "pin-hash-threads",
+                linux,
+                "--pin-hash-threads keeps each hashing thread on one CPU",
+            ),
+            feature(
+                "cpu-affinity",
+                linux,
+                "--cpu-list and --numa-node pin the whole process",
+            ),
+            feature(
+                "idle-priority",
+                linux,
+                "--idle-priority runs at the lowest CPU and I/O priority",
+            ),
+            feature(
+                "evict-after-read",
+                linux,
+                "--evict-after-read leaves hashed files out of the page cache",
+            ),
+            feature(
+                "populate-cache",
+                linux,
+                "--populate-cache reads files ahead into the page cache",
+            ),
+            feature("sandbox", linux, "--chroot, --setuid and --confine-to"),
+            feature(
+                "pushgateway",
+                true,
+                "--pushgateway pushes metrics over HTTP",
+            ),
+        ],
+        features: vec![
+            feature(
+                "alloc-stats",
+                cfg!(feature = "alloc-stats"),
+                "--stats counts heap allocations",
+            ),
+            feature(
+                "snapshots",
+                cfg!(feature = "snapshots"),
+                "--snapshot hashes btrfs, ZFS and LVM snapshots",
+            ),
+        ],
+    }
+}
+
+impl Capabilities {
+    /// A section per kind of capability, for people
+    pub fn text(&self) -> String {
+        let mut out = String::new();
+        writeln!(out, "surviving {}", self.version).unwrap();
+        writeln!(out, "\nalgorithms:").unwrap();
+        for algorithm in &self.algorithms {
+            match algorithm.digest_len {
+                Some(len) => writeln!(out, "  {} ({} bytes)", algorithm.name, len),
+                None => writeln!(out, "  {}", algorithm.name),
+            }
+            .unwrap();
+        }
+        if self.external_algorithms {
+            writeln!(out, "  ext:COMMAND").unwrap();
+        }
+        for (title, names) in [
+            ("encodings", &self.encodings),
+            ("output formats", &self.output_formats),
+            ("manifest formats", &self.manifest_formats),
+        ] {
+            writeln!(out, "\n{}: {}", title, names.join(", ")).unwrap();
+        }
+        for (title, features) in [("i/o", &self.io), ("features", &self.features)] {
+            writeln!(out, "\n{}:", title).unwrap();
+            for feature in features {
+                let mark = if feature.available { '+' } else { '-' };
+                writeln!(out, "  {}{}: {}", mark, feature.name, feature.description).unwrap();
+            }
+        }
+        out
+    }
+}
@@ -0,0 +1,46 @@
+//! `.cargo-checksum.json`, the SHA-256 manifest `cargo vendor` puts in every
+//! vendored crate's directory.
+
+use color_eyre::eyre;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    path::{Component, Path},
+};
+
+pub const FILE_NAME: &str = ".cargo-checksum.json";
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Checksums {
+    /// Digests by path relative to the crate's directory, always with `/`
+    pub files: BTreeMap<String, String>,
+    /// Digest of the `.crate` file the directory was unpacked from, if any
+    pub package: Option<String>,
+}
+
+impl Checksums {
+    pub async fn load(dir: &Path) -> Result<Self, eyre::Error> {
+        let json = async_std::fs::read_to_string(dir.join(FILE_NAME)).await?;
+        Ok(serde_json::from_str(&json)?)
+    }
+
+    pub async fn save(&self, dir: &Path) -> Result<(), eyre::Error> {
+        // cargo writes it on a single line
+        let json = serde_json::to_string(self)?;
+        async_std::fs::write(dir.join(FILE_NAME), json).await?;
+        Ok(())
+    }
+}
+
+/// The manifest key for `path`, a file somewhere under `dir`
+pub fn key(dir: &Path, path: &Path) -> String {
+    let relative = path.strip_prefix(dir).unwrap_or(path);
+    relative
+        .components()
+        .filter_map(|c| match c {
+            Component::Normal(c) => Some(c.to_string_lossy()),
+            _ => None,
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
@@ -0,0 +1,155 @@
+//! Compressed manifests, for `--output` with `--compress` and for reading
+//! them back with `--check`. gzip is built in; zstd goes through the
+//! `zstd` tool, which has to be installed.
+
+use color_eyre::eyre::{self, eyre};
+use flate2::{read::MultiGzDecoder, write::GzEncoder};
+use std::{
+    fs::File,
+    io::{self, Read, Write},
+    path::Path,
+    process::{Child, ChildStdin, Command, Stdio},
+    str::FromStr,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    Zstd,
+    Gzip,
+}
+
+impl Compression {
+    /// The one a file name's extension stands for
+    pub fn from_extension(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()? {
+            "zst" => Some(Self::Zstd),
+            "gz" => Some(Self::Gzip),
+            _ => None,
+        }
+    }
+}
+
+impl FromStr for Compression {
+    type Err = eyre::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "zstd" => Ok(Self::Zstd),
+            "gzip" => Ok(Self::Gzip),
+            _ => Err(eyre!("expected `zstd` or `gzip`, got {:?}", s)),
+        }
+    }
+}
+
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+
+/// A file written as it's compressed. Only [`Output::finish`] writes the
+/// end of the stream.
+pub enum Output {
+    Plain(File),
+    Gzip(GzEncoder<File>),
+    Zstd(Child, ChildStdin),
+}
+
+impl Output {
+    pub fn create(path: &Path, compression: Option<Compression>) -> Result<Self, eyre::Error> {
+        let file =
+            File::create(path).map_err(|e| eyre!("can't create {}: {}", path.display(), e))?;
+        Ok(match compression {
+            None => Self::Plain(file),
+            Some(Compression::Gzip) => {
+                Self::Gzip(GzEncoder::new(file, flate2::Compression::default()))
+            }
+            Some(Compression::Zstd) => {
+                let mut child = Command::new("zstd")
+                    .args(["-q", "-c"])
+                    .stdin(Stdio::piped())
+                    .stdout(file)
+                    .spawn()
+                    .map_err(|e| eyre!("can't run zstd: {}", e))?;
+                let stdin = child.stdin.take().expect("stdin is piped");
+                Self::Zstd(child, stdin)
+            }
+        })
+    }
+
+    pub fn finish(self) -> Result<(), eyre::Error> {
+        match self {
+            Self::Plain(mut file) => file.flush()?,
+            Self::Gzip(encoder) => encoder.finish()?.flush()?,
+            Self::Zstd(mut child, stdin) => {
+                // zstd writes the end of the frame once its input closes
+                drop(stdin);
+                let status = child.wait()?;
+                if !status.success() {
+                    return Err(eyre!("zstd failed: {}", status));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Self::Plain(file) => file.write(buf),
+            Self::Gzip(encoder) => encoder.write(buf),
+            Self::Zstd(_, stdin) => stdin.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Self::Plain(file) => file.flush(),
+            // a gzip flush ends a deflate block, which costs compression
+            // every time results trickle in
+            Self::Gzip(_) => Ok(()),
+            Self::Zstd(_, stdin) => stdin.flush(),
+        }
+    }
+}
+
+/// The text of the file at `path`, decompressed if it starts like gzip or
+/// zstd does, whatever it's called
+pub fn read_to_string(path: &Path) -> Result<String, eyre::Error> {
+    decode(path, std::fs::read(path)?)
+}
+
+/// The text of `bytes`, read from `path`, decompressed like
+/// [`read_to_string`] does, for whoever needs what was read to be what's
+/// parsed
+pub fn decode(path: &Path, mut bytes: Vec<u8>) -> Result<String, eyre::Error> {
+    if bytes.starts_with(GZIP_MAGIC) {
+        let mut text = String::new();
+        MultiGzDecoder::new(&bytes[..]).read_to_string(&mut text)?;
+        return Ok(text);
+    }
+    if bytes.starts_with(ZSTD_MAGIC) {
+        let mut child = Command::new("zstd")
+            .args(["-q", "-d", "-c"])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::inherit())
+            .spawn()
+            .map_err(|e| {
+                eyre!(
+                    "{} is zstd-compressed, but can't run zstd: {}",
+                    path.display(),
+                    e
+                )
+            })?;
+        let mut stdin = child.stdin.take().expect("stdin is piped");
+        // fed from another thread, so zstd never waits on a full stdout
+        let feeder = std::thread::spawn(move || stdin.write_all(&bytes));
+        let output = child.wait_with_output()?;
+        // zstd stopping early shows in its status
+        feeder.join().expect("the feeder doesn't panic").ok();
+        if !output.status.success() {
+            return Err(eyre!("zstd failed: {}", output.status));
+        }
+        bytes = output.stdout;
+    }
+    String::from_utf8(bytes).map_err(|_| eyre!("{} isn't UTF-8 text", path.display()))
+}
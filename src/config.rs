@@ -0,0 +1,132 @@
+//! The config file, where named scans are defined once and run with
+//! `surviving run NAME`, instead of living in shell wrappers. It's JSON:
+//!
+//! ```json
+//! {
+//!   "profiles": {
+//!     "nightly-archive": {
+//!       "paths": ["/srv/archive"],
+//!       "exclude": ["*.tmp"],
+//!       "algo": "blake3",
+//!       "format": "json-lines",
+//!       "output": "/var/lib/surviving/archive.jsonl",
+//!       "args": ["--one-file-system", "--profile", "low-impact"]
+//!     }
+//!   }
+//! }
+//! ```
+//!
+//! A profile is turned back into a command line, so each field means what
+//! the flag of the same name does, and `args` takes any other flag.
+
+use color_eyre::eyre::{self, eyre, WrapErr};
+use serde::Deserialize;
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+/// Where the config file is looked for when `$SURVIVING_CONFIG` doesn't
+/// say: `$XDG_CONFIG_HOME/surviving/config.json`, or below `~/.config`
+pub fn default_path() -> Option<PathBuf> {
+    if let Some(path) = std::env::var_os("SURVIVING_CONFIG") {
+        return Some(path.into());
+    }
+    let dir = match std::env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(std::env::var_os("HOME")?).join(".config"),
+    };
+    Some(dir.join("surviving").join("config.json"))
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    #[serde(default)]
+    pub profiles: BTreeMap<String, Profile>,
+}
+
+/// A named scan
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Profile {
+    /// What to hash, as the inputs on the command line
+    #[serde(default)]
+    pub paths: Vec<PathBuf>,
+    #[serde(default)]
+    pub include: Vec<String>,
+    #[serde(default)]
+    pub exclude: Vec<String>,
+    #[serde(default)]
+    pub only_paths: Vec<String>,
+    pub min_size: Option<String>,
+    pub max_size: Option<String>,
+    pub algo: Option<String>,
+    pub format: Option<String>,
+    pub output: Option<PathBuf>,
+    /// Any other flags, as they'd be written on the command line
+    #[serde(default)]
+    pub args: Vec<String>,
+}
+
+impl Config {
+    pub fn load(path: &Path) -> Result<Self, eyre::Error> {
+        let text = std::fs::read_to_string(path)
+            .wrap_err_with(|| format!("While reading config file {}", path.display()))?;
+        serde_json::from_str(&text)
+            .wrap_err_with(|| format!("While parsing config file {}", path.display()))
+    }
+
+    pub fn profile(&self, name: &str) -> Result<&Profile, eyre::Error> {
+        self.profiles.get(name).ok_or_else(|| {
+            let names: Vec<_> = self.profiles.keys().map(|n| format!("`{}`", n)).collect();
+            if names.is_empty() {
+                eyre!("no profile named {:?}: the config file defines none", name)
+            } else {
+                eyre!("no profile named {:?}, expected {}", name, names.join(", "))
+            }
+        })
+    }
+}
+
+impl Profile {
+    /// The flags this profile stands for, then its paths, after a `--` so
+    /// none is taken for a flag
+    pub fn argv(&self) -> Vec<String> {
+        let mut argv = Vec::new();
+        let mut flag = |name: &str, value: String| {
+            argv.push(format!("--{}", name));
+            argv.push(value);
+        };
+        for glob in &self.include {
+            flag("include", glob.clone());
+        }
+        for glob in &self.exclude {
+            flag("exclude", glob.clone());
+        }
+        for glob in &self.only_paths {
+            flag("only-paths", glob.clone());
+        }
+        if let Some(size) = &self.min_size {
+            flag("min-size", size.clone());
+        }
+        if let Some(size) = &self.max_size {
+            flag("max-size", size.clone());
+        }
+        if let Some(algo) = &self.algo {
+            flag("algo", algo.clone());
+        }
+        if let Some(format) = &self.format {
+            flag("format", format.clone());
+        }
+        if let Some(output) = &self.output {
+            flag("output", output.display().to_string());
+        }
+        argv.extend(self.args.iter().cloned());
+        if !self.paths.is_empty() {
+            argv.push("--".to_string());
+            argv.extend(self.paths.iter().map(|p| p.display().to_string()));
+        }
+        argv
+    }
+}
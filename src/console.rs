@@ -0,0 +1,170 @@
+//! A live view into a running hash: which files are in flight, what each one
+//! is waiting on, and how deep the queues are. Anyone connecting to the
+//! console address gets a plain-text snapshot, so `nc` is enough to find out
+//! which read an NFS mount is sitting on.
+
+use async_std::{io::prelude::*, net::TcpListener, prelude::*};
+use color_eyre::eyre;
+use std::{
+    collections::BTreeMap,
+    fmt::Write as _,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::Instant,
+};
+
+/// What an in-flight file is doing
+#[derive(Debug, Clone, Copy)]
+enum Phase {
+    Opening,
+    Reading,
+    Hashing,
+}
+
+struct InFlight {
+    path: PathBuf,
+    size: Option<u64>,
+    device: Option<crate::devices::DeviceId>,
+    offset: u64,
+    phase: Phase,
+    /// When the current phase started
+    since: Instant,
+}
+
+type Depth = Box<dyn Fn() -> usize + Send + Sync>;
+
+/// Shared state for the console, updated by workers as they go
+#[derive(Default)]
+pub struct Console {
+    next: AtomicU64,
+    files: Mutex<BTreeMap<u64, InFlight>>,
+    queues: Mutex<Vec<(String, Depth)>>,
+}
+
+impl Console {
+    /// Reports the depth of a queue under `name`, for as long as the run lasts
+    pub fn queue(
+        &self,
+        name: impl Into<String>,
+        depth: impl Fn() -> usize + Send + Sync + 'static,
+    ) {
+        self.queues
+            .lock()
+            .unwrap()
+            .push((name.into(), Box::new(depth)));
+    }
+
+    /// Starts tracking `path`, until the returned handle is dropped
+    pub fn track(self: &Arc<Self>, path: &Path) -> Tracked {
+        let id = self.next.fetch_add(1, Ordering::Relaxed);
+        self.files.lock().unwrap().insert(
+            id,
+            InFlight {
+                path: path.to_owned(),
+                size: None,
+                device: None,
+                offset: 0,
+                phase: Phase::Opening,
+                since: Instant::now(),
+            },
+        );
+        Tracked {
+            console: self.clone(),
+            id,
+        }
+    }
+
+    /// The queues and the files in flight, with where each one is and, if
+    /// known, what it's on
+    pub fn snapshot(&self) -> String {
+        let mut text = String::new();
+        for (name, depth) in self.queues.lock().unwrap().iter() {
+            writeln!(text, "queue {}: {} pending", name, depth()).unwrap();
+        }
+        let files = self.files.lock().unwrap();
+        writeln!(text, "{} file(s) in flight", files.len()).unwrap();
+        for file in files.values() {
+            let size = file
+                .size
+                .map(|s| s.to_string())
+                .unwrap_or_else(|| "?".to_string());
+            write!(
+                text,
+                "{:?} for {:.3}s: {} offset={} size={}",
+                file.phase,
+                file.since.elapsed().as_secs_f64(),
+                file.path.display(),
+                file.offset,
+                size
+            )
+            .unwrap();
+            if let Some(device) = file.device {
+                write!(text, " device={}", crate::devices::describe(device)).unwrap();
+            }
+            text.push('\n');
+        }
+        text
+    }
+
+    /// Answers every connection to `addr` with a snapshot
+    pub async fn serve(self: Arc<Self>, addr: &str) -> Result<(), eyre::Error> {
+        let listener = TcpListener::bind(addr).await?;
+        tracing::info!(addr = %listener.local_addr()?, "console listening");
+        crate::rt::spawn(async move {
+            let mut incoming = listener.incoming();
+            while let Some(stream) = incoming.next().await {
+                let snapshot = self.snapshot();
+                // a client that hangs up early only misses its own snapshot
+                if let Ok(mut stream) = stream {
+                    let _ = stream.write_all(snapshot.as_bytes()).await;
+                }
+            }
+        });
+        Ok(())
+    }
+}
+
+/// An in-flight file, forgotten once dropped
+pub struct Tracked {
+    console: Arc<Console>,
+    id: u64,
+}
+
+impl Tracked {
+    fn update(&self, f: impl FnOnce(&mut InFlight)) {
+        if let Some(file) = self.console.files.lock().unwrap().get_mut(&self.id) {
+            f(file);
+        }
+    }
+
+    pub fn opened(&self, size: u64, device: Option<crate::devices::DeviceId>) {
+        self.update(|file| {
+            file.size = Some(size);
+            file.device = device;
+        });
+    }
+
+    pub fn reading(&self, offset: u64) {
+        self.update(|file| {
+            file.offset = offset;
+            file.phase = Phase::Reading;
+            file.since = Instant::now();
+        });
+    }
+
+    pub fn hashing(&self) {
+        self.update(|file| {
+            file.phase = Phase::Hashing;
+            file.since = Instant::now();
+        });
+    }
+}
+
+impl Drop for Tracked {
+    fn drop(&mut self) {
+        self.console.files.lock().unwrap().remove(&self.id);
+    }
+}
@@ -0,0 +1,86 @@
+//! Grouping inputs by the device they live on, so each device gets its own
+//! concurrency limit.
+
+use color_eyre::eyre::{self, eyre};
+use std::{
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+/// An identifier for the block device backing a path (`st_dev` on Unix)
+pub type DeviceId = u64;
+
+/// A `PATH=N` concurrency override for whichever device holds `PATH`
+#[derive(Debug, Clone)]
+pub struct DeviceLimit {
+    pub path: PathBuf,
+    pub jobs: usize,
+}
+
+impl FromStr for DeviceLimit {
+    type Err = eyre::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (path, jobs) = s
+            .rsplit_once('=')
+            .ok_or_else(|| eyre!("expected PATH=N, got {:?}", s))?;
+        let jobs = jobs
+            .parse()
+            .map_err(|_| eyre!("{:?} is not a number of jobs", jobs))?;
+        if jobs == 0 {
+            return Err(eyre!("device limits must allow at least one job"));
+        }
+        Ok(Self {
+            path: path.into(),
+            jobs,
+        })
+    }
+}
+
+/// Returns the device `path` lives on, or `None` if it can't be determined
+/// (the path doesn't exist, or the platform has no notion of devices).
+pub async fn device_of(path: &Path) -> Option<DeviceId> {
+    let metadata = async_std::fs::metadata(path).await.ok()?;
+    device_id(&metadata)
+}
+
+/// The device a file's metadata says it lives on
+#[cfg(unix)]
+pub fn device_id(metadata: &std::fs::Metadata) -> Option<DeviceId> {
+    use std::os::unix::fs::MetadataExt;
+    Some(metadata.dev())
+}
+
+#[cfg(not(unix))]
+pub fn device_id(_metadata: &std::fs::Metadata) -> Option<DeviceId> {
+    None
+}
+
+/// Names a device for people: `MAJOR:MINOR`, followed on Linux by what's
+/// mounted from it and where, like `8:1 /dev/sda1 on /srv`
+#[cfg(target_os = "linux")]
+pub fn describe(id: DeviceId) -> String {
+    let number = format!("{}:{}", libc::major(id), libc::minor(id));
+    let mountinfo = std::fs::read_to_string("/proc/self/mountinfo").unwrap_or_default();
+    // `ID PARENT MAJ:MIN ROOT MOUNTPOINT OPTIONS... - FSTYPE SOURCE ...`
+    let mount = mountinfo.lines().find_map(|line| {
+        let (fields, rest) = line.split_once(" - ")?;
+        let fields: Vec<&str> = fields.split(' ').collect();
+        if fields.get(2) != Some(&number.as_str()) {
+            return None;
+        }
+        Some((
+            rest.split(' ').nth(1)?.to_string(),
+            fields.get(4)?.to_string(),
+        ))
+    });
+    match mount {
+        Some((source, mountpoint)) => format!("{} {} on {}", number, source, mountpoint),
+        None => number,
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn describe(id: DeviceId) -> String {
+    id.to_string()
+}
@@ -0,0 +1,169 @@
+//! Digests as values: written and parsed the ways other tools spell them,
+//! and compared without giving away where they differ.
+
+use crate::{algo::Algorithm, hex, unhex};
+use color_eyre::eyre::{self, eyre};
+use std::{fmt, str::FromStr};
+
+/// A digest, and the algorithm that computed it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Digest {
+    pub algorithm: Algorithm,
+    pub bytes: Vec<u8>,
+}
+
+/// How a digest's bytes are written
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    /// Lowercase hex, as every manifest format here uses
+    Hex,
+    /// Standard base64 with padding, as in Subresource Integrity
+    Base64,
+}
+
+impl Encoding {
+    pub const ALL: [Self; 2] = [Self::Hex, Self::Base64];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Hex => "hex",
+            Self::Base64 => "base64",
+        }
+    }
+}
+
+impl FromStr for Encoding {
+    type Err = eyre::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|encoding| encoding.name() == s)
+            .ok_or_else(|| eyre!("expected `hex` or `base64`, got {:?}", s))
+    }
+}
+
+impl Digest {
+    pub fn encode(&self, encoding: Encoding) -> String {
+        match encoding {
+            Encoding::Hex => hex(&self.bytes),
+            Encoding::Base64 => base64(&self.bytes),
+        }
+    }
+
+    /// Parses `s` as [`FromStr`] does, except that bare hex is taken to be
+    /// from `algorithm`. A prefix naming another algorithm is an error.
+    pub fn parse_as(algorithm: Algorithm, s: &str) -> Result<Self, eyre::Error> {
+        let (named, bytes) = parse(s)?;
+        match named {
+            Some(named) if named != algorithm => Err(eyre!(
+                "{:?} is a {} digest, expected {}",
+                s,
+                named,
+                algorithm
+            )),
+            _ => Ok(Self { algorithm, bytes }),
+        }
+    }
+
+    /// Whether both are the same digest from the same algorithm, taking as
+    /// long wherever the bytes differ
+    pub fn ct_eq(&self, other: &Self) -> bool {
+        self.algorithm == other.algorithm && ct_eq(&self.bytes, &other.bytes)
+    }
+}
+
+/// Writes the digest in hex, or with `{:#}` prefixed by its algorithm as
+/// `NAME:HEX`, which [`FromStr`] reads back
+impl fmt::Display for Digest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if f.alternate() {
+            write!(f, "{}:", self.algorithm)?;
+        }
+        f.write_str(&hex(&self.bytes))
+    }
+}
+
+/// Reads `NAME:HEX` (`sha256:…`), `NAME-BASE64` as in Subresource Integrity
+/// (`sha512-…`), or bare hex, taken as SHA3-256
+impl FromStr for Digest {
+    type Err = eyre::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (algorithm, bytes) = parse(s)?;
+        Ok(Self {
+            algorithm: algorithm.unwrap_or(Algorithm::Sha3_256),
+            bytes,
+        })
+    }
+}
+
+/// The algorithm `s` names, if it does, and its bytes, for callers that
+/// make up their own mind about bare digests
+pub fn parse(s: &str) -> Result<(Option<Algorithm>, Vec<u8>), eyre::Error> {
+    let (algorithm, bytes) = match s.split_once(':') {
+        Some((name, digest)) => (Some(name.parse()?), unhex(digest)),
+        // base64 never has a dash, algorithm names may
+        None => match s.rsplit_once('-') {
+            Some((name, digest)) if name.parse::<Algorithm>().is_ok() => {
+                (Some(name.parse()?), unbase64(digest))
+            }
+            _ => (None, unhex(s)),
+        },
+    };
+    match bytes {
+        Some(bytes) if !bytes.is_empty() => Ok((algorithm, bytes)),
+        _ => Err(eyre!("{:?} is not a digest", s)),
+    }
+}
+
+/// Compares `a` and `b` in a time that only depends on their lengths, so a
+/// digest can be checked without leaking how much of it matched
+pub fn ct_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    let diff = a.iter().zip(b).fold(0, |diff, (a, b)| diff | (a ^ b));
+    std::hint::black_box(diff) == 0
+}
+
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+pub(crate) fn base64(bytes: &[u8]) -> String {
+    let mut out = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (i, &b)| n | (b as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(BASE64[(n >> (18 - 6 * i)) as usize & 63] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+pub(crate) fn unbase64(s: &str) -> Option<Vec<u8>> {
+    let s = s.trim_end_matches('=');
+    if s.len() % 4 == 1 {
+        return None;
+    }
+    let mut out = Vec::with_capacity(s.len() * 3 / 4);
+    let (mut n, mut bits) = (0u32, 0);
+    for c in s.bytes() {
+        let value = BASE64.iter().position(|&b| b == c)? as u32;
+        n = n << 6 | value;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((n >> bits) as u8);
+        }
+    }
+    // leftover bits are padding, and must be zero
+    (n & ((1 << bits) - 1) == 0).then_some(out)
+}
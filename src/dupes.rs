@@ -0,0 +1,151 @@
+//! Finding duplicate files, for `dupes`. Files can only be the same if
+//! they're the same size, so only sizes more than one file has are
+//! hashed, and the files among those that also share a digest are
+//! clustered.
+//!
+//! `--dedupe-against` checks each file as it's hashed against the digests
+//! already archived instead, so a pipeline can drop copies on the fly.
+
+use color_eyre::eyre::{self, eyre};
+use serde::Serialize;
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    path::{Path, PathBuf},
+};
+
+/// `files` by size, leaving out sizes only one of them has
+pub fn same_size(files: impl IntoIterator<Item = (PathBuf, u64)>) -> BTreeMap<u64, Vec<PathBuf>> {
+    let mut by_size: BTreeMap<u64, Vec<PathBuf>> = BTreeMap::new();
+    for (path, size) in files {
+        by_size.entry(size).or_default().push(path);
+    }
+    by_size.retain(|_, paths| paths.len() > 1);
+    by_size
+}
+
+/// Files with the same contents
+#[derive(Debug, Serialize)]
+pub struct Cluster {
+    pub size: u64,
+    /// In hex
+    pub digest: String,
+    pub paths: Vec<PathBuf>,
+}
+
+impl Cluster {
+    /// What all the copies but one take up
+    pub fn wasted(&self) -> u64 {
+        self.size * (self.paths.len() as u64 - 1)
+    }
+}
+
+/// Hashed files, by size and digest
+#[derive(Default)]
+pub struct Clusters(HashMap<(u64, Vec<u8>), BTreeSet<PathBuf>>);
+
+impl Clusters {
+    pub fn add(&mut self, size: u64, digest: Vec<u8>, path: PathBuf) {
+        self.0.entry((size, digest)).or_default().insert(path);
+    }
+
+    /// The clusters of more than one file, those wasting the most space
+    /// first, and otherwise by digest, so runs print them alike
+    pub fn finish(self) -> Vec<Cluster> {
+        let mut clusters: Vec<_> = self
+            .0
+            .into_iter()
+            .filter(|(_, paths)| paths.len() > 1)
+            .map(|((size, digest), paths)| Cluster {
+                size,
+                digest: crate::hex(&digest),
+                paths: paths.into_iter().collect(),
+            })
+            .collect();
+        clusters.sort_by(|a, b| {
+            b.wasted()
+                .cmp(&a.wasted())
+                .then_with(|| a.digest.cmp(&b.digest))
+        });
+        clusters
+    }
+}
+
+/// Whether a file's contents were known already, for `--dedupe-against`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Verdict {
+    /// The path that had them first
+    Duplicate(String),
+    New,
+}
+
+/// The archived digests, with a path each, and those of the files hashed
+/// since
+pub struct Known(HashMap<Vec<u8>, String>);
+
+impl Known {
+    /// The digests `entries` give for `algorithm`; entries made with
+    /// another can't tell a duplicate, and are left out
+    pub fn new(
+        entries: Vec<crate::sums::Entry>,
+        algorithm: crate::algo::Algorithm,
+    ) -> Result<Self, eyre::Error> {
+        let mut known = HashMap::new();
+        for entry in entries {
+            let same = entry.algorithm == algorithm.name()
+                || entry.algorithm.parse::<crate::algo::Algorithm>().ok() == Some(algorithm);
+            if !same {
+                continue;
+            }
+            let digest = crate::unhex(&entry.digest)
+                .ok_or_else(|| eyre!("{:?} is not a hex digest", entry.digest))?;
+            known.entry(digest).or_insert(entry.path);
+        }
+        Ok(Self(known))
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Whether `digest` is known, remembering it as `path`'s if not, so
+    /// later copies are duplicates of the first
+    pub fn check(&mut self, digest: &[u8], path: &Path) -> Verdict {
+        match self.0.get(digest) {
+            Some(first) => Verdict::Duplicate(first.clone()),
+            None => {
+                self.0.insert(digest.to_vec(), path.display().to_string());
+                Verdict::New
+            }
+        }
+    }
+}
+
+/// A cluster in `dupes --json`
+#[derive(Serialize)]
+pub struct Record<'a> {
+    pub schema_version: u32,
+    pub algorithm: &'a str,
+    pub digest: &'a str,
+    pub size: u64,
+    pub paths: Vec<String>,
+}
+
+impl<'a> Record<'a> {
+    pub fn new(algorithm: &'a str, cluster: &'a Cluster) -> Self {
+        Self {
+            schema_version: crate::output::SCHEMA_VERSION,
+            algorithm,
+            digest: &cluster.digest,
+            size: cluster.size,
+            paths: cluster
+                .paths
+                .iter()
+                .map(|p| p.display().to_string())
+                .collect(),
+        }
+    }
+}
@@ -0,0 +1,44 @@
+//! The eDonkey2000 hash: MD4 over fixed-size chunks, then MD4 over the
+//! chunk digests.
+
+use md4::{Digest, Md4};
+
+/// Size of the independently hashed chunks
+pub const CHUNK_SIZE: u64 = 9_728_000;
+
+/// Incremental ed2k hasher
+#[derive(Default, Clone)]
+pub struct Ed2k {
+    chunk: Md4,
+    chunk_len: u64,
+    /// Digests of the chunks completed so far
+    chunks: Vec<u8>,
+}
+
+impl Ed2k {
+    pub fn update(&mut self, mut data: &[u8]) {
+        while !data.is_empty() {
+            let take = ((CHUNK_SIZE - self.chunk_len) as usize).min(data.len());
+            self.chunk.update(&data[..take]);
+            self.chunk_len += take as u64;
+            data = &data[take..];
+            if self.chunk_len == CHUNK_SIZE {
+                let chunk = std::mem::take(&mut self.chunk);
+                self.chunks.extend_from_slice(&chunk.finalize());
+                self.chunk_len = 0;
+            }
+        }
+    }
+
+    pub fn finalize(self) -> Vec<u8> {
+        // files that fit in one chunk are hashed as-is
+        if self.chunks.is_empty() {
+            return self.chunk.finalize().to_vec();
+        }
+        // the last chunk counts even when it's empty, as eMule and most
+        // published ed2k links do for sizes that are a multiple of the chunk
+        let mut chunks = self.chunks;
+        chunks.extend_from_slice(&self.chunk.finalize());
+        Md4::digest(&chunks).to_vec()
+    }
+}
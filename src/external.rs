@@ -0,0 +1,111 @@
+//! Digests computed by an external command, for checksums this crate doesn't
+//! implement, fed through the same reading pipeline as the built-in ones.
+//!
+//! The command is run with `sh -c` once per file and given its contents on
+//! stdin. The first word it prints is taken as the digest, in hex, so
+//! `ext:b2sum` or `ext:'openssl dgst -sm3 -r'` work as they are.
+
+use std::{
+    io::{self, Write},
+    process::{Child, ChildStdin, Command, Stdio},
+};
+
+/// The command running for one file
+pub struct Process {
+    command: &'static str,
+    child: Option<Child>,
+    stdin: Option<ChildStdin>,
+    /// The first failure to start it or to write to it
+    error: Option<io::Error>,
+    digest: Vec<u8>,
+}
+
+impl Process {
+    pub fn new(command: &'static str) -> Self {
+        let spawned = Command::new("sh")
+            .arg("-c")
+            .arg(command)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn();
+        let (child, error) = match spawned {
+            Ok(child) => (Some(child), None),
+            Err(e) => (None, Some(e)),
+        };
+        let mut process = Self {
+            command,
+            child,
+            stdin: None,
+            error,
+            digest: Vec::new(),
+        };
+        process.stdin = process.child.as_mut().and_then(|c| c.stdin.take());
+        process
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        if self.error.is_some() {
+            return;
+        }
+        if let Some(stdin) = &mut self.stdin {
+            if let Err(e) = stdin.write_all(data) {
+                self.error = Some(e);
+            }
+        }
+    }
+
+    /// Closes the command's input and waits for its digest
+    pub fn finish(&mut self) -> io::Result<()> {
+        drop(self.stdin.take());
+        let child = match self.child.take() {
+            Some(child) => child,
+            None => return self.error.take().map_or(Ok(()), Err),
+        };
+        let output = child.wait_with_output()?;
+        // a command that exits early breaks the pipe, its status says why
+        if !output.status.success() {
+            return Err(io::Error::other(format!(
+                "`{}` failed: {}",
+                self.command, output.status
+            )));
+        }
+        match self.error.take() {
+            Some(e) if e.kind() == io::ErrorKind::BrokenPipe => {
+                return Err(io::Error::other(format!(
+                    "`{}` exited without reading all of its input",
+                    self.command
+                )))
+            }
+            Some(e) => return Err(e),
+            None => {}
+        }
+        self.digest = String::from_utf8_lossy(&output.stdout)
+            .split_whitespace()
+            .next()
+            .and_then(crate::unhex)
+            .filter(|digest| !digest.is_empty())
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("`{}` didn't print a hex digest", self.command),
+                )
+            })?;
+        Ok(())
+    }
+
+    /// The digest, once [`Process::finish`] succeeded
+    pub fn finalize(mut self) -> Vec<u8> {
+        std::mem::take(&mut self.digest)
+    }
+}
+
+impl Drop for Process {
+    fn drop(&mut self) {
+        // read errors leave the command waiting for input nobody will send
+        drop(self.stdin.take());
+        if let Some(mut child) = self.child.take() {
+            child.kill().ok();
+            child.wait().ok();
+        }
+    }
+}
@@ -0,0 +1,152 @@
+//! Misbehaving on purpose: slow reads, short reads and failed reads, to see
+//! how everything above the reader copes, without a flaky disk at hand.
+//!
+//! Faults are drawn from a generator seeded by `--seed` and the file's
+//! path, so a run with the same seed fails the same reads again.
+
+use crate::SimpleRead;
+use async_trait::async_trait;
+use color_eyre::eyre::{self, eyre};
+use std::{io, path::Path, str::FromStr, time::Duration};
+
+/// What to inject into every read, written `latency=50ms,short=0.5,error=0.01`
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Faults {
+    /// How long to wait before each read
+    pub latency: Option<Duration>,
+    /// The odds of a read returning fewer bytes than were asked for
+    pub short: f64,
+    /// The odds of a read failing outright
+    pub error: f64,
+}
+
+impl Faults {
+    pub fn is_none(&self) -> bool {
+        self.latency.is_none() && self.short == 0.0 && self.error == 0.0
+    }
+}
+
+impl FromStr for Faults {
+    type Err = eyre::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut faults = Self::default();
+        for part in s.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+            let (key, value) = part
+                .split_once('=')
+                .ok_or_else(|| eyre!("expected `key=value`, got {:?}", part))?;
+            match key.trim() {
+                "latency" => {
+                    let crate::units::Duration(d) = value.parse()?;
+                    faults.latency = Some(d);
+                }
+                "short" => faults.short = odds(value)?,
+                "error" => faults.error = odds(value)?,
+                key => {
+                    return Err(eyre!(
+                        "expected `latency`, `short` or `error`, got {:?}",
+                        key
+                    ))
+                }
+            }
+        }
+        Ok(faults)
+    }
+}
+
+fn odds(s: &str) -> Result<f64, eyre::Error> {
+    match s.trim().parse::<f64>() {
+        Ok(p) if (0.0..=1.0).contains(&p) => Ok(p),
+        _ => Err(eyre!("expected odds between 0 and 1, got {:?}", s)),
+    }
+}
+
+/// The error injected reads fail with
+#[derive(Debug)]
+pub struct Injected;
+
+impl std::fmt::Display for Injected {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("injected read fault")
+    }
+}
+
+impl std::error::Error for Injected {}
+
+/// Wraps a reader, making its reads slow, short or failed as `faults` say
+pub struct FaultInjectingReader<R> {
+    inner: R,
+    faults: Faults,
+    rng: SplitMix,
+}
+
+impl<R> FaultInjectingReader<R> {
+    /// `seed` and `path` pick which reads misbehave
+    pub fn new(inner: R, faults: Faults, seed: u64, path: &Path) -> Self {
+        let mut hasher = crc32fast::Hasher::new();
+        hasher.update(path.to_string_lossy().as_bytes());
+        Self {
+            inner,
+            faults,
+            rng: SplitMix(seed ^ ((hasher.finalize() as u64) << 32)),
+        }
+    }
+}
+
+#[async_trait]
+impl<R> SimpleRead for FaultInjectingReader<R>
+where
+    R: SimpleRead + Send,
+{
+    async fn simple_read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.faults.is_none() {
+            return self.inner.simple_read(buf).await;
+        }
+        if let Some(latency) = self.faults.latency {
+            crate::rt::sleep(latency).await;
+        }
+        if self.rng.chance(self.faults.error) {
+            tracing::debug!("injecting a read error");
+            return Err(io::Error::other(Injected));
+        }
+        let len = if buf.len() > 1 && self.rng.chance(self.faults.short) {
+            let len = 1 + (self.rng.next() % (buf.len() as u64 - 1)) as usize;
+            tracing::debug!(len, asked = buf.len(), "injecting a short read");
+            len
+        } else {
+            buf.len()
+        };
+        self.inner.simple_read(&mut buf[..len]).await
+    }
+
+    fn poll_simple_read(
+        &mut self,
+        cx: &mut std::task::Context<'_>,
+        buf: &mut [u8],
+    ) -> Option<std::task::Poll<io::Result<usize>>> {
+        // faults take a future of their own, a clean read doesn't need one
+        if self.faults.is_none() {
+            self.inner.poll_simple_read(cx, buf)
+        } else {
+            None
+        }
+    }
+}
+
+/// A small, fast generator; faults don't need better randomness than this
+struct SplitMix(u64);
+
+impl SplitMix {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    fn chance(&mut self, odds: f64) -> bool {
+        let unit = (self.next() >> 11) as f64 / (1u64 << 53) as f64;
+        odds > 0.0 && unit < odds
+    }
+}
@@ -0,0 +1,103 @@
+//! Narrowing results down before they're printed.
+
+use crate::Hashed;
+use color_eyre::eyre::{self, eyre};
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use std::{collections::HashSet, fmt, path::Path, str::FromStr, time::Duration};
+
+/// Conditions a result must meet to be printed. Errors always are.
+#[derive(Default)]
+pub struct Filter {
+    pub digests: Option<HashSet<Vec<u8>>>,
+    pub min_size: Option<u64>,
+    pub max_size: Option<u64>,
+    /// Only files that took at least this long to hash
+    pub min_duration: Option<Duration>,
+    pub paths: Option<GlobSet>,
+}
+
+impl Filter {
+    pub fn matches(&self, path: &Path, hashed: &Hashed) -> bool {
+        self.digests
+            .as_ref()
+            .is_none_or(|digests| digests.contains(&hashed.hash))
+            && self.min_size.is_none_or(|min| hashed.size >= min)
+            && self.max_size.is_none_or(|max| hashed.size <= max)
+            && self.min_duration.is_none_or(|min| hashed.elapsed >= min)
+            && self.paths.as_ref().is_none_or(|paths| paths.is_match(path))
+    }
+}
+
+/// What to do with files over `--max-file-size`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Oversize {
+    /// Don't read them at all, and say so
+    Skip,
+    /// Hash only their first bytes, and mark the digest as partial
+    Truncate,
+}
+
+impl FromStr for Oversize {
+    type Err = eyre::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "skip" => Ok(Self::Skip),
+            "truncate" => Ok(Self::Truncate),
+            _ => Err(eyre!("expected `skip` or `truncate`, got {:?}", s)),
+        }
+    }
+}
+
+/// Why a file over `--max-file-size` was skipped, told apart from read
+/// errors by the writer
+#[derive(Debug)]
+pub struct TooLarge {
+    pub size: u64,
+    pub max: u64,
+}
+
+impl fmt::Display for TooLarge {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} bytes, over the maximum of {} bytes",
+            self.size, self.max
+        )
+    }
+}
+
+impl std::error::Error for TooLarge {}
+
+/// Builds a matcher for any of `patterns`
+pub fn globs(patterns: &[String]) -> Result<GlobSet, eyre::Error> {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        builder.add(Glob::new(pattern)?);
+    }
+    Ok(builder.build()?)
+}
+
+/// Shortest word taken for a digest, so short hex-looking paths aren't
+pub const MIN_DIGEST_LEN: usize = 16;
+
+/// Reads a list of hex digests, one per line. Each line's first word that
+/// looks like a digest is taken, so `sha3sum`-style manifests and this
+/// tool's own output work too.
+pub async fn load_digests(path: &Path) -> Result<HashSet<Vec<u8>>, eyre::Error> {
+    let text = async_std::fs::read_to_string(path).await?;
+    let mut digests = HashSet::new();
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let digest = line
+            .split_whitespace()
+            .filter(|word| word.len() >= MIN_DIGEST_LEN)
+            .find_map(crate::unhex)
+            .ok_or_else(|| eyre!("{}:{}: no hex digest found", path.display(), i + 1))?;
+        digests.insert(digest);
+    }
+    Ok(digests)
+}
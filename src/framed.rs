@@ -0,0 +1,72 @@
+//! Hashing a stream of length-prefixed blobs, one digest per blob.
+//!
+//! Each frame is an 8-byte big-endian length followed by that many bytes of
+//! payload. The stream must end on a frame boundary.
+
+use crate::algo::{Hasher, Update};
+use color_eyre::eyre::{self, eyre};
+use futures::io::{AsyncRead, AsyncReadExt};
+use std::io;
+
+/// Hashes every frame of `input`, each with a fresh hasher from `hasher`,
+/// calling `on_frame` with each frame's index and digest as soon as the
+/// frame is complete. Payloads are streamed through the hasher in
+/// `buffer_size` pieces, never held in memory whole.
+pub async fn hash_frames<R>(
+    mut input: R,
+    buffer_size: usize,
+    mut hasher: impl FnMut() -> Result<Hasher, eyre::Error>,
+    mut on_frame: impl FnMut(u64, Vec<u8>),
+) -> Result<u64, eyre::Error>
+where
+    R: AsyncRead + Unpin,
+{
+    let mut buf = vec![0u8; buffer_size];
+    let mut index = 0;
+    loop {
+        let len = match read_header(&mut input).await? {
+            Some(len) => len,
+            None => return Ok(index),
+        };
+
+        let mut hasher = hasher()?;
+        let mut remaining = len;
+        while remaining > 0 {
+            let want = (buf.len() as u64).min(remaining) as usize;
+            let n = input.read(&mut buf[..want]).await?;
+            if n == 0 {
+                return Err(eyre!(
+                    "frame {} was cut short: expected {} bytes, got {}",
+                    index,
+                    len,
+                    len - remaining
+                ));
+            }
+            hasher.update(&buf[..n]);
+            remaining -= n as u64;
+        }
+
+        hasher.finish()?;
+        on_frame(index, hasher.finalize());
+        index += 1;
+    }
+}
+
+/// Reads a length prefix, or returns `None` on a clean end of stream
+async fn read_header<R>(input: &mut R) -> Result<Option<u64>, eyre::Error>
+where
+    R: AsyncRead + Unpin,
+{
+    let mut header = [0u8; 8];
+    let mut filled = 0;
+    while filled < header.len() {
+        match input.read(&mut header[filled..]).await {
+            Ok(0) if filled == 0 => return Ok(None),
+            Ok(0) => return Err(eyre!("stream ended in the middle of a frame header")),
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(Some(u64::from_be_bytes(header)))
+}
@@ -0,0 +1,215 @@
+//! Verification failures and read errors summed up by directory and by
+//! device, so storage admins can see which shelf or dataset is rotting
+//! rather than scrolling through thousands of individual failures.
+
+use crate::devices::{self, DeviceId};
+use color_eyre::eyre::{self, eyre};
+use serde::Serialize;
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::Write as _,
+    path::{Component, Path, PathBuf},
+    str::FromStr,
+};
+
+/// Width of the text report's bars, for the worst directory or device
+const BAR_WIDTH: usize = 20;
+
+/// What happened to one file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    Ok,
+    /// It was read, but didn't have the expected digest or size
+    Mismatch,
+    /// It couldn't be read
+    ReadError,
+    Missing,
+}
+
+impl Outcome {
+    /// The outcome a verifier's status stands for, if it says anything
+    /// about the storage (`UNLISTED` doesn't)
+    pub fn from_status(status: &str) -> Option<Self> {
+        match status {
+            "OK" => Some(Self::Ok),
+            "MISSING" => Some(Self::Missing),
+            "FAILED" => Some(Self::Mismatch),
+            s if s.starts_with("FAILED (size ") => Some(Self::Mismatch),
+            s if s.starts_with("FAILED") => Some(Self::ReadError),
+            _ => None,
+        }
+    }
+}
+
+/// How the report is written
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Text,
+    Json,
+}
+
+impl FromStr for Format {
+    type Err = eyre::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(Self::Text),
+            "json" => Ok(Self::Json),
+            _ => Err(eyre!("expected `text` or `json`, got {:?}", s)),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, Serialize)]
+pub struct Counts {
+    pub files: u64,
+    pub mismatches: u64,
+    pub read_errors: u64,
+    pub missing: u64,
+}
+
+impl Counts {
+    fn add(&mut self, outcome: Outcome) {
+        self.files += 1;
+        match outcome {
+            Outcome::Ok => {}
+            Outcome::Mismatch => self.mismatches += 1,
+            Outcome::ReadError => self.read_errors += 1,
+            Outcome::Missing => self.missing += 1,
+        }
+    }
+
+    pub fn problems(&self) -> u64 {
+        self.mismatches + self.read_errors + self.missing
+    }
+
+    fn rate(&self) -> f64 {
+        self.problems() as f64 / self.files.max(1) as f64
+    }
+}
+
+#[derive(Default)]
+pub struct Heatmap {
+    total: Counts,
+    /// Totals for each directory, counting everything below it
+    dirs: BTreeMap<PathBuf, Counts>,
+    devices: BTreeMap<Option<DeviceId>, Counts>,
+    /// The device each directory seen so far is on
+    dir_devices: HashMap<PathBuf, Option<DeviceId>>,
+}
+
+impl Heatmap {
+    pub fn record(&mut self, path: &Path, outcome: Outcome) {
+        self.total.add(outcome);
+        let dir = path.parent().unwrap_or_else(|| Path::new(""));
+        let device = *self
+            .dir_devices
+            .entry(dir.to_owned())
+            // a missing file's directory may be gone too
+            .or_insert_with(|| dir.ancestors().find_map(device_of));
+        self.devices.entry(device).or_default().add(outcome);
+        for dir in dir.ancestors().filter(|d| !d.as_os_str().is_empty()) {
+            self.dirs.entry(dir.to_owned()).or_default().add(outcome);
+        }
+    }
+
+    /// Writes the report, only listing directories at most `depth` levels
+    /// deep when given
+    pub fn render(&self, format: Format, depth: Option<usize>) -> Result<String, eyre::Error> {
+        let dirs = self.dirs.iter().filter(|(dir, _)| {
+            depth.is_none_or(|depth| {
+                dir.components()
+                    .filter(|c| matches!(c, Component::Normal(_)))
+                    .count()
+                    <= depth
+            })
+        });
+        let devices = self
+            .devices
+            .iter()
+            .map(|(device, counts)| (device.map(devices::describe), counts));
+
+        match format {
+            Format::Json => {
+                #[derive(Serialize)]
+                struct Report<T> {
+                    total: Counts,
+                    devices: Vec<Row<Option<String>>>,
+                    directories: Vec<Row<T>>,
+                }
+                #[derive(Serialize)]
+                struct Row<T> {
+                    name: T,
+                    #[serde(flatten)]
+                    counts: Counts,
+                }
+                let report = Report {
+                    total: self.total,
+                    devices: devices
+                        .map(|(name, &counts)| Row { name, counts })
+                        .collect(),
+                    directories: dirs.map(|(name, &counts)| Row { name, counts }).collect(),
+                };
+                Ok(serde_json::to_string_pretty(&report)? + "\n")
+            }
+            Format::Text => {
+                let mut out = String::new();
+                writeln!(
+                    out,
+                    "{} problem(s) in {} file(s)",
+                    self.total.problems(),
+                    self.total.files
+                )?;
+                let devices: Vec<_> = devices
+                    .map(|(name, counts)| (name.unwrap_or_else(|| "unknown".to_string()), counts))
+                    .collect();
+                rows(&mut out, "By device", devices)?;
+                let dirs = dirs.map(|(dir, counts)| (dir.display().to_string(), counts));
+                rows(&mut out, "By directory", dirs.collect())?;
+                Ok(out)
+            }
+        }
+    }
+}
+
+/// The worst rows first, skipping those without problems
+fn rows(out: &mut String, title: &str, mut rows: Vec<(String, &Counts)>) -> std::fmt::Result {
+    rows.retain(|(_, counts)| counts.problems() > 0);
+    if rows.is_empty() {
+        return Ok(());
+    }
+    rows.sort_by(|(a_name, a), (b_name, b)| {
+        b.rate()
+            .total_cmp(&a.rate())
+            .then(b.problems().cmp(&a.problems()))
+            .then(a_name.cmp(b_name))
+    });
+    let worst = rows[0].1.rate();
+    writeln!(out, "\n{}:", title)?;
+    for (name, counts) in rows {
+        let bar = ((counts.rate() / worst) * BAR_WIDTH as f64).ceil() as usize;
+        writeln!(
+            out,
+            "{:>7.2}% {:<width$} {}/{} {} (mismatched={} errors={} missing={})",
+            counts.rate() * 100.0,
+            "#".repeat(bar),
+            counts.problems(),
+            counts.files,
+            name,
+            counts.mismatches,
+            counts.read_errors,
+            counts.missing,
+            width = BAR_WIDTH
+        )?;
+    }
+    Ok(())
+}
+
+fn device_of(path: &Path) -> Option<DeviceId> {
+    let path = if path.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        path
+    };
+    devices::device_id(&std::fs::metadata(path).ok()?)
+}
@@ -0,0 +1,116 @@
+//! Keyed digests, for `--hmac-key`: HMAC (RFC 2104) over the SHA-2 and
+//! SHA-3 hashers, and BLAKE3's own keyed mode, so a digest also proves it
+//! was made by someone holding the key.
+
+use crate::algo::{Algorithm, Hasher, Update};
+use color_eyre::eyre::{self, eyre};
+use std::{convert::TryInto, fmt, path::Path, str::FromStr, sync::Arc};
+
+/// A secret key, read from a file, the environment or hex
+#[derive(Clone)]
+pub struct Key(Arc<Vec<u8>>);
+
+impl Key {
+    pub fn bytes(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+/// Never shows the key itself
+impl fmt::Debug for Key {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Key({} bytes)", self.0.len())
+    }
+}
+
+/// `env:NAME` for the variable's value, a file's contents exactly, trailing
+/// newline included, or else the key in hex
+impl FromStr for Key {
+    type Err = eyre::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let key = if let Some(name) = s.strip_prefix("env:") {
+            std::env::var_os(name)
+                .ok_or_else(|| eyre!("${} isn't set", name))?
+                .into_encoded_bytes()
+        } else if Path::new(s).is_file() {
+            std::fs::read(s).map_err(|e| eyre!("can't read {}: {}", s, e))?
+        } else {
+            // the key itself mustn't end up in an error message
+            crate::unhex(s).ok_or_else(|| eyre!("expected a file, `env:NAME` or hex"))?
+        };
+        if key.is_empty() {
+            return Err(eyre!("the key is empty"));
+        }
+        Ok(Self(Arc::new(key)))
+    }
+}
+
+/// The block size HMAC pads keys to, the rate for SHA-3
+fn block_len(algorithm: Algorithm) -> Option<usize> {
+    match algorithm {
+        Algorithm::Sha3_256 => Some(136),
+        Algorithm::Sha3_512 => Some(72),
+        Algorithm::Sha256 => Some(64),
+        Algorithm::Sha512 => Some(128),
+        _ => None,
+    }
+}
+
+/// A hasher for `algorithm` keyed with `key`
+pub fn hasher(algorithm: Algorithm, key: &[u8]) -> Result<Hasher, eyre::Error> {
+    if algorithm == Algorithm::Blake3 {
+        let key: &[u8; crate::blake3::KEY_LEN] = key.try_into().map_err(|_| {
+            eyre!(
+                "BLAKE3 keys are {} bytes, this one is {}",
+                crate::blake3::KEY_LEN,
+                key.len()
+            )
+        })?;
+        return Ok(Hasher::Blake3(crate::blake3::Blake3::keyed(key)));
+    }
+    let block_len = block_len(algorithm).ok_or_else(|| eyre!("{} has no keyed mode", algorithm))?;
+    // longer keys are hashed first, shorter ones padded with zeroes
+    let mut block = if key.len() > block_len {
+        let mut hasher = algorithm.hasher();
+        hasher.update(key);
+        hasher.finalize()
+    } else {
+        key.to_vec()
+    };
+    block.resize(block_len, 0);
+    let padded = |pad: u8| {
+        let mut hasher = algorithm.hasher();
+        hasher.update(&block.iter().map(|b| b ^ pad).collect::<Vec<_>>());
+        hasher
+    };
+    Ok(Hasher::Hmac(Box::new(Hmac {
+        inner: padded(0x36),
+        outer: padded(0x5c),
+    })))
+}
+
+/// The inner hasher is fed the message, the outer one its digest
+pub struct Hmac {
+    inner: Hasher,
+    outer: Hasher,
+}
+
+impl Hmac {
+    pub fn update(&mut self, data: &[u8]) {
+        self.inner.update(data)
+    }
+
+    pub fn finalize(self) -> Vec<u8> {
+        let mut outer = self.outer;
+        outer.update(&self.inner.finalize());
+        outer.finalize()
+    }
+
+    pub fn try_clone(&self) -> Option<Self> {
+        Some(Self {
+            inner: self.inner.try_clone()?,
+            outer: self.outer.try_clone()?,
+        })
+    }
+}
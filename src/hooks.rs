@@ -0,0 +1,29 @@
+//! Commands run before and after everything else, typically to take a
+//! filesystem snapshot before hashing and drop it afterwards, so a manifest
+//! of a live system describes a single point in time.
+
+use color_eyre::eyre::{self, eyre};
+use std::process::Command;
+
+/// Runs the `flag` hook `command` with `sh -c`, failing unless it exits
+/// successfully
+pub fn run(flag: &str, command: &str, env: &[(&str, &str)]) -> Result<(), eyre::Error> {
+    tracing::info!(command, "running {}", flag);
+    let mut cmd = Command::new("sh");
+    cmd.arg("-c").arg(command);
+    for (key, value) in env {
+        cmd.env(key, value);
+    }
+    check(&mut cmd).map_err(|e| eyre!("{}: {}", flag, e))
+}
+
+/// Runs `cmd`, failing unless it exits successfully
+pub fn check(cmd: &mut Command) -> Result<(), eyre::Error> {
+    let status = cmd
+        .status()
+        .map_err(|e| eyre!("can't run {:?}: {}", cmd, e))?;
+    if !status.success() {
+        return Err(eyre!("{:?} failed: {}", cmd, status));
+    }
+    Ok(())
+}
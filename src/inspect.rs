@@ -0,0 +1,323 @@
+//! Looking at file contents as they stream past the hasher, so extra facts
+//! about each file come out of the same read pass.
+
+use futures::io::AsyncRead;
+use pin_project::pin_project;
+use std::{
+    io,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+/// Something that observes a file's bytes in order
+pub trait Inspector {
+    fn inspect(&mut self, offset: u64, chunk: &[u8]);
+}
+
+/// Which inspections to run on every file
+#[derive(Debug, Clone, Copy, Default)]
+pub struct InspectOptions {
+    pub detect_type: bool,
+    pub entropy: bool,
+    /// Report all-zero regions at least this many bytes long
+    pub zero_runs: Option<u64>,
+}
+
+/// The inspectors enabled for one file
+#[derive(Default)]
+pub struct Inspection {
+    sniffer: Option<TypeSniffer>,
+    entropy: Option<Entropy>,
+    zero_runs: Option<ZeroRuns>,
+}
+
+impl Inspection {
+    pub fn new(options: InspectOptions) -> Self {
+        Self {
+            sniffer: if options.detect_type {
+                Some(Default::default())
+            } else {
+                None
+            },
+            entropy: if options.entropy {
+                Some(Default::default())
+            } else {
+                None
+            },
+            zero_runs: options.zero_runs.map(ZeroRuns::new),
+        }
+    }
+
+    pub fn report(&self) -> Report {
+        Report {
+            file_type: self.sniffer.as_ref().map(TypeSniffer::file_type),
+            entropy: self.entropy.as_ref().map(Entropy::bits_per_byte),
+            zero_runs: self.zero_runs.as_ref().map(ZeroRuns::report),
+        }
+    }
+}
+
+impl Inspector for Inspection {
+    fn inspect(&mut self, offset: u64, chunk: &[u8]) {
+        if let Some(sniffer) = &mut self.sniffer {
+            sniffer.inspect(offset, chunk);
+        }
+        if let Some(entropy) = &mut self.entropy {
+            entropy.inspect(offset, chunk);
+        }
+        if let Some(zero_runs) = &mut self.zero_runs {
+            zero_runs.inspect(offset, chunk);
+        }
+    }
+}
+
+/// What the inspectors found out about a file
+#[derive(Debug, Clone, Default)]
+pub struct Report {
+    pub file_type: Option<&'static str>,
+    /// Shannon entropy of the byte distribution, from 0 to 8 bits per byte
+    pub entropy: Option<f64>,
+    pub zero_runs: Option<ZeroRunReport>,
+}
+
+/// Entropy above which data is most likely compressed or encrypted
+pub const HIGH_ENTROPY: f64 = 7.9;
+
+/// Wraps a reader, showing everything read from it to an inspector
+#[pin_project]
+pub struct InspectReader<R, I> {
+    #[pin]
+    inner: R,
+    offset: u64,
+    inspector: I,
+}
+
+impl<R, I> InspectReader<R, I> {
+    pub fn new(inner: R, inspector: I) -> Self {
+        Self {
+            inner,
+            offset: 0,
+            inspector,
+        }
+    }
+
+    pub fn into_inspector(self) -> I {
+        self.inspector
+    }
+}
+
+impl<R, I> AsyncRead for InspectReader<R, I>
+where
+    R: AsyncRead,
+    I: Inspector,
+{
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let proj = self.project();
+        let res = proj.inner.poll_read(cx, buf);
+        if let Poll::Ready(Ok(n)) = &res {
+            proj.inspector.inspect(*proj.offset, &buf[..*n]);
+            *proj.offset += *n as u64;
+        }
+        res
+    }
+}
+
+/// Estimates the Shannon entropy of a file from its byte histogram
+pub struct Entropy {
+    counts: [u64; 256],
+}
+
+impl Default for Entropy {
+    fn default() -> Self {
+        Self { counts: [0; 256] }
+    }
+}
+
+impl Inspector for Entropy {
+    fn inspect(&mut self, _offset: u64, chunk: &[u8]) {
+        for &b in chunk {
+            self.counts[b as usize] += 1;
+        }
+    }
+}
+
+impl Entropy {
+    pub fn bits_per_byte(&self) -> f64 {
+        let total: u64 = self.counts.iter().sum();
+        if total == 0 {
+            return 0.0;
+        }
+        let total = total as f64;
+        // from 0.0 rather than summed, which would make a single byte
+        // value's -0.0
+        self.counts.iter().filter(|&&c| c > 0).fold(0.0, |sum, &c| {
+            let p = c as f64 / total;
+            sum - p * p.log2()
+        })
+    }
+}
+
+/// Finds long all-zero regions — what many storage devices return for blocks
+/// that were never written, or that they lost.
+pub struct ZeroRuns {
+    min_len: u64,
+    run_start: u64,
+    run_len: u64,
+    found: ZeroRunReport,
+}
+
+/// All-zero regions found in a file
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ZeroRunReport {
+    /// How many regions were at least as long as the threshold
+    pub count: u64,
+    /// Offset and length of the longest one
+    pub longest: Option<(u64, u64)>,
+}
+
+impl ZeroRuns {
+    pub fn new(min_len: u64) -> Self {
+        Self {
+            min_len: min_len.max(1),
+            run_start: 0,
+            run_len: 0,
+            found: Default::default(),
+        }
+    }
+
+    pub fn report(&self) -> ZeroRunReport {
+        // a run may still be open at the end of the file
+        let mut report = self.found;
+        Self::record(&mut report, self.min_len, self.run_start, self.run_len);
+        report
+    }
+
+    fn record(report: &mut ZeroRunReport, min_len: u64, start: u64, len: u64) {
+        if len < min_len {
+            return;
+        }
+        report.count += 1;
+        if report.longest.map(|(_, l)| len > l).unwrap_or(true) {
+            report.longest = Some((start, len));
+        }
+    }
+}
+
+impl Inspector for ZeroRuns {
+    fn inspect(&mut self, offset: u64, chunk: &[u8]) {
+        let mut i = 0;
+        while i < chunk.len() {
+            if chunk[i] == 0 {
+                let end = chunk[i..]
+                    .iter()
+                    .position(|&b| b != 0)
+                    .map_or(chunk.len(), |p| i + p);
+                if self.run_len == 0 {
+                    self.run_start = offset + i as u64;
+                }
+                self.run_len += (end - i) as u64;
+                i = end;
+            } else {
+                Self::record(&mut self.found, self.min_len, self.run_start, self.run_len);
+                self.run_len = 0;
+                i = chunk[i..]
+                    .iter()
+                    .position(|&b| b == 0)
+                    .map_or(chunk.len(), |p| i + p);
+            }
+        }
+    }
+}
+
+/// How much of the start of a file to look at for magic numbers
+const SNIFF_LEN: usize = 512;
+
+/// Guesses a file's type from the magic numbers at its start
+#[derive(Default)]
+pub struct TypeSniffer {
+    head: Vec<u8>,
+}
+
+impl Inspector for TypeSniffer {
+    fn inspect(&mut self, offset: u64, chunk: &[u8]) {
+        let offset = offset as usize;
+        if offset >= SNIFF_LEN || offset != self.head.len() {
+            return;
+        }
+        let take = chunk.len().min(SNIFF_LEN - offset);
+        self.head.extend_from_slice(&chunk[..take]);
+    }
+}
+
+/// Magic numbers and the offset they appear at
+const MAGIC: &[(usize, &[u8], &str)] = &[
+    (0, b"\x89PNG\r\n\x1a\n", "png"),
+    (0, b"\xff\xd8\xff", "jpeg"),
+    (0, b"GIF87a", "gif"),
+    (0, b"GIF89a", "gif"),
+    (0, b"%PDF-", "pdf"),
+    (0, b"PK\x03\x04", "zip"),
+    (0, b"PK\x05\x06", "zip"),
+    (0, b"\x1f\x8b", "gzip"),
+    (0, b"BZh", "bzip2"),
+    (0, b"\xfd7zXZ\x00", "xz"),
+    (0, b"\x28\xb5\x2f\xfd", "zstd"),
+    (0, b"7z\xbc\xaf\x27\x1c", "7z"),
+    (0, b"Rar!\x1a\x07", "rar"),
+    (257, b"ustar", "tar"),
+    (0, b"\x7fELF", "elf"),
+    (0, b"\xcf\xfa\xed\xfe", "mach-o"),
+    (0, b"\xce\xfa\xed\xfe", "mach-o"),
+    (0, b"MZ", "pe"),
+    (0, b"\x00asm", "wasm"),
+    (0, b"SQLite format 3\x00", "sqlite"),
+    (0, b"ID3", "mp3"),
+    (0, b"fLaC", "flac"),
+    (0, b"OggS", "ogg"),
+    (4, b"ftyp", "mp4"),
+    (0, b"\x1a\x45\xdf\xa3", "matroska"),
+    (0, b"\xd0\xcf\x11\xe0\xa1\xb1\x1a\xe1", "ole2"),
+    (0, b"#!/", "script"),
+];
+
+impl TypeSniffer {
+    pub fn file_type(&self) -> &'static str {
+        let head = &self.head[..];
+        if head.is_empty() {
+            return "empty";
+        }
+        if head.starts_with(b"RIFF") && head.len() >= 12 {
+            return match &head[8..12] {
+                b"WAVE" => "wav",
+                b"AVI " => "avi",
+                b"WEBP" => "webp",
+                _ => "riff",
+            };
+        }
+        for (offset, magic, name) in MAGIC {
+            if head.get(*offset..*offset + magic.len()) == Some(*magic) {
+                return name;
+            }
+        }
+        if looks_like_text(head) {
+            "text"
+        } else {
+            "data"
+        }
+    }
+}
+
+fn looks_like_text(head: &[u8]) -> bool {
+    if head.contains(&0) {
+        return false;
+    }
+    match std::str::from_utf8(head) {
+        Ok(_) => true,
+        // the sniffed prefix may end in the middle of a multi-byte character
+        Err(e) => e.error_len().is_none(),
+    }
+}
@@ -0,0 +1,155 @@
+//! File-integrity manifests, for `surviving snapshot` and `surviving
+//! verify`: every file under a tree with its size, modification time and
+//! digest, and what changed since one was taken.
+
+use crate::compress;
+use color_eyre::eyre::{self, eyre};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    convert::TryFrom,
+    io::Write,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+/// Bumped whenever older versions couldn't read what's written
+pub const VERSION: u32 = 1;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Manifest {
+    pub version: u32,
+    pub algorithm: String,
+    /// The tree, as it was given
+    pub root: PathBuf,
+    /// When the snapshot was taken, as a Unix timestamp
+    pub created: u64,
+    /// By path relative to `root`, always with `/`
+    pub files: BTreeMap<String, Entry>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Entry {
+    pub size: u64,
+    /// In nanoseconds since the Unix epoch, when the platform tells
+    pub mtime: Option<u64>,
+    /// In lowercase hex
+    pub digest: String,
+}
+
+impl Entry {
+    pub fn new(metadata: &std::fs::Metadata, digest: String) -> Self {
+        Self {
+            size: metadata.len(),
+            mtime: metadata
+                .modified()
+                .ok()
+                .and_then(|t| t.duration_since(SystemTime::UNIX_EPOCH).ok())
+                .and_then(|d| u64::try_from(d.as_nanos()).ok()),
+            digest,
+        }
+    }
+}
+
+impl Manifest {
+    pub fn new(algorithm: &str, root: &Path, files: BTreeMap<String, Entry>) -> Self {
+        Self {
+            version: VERSION,
+            algorithm: algorithm.to_owned(),
+            root: root.to_owned(),
+            created: SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs()),
+            files,
+        }
+    }
+
+    /// Reads a manifest, decompressing it if it's compressed
+    pub fn load(path: &Path) -> Result<Self, eyre::Error> {
+        let json = compress::read_to_string(path)?;
+        let manifest: Self =
+            serde_json::from_str(&json).map_err(|e| eyre!("in {}: {}", path.display(), e))?;
+        if manifest.version > VERSION {
+            return Err(eyre!(
+                "{} is a version {} manifest, this build reads up to {}",
+                path.display(),
+                manifest.version,
+                VERSION
+            ));
+        }
+        Ok(manifest)
+    }
+
+    /// Writes the manifest, compressed if the name ends in `.zst` or `.gz`
+    pub fn save(&self, path: &Path) -> Result<(), eyre::Error> {
+        let mut out = std::io::BufWriter::new(compress::Output::create(
+            path,
+            compress::Compression::from_extension(path),
+        )?);
+        serde_json::to_writer_pretty(&mut out, self)?;
+        out.write_all(b"\n")?;
+        out.into_inner().map_err(|e| e.into_error())?.finish()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Change {
+    Added,
+    Removed,
+    /// Its contents or size changed
+    Modified,
+    /// Only its modification time changed
+    Touched,
+}
+
+impl Change {
+    /// Whether an integrity check should fail over it
+    pub fn is_failure(self) -> bool {
+        !matches!(self, Self::Touched)
+    }
+}
+
+/// One file that's not as it was
+#[derive(Debug, Serialize)]
+pub struct Difference {
+    pub path: String,
+    pub change: Change,
+    pub before: Option<Entry>,
+    pub after: Option<Entry>,
+}
+
+/// Every file that was added, removed or changed between `before` and
+/// `after`, in path order
+pub fn diff(before: &BTreeMap<String, Entry>, after: &BTreeMap<String, Entry>) -> Vec<Difference> {
+    let mut differences: Vec<_> = before
+        .iter()
+        .filter_map(|(path, old)| {
+            let change = match after.get(path) {
+                None => Change::Removed,
+                Some(new) if (new.size, &new.digest) != (old.size, &old.digest) => Change::Modified,
+                Some(new) if new.mtime != old.mtime => Change::Touched,
+                Some(_) => return None,
+            };
+            Some(Difference {
+                path: path.clone(),
+                change,
+                before: Some(old.clone()),
+                after: after.get(path).cloned(),
+            })
+        })
+        .collect();
+    differences.extend(
+        after
+            .iter()
+            .filter(|(path, _)| !before.contains_key(*path))
+            .map(|(path, new)| Difference {
+                path: path.clone(),
+                change: Change::Added,
+                before: None,
+                after: Some(new.clone()),
+            }),
+    );
+    differences.sort_by(|a, b| a.path.cmp(&b.path));
+    differences
+}
@@ -0,0 +1,1379 @@
+//! Hashing files, and everything the `surviving` command line is built
+//! from: the read pipeline and its tracing adapters, the hash functions,
+//! manifest formats and the output writer.
+//!
+//! [`hash_path`] and [`hash_reader`] cover the common cases. [`hash_file`]
+//! takes the same [`HashOptions`] as the command line, and reports
+//! everything else learned along the way.
+
+#![allow(unused)]
+
+use async_std::{fs::File, io::ReadExt};
+use color_eyre::eyre::{self, eyre};
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use pin_project::pin_project;
+
+pub mod affinity;
+pub mod algo;
+#[cfg(feature = "alloc-stats")]
+pub mod alloc;
+pub mod archive;
+pub mod audit;
+pub mod batch;
+pub mod bench;
+pub mod blake2b;
+pub mod blake3;
+pub mod bloom;
+pub mod budget;
+pub mod cache;
+pub mod cancel;
+pub mod capabilities;
+pub mod cargo_checksum;
+pub mod compress;
+pub mod config;
+pub mod console;
+pub mod devices;
+pub mod digest;
+pub mod dupes;
+pub mod ed2k;
+pub mod external;
+pub mod fault;
+pub mod filter;
+pub mod framed;
+pub mod heatmap;
+pub mod hmac;
+pub mod hooks;
+pub mod inspect;
+pub mod integrity;
+pub mod logging;
+pub mod merkle;
+pub mod messages;
+pub mod metrics;
+pub mod mime;
+pub mod open;
+pub mod output;
+pub mod parity;
+pub mod pool;
+pub mod positioned;
+pub mod profile;
+pub mod progress;
+pub mod quote;
+pub mod remedy;
+pub mod remote;
+pub mod repo;
+pub mod retry;
+pub mod rt;
+pub mod runs;
+pub mod s3;
+pub mod salvage;
+#[cfg(target_os = "linux")]
+pub mod sandbox;
+pub mod selftest;
+pub mod sfv;
+pub mod shard;
+pub mod sign;
+#[cfg(feature = "snapshots")]
+pub mod snapshot;
+pub mod sort;
+pub mod source;
+pub mod state;
+pub mod stats;
+pub mod sums;
+pub mod tee;
+pub mod throttle;
+pub mod tth;
+pub mod tune;
+pub mod units;
+pub mod walk;
+pub mod watch;
+pub mod watermark;
+pub mod write;
+pub mod zsync;
+
+pub use algo::{Algorithm, Hasher};
+pub use digest::{Digest, Encoding};
+pub use write::{SimpleAsyncWriter, SimpleWrite};
+
+/// Hashes everything `reader` yields, until it's exhausted
+pub async fn hash_reader<R>(algorithm: Algorithm, mut reader: R) -> io::Result<Digest>
+where
+    R: AsyncRead + Unpin,
+{
+    let mut hasher = algorithm.hasher();
+    let mut buf = vec![0u8; tune::DEFAULT_BUFFER_SIZE];
+    loop {
+        let n = reader.read(&mut buf).await?;
+        if n == 0 {
+            break;
+        }
+        algo::Update::update(&mut hasher, &buf[..n]);
+    }
+    algo::Update::finish(&mut hasher)?;
+    Ok(Digest {
+        algorithm,
+        bytes: hasher.finalize(),
+    })
+}
+
+/// Hashes the file at `path` the way the command line does by default
+pub async fn hash_path(algorithm: Algorithm, path: &Path) -> Result<Digest, eyre::Error> {
+    let hashed = hash_file(path, &HashOptions::new(algorithm)).await?;
+    Ok(Digest {
+        algorithm,
+        bytes: hashed.hash,
+    })
+}
+
+/// Settings that apply to every file hashed on a device
+#[derive(Clone)]
+pub struct HashOptions {
+    pub algorithm: algo::Algorithm,
+    pub noatime: bool,
+    pub buffer: tune::Buffer,
+    pub inspect: inspect::InspectOptions,
+    pub trace_sample: Option<logging::Sample>,
+    pub console: Option<Arc<console::Console>>,
+    /// Counts what is read from each file, for `--progress`
+    pub progress: Option<Arc<progress::Progress>>,
+    /// Threads to feed hashers on, instead of the executor's
+    pub pool: Option<Arc<pool::Pool>>,
+    /// With a pool, files smaller than this are read with a single buffer
+    /// from it and hashed in one job
+    pub small_file_size: u64,
+    /// Whether to record owners and modes
+    pub audit: bool,
+    pub checkpoint_every: Option<u64>,
+    /// With `--max-file-size`, the limit and what to do past it
+    pub max_file_size: Option<(u64, filter::Oversize)>,
+    pub changing_files: open::Changing,
+    /// How many times to read each file, keeping the digest most reads agree
+    /// on
+    pub reads: usize,
+    /// Whether to evict each file from the page cache before reading it, so
+    /// every read comes from storage
+    pub drop_cache: bool,
+    /// Whether to evict each file from the page cache once it's hashed, so
+    /// a scan doesn't push out what everything else has cached
+    pub evict_after_read: bool,
+    /// Whether to have each file read ahead into the page cache, and read
+    /// even when a digest for it is cached, to leave it there for others
+    pub populate_cache: bool,
+    /// Latency, short reads and errors to inject into every read
+    pub faults: fault::Faults,
+    /// Picks which reads the faults hit
+    pub seed: u64,
+    /// With `--hmac-key`, what every digest is keyed with
+    pub key: Option<hmac::Key>,
+    /// With `--cache`, digests from earlier runs to reuse
+    pub cache: Option<Arc<cache::Cache>>,
+    /// With `--tree`, the chunk size, making the digest a Merkle root
+    pub tree: Option<u64>,
+    /// How many threads hash each BLAKE3 file, a segment each
+    pub threads: usize,
+    /// Whether to hash past blocks that can't be read, as zeros
+    pub salvage: bool,
+    /// How files are read
+    pub backend: source::Backend,
+    /// With `--batch-small-files`, the directories files are opened from
+    pub dirs: Option<Arc<open::Dirs>>,
+    /// How long a file may take before it's given up on
+    pub timeout: Option<std::time::Duration>,
+    /// Whether to hash the members of tar and zip archives too
+    pub archive: bool,
+    /// With `--limit-rate`, the bandwidth every read shares
+    pub limit_rate: Option<Arc<throttle::Bucket>>,
+    /// More algorithms to hash each file with in the same read
+    pub also: Vec<Algorithm>,
+    /// With a `--resume-state` directory, where files' states are saved as
+    /// they're hashed
+    pub resume_dir: Option<Arc<state::ResumeDir>>,
+    /// With `--retries`, how reads that fail are tried again
+    pub retry: Option<retry::Policy>,
+}
+
+impl HashOptions {
+    /// The command line's defaults, for `algorithm`
+    pub fn new(algorithm: Algorithm) -> Self {
+        Self {
+            algorithm,
+            noatime: false,
+            buffer: tune::Buffer::new(tune::BufferSize::Fixed(tune::DEFAULT_BUFFER_SIZE)),
+            inspect: Default::default(),
+            trace_sample: None,
+            console: None,
+            progress: None,
+            pool: None,
+            small_file_size: pool::DEFAULT_SMALL_FILE_SIZE,
+            audit: false,
+            checkpoint_every: None,
+            max_file_size: None,
+            changing_files: open::Changing::Flag,
+            reads: 1,
+            drop_cache: false,
+            evict_after_read: false,
+            populate_cache: false,
+            faults: Default::default(),
+            seed: 0,
+            key: None,
+            cache: None,
+            tree: None,
+            threads: 1,
+            salvage: false,
+            backend: source::Backend::Read,
+            dirs: None,
+            timeout: None,
+            archive: false,
+            limit_rate: None,
+            also: Vec::new(),
+            resume_dir: None,
+            retry: None,
+        }
+    }
+
+    /// A hasher for the algorithm, keyed if there's a key
+    pub fn hasher(&self) -> Result<Hasher, eyre::Error> {
+        match &self.key {
+            Some(key) => self.algorithm.keyed_hasher(key.bytes()),
+            None => Ok(self.algorithm.hasher()),
+        }
+    }
+}
+
+pub fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+pub fn unhex(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| s.get(i..i + 2).and_then(|b| u8::from_str_radix(b, 16).ok()))
+        .collect()
+}
+
+/// Everything learned about a file by hashing it
+pub struct Hashed {
+    pub hash: Vec<u8>,
+    /// With `--checkpoint-every`, the digest of the bytes before each offset
+    pub checkpoints: Vec<(u64, Vec<u8>)>,
+    /// With `--tree`, the digest of each chunk, the root being `hash`
+    pub leaves: Vec<Vec<u8>>,
+    /// With `--salvage`, the ranges that couldn't be read and were hashed
+    /// as zeros, making `hash` a best effort
+    pub unreadable: Vec<std::ops::Range<u64>>,
+    pub size: u64,
+    /// With `--oversize truncate`, the whole file's size when only its
+    /// first `size` bytes were hashed
+    pub truncated: Option<u64>,
+    /// Whether the file changed while it was read, so the digest may not
+    /// match any version of it
+    pub unstable: bool,
+    /// With `--reads`, how many reads gave `hash` out of how many were made,
+    /// when they didn't all agree
+    pub disagreement: Option<(usize, usize)>,
+    /// How long reading and hashing took
+    pub elapsed: std::time::Duration,
+    pub report: inspect::Report,
+    pub timings: Timings,
+    pub audit: Option<audit::Audit>,
+    /// With `--archive`, what's in the archive, in the order it holds them
+    pub members: Vec<archive::Member>,
+    /// With several `--algo`, the digests by the algorithms past the first
+    pub also: Vec<(Algorithm, Vec<u8>)>,
+}
+
+/// Where the time went for one file
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Timings {
+    /// Opening the file and reading its metadata
+    pub open: std::time::Duration,
+    /// Waiting for reads to complete
+    pub read: std::time::Duration,
+    /// Feeding the hasher, which is all CPU
+    pub hash: std::time::Duration,
+    /// Formatting and writing the result, filled in by the writer
+    pub output: std::time::Duration,
+    /// How many reads were made of the file, for `--stats`
+    pub read_calls: u64,
+}
+
+/// How many more times `--changing-files retry` hashes a file that changed
+const CHANGE_RETRIES: usize = 2;
+
+/// Whether `path` is a file, rather than stdin or a URL
+fn is_local(path: &Path) -> bool {
+    !open::is_stdin(path) && !remote::is_url(path)
+}
+
+pub async fn hash_file(path: &Path, options: &HashOptions) -> Result<Hashed, eyre::Error> {
+    use tracing_futures::Instrument;
+
+    // everything done for the file, from the cache to its last read, so
+    // `--trace` shows how long each one took in all
+    let span = match options.trace_sample {
+        Some(sample) if !sample.includes(path) => tracing::Span::none(),
+        _ => tracing::debug_span!("hash_file", path = %path.display()),
+    };
+    let hashing = hash_file_cached(path, options).instrument(span);
+    cancel::bounded(hashing, options.timeout).await
+}
+
+async fn hash_file_cached(path: &Path, options: &HashOptions) -> Result<Hashed, eyre::Error> {
+    // reading again is the point of these, and what they report isn't
+    // cached
+    let cache = options.cache.as_ref().filter(|_| {
+        is_local(path)
+            && options.reads == 1
+            && !options.drop_cache
+            && !options.populate_cache
+            && options.checkpoint_every.is_none()
+            && options.tree.is_none()
+            && options.max_file_size.is_none()
+            && !options.inspect.detect_type
+            && !options.inspect.entropy
+            && options.inspect.zero_runs.is_none()
+            && !(options.archive && archive::Kind::of(path).is_some())
+            && options.also.is_empty()
+    });
+    let cache = match cache {
+        Some(cache) => cache,
+        None => return hash_file_uncached(path, options).await,
+    };
+    let start = std::time::Instant::now();
+    // taken before reading, so a change made meanwhile shows next time
+    let stamp = match cache::Stamp::of(path).await {
+        Ok(stamp) => stamp,
+        Err(_) => return hash_file_uncached(path, options).await,
+    };
+    if let Some(hash) = cache.get(path, &stamp).await {
+        tracing::debug!(path = %path.display(), "unchanged since cached");
+        let audit = if options.audit {
+            audit::Audit::of(&async_std::fs::metadata(path).await?)
+        } else {
+            None
+        };
+        return Ok(Hashed {
+            hash,
+            checkpoints: Vec::new(),
+            leaves: Vec::new(),
+            unreadable: Vec::new(),
+            size: stamp.size,
+            truncated: None,
+            unstable: false,
+            disagreement: None,
+            elapsed: start.elapsed(),
+            report: Default::default(),
+            timings: Default::default(),
+            audit,
+            members: Vec::new(),
+            also: Vec::new(),
+        });
+    }
+    let hashed = hash_file_uncached(path, options).await?;
+    if !hashed.unstable && hashed.unreadable.is_empty() {
+        cache.insert(path, stamp, &hashed.hash).await;
+    }
+    Ok(hashed)
+}
+
+async fn hash_file_uncached(path: &Path, options: &HashOptions) -> Result<Hashed, eyre::Error> {
+    let start = std::time::Instant::now();
+    if open::is_stdin(path) && options.reads > 1 {
+        return Err(eyre::eyre!(
+            "stdin can only be read once, --reads needs files"
+        ));
+    }
+    let mut reads = Vec::with_capacity(options.reads);
+    while reads.is_empty() || reads.len() < options.reads {
+        if options.drop_cache && is_local(path) {
+            open::drop_cache(path).await?;
+        }
+        reads.push(hash_once(path, options).await?);
+    }
+    if options.evict_after_read && is_local(path) {
+        // the digest is what matters, a page left cached isn't worth failing
+        // over
+        if let Err(e) = open::drop_cache(path).await {
+            tracing::debug!(path = %path.display(), error = %e, "can't evict from the page cache");
+        }
+    }
+
+    // a single read can't tell flaky storage or RAM from the real contents
+    let agreeing = |hash: &[u8]| reads.iter().filter(|r| r.hash == hash).count();
+    let (best, count) = reads
+        .iter()
+        .enumerate()
+        .map(|(i, r)| (i, agreeing(&r.hash)))
+        // the earliest read wins a tie
+        .max_by_key(|&(i, count)| (count, std::cmp::Reverse(i)))
+        .unwrap();
+    let total = reads.len();
+    let unstable = reads.iter().any(|r| r.unstable);
+    let mut hashed = reads.swap_remove(best);
+    hashed.unstable = unstable;
+    if count < total {
+        hashed.disagreement = Some((count, total));
+    }
+    hashed.elapsed = start.elapsed();
+    Ok(hashed)
+}
+
+/// Reads and hashes `path` once, or a few more times with
+/// `--changing-files retry`
+async fn hash_once(path: &Path, options: &HashOptions) -> Result<Hashed, eyre::Error> {
+    let start = std::time::Instant::now();
+    let kind = archive::Kind::of(path).filter(|_| options.archive);
+    let mut retries = 0;
+    loop {
+        let mut members = Vec::new();
+        let mut also = Vec::new();
+        let resume = options.resume_dir.as_ref().filter(|_| is_local(path));
+        let (hash, checkpoints, leaves, fed) = match (
+            options.tree,
+            options.checkpoint_every,
+            kind,
+            resume,
+        ) {
+            (Some(chunk_size), _, _, _) => {
+                let tree = merkle::Tree::new(options.hasher()?, chunk_size).ok_or_else(|| {
+                    eyre::eyre!("--tree needs an algorithm whose state can be copied")
+                })?;
+                let (tree, fed) = feed_file(path, options, tree).await?;
+                let (root, leaves) = tree.finalize();
+                (root, Vec::new(), leaves, fed)
+            }
+            (None, Some(_), _, _) if !is_local(path) => {
+                return Err(eyre::eyre!(
+                    "--checkpoint-every needs to know the input's size, which stdin and URLs don't tell"
+                ))
+            }
+            (None, Some(every), _, _) => {
+                let len = async_std::fs::metadata(path).await?.len();
+                let offsets = (every..len).step_by(every as usize);
+                let hasher = algo::Checkpoints::new(options.hasher()?, offsets);
+                let (hasher, fed) = feed_file(path, options, hasher).await?;
+                let (hash, checkpoints) = hasher.finalize();
+                (hash, checkpoints, Vec::new(), fed)
+            }
+            (None, None, Some(kind), _) => {
+                let hasher = archive::Members::new(kind, options.hasher()?).ok_or_else(|| {
+                    eyre::eyre!("--archive needs an algorithm whose state can be copied")
+                })?;
+                let (hasher, fed) = feed_file(path, options, hasher).await?;
+                let (hash, found) = hasher.finalize();
+                members = found;
+                (hash, Vec::new(), Vec::new(), fed)
+            }
+            (None, None, None, Some(resume)) => {
+                let stamp = cache::Stamp::of(path).await?;
+                let file = resume.file(path);
+                let (hasher, offset) = match resume
+                    .load(&file, path, &stamp, options.algorithm)
+                    .await
+                {
+                    Some(saved) => saved,
+                    None => (options.hasher()?, 0),
+                };
+                let hasher = state::Saving::new(
+                    hasher,
+                    options.algorithm,
+                    offset,
+                    resume,
+                    file.clone(),
+                    path,
+                    stamp,
+                )?;
+                let (hasher, fed) = feed_file_from(path, options, hasher, offset).await?;
+                resume.done(&file).await;
+                (hasher.finalize(), Vec::new(), Vec::new(), fed)
+            }
+            (None, None, None, None) if !options.also.is_empty() => {
+                let algorithms: Vec<_> = std::iter::once(options.algorithm)
+                    .chain(options.also.iter().copied())
+                    .collect();
+                let key = options.key.as_ref().map(|key| key.bytes());
+                let hasher = algo::Several::new(&algorithms, key);
+                let (hasher, fed) = feed_file(path, options, hasher).await?;
+                let mut digests = hasher.finalize().into_iter();
+                let (_, hash) = digests.next().expect("the first algorithm is always there");
+                also = digests.collect();
+                (hash, Vec::new(), Vec::new(), fed)
+            }
+            (None, None, None, None) if options.threads > 1 => {
+                let hasher = match options.hasher()? {
+                    Hasher::Blake3(hasher) => blake3::Parallel::new(hasher, options.threads),
+                    _ => {
+                        return Err(eyre::eyre!(
+                            "only BLAKE3 can be hashed on several threads at once"
+                        ))
+                    }
+                };
+                if reads_in_parallel(path, options) {
+                    let (hash, fed) = hash_in_parallel(path, options, hasher).await?;
+                    (hash, Vec::new(), Vec::new(), fed)
+                } else {
+                    let (hasher, fed) = feed_file(path, options, hasher).await?;
+                    (hasher.finalize(), Vec::new(), Vec::new(), fed)
+                }
+            }
+            (None, None, None, None) => {
+                let hasher = options.hasher()?;
+                let (hasher, fed) = feed_file(path, options, hasher).await?;
+                (hasher.finalize(), Vec::new(), Vec::new(), fed)
+            }
+        };
+        if fed.changed
+            && options.changing_files == open::Changing::Retry
+            && retries < CHANGE_RETRIES
+        {
+            retries += 1;
+            tracing::debug!(path = %path.display(), retries, "changed while hashing, retrying");
+            continue;
+        }
+        return Ok(Hashed {
+            hash,
+            checkpoints,
+            leaves,
+            unreadable: fed.unreadable,
+            size: fed.size,
+            truncated: fed.truncated,
+            unstable: fed.changed,
+            disagreement: None,
+            elapsed: start.elapsed(),
+            report: fed.report,
+            timings: fed.timings,
+            audit: fed.audit,
+            members,
+            also,
+        });
+    }
+}
+
+/// Whether `--threads` can have each thread read its own segment of `path`,
+/// which it can for files when nothing asked of the reads needs them to
+/// come in order
+fn reads_in_parallel(path: &Path, options: &HashOptions) -> bool {
+    is_local(path)
+        && options.backend == source::Backend::Read
+        && options.progress.is_none()
+        && options.console.is_none()
+        && options.limit_rate.is_none()
+        && options.max_file_size.is_none()
+        && options.retry.is_none()
+        && options.faults.is_none()
+        && !options.salvage
+        && !options.inspect.detect_type
+        && !options.inspect.entropy
+        && options.inspect.zero_runs.is_none()
+}
+
+/// Hashes `path` with `hasher`'s threads each reading a segment of it, with
+/// positioned reads on the one handle
+async fn hash_in_parallel(
+    path: &Path,
+    options: &HashOptions,
+    hasher: blake3::Parallel,
+) -> Result<(Vec<u8>, Fed), eyre::Error> {
+    let mut timings = Timings::default();
+    let start = std::time::Instant::now();
+    let file = source::open_file(path, options).await?;
+    let metadata = file.metadata().await?;
+    #[cfg(unix)]
+    let file = std::fs::File::from(std::os::unix::io::OwnedFd::from(file));
+    #[cfg(windows)]
+    let file = std::fs::File::from(std::os::windows::io::OwnedHandle::from(file));
+    let reader = positioned::PositionedReader::from_file(file)?;
+    let size = reader.remaining();
+    timings.open = start.elapsed();
+
+    let start = std::time::Instant::now();
+    let hash = rt::spawn_blocking(move || hasher.read_from(&reader)).await?;
+    // reading and hashing overlap, so it all counts as waiting for reads
+    timings.read = start.elapsed();
+    // one per segment, short reads aside
+    timings.read_calls = size.div_ceil(blake3::SEGMENT_LEN as u64);
+
+    let after = async_std::fs::metadata(path).await?;
+    let fed = Fed {
+        size,
+        truncated: None,
+        changed: after.len() != metadata.len() || after.modified().ok() != metadata.modified().ok(),
+        unreadable: Vec::new(),
+        report: Default::default(),
+        timings,
+        audit: Some(&metadata)
+            .filter(|_| options.audit)
+            .and_then(audit::Audit::of),
+    };
+    Ok((hash, fed))
+}
+
+/// What [`feed_file`] found out along the way
+pub struct Fed {
+    /// How many bytes were read
+    pub size: u64,
+    /// The file's size, when it was over `--max-file-size` and only its
+    /// first bytes were read
+    pub truncated: Option<u64>,
+    /// Whether its size or modification time changed while it was read
+    pub changed: bool,
+    /// With `--salvage`, what couldn't be read
+    pub unreadable: Vec<std::ops::Range<u64>>,
+    pub report: inspect::Report,
+    pub timings: Timings,
+    /// With `--audit`, the file's owner and mode
+    pub audit: Option<audit::Audit>,
+}
+
+/// Reads all of `path` into `hasher`, handing it back once fed
+pub async fn feed_file<H: algo::Update + 'static>(
+    path: &Path,
+    options: &HashOptions,
+    hasher: H,
+) -> Result<(H, Fed), eyre::Error> {
+    feed_file_from(path, options, hasher, 0).await
+}
+
+/// Reads `path` into `hasher` from offset `from` on, for a hasher that has
+/// consumed the bytes before already. The size fed counts those too.
+pub async fn feed_file_from<H: algo::Update + 'static>(
+    path: &Path,
+    options: &HashOptions,
+    hasher: H,
+    from: u64,
+) -> Result<(H, Fed), eyre::Error> {
+    use tracing_futures::Instrument;
+
+    let mut position = Position::new(path);
+    position.sampled = options.trace_sample.is_none_or(|s| s.includes(path));
+    position.offset = from;
+    let span = position.span("feed_file");
+    let res = read_into(path, options, hasher, from, &mut position)
+        .instrument(span)
+        .await;
+    if let Err(e) = &res {
+        // failures are always worth a trace, sampled or not
+        position.sampled = true;
+        position
+            .span("feed_file")
+            .in_scope(|| tracing::debug!(error = %e, "read failed"));
+    }
+    res
+}
+
+/// The body of [`feed_file`], keeping `position` up to date so failures can
+/// say where they happened
+async fn read_into<H: algo::Update + 'static>(
+    path: &Path,
+    options: &HashOptions,
+    hasher: H,
+    from: u64,
+    position: &mut Position,
+) -> Result<(H, Fed), eyre::Error> {
+    let tracked = options.console.as_ref().map(|console| console.track(path));
+    let mut timings = Timings::default();
+    let start = std::time::Instant::now();
+    let resume = salvage::Resume::default();
+    if from > 0 {
+        resume.at(from);
+    }
+    let source::ByteSource {
+        reader: file,
+        metadata,
+        len,
+    } = source::ByteSource::open(path, options, &resume).await?;
+    let read_calls = std::sync::Arc::new(std::sync::atomic::AtomicU64::new(0));
+    let file = stats::ReadCounter::new(file, read_calls.clone());
+    // only files can be read past a bad block
+    let salvaging = options.salvage && metadata.is_some();
+    let len = metadata.as_ref().map(|m| m.len()).or(len);
+    position.size = len.unwrap_or(0);
+    timings.open = start.elapsed();
+    if let Some(tracked) = &tracked {
+        tracked.opened(
+            position.size,
+            metadata.as_ref().and_then(devices::device_id),
+        );
+    }
+    let limit = match options.max_file_size {
+        Some((max, policy)) if position.size > max => match policy {
+            filter::Oversize::Skip => {
+                return Err(filter::TooLarge {
+                    size: position.size,
+                    max,
+                }
+                .into())
+            }
+            filter::Oversize::Truncate => Some(max),
+        },
+        _ => None,
+    };
+    let file: Box<dyn AsyncRead + Send + Unpin> = match &options.progress {
+        Some(progress) => {
+            let tracked = progress.track(path, len);
+            Box::new(progress::CountingReader::new(file, tracked))
+        }
+        None => Box::new(file),
+    };
+    // inside the tracing, so each read's span shows what it waited
+    let file: Box<dyn AsyncRead + Send + Unpin> = match &options.limit_rate {
+        Some(bucket) => Box::new(throttle::ThrottledReader::new(file, bucket.clone())),
+        None => file,
+    };
+    let file = TracingReader::new(file, position.clone());
+    let file = fault::FaultInjectingReader::new(file, options.faults, options.seed, path);
+    let file = SimpleAsyncReader::new(file, position.clone());
+    let mut file = inspect::InspectReader::new(file, inspect::Inspection::new(options.inspect));
+
+    // a small file is read in one go, into buffers the pool keeps between
+    // files, rather than allocating a full-size buffer for it twice
+    let small = options.pool.as_ref().filter(|_| {
+        metadata.is_some() && limit.is_none() && position.size < options.small_file_size
+    });
+    let (mut buf, mut spare) = match small {
+        Some(pool) => (pool.buffer(), pool.buffer()),
+        None => Default::default(),
+    };
+    let mut total = from;
+    let mut feeding = Feeding::Idle(hasher);
+    let mut unreadable = Vec::new();
+    // after a failed read, reads up to here go a block at a time
+    let mut careful_until = 0;
+    loop {
+        let mut size = match small {
+            // one more byte to tell whether it grew
+            Some(_) => position.size as usize + 1,
+            None => options.buffer.size(),
+        };
+        let careful = total < careful_until;
+        if careful {
+            size = size.min(salvage::BLOCK as usize);
+        }
+        if let Some(limit) = limit {
+            if total >= limit {
+                break;
+            }
+            // never read past the limit, so nothing beyond it is hashed
+            if limit - total < size as u64 {
+                size = (limit - total) as usize;
+            }
+        }
+        buf.resize(size, 0);
+
+        if let Some(tracked) = &tracked {
+            tracked.reading(total);
+        }
+        let start = std::time::Instant::now();
+        let n = match file.read(&mut buf[..]).await {
+            Ok(n) => n,
+            Err(e) if salvaging => {
+                if !careful && size as u64 > salvage::BLOCK {
+                    tracing::debug!(offset = total, error = %e, "read failed, narrowing it down");
+                    careful_until = total + size as u64;
+                    continue;
+                }
+                let len = salvage::BLOCK.min(position.size.saturating_sub(total));
+                // everything there was at open was read, or given up on
+                if len == 0 {
+                    tracing::debug!(offset = total, error = %e, "read failed past the end");
+                    break;
+                }
+                tracing::warn!(path = %path.display(), offset = total, len, error = %e, "unreadable, hashing zeros in its place");
+                salvage::record(&mut unreadable, total..total + len);
+                resume.at(total + len);
+                buf[..len as usize].fill(salvage::PLACEHOLDER);
+                len as usize
+            }
+            Err(e) => return Err(e.into()),
+        };
+        let waited = start.elapsed();
+        if let Some(tracked) = &tracked {
+            tracked.hashing();
+        }
+        // reads narrowed down to a block say nothing about the best size
+        if small.is_none() && !careful {
+            options.buffer.record(size, n, waited);
+        }
+        timings.read += waited;
+        if n == 0 {
+            break;
+        }
+        let (mut hasher, chunk) = feeding.settle(&mut timings).await;
+        if let Some(chunk) = chunk {
+            spare = chunk;
+        }
+        feeding = match &options.pool {
+            None => {
+                let start = std::time::Instant::now();
+                hasher.update(&buf[..n]);
+                timings.hash += start.elapsed();
+                Feeding::Idle(hasher)
+            }
+            // the next chunk is read while this one is hashed
+            Some(pool) => {
+                let mut chunk = std::mem::replace(&mut buf, std::mem::take(&mut spare));
+                chunk.truncate(n);
+                Feeding::Busy(
+                    pool.spawn(move || {
+                        let start = std::time::Instant::now();
+                        hasher.update(&chunk);
+                        (hasher, chunk, start.elapsed())
+                    })
+                    .await,
+                )
+            }
+        };
+        total += n as u64;
+        position.offset = total;
+    }
+    let (mut hasher, chunk) = feeding.settle(&mut timings).await;
+    if let Some(pool) = small {
+        pool.recycle(buf);
+        pool.recycle(chunk.unwrap_or(spare));
+    }
+    hasher.finish()?;
+
+    // a file that grew past its size at open, or whose size or mtime moved,
+    // was written to under our feet
+    let changed = match &metadata {
+        Some(metadata) => {
+            let after = async_std::fs::metadata(path).await?;
+            (limit.is_none() && total != metadata.len())
+                || after.len() != metadata.len()
+                || after.modified().ok() != metadata.modified().ok()
+        }
+        None => false,
+    };
+    timings.read_calls = read_calls.load(std::sync::atomic::Ordering::Relaxed);
+
+    let fed = Fed {
+        size: total,
+        truncated: limit.map(|_| position.size),
+        changed,
+        unreadable,
+        report: file.into_inspector().report(),
+        timings,
+        audit: metadata
+            .as_ref()
+            .filter(|_| options.audit)
+            .and_then(audit::Audit::of),
+    };
+    Ok((hasher, fed))
+}
+
+/// Where a hasher is between reads
+enum Feeding<H> {
+    Idle(H),
+    /// On the pool, with the chunk it's hashing and how long that took
+    Busy(pool::Task<(H, Vec<u8>, std::time::Duration)>),
+}
+
+impl<H> Feeding<H> {
+    /// Waits for the hasher to be done, returning it along with the buffer
+    /// it was hashing, if any, for the next read
+    async fn settle(self, timings: &mut Timings) -> (H, Option<Vec<u8>>) {
+        match self {
+            Self::Idle(hasher) => (hasher, None),
+            Self::Busy(task) => {
+                let (hasher, chunk, took) = task.join().await;
+                timings.hash += took;
+                (hasher, Some(chunk))
+            }
+        }
+    }
+}
+
+use futures::{io::AsyncRead, Future};
+use std::{
+    io,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+/// Which file a reader is reading, and where it's at, for span fields
+#[derive(Clone)]
+pub struct Position {
+    path: Arc<str>,
+    size: u64,
+    offset: u64,
+    /// Whether this file's reads get spans at all, see `--trace-sample`
+    sampled: bool,
+}
+
+impl Position {
+    /// The start of `path`, sampled
+    pub fn new(path: &Path) -> Self {
+        Self {
+            path: path.display().to_string().into(),
+            size: 0,
+            offset: 0,
+            sampled: true,
+        }
+    }
+
+    pub fn span(&self, name: &'static str) -> tracing::Span {
+        if !self.sampled {
+            return tracing::Span::none();
+        }
+        tracing::debug_span!(
+            "read",
+            op = name,
+            path = %self.path,
+            size = self.size,
+            offset = self.offset
+        )
+    }
+
+    fn advance(&mut self, result: &io::Result<usize>) {
+        if let Ok(n) = result {
+            self.offset += *n as u64;
+        }
+    }
+}
+
+pub struct TracingReader<R>
+where
+    R: AsyncRead,
+{
+    inner: R,
+    position: Position,
+}
+
+impl<R> TracingReader<R>
+where
+    R: AsyncRead,
+{
+    pub fn new(inner: R, position: Position) -> Self {
+        Self { inner, position }
+    }
+}
+
+use async_trait::async_trait;
+
+#[async_trait]
+pub trait SimpleRead {
+    async fn simple_read(&mut self, buf: &mut [u8]) -> io::Result<usize>;
+
+    /// Reads without a future, for readers that can: [`SimpleAsyncReader`]
+    /// polls this first, and only boxes a [`SimpleRead::simple_read`]
+    /// future when it's `None`. Readers that return `Some` must always do.
+    fn poll_simple_read(
+        &mut self,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Option<Poll<io::Result<usize>>> {
+        let _ = (cx, buf);
+        None
+    }
+}
+
+#[async_trait]
+impl<R> SimpleRead for TracingReader<R>
+where
+    R: AsyncRead + Send + Unpin,
+{
+    async fn simple_read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        use tracing_futures::Instrument;
+
+        let span = self.position.span("simple_read");
+        let res = async {
+            tracing::debug!("doing read...");
+            let res = self.inner.read(buf).await;
+            tracing::debug!("doing read...done!");
+            res
+        }
+        .instrument(span)
+        .await;
+        self.position.advance(&res);
+        res
+    }
+
+    fn poll_simple_read(
+        &mut self,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Option<Poll<io::Result<usize>>> {
+        let span = self.position.span("simple_read");
+        let _enter = span.enter();
+        let res = Pin::new(&mut self.inner).poll_read(cx, buf);
+        if let Poll::Ready(res) = &res {
+            self.position.advance(res);
+        }
+        Some(res)
+    }
+}
+
+/// Turns a [`SimpleRead`] into an [`AsyncRead`], and an [`AsyncBufRead`]
+/// that hands out what was read without copying it
+///
+/// [`AsyncBufRead`]: futures::io::AsyncBufRead
+pub struct SimpleAsyncReader<R>
+where
+    R: SimpleRead,
+{
+    state: State<R>,
+    position: Position,
+    /// Read but not yet returned: `buf[pos..filled]`. `buf` itself is all
+    /// initialized, and only ever grows.
+    pos: usize,
+    filled: usize,
+    /// How much [`AsyncBufRead::poll_fill_buf`] asks for at once
+    ///
+    /// [`AsyncBufRead::poll_fill_buf`]: futures::io::AsyncBufRead::poll_fill_buf
+    capacity: usize,
+}
+
+impl<R> SimpleAsyncReader<R>
+where
+    R: SimpleRead,
+{
+    pub fn new(inner: R, position: Position) -> Self {
+        Self::with_capacity(inner, position, tune::DEFAULT_BUFFER_SIZE)
+    }
+
+    pub fn with_capacity(inner: R, position: Position, capacity: usize) -> Self {
+        Self {
+            state: State::Idle(inner, Vec::new()),
+            position,
+            pos: 0,
+            filled: 0,
+            capacity: capacity.max(1),
+        }
+    }
+}
+
+// the inner reader moves in and out of futures between reads, it's never
+// pinned in place
+impl<R: SimpleRead> Unpin for SimpleAsyncReader<R> {}
+
+type BoxFut<T> = Pin<Box<dyn Future<Output = T> + Send>>;
+
+enum State<R> {
+    Idle(R, Vec<u8>),
+    Pending(BoxFut<(R, Vec<u8>, io::Result<usize>)>),
+    Transitional,
+}
+
+impl<R> SimpleAsyncReader<R>
+where
+    R: SimpleRead + Send + 'static,
+{
+    /// Makes sure something is buffered, reading up to `want` bytes if
+    /// nothing is. Nothing buffered once this is ready means end of file.
+    fn poll_fill(&mut self, cx: &mut Context<'_>, want: usize) -> Poll<io::Result<()>> {
+        let span = self.position.span("poll_read");
+        let _enter = span.enter();
+        if self.pos < self.filled {
+            return Poll::Ready(Ok(()));
+        }
+
+        let mut fut = match std::mem::replace(&mut self.state, State::Transitional) {
+            State::Idle(mut inner, mut buf) => {
+                // zeroed once, when it grows, not before every read
+                if buf.len() < want {
+                    buf.resize(want, 0);
+                }
+                // nothing to allocate for readers that can be polled
+                if let Some(res) = inner.poll_simple_read(cx, &mut buf[..want]) {
+                    self.state = State::Idle(inner, buf);
+                    return match res {
+                        Poll::Ready(result) => Poll::Ready(self.filled_by(result)),
+                        Poll::Pending => Poll::Pending,
+                    };
+                }
+                tracing::debug!("getting new future...");
+                Box::pin(async move {
+                    let res = inner.simple_read(&mut buf[..want]).await;
+                    (inner, buf, res)
+                })
+            }
+            State::Pending(fut) => {
+                tracing::debug!("polling existing future...");
+                fut
+            }
+            State::Transitional => unreachable!(),
+        };
+
+        match fut.as_mut().poll(cx) {
+            Poll::Ready((inner, buf, result)) => {
+                tracing::debug!("future was ready!");
+                self.state = State::Idle(inner, buf);
+                Poll::Ready(self.filled_by(result))
+            }
+            Poll::Pending => {
+                tracing::debug!("future was pending!");
+                self.state = State::Pending(fut);
+                Poll::Pending
+            }
+        }
+    }
+
+    /// Takes the result of a read into the idle buffer
+    fn filled_by(&mut self, result: io::Result<usize>) -> io::Result<()> {
+        let len = match &self.state {
+            State::Idle(_, buf) => buf.len(),
+            _ => 0,
+        };
+        self.position.advance(&result);
+        self.pos = 0;
+        self.filled = match &result {
+            // a reader claiming more than it was given can't be trusted
+            // with the rest either
+            Ok(n) => (*n).min(len),
+            Err(_) => 0,
+        };
+        result.map(|_| ())
+    }
+
+    fn buffered(&self) -> &[u8] {
+        match &self.state {
+            State::Idle(_, buf) => &buf[self.pos..self.filled],
+            // only idle readers have anything buffered
+            _ => &[],
+        }
+    }
+}
+
+impl<R> AsyncRead for SimpleAsyncReader<R>
+where
+    R: SimpleRead + Send + 'static,
+{
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        if buf.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
+        // each read asks the inner reader for as much as it was asked for,
+        // so read sizes still mean what the caller meant by them
+        futures::ready!(this.poll_fill(cx, buf.len()))?;
+        let buffered = this.buffered();
+        let n = buffered.len().min(buf.len());
+        buf[..n].copy_from_slice(&buffered[..n]);
+        this.pos += n;
+        Poll::Ready(Ok(n))
+    }
+}
+
+impl<R> futures::io::AsyncBufRead for SimpleAsyncReader<R>
+where
+    R: SimpleRead + Send + 'static,
+{
+    fn poll_fill_buf(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<&[u8]>> {
+        let this = self.get_mut();
+        futures::ready!(this.poll_fill(cx, this.capacity))?;
+        Poll::Ready(Ok(this.buffered()))
+    }
+
+    fn consume(self: Pin<&mut Self>, amt: usize) {
+        let this = self.get_mut();
+        this.pos = (this.pos + amt).min(this.filled);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::io::AsyncBufReadExt;
+    use std::collections::VecDeque;
+
+    /// Returns scripted chunks, then end of file, recording what each read
+    /// asked for
+    struct Script {
+        steps: VecDeque<io::Result<Vec<u8>>>,
+        asked: Vec<usize>,
+    }
+
+    impl Script {
+        fn new(steps: Vec<io::Result<Vec<u8>>>) -> Self {
+            Self {
+                steps: steps.into(),
+                asked: Vec::new(),
+            }
+        }
+    }
+
+    #[async_trait]
+    impl SimpleRead for Script {
+        async fn simple_read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.asked.push(buf.len());
+            match self.steps.pop_front() {
+                None => Ok(0),
+                Some(Err(e)) => Err(e),
+                Some(Ok(mut chunk)) => {
+                    let n = chunk.len().min(buf.len());
+                    buf[..n].copy_from_slice(&chunk[..n]);
+                    if n < chunk.len() {
+                        self.steps.push_front(Ok(chunk.split_off(n)));
+                    }
+                    Ok(n)
+                }
+            }
+        }
+    }
+
+    fn reader(steps: Vec<io::Result<Vec<u8>>>) -> SimpleAsyncReader<Script> {
+        SimpleAsyncReader::with_capacity(Script::new(steps), Position::new(Path::new("test")), 4)
+    }
+
+    fn inner(reader: &SimpleAsyncReader<Script>) -> &Script {
+        match &reader.state {
+            State::Idle(inner, _) => inner,
+            _ => panic!("reader isn't idle"),
+        }
+    }
+
+    #[test]
+    fn short_reads_come_through_as_they_are() {
+        async_std::task::block_on(async {
+            let mut reader = reader(vec![Ok(b"ab".to_vec()), Ok(b"cdefg".to_vec())]);
+            let mut buf = [0u8; 8];
+            assert_eq!(reader.read(&mut buf).await.unwrap(), 2);
+            assert_eq!(&buf[..2], b"ab");
+            assert_eq!(reader.read(&mut buf).await.unwrap(), 5);
+            assert_eq!(&buf[..5], b"cdefg");
+            assert_eq!(reader.read(&mut buf).await.unwrap(), 0);
+            assert_eq!(inner(&reader).asked, [8, 8, 8]);
+        });
+    }
+
+    #[test]
+    fn reads_ask_for_what_the_caller_asked_for() {
+        async_std::task::block_on(async {
+            let mut reader = reader(vec![Ok(b"abcdefgh".to_vec())]);
+            let mut out = Vec::new();
+            for size in [3, 1, 16] {
+                let mut buf = vec![0u8; size];
+                let n = reader.read(&mut buf).await.unwrap();
+                out.extend_from_slice(&buf[..n]);
+            }
+            assert_eq!(out, b"abcdefgh");
+            assert_eq!(inner(&reader).asked, [3, 1, 16]);
+        });
+    }
+
+    #[test]
+    fn fill_buf_hands_out_what_was_read() {
+        async_std::task::block_on(async {
+            let mut reader = reader(vec![Ok(b"abcdef".to_vec())]);
+            assert_eq!(reader.fill_buf().await.unwrap(), b"abcd");
+            reader.consume_unpin(1);
+            // the rest is served before anything else is read
+            let mut buf = [0u8; 8];
+            assert_eq!(reader.read(&mut buf).await.unwrap(), 3);
+            assert_eq!(&buf[..3], b"bcd");
+            assert_eq!(reader.fill_buf().await.unwrap(), b"ef");
+            reader.consume_unpin(2);
+            assert_eq!(reader.fill_buf().await.unwrap(), b"");
+        });
+    }
+
+    #[test]
+    fn errors_lose_nothing_and_leave_the_reader_usable() {
+        async_std::task::block_on(async {
+            let mut reader = reader(vec![
+                Ok(b"ab".to_vec()),
+                Err(io::Error::other("flaky")),
+                Ok(b"cd".to_vec()),
+            ]);
+            let mut buf = [0u8; 4];
+            assert_eq!(reader.read(&mut buf).await.unwrap(), 2);
+            let e = reader.read(&mut buf).await.unwrap_err();
+            assert_eq!(e.to_string(), "flaky");
+            assert_eq!(reader.read(&mut buf).await.unwrap(), 2);
+            assert_eq!(&buf[..2], b"cd");
+            assert_eq!(reader.read(&mut buf).await.unwrap(), 0);
+        });
+    }
+
+    #[test]
+    fn empty_reads_read_nothing() {
+        async_std::task::block_on(async {
+            let mut reader = reader(vec![Ok(b"ab".to_vec())]);
+            assert_eq!(reader.read(&mut []).await.unwrap(), 0);
+            assert!(inner(&reader).asked.is_empty());
+            let mut out = Vec::new();
+            reader.read_to_end(&mut out).await.unwrap();
+            assert_eq!(out, b"ab");
+        });
+    }
+
+    /// Makes every other poll wait, like a reader waiting on the disk
+    struct Hesitant<R> {
+        inner: R,
+        waited: bool,
+    }
+
+    impl<R: AsyncRead + Unpin> AsyncRead for Hesitant<R> {
+        fn poll_read(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &mut [u8],
+        ) -> Poll<io::Result<usize>> {
+            self.waited = !self.waited;
+            if self.waited {
+                cx.waker().wake_by_ref();
+                return Poll::Pending;
+            }
+            Pin::new(&mut self.inner).poll_read(cx, buf)
+        }
+    }
+
+    #[test]
+    fn polled_readers_wait_without_a_future() {
+        async_std::task::block_on(async {
+            let data: Vec<u8> = (0..10_000u32).map(|i| (i * 7) as u8).collect();
+            let path = Path::new("test");
+            let inner = Hesitant {
+                inner: futures::io::Cursor::new(data.clone()),
+                waited: false,
+            };
+            let mut reader = SimpleAsyncReader::new(
+                TracingReader::new(inner, Position::new(path)),
+                Position::new(path),
+            );
+            let mut out = Vec::new();
+            let mut buf = [0u8; 999];
+            loop {
+                let n = reader.read(&mut buf).await.unwrap();
+                // never boxed, so never seen pending
+                assert!(matches!(reader.state, State::Idle(..)));
+                if n == 0 {
+                    break;
+                }
+                out.extend_from_slice(&buf[..n]);
+            }
+            assert_eq!(out, data);
+        });
+    }
+
+    #[test]
+    fn injected_short_reads_keep_every_byte() {
+        async_std::task::block_on(async {
+            let data: Vec<u8> = (0..10_000u32).map(|i| i as u8).collect();
+            let faults = fault::Faults {
+                short: 0.5,
+                ..Default::default()
+            };
+            let script = Script::new(vec![Ok(data.clone())]);
+            let path = Path::new("test");
+            let mut reader = SimpleAsyncReader::new(
+                fault::FaultInjectingReader::new(script, faults, 1, path),
+                Position::new(path),
+            );
+            let mut out = Vec::new();
+            reader.read_to_end(&mut out).await.unwrap();
+            assert_eq!(out, data);
+        });
+    }
+}
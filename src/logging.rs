@@ -0,0 +1,277 @@
+//! Sending diagnostics to the system log, where failures get noticed, and
+//! to stderr with `--trace`, to see where the time goes.
+
+use color_eyre::eyre::{self, eyre};
+use std::{ffi::CString, fmt::Write as _, str::FromStr};
+use tracing::{
+    field::{Field, Visit},
+    span::{Attributes, Id, Record},
+    Event, Level, Subscriber,
+};
+use tracing_subscriber::{
+    filter::LevelFilter, fmt::format::FmtSpan, layer::Context, prelude::*, registry::LookupSpan,
+    Layer, Registry,
+};
+
+/// Where tracing events go
+#[derive(Debug, Clone, Copy)]
+pub enum LogTarget {
+    /// The systemd journal, with each field as a journal field
+    Journald,
+    /// syslog(3), with fields appended as `key=value`
+    Syslog,
+}
+
+impl FromStr for LogTarget {
+    type Err = eyre::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "journald" => Ok(Self::Journald),
+            "syslog" => Ok(Self::Syslog),
+            _ => Err(eyre!("expected `journald` or `syslog`, got {:?}", s)),
+        }
+    }
+}
+
+/// How spans and events are printed to stderr
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Trace {
+    #[default]
+    Off,
+    /// Multi-line events, and each span's time when it closes
+    Pretty,
+    /// Spans indented under the ones they're in
+    Tree,
+    /// One JSON object per event, and per span when it closes
+    Json,
+}
+
+impl FromStr for Trace {
+    type Err = eyre::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "off" => Ok(Self::Off),
+            "pretty" => Ok(Self::Pretty),
+            "tree" => Ok(Self::Tree),
+            "json" => Ok(Self::Json),
+            _ => Err(eyre!(
+                "expected `off`, `pretty`, `tree` or `json`, got {:?}",
+                s
+            )),
+        }
+    }
+}
+
+/// A fraction of files to trace, written `taken/of`
+#[derive(Debug, Clone, Copy)]
+pub struct Sample {
+    taken: u32,
+    of: u32,
+    /// Picks a different sample of the same size, see `--seed`
+    seed: u64,
+}
+
+impl FromStr for Sample {
+    type Err = eyre::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let bad = || eyre!("expected a fraction like `1/1000`, got {:?}", s);
+        let (taken, of) = s.split_once('/').ok_or_else(bad)?;
+        let (taken, of) = (
+            taken.trim().parse().map_err(|_| bad())?,
+            of.trim().parse().map_err(|_| bad())?,
+        );
+        if of == 0 || taken > of {
+            return Err(bad());
+        }
+        Ok(Self { taken, of, seed: 0 })
+    }
+}
+
+impl Sample {
+    pub fn with_seed(self, seed: u64) -> Self {
+        Self { seed, ..self }
+    }
+
+    /// Whether `path` is in the sample. Paths are hashed rather than counted,
+    /// so the choice doesn't depend on the order files are hashed in.
+    pub fn includes(&self, path: &std::path::Path) -> bool {
+        let mut hasher = crc32fast::Hasher::new();
+        hasher.update(&self.seed.to_le_bytes());
+        hasher.update(path.to_string_lossy().as_bytes());
+        hasher.finalize() % self.of < self.taken
+    }
+}
+
+/// Installs the global subscriber for `target` and `trace`, unless
+/// neither wants one. `RUST_LOG` may name the lowest level that gets
+/// logged: `info` by default, or `debug` when tracing, which is the level
+/// of the spans around each file and each read.
+pub fn init(target: Option<LogTarget>, trace: Trace) -> Result<(), eyre::Error> {
+    if target.is_none() && trace == Trace::Off {
+        return Ok(());
+    }
+    let filter = match std::env::var("RUST_LOG") {
+        Ok(level) if !level.is_empty() => level
+            .parse::<LevelFilter>()
+            .map_err(|_| eyre!("RUST_LOG should be a level like `info`, got {:?}", level))?,
+        _ if trace != Trace::Off => LevelFilter::DEBUG,
+        _ => LevelFilter::INFO,
+    };
+    let journald = match target {
+        Some(LogTarget::Journald) => {
+            Some(tracing_journald::layer().map_err(|e| eyre!("can't connect to journald: {}", e))?)
+        }
+        _ => None,
+    };
+    let syslog = matches!(target, Some(LogTarget::Syslog)).then(SyslogLayer::new);
+
+    // stdout is for results, and a span's time is only known once it's
+    // closed
+    let colors = unsafe { libc::isatty(libc::STDERR_FILENO) } == 1;
+    let pretty = (trace == Trace::Pretty).then(|| {
+        tracing_subscriber::fmt::layer()
+            .pretty()
+            .with_ansi(colors)
+            .with_writer(std::io::stderr)
+            .with_span_events(FmtSpan::CLOSE)
+    });
+    let json = (trace == Trace::Json).then(|| {
+        tracing_subscriber::fmt::layer()
+            .json()
+            .with_writer(std::io::stderr)
+            .with_span_events(FmtSpan::CLOSE)
+    });
+    let tree = (trace == Trace::Tree).then(|| {
+        tracing_tree::HierarchicalLayer::new(2)
+            .with_ansi(colors)
+            .with_writer(std::io::stderr)
+    });
+
+    // the tree wants to be able to print what it's layered on, which the
+    // other layers can't be
+    let subscriber = Registry::default()
+        .with(filter)
+        .with(tree)
+        .with(journald)
+        .with(syslog)
+        .with(pretty)
+        .with(json);
+    tracing::subscriber::set_global_default(subscriber)?;
+    Ok(())
+}
+
+struct SyslogLayer;
+
+impl SyslogLayer {
+    fn new() -> Self {
+        // openlog keeps the pointer, so the identifier must live forever
+        static IDENT: &[u8] = b"surviving\0";
+        unsafe {
+            libc::openlog(
+                IDENT.as_ptr() as *const libc::c_char,
+                libc::LOG_PID,
+                libc::LOG_DAEMON,
+            )
+        };
+        Self
+    }
+}
+
+/// The fields of a span, formatted once when they're recorded
+struct SpanFields(Vec<(&'static str, String)>);
+
+impl<S> Layer<S> for SyslogLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let mut line = Line::default();
+        attrs.record(&mut line);
+        if let Some(span) = ctx.span(id) {
+            span.extensions_mut().insert(SpanFields(line.fields));
+        }
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        let mut line = Line::default();
+        values.record(&mut line);
+        if let Some(span) = ctx.span(id) {
+            if let Some(fields) = span.extensions_mut().get_mut::<SpanFields>() {
+                fields.0.retain(|(name, _)| line.field(name).is_none());
+                fields.0.append(&mut line.fields);
+            }
+        }
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let priority = match *event.metadata().level() {
+            Level::ERROR => libc::LOG_ERR,
+            Level::WARN => libc::LOG_WARNING,
+            Level::INFO => libc::LOG_INFO,
+            Level::DEBUG | Level::TRACE => libc::LOG_DEBUG,
+        };
+        let mut line = Line::default();
+        event.record(&mut line);
+        // then the enclosing spans' fields, the innermost value of each
+        if let Some(scope) = ctx.event_scope(event) {
+            for span in scope {
+                if let Some(fields) = span.extensions().get::<SpanFields>() {
+                    for (name, value) in &fields.0 {
+                        if line.field(name).is_none() {
+                            line.fields.push((name, value.clone()));
+                        }
+                    }
+                }
+            }
+        }
+        let mut text = line.message;
+        for (name, value) in line.fields {
+            write!(text, " {}={}", name, value).unwrap();
+        }
+        let text = CString::new(text.replace('\0', "\\0")).unwrap();
+        unsafe {
+            libc::syslog(
+                priority,
+                b"%s\0".as_ptr() as *const libc::c_char,
+                text.as_ptr(),
+            )
+        };
+    }
+}
+
+/// An event's message, and its other fields formatted with `{:?}`
+#[derive(Default)]
+struct Line {
+    message: String,
+    fields: Vec<(&'static str, String)>,
+}
+
+impl Line {
+    fn field(&self, name: &str) -> Option<&str> {
+        self.fields
+            .iter()
+            .find(|(n, _)| *n == name)
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+impl Visit for Line {
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        if field.name() == "message" {
+            write!(self.message, "{:?}", value).unwrap();
+        } else {
+            self.fields.push((field.name(), format!("{:?}", value)));
+        }
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "message" {
+            self.message.push_str(value);
+        } else {
+            self.fields.push((field.name(), format!("{:?}", value)));
+        }
+    }
+}
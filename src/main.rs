@@ -3,194 +3,4790 @@
 use argh::FromArgs;
 use async_std::{fs::File, io::ReadExt};
 
-use color_eyre::eyre;
+use color_eyre::eyre::{self, eyre};
 use sha3::Digest;
-use std::path::{Path, PathBuf};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    path::{Path, PathBuf},
+    sync::{Arc, OnceLock},
+};
+
+use pin_project::pin_project;
+
+#[cfg(feature = "alloc-stats")]
+use surviving::alloc;
+#[cfg(target_os = "linux")]
+use surviving::sandbox;
+#[cfg(feature = "snapshots")]
+use surviving::snapshot;
+use surviving::{
+    affinity, algo, audit, batch, bench, bloom, budget, cache, cancel, cargo_checksum, compress,
+    config, console, devices, digest, dupes, fault, feed_file, filter, framed, hash_file, heatmap,
+    hex, hmac, hooks, inspect, integrity, logging, merkle, messages, metrics, mime, open, output,
+    parity, pool, positioned, profile, progress, quote, remedy, remote, repo, retry, rt, runs, s3,
+    salvage, sfv, shard, sign, sort, source, state, stats, sums, tee, throttle, tune, unhex, units,
+    walk, watch, watermark, zsync, HashOptions,
+};
+
+/// Prints the hash of some files (SHA3-256 unless --algo says otherwise)
+#[derive(FromArgs)]
+struct Args {
+    /// the files whose contents to hash and print, directories meaning all
+    /// the files below them, `http://` and `https://` URLs fetched as they're
+    /// hashed, and `-` or no files at all meaning stdin
+    #[argh(positional)]
+    files: Vec<PathBuf>,
+
+    /// hash the files below directories among the inputs, which is what's
+    /// done with them with or without it
+    #[argh(switch, short = 'r')]
+    recursive: bool,
+
+    /// don't skip paths listed in `.survivingignore` files
+    #[argh(switch)]
+    no_ignore: bool,
+
+    /// in directories, only hash files whose path or name matches this glob
+    /// (repeatable)
+    #[argh(option)]
+    include: Vec<String>,
+
+    /// in directories, skip files and directories whose path or name
+    /// matches this glob (repeatable)
+    #[argh(option)]
+    exclude: Vec<String>,
+
+    /// in directories, walk into symlinked directories and hash symlinked
+    /// files, which are skipped otherwise
+    #[argh(switch)]
+    follow_symlinks: bool,
+
+    /// in directories, don't walk into other filesystems, like mounted
+    /// snapshots, bind mounts or network mounts, as `du -x` and `rsync -x`
+    /// don't
+    #[argh(switch)]
+    one_file_system: bool,
+
+    /// walk into other filesystems, as by default, even with
+    /// --one-file-system, say from an alias
+    #[argh(switch)]
+    cross_file_systems: bool,
+
+    /// hash algorithm: sha3-256 (default), sha3-512, sha256, sha512,
+    /// blake3, ed2k, tth, or `ext:COMMAND` to run COMMAND with each file
+    /// on stdin and take the hex digest it prints. Repeat it, or give a
+    /// comma list, to compute several digests in a single read of each
+    /// file, printing one result per algorithm.
+    #[argh(option, long = "algo", from_str_fn(parse_algos))]
+    algos: Vec<Vec<algo::Algorithm>>,
+
+    /// print HMACs made with this key instead of plain digests, or BLAKE3's
+    /// keyed hashes, which take a 32-byte key; --check, --expect and
+    /// --verify-stream then expect them too. A file holding the key (its
+    /// exact bytes), `env:NAME` for a variable holding it, or the key in
+    /// hex, which `ps` shows.
+    #[argh(option)]
+    hmac_key: Option<hmac::Key>,
+
+    /// reuse digests kept from earlier runs for files that haven't changed
+    /// since, and keep this run's: `sidecar:PATH` (or just PATH) keeps
+    /// them in one file, checked against each file's inode, size, ctime
+    /// and inode generation, and started over for another --algo; `xattr`
+    /// in an extended attribute of each file, checked against its size and
+    /// mtime (Linux and macOS)
+    #[argh(option)]
+    cache: Option<cache::Store>,
+
+    /// with --cache, hash every file again, and keep the digests for the
+    /// next run
+    #[argh(switch)]
+    no_cache_read: bool,
+
+    /// write a Bloom filter of all digests to this file
+    #[argh(option)]
+    bloom_out: Option<PathBuf>,
+
+    /// false positive rate of the Bloom filter written by --bloom-out
+    #[argh(option, default = "0.001", from_str_fn(parse_fpr))]
+    fpr: f64,
+
+    /// mark each digest as `seen` or `new` against this Bloom filter
+    #[argh(option)]
+    bloom_check: Option<PathBuf>,
+
+    /// mark each file as `new`, or as a duplicate of a file this manifest
+    /// lists or that was hashed earlier in the run, as soon as it's hashed
+    #[argh(option)]
+    dedupe_against: Option<PathBuf>,
+
+    /// format of the --dedupe-against manifest: lines (default), gnu, bsd,
+    /// json, csv or hashdeep
+    #[argh(option, default = "sums::Format::Lines")]
+    dedupe_format: sums::Format,
+
+    /// leave duplicates out of the output (needs --dedupe-against)
+    #[argh(switch)]
+    only_new: bool,
+
+    /// preset tunables: `low-impact` for sharing a busy host (few jobs,
+    /// small throttled reads, idle priority, nothing left in the page cache),
+    /// or `max-throughput` for a dedicated one; options given alongside win
+    #[argh(option)]
+    profile: Option<profile::Profile>,
+
+    /// how many files to hash at once, across all devices (default: the
+    /// number of logical CPUs)
+    #[argh(option)]
+    jobs: Option<usize>,
+
+    /// how many threads run hash functions, apart from those reading files
+    /// (default: the number of logical CPUs; 0 hashes on the reading threads)
+    #[argh(option)]
+    hash_threads: Option<usize>,
+
+    /// with BLAKE3, hash each file on this many threads, 4MiB apiece, each
+    /// reading its own part where it can, so one large file isn't held to
+    /// a single core or a single stream of reads (default: 1)
+    #[argh(option, default = "1")]
+    threads: usize,
+
+    /// keep each hashing thread on one CPU, taking turns over those the
+    /// process may use (Linux only)
+    #[argh(switch)]
+    pin_hash_threads: bool,
+
+    /// files smaller than this are read with a single reused buffer and
+    /// hashed in one go on a hashing thread (default: 64KiB; 0 to read
+    /// every file alike)
+    #[argh(option)]
+    small_file_size: Option<units::ByteSize>,
+
+    /// how many files to hash at once on each device (default: --jobs)
+    #[argh(option)]
+    per_device_jobs: Option<usize>,
+
+    /// let each worker stat, open and hash up to this many files below
+    /// --small-file-size at once, from directory handles it keeps open,
+    /// for network shares where every open waits on the server; batches
+    /// grow only while that waiting is what takes the time
+    #[argh(option)]
+    batch_small_files: Option<usize>,
+
+    /// PATH=N: hash at most N files at once on the device holding PATH
+    #[argh(option)]
+    device_jobs: Vec<devices::DeviceLimit>,
+
+    /// pin hashing threads to these CPUs, e.g. `0-7` or `0,2,4-6` (Linux only)
+    #[argh(option)]
+    cpu_list: Option<affinity::CpuList>,
+
+    /// pin hashing threads and their buffers to this NUMA node (Linux only)
+    #[argh(option)]
+    numa_node: Option<usize>,
+
+    /// run at the lowest CPU priority and in the idle I/O class, so anything
+    /// else on the host goes first (Linux only)
+    #[argh(switch)]
+    idle_priority: bool,
+
+    /// chroot to this directory before hashing; inputs are then resolved
+    /// inside it (Linux only)
+    #[argh(option)]
+    chroot: Option<PathBuf>,
+
+    /// switch to this user, by name or uid, before hashing (Linux only)
+    #[argh(option)]
+    setuid: Option<String>,
+
+    /// with Landlock, only allow reading below this tree (repeatable); the
+    /// inputs are readable too, and nothing but the output files can be
+    /// written (Linux only)
+    #[argh(option)]
+    confine_to: Vec<PathBuf>,
+
+    /// shell command to run before anything else, e.g. to take a snapshot
+    #[argh(option)]
+    pre_hook: Option<String>,
+
+    /// shell command to run once done, even when the run failed, with
+    /// `SURVIVING_STATUS` set to `success` or `failure`
+    #[argh(option)]
+    post_hook: Option<String>,
+
+    /// take a snapshot for the length of the run, dropping it afterwards:
+    /// `btrfs:SOURCE:DEST`, `zfs:DATASET@NAME` or
+    /// `lvm:VG/LV:SIZE:MOUNTPOINT` (repeatable, needs the `snapshots`
+    /// feature)
+    #[argh(option)]
+    snapshot: Vec<String>,
+
+    /// don't update access times of the files read (when we own them)
+    #[argh(switch)]
+    noatime: bool,
+
+    /// read buffer size, e.g. `1M`, or `auto` to measure a few sizes per
+    /// device and keep the fastest (default: 256K)
+    #[argh(option)]
+    buffer_size: Option<tune::BufferSize>,
+
+    /// how files are read: `read` (default) into the buffer, or `mmap` to
+    /// map them and copy from the page cache without a system call per
+    /// read; a mapped file that shrinks while it's read kills the process,
+    /// unless --changing-files lock keeps writers out
+    #[argh(option, default = "source::Backend::Read")]
+    backend: source::Backend,
+
+    /// continue hashing from a state saved with --emit-state, treating the
+    /// (single) input as the bytes that follow; or, given a directory, save
+    /// each file's hasher state there as it's hashed, so the next run picks
+    /// up where an interrupted one left off
+    #[argh(option)]
+    resume_state: Option<PathBuf>,
+
+    /// with a --resume-state directory, how often each file's state is
+    /// saved (default: 1G)
+    #[argh(option)]
+    state_every: Option<units::ByteSize>,
+
+    /// save the hasher state to this file instead of printing a digest, so
+    /// the next piece of the object can be hashed later or elsewhere
+    #[argh(option)]
+    emit_state: Option<PathBuf>,
+
+    /// read length-prefixed blobs (8-byte big-endian length, then payload)
+    /// from stdin and print one digest per blob
+    #[argh(switch)]
+    framed: bool,
+
+    /// copy stdin to stdout, then fail unless what went through has this
+    /// digest: hex, `NAME:HEX` or `NAME-BASE64`
+    #[argh(option)]
+    verify_stream: Option<String>,
+
+    /// check that each input has this digest: hex, `NAME:HEX` or
+    /// `NAME-BASE64`. Without a name or --algo, every algorithm that gives
+    /// digests of its length is tried in the same read, and the one that
+    /// matched is reported. May be repeated: an input passes if it has any
+    /// of the digests. `PATH=DIGEST` applies to that input only, and with
+    /// no inputs given, the paths named are the inputs. Exits with 2 if any
+    /// input has another digest, or 3 if the only failures are inputs that
+    /// couldn't be read.
+    #[argh(option)]
+    expect: Vec<String>,
+
+    /// with --verify-stream, empty the output on a mismatch, when it's a
+    /// regular file
+    #[argh(switch)]
+    truncate_on_mismatch: bool,
+
+    /// verify every file listed in this checksum manifest instead of
+    /// hashing inputs, printing each one's status
+    #[argh(option)]
+    check: Option<PathBuf>,
+
+    /// format of the --check manifest: lines (this tool's own output), gnu,
+    /// bsd, json, csv or hashdeep (default: lines). gnu manifests don't name
+    /// an algorithm, so without --algo every one that gives digests of the
+    /// right length is tried.
+    #[argh(option, default = "sums::Format::Lines")]
+    check_format: sums::Format,
+
+    /// with --check, verify the manifest's minisign signature, in
+    /// `<manifest>.minisig`, with this public key (its file, or the key in
+    /// base64) before trusting any of its digests
+    #[argh(option)]
+    verify_signature: Option<sign::PublicKey>,
+
+    /// sign the --output manifest with this minisign secret key, which
+    /// mustn't have a password, into `<output>.minisig`
+    #[argh(option)]
+    sign: Option<sign::SecretKey>,
+
+    /// treat inputs as .eml or mbox files, and print one digest per
+    /// attachment along with its message id and file name
+    #[argh(switch)]
+    mime: bool,
+
+    /// hash the members of .tar, .tar.gz and .zip inputs too, each as
+    /// `ARCHIVE!PATH`, reading the archive as it streams
+    #[argh(switch)]
+    archive: bool,
+
+    /// detect each file's type from its magic numbers, and print it
+    #[argh(switch)]
+    detect_type: bool,
+
+    /// estimate each file's entropy, flagging likely compressed or
+    /// encrypted contents
+    #[argh(switch)]
+    entropy: bool,
+
+    /// report all-zero regions at least this long (e.g. `4K`), a common sign
+    /// of storage returning blocks it never wrote
+    #[argh(option)]
+    detect_zero_runs: Option<units::ByteSize>,
+
+    /// print each distinct digest once: with its `first` path as soon as
+    /// it's hashed, or with `all` its paths grouped together at the end
+    #[argh(option)]
+    unique: Option<output::UniqueMode>,
+
+    /// how to lay out results: `lines` (default), `gnu` or `bsd` like
+    /// `sha256sum` and `sha256sum --tag`, a `json` array or `json-lines` with
+    /// each file's path, algorithm, digest, size and elapsed time, or paths
+    /// grouped by digest as `groups` or `groups-json`
+    #[argh(option, default = "output::Format::Lines")]
+    format: output::Format,
+
+    /// start the `lines`, `gnu` or `bsd` output with a comment saying which
+    /// run made it: a random run ID, the host, this version and the command
+    /// line, which --check skips and prints
+    #[argh(switch)]
+    watermark: bool,
+
+    /// print the JSON Schema the JSON formats follow, and exit; every record
+    /// carries its `schema_version`
+    #[argh(switch)]
+    schema: bool,
+
+    /// print results in the order files were given, rather than as they
+    /// finish; files below a directory come in path order, and with --fair
+    /// inputs take turns
+    #[argh(switch)]
+    ordered: bool,
+
+    /// hold results back and print them sorted once all are in, by `hash`
+    /// then path, or by `path`, spilling to temporary files past
+    /// --sort-memory
+    #[argh(option)]
+    sort: Option<sort::By>,
+
+    /// with --sort, how much output to hold in memory before spilling it
+    /// (default: 256M)
+    #[argh(option)]
+    sort_memory: Option<units::ByteSize>,
+
+    /// with --sort, where to spill (default: the temporary directory)
+    #[argh(option)]
+    sort_dir: Option<PathBuf>,
+
+    /// with several inputs, take files from each in turn rather than all of
+    /// one before the next, so results start coming from every one of them
+    /// at once
+    #[argh(switch)]
+    fair: bool,
+
+    /// write results to this file rather than stdout
+    #[argh(option, short = 'o')]
+    output: Option<PathBuf>,
+
+    /// compress --output as it's written: zstd or gzip (default: by its
+    /// extension, `.zst` or `.gz`). zstd needs the `zstd` tool installed.
+    #[argh(option)]
+    compress: Option<compress::Compression>,
+
+    /// show how far along each file in flight and the whole run are on
+    /// stderr, redrawn in place on a terminal and every few seconds
+    /// otherwise
+    #[argh(switch)]
+    progress: bool,
+
+    /// how to write paths: `none` (default), `shell` to quote them for
+    /// pasting into a shell, or `c` for C string literals
+    #[argh(option, default = "quote::Quote::None")]
+    quote: quote::Quote,
+
+    /// language of the status words verifiers print: `en` (default), `de`,
+    /// `fr` or `es`; JSON output and journals stay in English
+    #[argh(option, default = "messages::Lang::En")]
+    lang: messages::Lang,
+
+    /// write one manifest per directory this many levels deep, instead of
+    /// printing results (needs --shard-dir)
+    #[argh(option)]
+    shard_output_by_dir: Option<usize>,
+
+    /// where sharded manifests go, mirroring the input directories
+    #[argh(option)]
+    shard_dir: Option<PathBuf>,
+
+    /// write how long each file spent opening, waiting for reads, hashing
+    /// and being written out, in microseconds, to this CSV file
+    #[argh(option)]
+    timings_out: Option<PathBuf>,
+
+    /// serve a snapshot of in-flight files and queue depths to anyone
+    /// connecting to this address, like `127.0.0.1:7070`
+    #[argh(option)]
+    console: Option<String>,
+
+    /// also send diagnostics and failures to `journald` or `syslog`
+    #[argh(option)]
+    log_target: Option<logging::LogTarget>,
+
+    /// print spans and events to stderr as they happen: `off` (default),
+    /// `pretty`, `tree` or `json`, with how long each file and read took
+    #[argh(option, default = "logging::Trace::Off")]
+    trace: logging::Trace,
+
+    /// only trace reads for this fraction of files, like `1/1000`, picked by
+    /// path so reruns trace the same ones; failed reads are always traced
+    #[argh(option)]
+    trace_sample: Option<logging::Sample>,
+
+    /// seed for every random choice, like which files --trace-sample picks;
+    /// runs with the same seed and inputs make the same choices
+    #[argh(option, default = "0")]
+    seed: u64,
+
+    /// record this run's arguments, host, duration and totals as a JSON file
+    /// in this directory, for `surviving runs`
+    #[argh(option)]
+    record_run: Option<PathBuf>,
+
+    /// print each file's bytes, reads, time and throughput to stderr when
+    /// done, then run totals with the slowest file, and heap allocations
+    /// when built with the `alloc-stats` feature
+    #[argh(switch)]
+    stats: bool,
+
+    /// how to print --stats: `text` (default) or `json`
+    #[argh(option, default = "stats::Format::Text")]
+    stats_format: stats::Format,
+
+    /// with --stats, also total files by `ext`, their extension, or by
+    /// `dir`, the directory right below the input they're in
+    #[argh(option)]
+    report_by: Option<stats::By>,
+
+    /// write run totals to this file in the Prometheus text format, for
+    /// node_exporter's textfile collector
+    #[argh(option)]
+    metrics_out: Option<PathBuf>,
+
+    /// push run totals to the Prometheus Pushgateway at this URL
+    #[argh(option)]
+    pushgateway: Option<String>,
+
+    /// only print files whose digest is listed in this file, one hex digest
+    /// per line
+    #[argh(option)]
+    only_matching: Option<PathBuf>,
+
+    /// only print files at least this large, e.g. `1M`
+    #[argh(option)]
+    min_size: Option<units::ByteSize>,
+
+    /// when a symlink and the file it points to are both hashed, mark the
+    /// symlink as `alias-of=TARGET` (`alias_of` in JSON) rather than as a
+    /// file of its own
+    #[argh(switch)]
+    report_aliases: bool,
+
+    /// only print files at most this large
+    #[argh(option)]
+    max_size: Option<units::ByteSize>,
+
+    /// wait this long before every read, e.g. `50ms`, to see how a run
+    /// behaves against slow storage; overrides --inject-faults' latency
+    #[argh(option)]
+    throttle: Option<units::Duration>,
+
+    /// read no more than this many bytes a second across all files, e.g.
+    /// `50M`, to leave a busy disk to everything else
+    #[argh(option)]
+    limit_rate: Option<units::ByteSize>,
+
+    /// make reads misbehave, for testing: `latency=50ms` before each,
+    /// `short=0.5` odds of returning fewer bytes than asked, `error=0.01`
+    /// odds of failing; reads hit depend on --seed
+    #[argh(option)]
+    inject_faults: Option<fault::Faults>,
+
+    /// only print files that took at least this long to hash, e.g. `500ms`
+    #[argh(option)]
+    min_duration: Option<units::Duration>,
+
+    /// don't hash files larger than this, e.g. `100G`; see --oversize
+    #[argh(option)]
+    max_file_size: Option<units::ByteSize>,
+
+    /// what to do with files over --max-file-size: `skip` them with a
+    /// warning record (default), or `truncate` to hash only their first
+    /// bytes, marking the digest as partial
+    #[argh(option, default = "filter::Oversize::Skip")]
+    oversize: filter::Oversize,
+
+    /// what to do with files that change while they're hashed: `flag` them
+    /// as unstable (default), `retry` a few times first, or `lock` them with
+    /// a shared flock while reading
+    #[argh(option, default = "open::Changing::Flag")]
+    changing_files: open::Changing,
+
+    /// read each file this many times, printing the digest most reads agree
+    /// on and flagging the file as `reads=AGREEING/N` if any disagrees, to
+    /// catch flaky storage or RAM (default: 1)
+    #[argh(option, default = "1")]
+    reads: usize,
+
+    /// evict each file from the page cache before every read, so --reads
+    /// go to storage rather than RAM (Linux only)
+    #[argh(switch)]
+    drop_cache: bool,
+
+    /// evict each file from the page cache once it's hashed, so a scan
+    /// doesn't push out what everything else has cached (Linux only)
+    #[argh(switch)]
+    evict_after_read: bool,
+
+    /// leave hashed files in the page cache, even under --profile
+    /// low-impact, for whatever reads them next
+    #[argh(switch)]
+    keep_cache: bool,
+
+    /// read each file ahead into the page cache and leave it there, so a
+    /// backup or copy run next reads from RAM; files --cache would skip
+    /// are read anyway (Linux only)
+    #[argh(switch)]
+    populate_cache: bool,
+
+    /// go on past a failed read, retrying it 4KiB at a time and hashing
+    /// zeros for each block that still fails, for a best-effort digest
+    /// flagged `unreadable=BYTES`
+    #[argh(switch)]
+    salvage: bool,
+
+    /// with --salvage, write where each damaged file couldn't be read to
+    /// this file, a JSON line per file
+    #[argh(option)]
+    bad_ranges: Option<PathBuf>,
+
+    /// what verifying subcommands do with files that fail or aren't listed:
+    /// `report` them (default), `delete` them, or `quarantine:DIR` to move
+    /// them below DIR
+    #[argh(option, default = "remedy::OnMismatch::Report")]
+    on_mismatch: remedy::OnMismatch,
+
+    /// write a report of verification failures and read errors summed up by
+    /// directory and device to this file
+    #[argh(option)]
+    heatmap: Option<PathBuf>,
+
+    /// how to write the --heatmap report: `text` (default) or `json`
+    #[argh(option, default = "heatmap::Format::Text")]
+    heatmap_format: heatmap::Format,
+
+    /// only list directories this many levels deep in the --heatmap report
+    #[argh(option)]
+    heatmap_depth: Option<usize>,
+
+    /// append a JSON line per --on-mismatch action to this file (default:
+    /// `journal.jsonl` in the quarantine directory)
+    #[argh(option)]
+    mismatch_journal: Option<PathBuf>,
+
+    /// also print the digest of each file's first N, 2N, ... bytes, e.g.
+    /// `64M`, so chunked uploads can be checked as each chunk lands
+    #[argh(option)]
+    checkpoint_every: Option<units::ByteSize>,
+
+    /// print the root of a Merkle tree over chunks of this size, e.g. `1M`,
+    /// instead of a plain digest, so any chunk can be checked on its own
+    /// later with `tree-verify`
+    #[argh(option)]
+    tree: Option<units::ByteSize>,
+
+    /// with --tree, write each file's leaves to this file, a JSON line per
+    /// file, for `tree-verify`
+    #[argh(option)]
+    tree_leaves: Option<PathBuf>,
+
+    /// print each file's owner and mode, flagging world-writable, setuid
+    /// and setgid files
+    #[argh(switch)]
+    audit: bool,
+
+    /// like --audit, also flagging files whose owner or mode changed since
+    /// this earlier --audit output
+    #[argh(option)]
+    audit_against: Option<PathBuf>,
+
+    /// stop starting files after running this long, e.g. `6h`; files in
+    /// flight are finished, see --leftovers
+    #[argh(option)]
+    max_runtime: Option<units::Duration>,
+
+    /// stop starting files once this many bytes worth of them started,
+    /// e.g. `2T`; the last one may take the run past it
+    #[argh(option)]
+    max_bytes: Option<units::ByteSize>,
+
+    /// stop after starting this many files
+    #[argh(option)]
+    max_files: Option<usize>,
+
+    /// on the first file that can't be hashed, stop starting files and
+    /// abandon those in flight
+    #[argh(switch)]
+    fail_fast: bool,
+
+    /// stop starting files once more than this many failed, or this
+    /// percentage of the inputs, like `5%`, and exit with status 4
+    #[argh(option)]
+    max_errors: Option<budget::MaxErrors>,
+
+    /// give up on a file that takes longer than this, like `30s`, reporting
+    /// it as timed out; for hung network filesystems
+    #[argh(option)]
+    timeout: Option<units::Duration>,
+
+    /// try a file's read again this many times when it fails with what
+    /// looks transient, like EIO or ESTALE on NFS, reopening the file and
+    /// picking up where it left off
+    #[argh(option)]
+    retries: Option<u32>,
+
+    /// with --retries, how long to wait before the first retry, doubled
+    /// for each after it (default: 200ms)
+    #[argh(option)]
+    retry_backoff: Option<units::Duration>,
+
+    /// take a read that returns nothing for this long, like `10s`, for a
+    /// stall, and fail it, or retry it with --retries
+    #[argh(option)]
+    read_timeout: Option<units::Duration>,
+
+    /// when a --max-* limit stops the run, list the files it didn't get to
+    /// here; a run finding this file hashes those instead of its inputs,
+    /// and removes it once it gets through them all
+    #[argh(option)]
+    leftovers: Option<PathBuf>,
+
+    /// also hash the files listed in this file, one per line, or on stdin
+    /// for `-`, as they're read; a line may start with `ID<TAB>`, to have
+    /// the ID echoed in json and json-lines records
+    #[argh(option)]
+    files_from: Option<PathBuf>,
+
+    /// read --files-from entries ending in a NUL, as `find -print0` writes
+    /// them, and end lines, gnu and bsd records with a NUL, paths as they
+    /// are
+    #[argh(switch, short = '0')]
+    null: bool,
+
+    /// only print files whose path matches this glob (repeatable)
+    #[argh(option)]
+    only_paths: Vec<String>,
+
+    /// once everything is hashed, keep watching the inputs, directories
+    /// with everything below them, and hash files again as they change
+    /// (Linux only)
+    #[argh(switch)]
+    watch: bool,
+
+    /// with --watch, wait until nothing has changed for this long before
+    /// hashing what did (default: 200ms)
+    #[argh(option)]
+    debounce: Option<units::Duration>,
+
+    #[argh(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(FromArgs)]
+#[argh(subcommand)]
+enum Command {
+    Sums(SumsArgs),
+    Sfv(SfvArgs),
+    Parity(ParityArgs),
+    Zsync(ZsyncArgs),
+    CargoChecksum(CargoChecksumArgs),
+    Repo(RepoArgs),
+    LinkFarm(LinkFarmArgs),
+    Runs(RunsArgs),
+    Capabilities(CapabilitiesArgs),
+    SelfTest(SelfTestArgs),
+    Bench(BenchArgs),
+    Copy(CopyArgs),
+    Snapshot(SnapshotArgs),
+    Verify(VerifyArgs),
+    TreeVerify(TreeVerifyArgs),
+    Dupes(DupesArgs),
+    UploadVerify(UploadVerifyArgs),
+    Run(RunArgs),
+}
+
+/// Work with existing checksum manifests
+#[derive(FromArgs)]
+#[argh(subcommand, name = "sums")]
+struct SumsArgs {
+    #[argh(subcommand)]
+    command: SumsCommand,
+}
+
+#[derive(FromArgs)]
+#[argh(subcommand)]
+enum SumsCommand {
+    Convert(ConvertArgs),
+}
+
+/// Converts a checksum manifest between formats, without re-hashing anything
+#[derive(FromArgs)]
+#[argh(subcommand, name = "convert")]
+struct ConvertArgs {
+    /// format of the input: gnu, bsd, json, csv, hashdeep or lines
+    #[argh(option, default = "sums::Format::Gnu")]
+    from: sums::Format,
+
+    /// format of the output: gnu, bsd, json, csv, hashdeep or lines
+    #[argh(option)]
+    to: sums::Format,
+
+    /// algorithm name for gnu manifests, which don't record one (default:
+    /// the only built-in algorithm whose digests are as long, if there's
+    /// just one)
+    #[argh(option)]
+    algo: Option<String>,
+
+    /// the manifest to convert (default: stdin)
+    #[argh(positional)]
+    input: Option<PathBuf>,
+}
+
+/// Create and check Simple File Verification (CRC32) files
+#[derive(FromArgs)]
+#[argh(subcommand, name = "sfv")]
+struct SfvArgs {
+    #[argh(subcommand)]
+    command: SfvCommand,
+}
+
+#[derive(FromArgs)]
+#[argh(subcommand)]
+enum SfvCommand {
+    Create(SfvCreateArgs),
+    Check(SfvCheckArgs),
+}
+
+/// Prints an SFV file listing the CRC32 of each file
+#[derive(FromArgs)]
+#[argh(subcommand, name = "create")]
+struct SfvCreateArgs {
+    /// the files to list
+    #[argh(positional)]
+    files: Vec<PathBuf>,
+}
+
+/// Verifies the files listed in an SFV file, relative to its directory
+#[derive(FromArgs)]
+#[argh(subcommand, name = "check")]
+struct SfvCheckArgs {
+    /// the SFV file to check
+    #[argh(positional)]
+    sfv: PathBuf,
+}
+
+/// Create recovery data for files, and repair them with it
+#[derive(FromArgs)]
+#[argh(subcommand, name = "parity")]
+struct ParityArgs {
+    #[argh(subcommand)]
+    command: ParityCommand,
+}
+
+#[derive(FromArgs)]
+#[argh(subcommand)]
+enum ParityCommand {
+    Create(ParityCreateArgs),
+    Repair(ParityRepairArgs),
+}
+
+/// Writes a `.svpar` recovery volume next to each file
+#[derive(FromArgs)]
+#[argh(subcommand, name = "create")]
+struct ParityCreateArgs {
+    /// how much recovery data to write, relative to the file (default: 5%)
+    #[argh(option, default = "parity::Redundancy(0.05)")]
+    redundancy: parity::Redundancy,
+
+    /// size of the blocks that can be repaired (default: 64K)
+    #[argh(option, default = "units::ByteSize(64 << 10)")]
+    block_size: units::ByteSize,
+
+    /// the files to protect
+    #[argh(positional)]
+    files: Vec<PathBuf>,
+}
+
+/// Repairs the files described by `.svpar` recovery volumes, in place
+#[derive(FromArgs)]
+#[argh(subcommand, name = "repair")]
+struct ParityRepairArgs {
+    /// the recovery volumes
+    #[argh(positional)]
+    volumes: Vec<PathBuf>,
+}
+
+/// Creates a `<digest>` symlink to every file under a directory, a content
+/// index that doesn't copy any data
+#[derive(FromArgs)]
+#[argh(subcommand, name = "link-farm")]
+struct LinkFarmArgs {
+    /// the directory to put the links in, created if needed
+    #[argh(option)]
+    out: PathBuf,
+
+    /// the directory to index
+    #[argh(positional)]
+    dir: PathBuf,
+}
+
+/// Lists the algorithms, encodings, formats and I/O features this binary
+/// supports
+#[derive(FromArgs)]
+#[argh(subcommand, name = "capabilities")]
+struct CapabilitiesArgs {
+    /// print them as a JSON object, for scripts
+    #[argh(switch)]
+    json: bool,
+}
+
+/// Checks every built-in algorithm against known answers, directly and
+/// through the reader and writer adapters, to validate an installation
+#[derive(FromArgs)]
+#[argh(subcommand, name = "self-test")]
+struct SelfTestArgs {}
+
+/// Measures how fast synthetic data in memory is read and hashed with a
+/// plain read loop and through the reader adapter, polled and boxed, and
+/// prints them side by side
+#[derive(FromArgs)]
+#[argh(subcommand, name = "bench")]
+struct BenchArgs {
+    /// how much data each case reads (default: 64M)
+    #[argh(option, default = "units::ByteSize(64 << 20)")]
+    size: units::ByteSize,
+
+    /// what to do with the data: `none` to only read it, or an algorithm
+    /// (repeatable; default: none, then every built-in algorithm)
+    #[argh(option)]
+    algo: Vec<bench::Work>,
+
+    /// read buffer size to try (repeatable; default: 4K, 64K and 256K)
+    #[argh(option)]
+    buffer_size: Vec<units::ByteSize>,
+
+    /// how many times to run each case, keeping the fastest (default: 3)
+    #[argh(option, default = "3")]
+    rounds: usize,
+}
+
+/// Copies a file, or stdin, while hashing it, printing the digest once it's
+/// written, so the data is only read once
+#[derive(FromArgs)]
+#[argh(subcommand, name = "copy")]
+struct CopyArgs {
+    /// the file or URL to copy, or `-` for stdin
+    #[argh(positional)]
+    src: PathBuf,
+
+    /// where to copy it, replacing any file there; a directory gets a file
+    /// of the same name
+    #[argh(positional)]
+    dest: PathBuf,
+}
+
+/// Records every file under a tree with its size, modification time and
+/// digest, for `verify` to compare against later
+#[derive(FromArgs)]
+#[argh(subcommand, name = "snapshot")]
+struct SnapshotArgs {
+    /// where to write the manifest, compressed if it ends in `.zst` or `.gz`
+    #[argh(option, short = 'o')]
+    output: PathBuf,
+
+    /// the tree to record
+    #[argh(positional)]
+    root: PathBuf,
+}
+
+/// Re-hashes the tree a `snapshot` manifest describes, listing the files
+/// added, removed, modified or only touched since
+#[derive(FromArgs)]
+#[argh(subcommand, name = "verify")]
+struct VerifyArgs {
+    /// the tree to check (default: the one the snapshot was taken of)
+    #[argh(option)]
+    root: Option<PathBuf>,
+
+    /// print one JSON object per change, with the entry before and after
+    #[argh(switch)]
+    json: bool,
+
+    /// the manifest `snapshot` wrote
+    #[argh(positional)]
+    manifest: PathBuf,
+}
+
+/// Checks one chunk of a file against the leaves `--tree-leaves` wrote,
+/// reading that chunk only
+#[derive(FromArgs)]
+#[argh(subcommand, name = "tree-verify")]
+struct TreeVerifyArgs {
+    /// the sidecar --tree-leaves wrote
+    #[argh(option)]
+    leaves: PathBuf,
+
+    /// any offset within the chunk to check, e.g. `3M` (default: 0)
+    #[argh(option, default = "units::ByteSize(0)")]
+    offset: units::ByteSize,
+
+    /// the file, named as it was when it was hashed
+    #[argh(positional)]
+    file: PathBuf,
+}
+
+/// Lists files with the same contents, hashing only those that share a
+/// size with another
+#[derive(FromArgs)]
+#[argh(subcommand, name = "dupes")]
+struct DupesArgs {
+    /// print the clusters as a JSON array
+    #[argh(switch)]
+    json: bool,
+
+    /// the directories to look through, or files to compare
+    #[argh(positional)]
+    paths: Vec<PathBuf>,
+}
+
+/// Checks what was uploaded to S3 against the manifest of the files it was
+/// uploaded from, using the SHA-256 the store keeps where it has one and
+/// downloading the object otherwise; credentials come from $AWS_ACCESS_KEY_ID,
+/// $AWS_SECRET_ACCESS_KEY and $AWS_SESSION_TOKEN
+#[derive(FromArgs)]
+#[argh(subcommand, name = "upload-verify")]
+struct UploadVerifyArgs {
+    /// the manifest of the files uploaded
+    #[argh(option)]
+    manifest: PathBuf,
+
+    /// format of the manifest: gnu, bsd, json, csv, hashdeep or lines
+    /// (default: gnu)
+    #[argh(option, default = "sums::Format::Gnu")]
+    manifest_format: sums::Format,
+
+    /// directory the prefix stands for, which the manifest's paths are
+    /// under (default: they're relative to the prefix already)
+    #[argh(option)]
+    root: Option<PathBuf>,
+
+    /// the S3 API endpoint (default: $AWS_ENDPOINT_URL, or AWS's for the
+    /// region)
+    #[argh(option)]
+    endpoint: Option<String>,
+
+    /// the bucket's region (default: $AWS_REGION, $AWS_DEFAULT_REGION or
+    /// us-east-1)
+    #[argh(option)]
+    region: Option<String>,
+
+    /// how many objects to check at once (default: 8)
+    #[argh(option, default = "8")]
+    jobs: usize,
+
+    /// download and hash every object, even those the store keeps a
+    /// SHA-256 of
+    #[argh(switch)]
+    download: bool,
+
+    /// where the files were uploaded, as `s3://bucket/prefix`
+    #[argh(positional)]
+    dest: s3::Location,
+}
+
+/// Writes a zsync control file, for delta downloads of a file
+#[derive(FromArgs)]
+#[argh(subcommand, name = "zsync")]
+struct ZsyncArgs {
+    /// where clients download the file from (default: its name, next to the
+    /// control file)
+    #[argh(option)]
+    url: Option<String>,
+
+    /// block size, a power of two (default: 2K, or 4K from 100MB up)
+    #[argh(option)]
+    block_size: Option<units::ByteSize>,
+
+    /// where to write the control file (default: the file's path plus `.zsync`)
+    #[argh(option, short = 'o')]
+    output: Option<PathBuf>,
+
+    /// the file to describe
+    #[argh(positional)]
+    file: PathBuf,
+}
+
+/// Generate and verify `.cargo-checksum.json` files of vendored crates
+#[derive(FromArgs)]
+#[argh(subcommand, name = "cargo-checksum")]
+struct CargoChecksumArgs {
+    #[argh(subcommand)]
+    command: CargoChecksumCommand,
+}
+
+#[derive(FromArgs)]
+#[argh(subcommand)]
+enum CargoChecksumCommand {
+    Create(CargoChecksumCreateArgs),
+    Verify(CargoChecksumVerifyArgs),
+}
+
+/// Writes a `.cargo-checksum.json` listing every file in a crate directory
+#[derive(FromArgs)]
+#[argh(subcommand, name = "create")]
+struct CargoChecksumCreateArgs {
+    /// the `.crate` file the directory was unpacked from, recorded as the
+    /// package digest
+    #[argh(option)]
+    crate_file: Option<PathBuf>,
+
+    /// the crate directory
+    #[argh(positional)]
+    dir: PathBuf,
+}
+
+/// Checks a crate directory against its `.cargo-checksum.json`
+#[derive(FromArgs)]
+#[argh(subcommand, name = "verify")]
+struct CargoChecksumVerifyArgs {
+    /// also check this `.crate` file against the package digest
+    #[argh(option)]
+    crate_file: Option<PathBuf>,
+
+    /// the crate directory
+    #[argh(positional)]
+    dir: PathBuf,
+}
+
+/// Work with APT and YUM repository metadata
+#[derive(FromArgs)]
+#[argh(subcommand, name = "repo")]
+struct RepoArgs {
+    #[argh(subcommand)]
+    command: RepoCommand,
+}
+
+#[derive(FromArgs)]
+#[argh(subcommand)]
+enum RepoCommand {
+    Verify(RepoVerifyArgs),
+}
+
+/// Checks the files listed by a `Release`, `Packages`, `repomd.xml` or
+/// `primary.xml` index against their sizes and digests
+#[derive(FromArgs)]
+#[argh(subcommand, name = "verify")]
+struct RepoVerifyArgs {
+    /// kind of index: release, packages, repomd or primary (default: guessed
+    /// from the file name)
+    #[argh(option)]
+    kind: Option<repo::Kind>,
+
+    /// directory the listed paths are relative to (default: next to the
+    /// `Release`, above `dists/` or above `repodata/`)
+    #[argh(option)]
+    root: Option<PathBuf>,
+
+    /// how many files to check at once (default: 8)
+    #[argh(option, default = "8")]
+    jobs: usize,
+
+    /// don't fail for listed files that aren't there, as on partial mirrors
+    #[argh(switch)]
+    ignore_missing: bool,
+
+    /// the index, optionally gzipped
+    #[argh(positional)]
+    index: PathBuf,
+}
+
+/// Run a scan defined in the config file, or list them without a name;
+/// options given before `run` are added to the profile's
+#[derive(FromArgs)]
+#[argh(subcommand, name = "run")]
+struct RunArgs {
+    /// the profile to run
+    #[argh(positional)]
+    name: Option<String>,
+
+    /// the config file (default: $SURVIVING_CONFIG, or
+    /// surviving/config.json in $XDG_CONFIG_HOME or ~/.config)
+    #[argh(option)]
+    config: Option<PathBuf>,
+}
+
+/// Look back at runs recorded with --record-run
+#[derive(FromArgs)]
+#[argh(subcommand, name = "runs")]
+struct RunsArgs {
+    #[argh(subcommand)]
+    command: RunsCommand,
+}
+
+#[derive(FromArgs)]
+#[argh(subcommand)]
+enum RunsCommand {
+    List(RunsListArgs),
+    Compare(RunsCompareArgs),
+}
+
+/// Lists recorded runs, oldest first
+#[derive(FromArgs)]
+#[argh(subcommand, name = "list")]
+struct RunsListArgs {
+    /// the directory runs were recorded in
+    #[argh(positional)]
+    dir: PathBuf,
+}
+
+/// Shows what changed between two recorded runs
+#[derive(FromArgs)]
+#[argh(subcommand, name = "compare")]
+struct RunsCompareArgs {
+    /// the directory runs were recorded in
+    #[argh(positional)]
+    dir: PathBuf,
+
+    /// the earlier run's id (default: the next to last run)
+    #[argh(option)]
+    from: Option<String>,
+
+    /// the later run's id (default: the last run)
+    #[argh(option)]
+    to: Option<String>,
+}
+
+impl Args {
+    /// `--resume-state`, when it's a directory to save each file's state in
+    fn resume_dir(&self) -> Option<&Path> {
+        self.resume_state.as_deref().filter(|path| path.is_dir())
+    }
+
+    /// `--resume-state`, when it's a state saved with `--emit-state`
+    fn resume_file(&self) -> Option<&Path> {
+        self.resume_state.as_deref().filter(|path| !path.is_dir())
+    }
+
+    /// The first --algo, if any was given
+    fn algo(&self) -> Option<algo::Algorithm> {
+        self.algos.iter().flatten().next().copied()
+    }
+
+    /// --algo, or the default
+    fn algorithm(&self) -> algo::Algorithm {
+        self.algo().unwrap_or(algo::Algorithm::Sha3_256)
+    }
+
+    /// How --output is compressed: as --compress says, or as its name does
+    fn output_compression(&self) -> Option<compress::Compression> {
+        let path = self.output.as_deref()?;
+        self.compress
+            .or_else(|| compress::Compression::from_extension(path))
+    }
+
+    /// The algorithms past the first, each computed in the same read
+    fn also(&self) -> Vec<algo::Algorithm> {
+        self.algos.iter().flatten().skip(1).copied().collect()
+    }
+
+    /// A hasher for --algo, keyed with --hmac-key if it's given
+    fn hasher(&self) -> Result<algo::Hasher, eyre::Error> {
+        match &self.hmac_key {
+            Some(key) => self.algorithm().keyed_hasher(key.bytes()),
+            None => Ok(self.algorithm().hasher()),
+        }
+    }
+
+    /// --buffer-size, or the default
+    fn buffer_size(&self) -> tune::BufferSize {
+        self.buffer_size
+            .unwrap_or(tune::BufferSize::Fixed(tune::DEFAULT_BUFFER_SIZE))
+    }
+
+    /// Fills in what --profile sets and the command line didn't
+    fn apply_profile(&mut self) {
+        let preset = match self.profile {
+            Some(profile) => {
+                profile.preset(std::thread::available_parallelism().map_or(1, |n| n.get()))
+            }
+            None => return,
+        };
+        self.jobs = self.jobs.or(preset.jobs);
+        self.per_device_jobs = self.per_device_jobs.or(preset.per_device_jobs);
+        self.hash_threads = self.hash_threads.or(preset.hash_threads);
+        // pinning needs hashing threads to pin
+        self.pin_hash_threads |= preset.pin_hash_threads && self.hash_threads != Some(0);
+        self.buffer_size = self.buffer_size.or(preset.buffer_size);
+        self.small_file_size = self.small_file_size.or(preset.small_file_size);
+        self.throttle = self.throttle.or(preset.throttle.map(units::Duration));
+        self.idle_priority |= preset.idle_priority;
+        // unless the cache is being kept warm on purpose
+        self.evict_after_read |=
+            preset.evict_after_read && !self.keep_cache && !self.populate_cache;
+    }
+}
+
+fn parse_algos(s: &str) -> Result<Vec<algo::Algorithm>, String> {
+    algo::Algorithm::parse_cli_list(s).map_err(|e| e.to_string())
+}
+
+fn parse_fpr(s: &str) -> Result<f64, String> {
+    match s.parse() {
+        Ok(fpr) if fpr > 0.0 && fpr < 1.0 => Ok(fpr),
+        _ => Err(format!("{:?} is not a rate between 0 and 1", s)),
+    }
+}
+
+/// Stands in for a lone `-` while argh parses the command line
+const STDIN_PLACEHOLDER: &str = "\0stdin";
+
+/// Parses the command line like `argh::from_env`, except that a lone `-`,
+/// which argh would take for a switch, can name stdin among the inputs
+fn parse_args() -> Args {
+    let strings: Vec<String> = std::env::args_os()
+        .map(|s| s.into_string())
+        .collect::<Result<_, _>>()
+        .unwrap_or_else(|arg| {
+            eprintln!("Invalid utf8: {}", arg.to_string_lossy());
+            std::process::exit(1)
+        });
+    let cmd = strings
+        .first()
+        .and_then(|path| Path::new(path).file_name()?.to_str())
+        .unwrap_or("surviving");
+    let takes_value = |arg: &str| {
+        matches!(Args::from_args(&[cmd], &[arg]),
+            Err(e) if e.output.starts_with("No value provided"))
+    };
+    let mut argv: Vec<&str> = strings.iter().skip(1).map(String::as_str).collect();
+    for i in 0..argv.len() {
+        // past `--`, argh already takes `-` as an input
+        if argv[i] == "--" {
+            break;
+        }
+        if argv[i] == open::STDIN && (i == 0 || !takes_value(argv[i - 1])) {
+            argv[i] = STDIN_PLACEHOLDER;
+        }
+    }
+
+    let parse = |argv: &[&str]| {
+        Args::from_args(&[cmd], argv).unwrap_or_else(|early_exit| {
+            std::process::exit(match early_exit.status {
+                Ok(()) => {
+                    println!("{}", early_exit.output);
+                    0
+                }
+                Err(()) => {
+                    eprintln!(
+                        "{}\nRun {} --help for more information.",
+                        early_exit.output.replace(STDIN_PLACEHOLDER, open::STDIN),
+                        cmd
+                    );
+                    1
+                }
+            })
+        })
+    };
+    let mut args = parse(&argv);
+    if let Some(Command::Run(run)) = &args.command {
+        let profile = run_profile(run).unwrap_or_else(|e| {
+            eprintln!("Error: {:#}", e);
+            std::process::exit(1)
+        });
+        // what comes before `run` is the top-level options, then the
+        // profile's go where `run` was
+        let at = subcommand_at(&argv, takes_value);
+        let expanded: Vec<&str> = argv[..at]
+            .iter()
+            .copied()
+            .chain(profile.iter().map(String::as_str))
+            .collect();
+        args = parse(&expanded);
+        if let Some(Command::Run(_)) = &args.command {
+            eprintln!("Error: a profile can't run another one");
+            std::process::exit(1)
+        }
+    }
+    let restore = |path: &mut PathBuf| {
+        if path == Path::new(STDIN_PLACEHOLDER) {
+            *path = PathBuf::from(open::STDIN);
+        }
+    };
+    args.files.iter_mut().for_each(restore);
+    if let Some(Command::Sums(SumsArgs {
+        command: SumsCommand::Convert(convert),
+    })) = &mut args.command
+    {
+        convert.input.iter_mut().for_each(restore);
+    }
+    if let Some(Command::Copy(copy)) = &mut args.command {
+        restore(&mut copy.src);
+    }
+    args.apply_profile();
+    args
+}
+
+/// Where in `argv` the `run` subcommand starts, skipping option values
+/// that happen to be `run` too. Only called once argh found it.
+fn subcommand_at(argv: &[&str], takes_value: impl Fn(&str) -> bool) -> usize {
+    let mut i = 0;
+    while argv[i] != "run" {
+        i += if argv[i].starts_with('-') && takes_value(argv[i]) {
+            2
+        } else {
+            1
+        };
+    }
+    i
+}
+
+/// The command line of the profile `run` names, or, without a name, the
+/// profiles the config file defines, printed before exiting
+fn run_profile(run: &RunArgs) -> Result<Vec<String>, eyre::Error> {
+    let path = match run.config.clone().or_else(config::default_path) {
+        Some(path) => path,
+        None => {
+            return Err(eyre!(
+                "no config file: set $SURVIVING_CONFIG or pass --config"
+            ))
+        }
+    };
+    let config = config::Config::load(&path)?;
+    let name = match &run.name {
+        Some(name) => name,
+        None => {
+            for (name, profile) in &config.profiles {
+                let paths: Vec<_> = profile
+                    .paths
+                    .iter()
+                    .map(|p| p.display().to_string())
+                    .collect();
+                println!("{}\t{}", name, paths.join(" "));
+            }
+            std::process::exit(0)
+        }
+    };
+    Ok(config.profile(name)?.argv())
+}
+
+fn main() -> Result<(), eyre::Error> {
+    color_eyre::install().unwrap();
+    let args = parse_args();
+    logging::init(args.log_target, args.trace)?;
+    LANG.set(args.lang).ok();
+
+    // threads inherit affinity and memory policy, so this has to happen
+    // before the runtime spawns its workers
+    if let Some(node) = args.numa_node {
+        affinity::pin_to_numa_node(node)?;
+    }
+    if let Some(cpus) = &args.cpu_list {
+        affinity::pin_to_cpus(cpus)?;
+    }
+    if args.idle_priority {
+        affinity::idle_priority()?;
+    }
+
+    // cleaning up after hooks and snapshots takes the privileges a sandbox
+    // gives up
+    if (args.pre_hook.is_some() || args.post_hook.is_some() || !args.snapshot.is_empty())
+        && (args.chroot.is_some() || args.setuid.is_some() || !args.confine_to.is_empty())
+    {
+        return Err(eyre!(
+            "--pre-hook, --post-hook and --snapshot can't be combined with \
+             --chroot, --setuid or --confine-to"
+        ));
+    }
+    // and so does running anything, which a sandbox may not reach or be
+    // allowed to start
+    if args.chroot.is_some() || args.setuid.is_some() || !args.confine_to.is_empty() {
+        if args.output_compression() == Some(compress::Compression::Zstd) {
+            return Err(eyre!(
+                "zstd output runs zstd, which can't be combined with --chroot, --setuid or \
+                 --confine-to: use --compress gzip"
+            ));
+        }
+        if let Some(algorithm) = args
+            .algos
+            .iter()
+            .flatten()
+            .find(|algorithm| matches!(algorithm, algo::Algorithm::External(_)))
+        {
+            return Err(eyre!(
+                "--algo {} runs a command, which can't be combined with --chroot, --setuid \
+                 or --confine-to",
+                algorithm.name()
+            ));
+        }
+    }
+    #[cfg(feature = "snapshots")]
+    let snapshots = args
+        .snapshot
+        .iter()
+        .map(|s| s.parse())
+        .collect::<Result<Vec<snapshot::Snapshot>, _>>()?;
+    #[cfg(not(feature = "snapshots"))]
+    if !args.snapshot.is_empty() {
+        return Err(eyre!(
+            "--snapshot needs a build with the `snapshots` feature"
+        ));
+    }
+
+    if let Some(hook) = &args.pre_hook {
+        hooks::run("--pre-hook", hook, &[])?;
+    }
+    // from here on, the post hook runs no matter what
+    let post_hook = args.post_hook.clone();
+    let hashed = || {
+        sandbox(&args)?;
+        async_std::task::block_on(run(args))
+    };
+    #[cfg(feature = "snapshots")]
+    let res = snapshot::around(&snapshots, hashed);
+    #[cfg(not(feature = "snapshots"))]
+    let res = hashed();
+
+    if let Some(hook) = post_hook {
+        let status = if res.is_ok() { "success" } else { "failure" };
+        let hooked = hooks::run("--post-hook", &hook, &[("SURVIVING_STATUS", status)]);
+        // the run's own error matters most
+        return exit_status(res.and(hooked));
+    }
+    exit_status(res)
+}
+
+/// Exits with --expect's own status when that's what failed, with 4 when
+/// --max-errors stopped the run and with 130, as shells do, when it was
+/// interrupted; any other error exits with 1 as usual
+fn exit_status(res: Result<(), eyre::Error>) -> Result<(), eyre::Error> {
+    if let Err(e) = &res {
+        if let Some(unverified) = e.downcast_ref::<Unverified>() {
+            eprintln!("{}", unverified);
+            std::process::exit(unverified.code());
+        }
+        if let Some(too_many) = e.downcast_ref::<TooManyErrors>() {
+            eprintln!("{}", too_many);
+            std::process::exit(4);
+        }
+        if let Some(interrupted) = e.downcast_ref::<Interrupted>() {
+            eprintln!("{}", interrupted);
+            std::process::exit(130);
+        }
+    }
+    res
+}
+
+/// Everything a run may write under `--confine-to`, from every option that
+/// names an output, so the sandbox can't miss one
+#[cfg(target_os = "linux")]
+fn writable(args: &Args) -> Result<Vec<PathBuf>, eyre::Error> {
+    if args.cache == Some(cache::Store::Xattr) {
+        return Err(eyre!(
+            "--cache xattr writes to every input, which --confine-to doesn't allow: use --cache sidecar:PATH"
+        ));
+    }
+    let signature = args
+        .sign
+        .as_ref()
+        .and(args.output.as_deref())
+        .map(sign::signature_path);
+    let sidecar = match &args.cache {
+        Some(cache::Store::Sidecar(path)) => Some(path),
+        _ => None,
+    };
+    // outputs may not exist yet, so they're allowed through their parent
+    let files = [
+        args.output.as_ref(),
+        args.bloom_out.as_ref(),
+        args.emit_state.as_ref(),
+        args.metrics_out.as_ref(),
+        args.timings_out.as_ref(),
+        args.heatmap.as_ref(),
+        args.mismatch_journal.as_ref(),
+        args.leftovers.as_ref(),
+        args.tree_leaves.as_ref(),
+        args.bad_ranges.as_ref(),
+        sidecar,
+        signature.as_ref(),
+    ];
+    let mut write: Vec<PathBuf> = files
+        .iter()
+        .flatten()
+        .map(|path| match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent.to_owned(),
+            _ => PathBuf::from("."),
+        })
+        .collect();
+    // directories are made up front, since only what exists can be allowed
+    let quarantine = match &args.on_mismatch {
+        remedy::OnMismatch::Quarantine(dir) => Some(dir),
+        _ => None,
+    };
+    for dir in [
+        args.shard_dir.as_ref(),
+        args.record_run.as_ref(),
+        quarantine,
+    ]
+    .iter()
+    .flatten()
+    {
+        std::fs::create_dir_all(dir)?;
+        write.push(dir.to_path_buf());
+    }
+    write.extend(args.resume_dir().map(Path::to_owned));
+    if args.sort.is_some() {
+        write.push(args.sort_dir.clone().unwrap_or_else(std::env::temp_dir));
+    }
+    Ok(write)
+}
+
+/// Gives up whatever `--chroot`, `--setuid` and `--confine-to` ask for
+#[cfg(target_os = "linux")]
+fn sandbox(args: &Args) -> Result<(), eyre::Error> {
+    let user = args.setuid.as_deref().map(sandbox::lookup).transpose()?;
+    if let Some(dir) = &args.chroot {
+        sandbox::chroot(dir)?;
+    }
+    if !args.confine_to.is_empty() {
+        let mut read = args.confine_to.clone();
+        read.extend(
+            args.files
+                .iter()
+                .filter(|path| !open::is_stdin(path) && !remote::is_url(path))
+                .cloned(),
+        );
+        read.extend(
+            [
+                &args.bloom_check,
+                &args.dedupe_against,
+                &args.resume_state,
+                &args.only_matching,
+            ]
+            .iter()
+            .copied()
+            .flatten()
+            .cloned(),
+        );
+        let write = writable(args)?;
+        sandbox::confine(&read, &write)?;
+    }
+    if let Some(user) = user {
+        sandbox::drop_to(user)?;
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn sandbox(args: &Args) -> Result<(), eyre::Error> {
+    if args.chroot.is_some() || args.setuid.is_some() || !args.confine_to.is_empty() {
+        return Err(eyre!(
+            "--chroot, --setuid and --confine-to are only supported on Linux"
+        ));
+    }
+    Ok(())
+}
+
+#[tracing::instrument(skip(args))]
+async fn run(args: Args) -> Result<(), eyre::Error> {
+    if args.schema {
+        println!("{}", serde_json::to_string_pretty(&output::schema())?);
+        return Ok(());
+    }
+    // before anything is read, rather than failing on every file
+    args.hasher()?;
+    if args.jobs == Some(0) || args.per_device_jobs == Some(0) {
+        return Err(eyre!("--jobs and --per-device-jobs must be at least 1"));
+    }
+    if args.pin_hash_threads && args.hash_threads == Some(0) {
+        return Err(eyre!("--pin-hash-threads needs hashing threads"));
+    }
+    if args.pin_hash_threads && !cfg!(target_os = "linux") {
+        return Err(eyre!("--pin-hash-threads is only supported on Linux"));
+    }
+    match args.threads {
+        0 => return Err(eyre!("--threads must be at least 1")),
+        1 => {}
+        _ if args.algorithm() != algo::Algorithm::Blake3 => {
+            return Err(eyre!(
+                "--threads only applies to BLAKE3, whose tree can be hashed in parts"
+            ))
+        }
+        _ if args.tree.is_some() || args.checkpoint_every.is_some() => {
+            return Err(eyre!(
+                "--threads can't be combined with --tree or --checkpoint-every"
+            ))
+        }
+        _ => {}
+    }
+    if args.watch {
+        check_watch(&args)?;
+    } else if args.debounce.is_some() {
+        return Err(eyre!("--debounce needs --watch"));
+    }
+    if args.evict_after_read && (args.keep_cache || args.populate_cache) {
+        return Err(eyre!(
+            "--evict-after-read can't be combined with --keep-cache or --populate-cache"
+        ));
+    }
+    if args.populate_cache && !cfg!(target_os = "linux") {
+        return Err(eyre!("--populate-cache is only supported on Linux"));
+    }
+    if args.batch_small_files == Some(0) {
+        return Err(eyre!("--batch-small-files must be at least 1"));
+    }
+    if args.bad_ranges.is_some() && !args.salvage {
+        return Err(eyre!("--bad-ranges needs --salvage"));
+    }
+    if args.only_new && args.dedupe_against.is_none() {
+        return Err(eyre!("--only-new needs --dedupe-against"));
+    }
+    if args.salvage && args.backend == source::Backend::Mmap {
+        return Err(eyre!(
+            "--salvage needs --backend read, a mapped file can't be read past an error"
+        ));
+    }
+    if (args.retries.is_some() || args.read_timeout.is_some())
+        && args.backend == source::Backend::Mmap
+    {
+        return Err(eyre!(
+            "--retries and --read-timeout need --backend read, a mapped file isn't read"
+        ));
+    }
+    if args.retry_backoff.is_some() && args.retries.is_none() {
+        return Err(eyre!("--retry-backoff needs --retries"));
+    }
+    if args
+        .read_timeout
+        .is_some_and(|units::Duration(d)| d.is_zero())
+    {
+        return Err(eyre!("--read-timeout must be more than zero"));
+    }
+    match (args.tree, &args.tree_leaves) {
+        (Some(units::ByteSize(0)), _) => return Err(eyre!("--tree must be more than zero")),
+        (Some(_), _) if args.checkpoint_every.is_some() => {
+            return Err(eyre!("--tree and --checkpoint-every can't be combined"))
+        }
+        (Some(_), _) if matches!(args.algorithm(), algo::Algorithm::External(_)) => {
+            return Err(eyre!(
+                "--tree doesn't work with external algorithms, which can't hash leaves apart"
+            ))
+        }
+        (Some(_), _)
+            if args.command.is_some()
+                || args.check.is_some()
+                || !args.expect.is_empty()
+                || args.verify_stream.is_some()
+                || args.framed
+                || args.resume_state.is_some()
+                || args.emit_state.is_some()
+                || args.mime =>
+        {
+            return Err(eyre!(
+                "--tree only applies to hashing files; check a chunk with `tree-verify`"
+            ))
+        }
+        (Some(_), _) if matches!(args.format, output::Format::Gnu | output::Format::Bsd) => {
+            return Err(eyre!(
+                "--tree roots aren't digests other tools can check, so the gnu and bsd formats can't carry them"
+            ))
+        }
+        (None, Some(_)) => return Err(eyre!("--tree-leaves needs --tree")),
+        _ => {}
+    }
+    if args.limit_rate == Some(units::ByteSize(0)) {
+        return Err(eyre!("--limit-rate needs more than 0 bytes a second"));
+    }
+    if !args.also().is_empty() {
+        if args.command.is_some()
+            || args.check.is_some()
+            || !args.expect.is_empty()
+            || args.verify_stream.is_some()
+            || args.framed
+            || args.resume_state.is_some()
+            || args.emit_state.is_some()
+            || args.mime
+        {
+            return Err(eyre!("several --algo only apply to hashing files"));
+        }
+        if args.tree.is_some()
+            || args.checkpoint_every.is_some()
+            || args.archive
+            || args.threads > 1
+        {
+            return Err(eyre!(
+                "several --algo can't be combined with --tree, --checkpoint-every, --archive or --threads"
+            ));
+        }
+        if args.unique.is_some()
+            || matches!(
+                args.format,
+                output::Format::Groups | output::Format::GroupsJson
+            )
+        {
+            return Err(eyre!(
+                "several --algo give each file several digests, which --unique and the group formats can't tell apart"
+            ));
+        }
+        if let Some(key) = &args.hmac_key {
+            for algorithm in args.also() {
+                algorithm.keyed_hasher(key.bytes())?;
+            }
+        }
+    }
+    if args.resume_dir().is_some() {
+        if args.emit_state.is_some() {
+            return Err(eyre!(
+                "--emit-state saves one input's state to a file, not to a --resume-state directory"
+            ));
+        }
+        if args.checkpoint_every.is_some() || args.archive || args.threads > 1 {
+            return Err(eyre!(
+                "a --resume-state directory can't be combined with --checkpoint-every, --archive or --threads"
+            ));
+        }
+        if args.hmac_key.is_some() {
+            return Err(eyre!(
+                "a --resume-state directory would hold states keyed with --hmac-key, so they can't be combined"
+            ));
+        }
+        if args.backend == source::Backend::Mmap {
+            return Err(eyre!(
+                "a --resume-state directory needs --backend read, to seek to where a file was left"
+            ));
+        }
+        if args.algorithm().hasher().serialize().is_none() {
+            return Err(eyre!(
+                "{}'s state can't be saved, so a --resume-state directory can't be used with it",
+                args.algorithm()
+            ));
+        }
+    }
+    match args.state_every {
+        Some(_) if args.resume_dir().is_none() => {
+            return Err(eyre!("--state-every needs a --resume-state directory"))
+        }
+        Some(units::ByteSize(0)) => return Err(eyre!("--state-every must be more than zero")),
+        _ => {}
+    }
+    if let Some(command) = &args.command {
+        return match command {
+            Command::Sums(SumsArgs {
+                command: SumsCommand::Convert(convert),
+            }) => convert_sums(convert).await,
+            Command::Sfv(SfvArgs {
+                command: SfvCommand::Create(create),
+            }) => create_sfv(&args, create).await,
+            Command::Sfv(SfvArgs {
+                command: SfvCommand::Check(check),
+            }) => check_sfv(&args, check).await,
+            Command::Parity(ParityArgs {
+                command: ParityCommand::Create(create),
+            }) => create_parity(create).await,
+            Command::Parity(ParityArgs {
+                command: ParityCommand::Repair(repair),
+            }) => repair_parity(repair).await,
+            Command::Zsync(zsync) => make_zsync(zsync).await,
+            Command::CargoChecksum(CargoChecksumArgs {
+                command: CargoChecksumCommand::Create(create),
+            }) => create_cargo_checksum(&args, create).await,
+            Command::CargoChecksum(CargoChecksumArgs {
+                command: CargoChecksumCommand::Verify(verify),
+            }) => verify_cargo_checksum(&args, verify).await,
+            Command::Repo(RepoArgs {
+                command: RepoCommand::Verify(verify),
+            }) => verify_repo(&args, verify).await,
+            Command::LinkFarm(farm) => make_link_farm(&args, farm).await,
+            Command::Capabilities(capabilities) => {
+                let detected = surviving::capabilities::detect();
+                if capabilities.json {
+                    println!("{}", serde_json::to_string_pretty(&detected)?);
+                } else {
+                    print!("{}", detected.text());
+                }
+                Ok(())
+            }
+            Command::SelfTest(_) => self_test().await,
+            Command::Bench(bench) => run_bench(bench).await,
+            Command::Snapshot(snapshot) => take_snapshot(&args, snapshot).await,
+            Command::Verify(verify) => verify_snapshot(&args, verify).await,
+            Command::TreeVerify(verify) => verify_tree(&args, verify).await,
+            Command::Dupes(dupes) => find_dupes(&args, dupes).await,
+            Command::UploadVerify(verify) => verify_upload(&args, verify).await,
+            Command::Run(_) => unreachable!("profiles are expanded while parsing arguments"),
+            Command::Copy(copy) => {
+                let copied = tee::copy(&copy.src, &copy.dest, &hash_options(&args)).await?;
+                tracing::debug!(dest = %copied.dest.display(), size = copied.size, "copied");
+                // the same line hashing the source on its own would print
+                let mut line = format!("{} {}", copy.src.display(), hex(&copied.hash));
+                if args.algorithm() != algo::Algorithm::Sha3_256 {
+                    line += &format!(" algo={}", args.algorithm());
+                }
+                if args.hmac_key.is_some() {
+                    line += " keyed";
+                }
+                println!("{}", line);
+                Ok(())
+            }
+            Command::Runs(RunsArgs {
+                command: RunsCommand::List(list),
+            }) => {
+                print!("{}", runs::list(&runs::load(&list.dir).await?));
+                Ok(())
+            }
+            Command::Runs(RunsArgs {
+                command: RunsCommand::Compare(compare),
+            }) => compare_runs(compare).await,
+        };
+    }
+
+    if args.framed {
+        if !args.files.is_empty() || args.files_from.is_some() {
+            return Err(eyre!("--framed reads from stdin and takes no inputs"));
+        }
+        let buffer = tune::Buffer::new(args.buffer_size());
+        framed::hash_frames(
+            async_std::io::stdin(),
+            buffer.size(),
+            || args.hasher(),
+            |index, hash| println!("{} {}", index, hex(&hash)),
+        )
+        .await?;
+        return Ok(());
+    }
+
+    if let Some(expected) = &args.verify_stream {
+        if !args.files.is_empty() || args.files_from.is_some() {
+            return Err(eyre!(
+                "--verify-stream reads from stdin and takes no inputs"
+            ));
+        }
+        return verify_stream(&args, expected).await;
+    }
+    if args.truncate_on_mismatch {
+        return Err(eyre!("--truncate-on-mismatch needs --verify-stream"));
+    }
+
+    if !args.expect.is_empty() {
+        if args.check.is_some() {
+            return Err(eyre!("--expect and --check can't be combined"));
+        }
+        return check_expected(&args).await;
+    }
+
+    if let Some(manifest) = &args.check {
+        if !args.files.is_empty() || args.files_from.is_some() {
+            return Err(eyre!(
+                "--check verifies the files its manifest lists and takes no inputs"
+            ));
+        }
+        return check_manifest(&args, manifest).await;
+    }
+
+    if args.resume_file().is_some() || args.emit_state.is_some() {
+        return hash_with_state(&args).await;
+    }
+
+    if args.mime {
+        return hash_attachments(&args).await;
+    }
+
+    if args.watermark {
+        if !matches!(
+            args.format,
+            output::Format::Lines | output::Format::Gnu | output::Format::Bsd
+        ) {
+            return Err(eyre!(
+                "--watermark only applies to the `lines`, `gnu` and `bsd` formats"
+            ));
+        }
+        if args.shard_output_by_dir.is_some() {
+            return Err(eyre!(
+                "--watermark can't be combined with --shard-output-by-dir"
+            ));
+        }
+    }
+    if args.unique.is_some() && args.format != output::Format::Lines {
+        return Err(eyre!("--unique only applies to the `lines` format"));
+    }
+    if args.sort.is_some() {
+        if args.unique.is_some() || args.ordered || args.shard_output_by_dir.is_some() {
+            return Err(eyre!(
+                "--sort can't be combined with --unique, --ordered or --shard-output-by-dir"
+            ));
+        }
+        if matches!(
+            args.format,
+            output::Format::Groups | output::Format::GroupsJson
+        ) {
+            return Err(eyre!(
+                "the `groups` and `groups-json` formats are in digest order already"
+            ));
+        }
+    }
+    if args.sign.is_some() && args.output.is_none() {
+        return Err(eyre!("--sign needs --output"));
+    }
+    if args.verify_signature.is_some() && args.check.is_none() {
+        return Err(eyre!("--verify-signature needs --check"));
+    }
+    if args.null && args.format == output::Format::Groups {
+        return Err(eyre!("--null doesn't apply to the `groups` format"));
+    }
+    match args.sort_memory {
+        _ if args.sort.is_none() && (args.sort_memory.is_some() || args.sort_dir.is_some()) => {
+            return Err(eyre!("--sort-memory and --sort-dir need --sort"))
+        }
+        Some(units::ByteSize(0)) => return Err(eyre!("--sort-memory must be more than zero")),
+        _ => {}
+    }
+    if args.report_by.is_some() && !args.stats {
+        return Err(eyre!("--report-by needs --stats"));
+    }
+    if args.archive && (args.tree.is_some() || args.checkpoint_every.is_some()) {
+        return Err(eyre!(
+            "--archive can't be combined with --tree or --checkpoint-every"
+        ));
+    }
+    if args.reads == 0 {
+        return Err(eyre!("--reads must be at least 1"));
+    }
+    if args.checkpoint_every.is_some() && matches!(args.algorithm(), algo::Algorithm::External(_)) {
+        return Err(eyre!(
+            "--checkpoint-every doesn't work with external algorithms, which only give a digest at the end"
+        ));
+    }
+    if let Some(units::ByteSize(0)) = args.checkpoint_every {
+        return Err(eyre!("--checkpoint-every must be more than zero"));
+    }
+
+    // before the first round, so what changes during it isn't missed
+    let watcher = if args.watch {
+        Some(watch::Watcher::new(&args.files)?)
+    } else {
+        None
+    };
+    let pass = hash_inputs(&args, None).await?;
+    if let Some(watcher) = watcher {
+        // failures are reported as they come, and the files hashed again
+        // once they change
+        watch(&args, watcher, pass.files).await?;
+    }
+    if pass.failed > 0 {
+        return Err(eyre!("{} file(s) couldn't be hashed", pass.failed));
+    }
+    Ok(())
+}
+
+/// Hashes and prints the inputs, or only the files in `only`
+async fn hash_inputs(args: &Args, only: Option<Vec<PathBuf>>) -> Result<Pass, eyre::Error> {
+    let started = std::time::Instant::now();
+    let started_at = std::time::SystemTime::now();
+    let bloom_check = match &args.bloom_check {
+        Some(path) => {
+            let mut file = std::io::BufReader::new(std::fs::File::open(path)?);
+            Some(bloom::Bloom::read_from(&mut file)?)
+        }
+        None => None,
+    };
+    let dedupe = match &args.dedupe_against {
+        Some(path) => Some(load_known(args, path).await?),
+        None => None,
+    };
+    let leftovers = match &args.leftovers {
+        Some(path) => budget::load(path).await?,
+        None => None,
+    };
+    let ids = Arc::new(output::Ids::default());
+    // inputs are hashed as they're listed and walked, unless something
+    // needs every file up front
+    let streamed = only.is_none() && leftovers.is_none() && streams_inputs(args);
+    let files = match (only, &leftovers) {
+        _ if streamed => Vec::new(),
+        (Some(only), _) => only,
+        (None, Some(leftovers)) => {
+            eprintln!(
+                "resuming {} file(s) left by a run stopped at {}",
+                leftovers.paths.len(),
+                leftovers.stopped_at
+            );
+            leftovers.paths.clone()
+        }
+        (None, None) => {
+            let (files, found_ids) = expand_inputs_with_ids(args).await?;
+            for (index, id) in found_ids.into_iter().enumerate() {
+                if let Some(id) = id {
+                    ids.insert(index, id);
+                }
+            }
+            files
+        }
+    };
+
+    let shards = match (args.shard_output_by_dir, &args.shard_dir) {
+        (Some(_), _) if args.unique.is_some() || args.format != output::Format::Lines => {
+            return Err(eyre!(
+                "--shard-output-by-dir only applies to the `lines` format, without --unique"
+            ))
+        }
+        (Some(depth), Some(dir)) => Some(shard::Shards::new(dir.clone(), depth, &files)),
+        (Some(_), None) => return Err(eyre!("--shard-output-by-dir needs --shard-dir")),
+        (None, Some(_)) => return Err(eyre!("--shard-dir needs --shard-output-by-dir")),
+        (None, None) => None,
+    };
+
+    let filter = filter::Filter {
+        digests: match &args.only_matching {
+            Some(path) => Some(filter::load_digests(path).await?),
+            None => None,
+        },
+        min_size: args.min_size.map(|units::ByteSize(n)| n),
+        max_size: args.max_size.map(|units::ByteSize(n)| n),
+        min_duration: args.min_duration.map(|units::Duration(d)| d),
+        paths: if args.only_paths.is_empty() {
+            None
+        } else {
+            Some(filter::globs(&args.only_paths)?)
+        },
+    };
+
+    let mut limits = HashMap::new();
+    for limit in &args.device_jobs {
+        let device = devices::device_of(&limit.path)
+            .await
+            .ok_or_else(|| eyre!("can't determine the device of {}", limit.path.display()))?;
+        limits.insert(device, limit.jobs);
+    }
+
+    // each device gets its own pool of workers, so a slow disk only holds up
+    // the files that live on it; ordered, so workers start the same way on
+    // every run
+    let mut groups: BTreeMap<Option<devices::DeviceId>, Vec<(usize, PathBuf)>> = BTreeMap::new();
+    for (index, file) in files.iter().enumerate() {
+        groups
+            .entry(devices::device_of(file).await)
+            .or_default()
+            .push((index, file.clone()));
+    }
+
+    // all output goes through a single writer, and workers wait for it when
+    // it falls behind rather than piling up results in memory
+    let (results_tx, results_rx) = async_std::channel::bounded(output::RESULTS_CAPACITY);
+    // kept whether or not it's served, so an interrupt can print what was
+    // in flight
+    let console = Arc::new(console::Console::default());
+    if let Some(addr) = &args.console {
+        console.clone().serve(addr).await?;
+    }
+    let results = results_rx.clone();
+    console.queue("results", move || results.len());
+    if let Some(pool) = hash_pool(args) {
+        console.queue("hashing", move || pool.queued());
+    }
+    let progress = args.progress.then(|| {
+        let bytes = files
+            .iter()
+            .filter_map(|file| std::fs::metadata(file).ok())
+            .map(|metadata| metadata.len())
+            .sum();
+        progress::Progress::start(files.len(), bytes)
+    });
+    let writer = output::Writer {
+        watermark: args.watermark.then(|| {
+            watermark::Watermark::new(args.algorithm(), args.hmac_key.is_some(), started_at).line()
+        }),
+        bloom_check,
+        dedupe,
+        only_new: args.only_new,
+        bloom_out: args
+            .bloom_out
+            .as_ref()
+            .map(|_| bloom::Bloom::with_capacity(files.len(), args.fpr)),
+        unique: args.unique.map(output::Unique::new),
+        shards,
+        filter,
+        metrics: Default::default(),
+        stats: args.stats.then(|| match args.report_by {
+            Some(by) => stats::Stats::grouped(by, args.files.clone()),
+            None => Default::default(),
+        }),
+        timings: args
+            .timings_out
+            .as_ref()
+            .map(output::TimingsFile::create)
+            .transpose()?,
+        format: args.format,
+        quote: args.quote,
+        null: args.null,
+        previous_audit: match &args.audit_against {
+            Some(path) => Some(audit::load(path).await?),
+            None => None,
+        },
+        algorithm: args.algorithm(),
+        keyed: args.hmac_key.is_some(),
+        tree: args.tree.map(|units::ByteSize(n)| n),
+        leaves: match (&args.tree_leaves, args.tree) {
+            (Some(path), Some(units::ByteSize(chunk_size))) => {
+                Some(merkle::Sidecar::create(path, chunk_size)?)
+            }
+            _ => None,
+        },
+        bad_ranges: args
+            .bad_ranges
+            .as_deref()
+            .map(salvage::Map::create)
+            .transpose()?,
+        groups: Default::default(),
+        heatmap: args.heatmap.as_ref().map(|_| Default::default()),
+        records: 0,
+        ordered: args.ordered.then(Default::default),
+        progress: progress.clone(),
+        ids: ids.clone(),
+        aliases: if args.report_aliases {
+            walk::aliases(&files)
+        } else {
+            Vec::new()
+        },
+        output: match &args.output {
+            Some(path) => Some(compress::Output::create(path, args.output_compression())?),
+            None if args.compress.is_some() => {
+                return Err(eyre!("--compress needs --output"));
+            }
+            None => None,
+        },
+        sort: args.sort.map(|by| {
+            sort::Sorter::new(
+                by,
+                args.sort_memory
+                    .map_or(sort::DEFAULT_MEMORY, |units::ByteSize(n)| n),
+                args.sort_dir.clone().unwrap_or_else(std::env::temp_dir),
+            )
+        }),
+    };
+    let writer = async_std::task::spawn(writer.run(results_rx));
+
+    let budget = Arc::new(budget::Budget::new(budget::Limits {
+        runtime: args.max_runtime.map(|units::Duration(d)| d),
+        bytes: args.max_bytes.map(|units::ByteSize(n)| n),
+        files: args.max_files,
+        errors: args.max_errors.map(|max| max.of(files.len())),
+        fail_fast: args.fail_fast,
+    }));
+    let not_started = Arc::new(std::sync::Mutex::new(Vec::new()));
+    // files in flight when the run was interrupted, which are also left
+    // for the next run
+    let aborted = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+    let interrupts = cancel::Guard::install();
+    let status = console.clone();
+    interrupts.report(move || status.snapshot());
+
+    let cache = match &args.cache {
+        Some(_) if args.hmac_key.is_some() => {
+            return Err(eyre!("--cache can't be combined with --hmac-key"))
+        }
+        Some(store) => {
+            let cache = cache::Cache::load(store, args.algorithm().name()).await?;
+            Some(Arc::new(if args.no_cache_read {
+                cache.write_only()
+            } else {
+                cache
+            }))
+        }
+        None if args.no_cache_read => return Err(eyre!("--no-cache-read needs --cache")),
+        None => None,
+    };
+
+    // workers take one of these slots for each file, so no more than --jobs
+    // files are hashed at once across all devices
+    let total_jobs = jobs(args);
+    let (slots_tx, slots_rx) = async_std::channel::bounded(total_jobs);
+    for _ in 0..total_jobs {
+        slots_tx.try_send(())?;
+    }
+
+    let workers = Workers {
+        options: Arc::new(HashOptions {
+            inspect: inspect::InspectOptions {
+                detect_type: args.detect_type,
+                entropy: args.entropy,
+                zero_runs: args.detect_zero_runs.map(|units::ByteSize(n)| n),
+            },
+            console: Some(console.clone()),
+            progress: progress.clone(),
+            cache: cache.clone(),
+            ..hash_options(args)
+        }),
+        results: results_tx.clone(),
+        slots: (slots_tx, slots_rx),
+        budget: budget.clone(),
+        not_started: not_started.clone(),
+        aborted: aborted.clone(),
+        count_bytes: args.max_bytes.is_some(),
+        batch_small_files: args.batch_small_files,
+        console: console.clone(),
+    };
+    let device_jobs = DeviceJobs {
+        limits,
+        per_device: args.per_device_jobs,
+        total: total_jobs,
+    };
+    let mut handles = Vec::new();
+    let feeder = match streamed {
+        true => Some(async_std::task::spawn(feed(
+            workers.clone(),
+            device_jobs,
+            Feed {
+                files: args.files.clone(),
+                list: args.files_from.clone(),
+                null: args.null,
+                walk: walk_options(args)?,
+                ids: ids.clone(),
+            },
+        ))),
+        false => {
+            for (device, files) in groups {
+                let jobs = device_jobs.of(device).min(files.len());
+                tracing::debug!(
+                    ?device,
+                    files = files.len(),
+                    jobs,
+                    "starting device workers"
+                );
+                let (tx, rx) = async_std::channel::unbounded();
+                for file in files {
+                    tx.try_send(file)?;
+                }
+                drop(tx);
+                handles.extend(workers.start(device, rx, jobs));
+            }
+            None
+        }
+    };
+    drop(workers);
+    drop(results_tx);
+    // the inputs were read as far as they could be, whatever stopped it
+    let fed = match feeder {
+        Some(feeder) => {
+            let (started, fed) = feeder.await;
+            handles.extend(started);
+            fed
+        }
+        None => Ok(()),
+    };
+
+    for handle in handles {
+        handle.await;
+    }
+    let writer = writer.await;
+    // a writer that failed has left the bars up
+    if let Some(progress) = &progress {
+        progress.finish();
+    }
+    let writer = writer?;
+    fed?;
+    if let (Some(key), Some(path)) = (&args.sign, &args.output) {
+        sign_output(key, path).await?;
+    }
+    write_heatmap(args, writer.heatmap.as_ref())?;
+    if let Some(cache) = &cache {
+        cache.save().await?;
+    }
+
+    if let (Some(path), Some(bloom)) = (&args.bloom_out, &writer.bloom_out) {
+        let mut file = std::io::BufWriter::new(std::fs::File::create(path)?);
+        bloom.write_to(&mut file)?;
+        std::io::Write::flush(&mut file)?;
+    }
+
+    if args.metrics_out.is_some() || args.pushgateway.is_some() {
+        let metrics = writer.metrics.render(
+            args.algorithm(),
+            started.elapsed(),
+            std::time::SystemTime::now(),
+        );
+        if let Some(path) = &args.metrics_out {
+            metrics::write_textfile(path, &metrics).await?;
+        }
+        if let Some(url) = &args.pushgateway {
+            metrics::push(url, &metrics).await?;
+        }
+    }
+
+    if let Some(dir) = &args.record_run {
+        let run = runs::Run::new(
+            args.algorithm(),
+            started_at,
+            started.elapsed(),
+            &writer.metrics,
+        );
+        runs::record(dir, &run).await?;
+    }
+
+    if let Some(stats) = &writer.stats {
+        print_stats(args, stats, &writer.metrics, started.elapsed())?;
+    }
+
+    match budget.exceeded() {
+        Some(stopped_at) => {
+            let mut not_started = std::mem::take(&mut *not_started.lock().unwrap());
+            not_started.sort();
+            let paths: Vec<_> = not_started.into_iter().map(|(_, path)| path).collect();
+            let not_hashed = paths.len();
+            if stopped_at != budget::Exceeded::Interrupted {
+                eprintln!(
+                    "stopped at {}, {} file(s) not hashed",
+                    stopped_at, not_hashed
+                );
+            }
+            if let Some(path) = &args.leftovers {
+                budget::save(path, &budget::Leftovers { stopped_at, paths }).await?;
+            }
+            if stopped_at == budget::Exceeded::MaxErrors {
+                return Err(TooManyErrors {
+                    errors: budget.errors(),
+                }
+                .into());
+            }
+            if stopped_at == budget::Exceeded::Interrupted {
+                let aborted = aborted.load(std::sync::atomic::Ordering::Relaxed);
+                return Err(Interrupted {
+                    hashed: writer.metrics.files,
+                    failed: writer.metrics.errors,
+                    aborted,
+                    not_started: not_hashed - aborted,
+                }
+                .into());
+            }
+        }
+        None => {
+            if let (Some(path), Some(_)) = (&args.leftovers, &leftovers) {
+                async_std::fs::remove_file(path).await?;
+            }
+        }
+    }
+
+    Ok(Pass {
+        files,
+        failed: writer.metrics.errors,
+    })
+}
+
+/// Signs the manifest written to `path` into the file next to it
+async fn sign_output(key: &sign::SecretKey, path: &Path) -> Result<(), eyre::Error> {
+    let digest = {
+        let path = path.to_owned();
+        async_std::task::spawn_blocking(move || sign::digest(std::fs::File::open(path)?)).await?
+    };
+    let timestamp = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)?
+        .as_secs();
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    // as minisign writes it
+    let trusted_comment = format!("timestamp:{}\tfile:{}\thashed", timestamp, name);
+    let signature = sign::signature_path(path);
+    async_std::fs::write(&signature, key.sign(&digest, &trusted_comment))
+        .await
+        .map_err(|e| eyre!("can't write {}: {}", signature.display(), e))?;
+    tracing::debug!(path = %signature.display(), "signed the manifest");
+    Ok(())
+}
+
+/// What a round of hashing went through
+struct Pass {
+    /// Empty when the inputs were hashed as they were listed and walked
+    files: Vec<PathBuf>,
+    /// How many of them couldn't be hashed
+    failed: u64,
+}
+
+/// How many workers each device gets
+struct DeviceJobs {
+    /// From --device-jobs
+    limits: HashMap<devices::DeviceId, usize>,
+    per_device: Option<usize>,
+    total: usize,
+}
+
+impl DeviceJobs {
+    fn of(&self, device: Option<devices::DeviceId>) -> usize {
+        device
+            .and_then(|device| self.limits.get(&device).copied())
+            .or(self.per_device)
+            .unwrap_or(self.total)
+    }
+}
+
+/// What each worker shares with the others, whichever device it's on
+#[derive(Clone)]
+struct Workers {
+    options: Arc<HashOptions>,
+    results: async_std::channel::Sender<output::FileResult>,
+    /// Taken for each file, so no more than --jobs are hashed at once
+    slots: (
+        async_std::channel::Sender<()>,
+        async_std::channel::Receiver<()>,
+    ),
+    budget: Arc<budget::Budget>,
+    not_started: Arc<std::sync::Mutex<Vec<(usize, PathBuf)>>>,
+    aborted: Arc<std::sync::atomic::AtomicUsize>,
+    /// Whether files are stat'ed for --max-bytes
+    count_bytes: bool,
+    batch_small_files: Option<usize>,
+    console: Arc<console::Console>,
+}
+
+impl Workers {
+    /// Starts `jobs` workers on the files of `device` coming in on `rx`
+    fn start(
+        &self,
+        device: Option<devices::DeviceId>,
+        rx: async_std::channel::Receiver<(usize, PathBuf)>,
+        jobs: usize,
+    ) -> Vec<async_std::task::JoinHandle<()>> {
+        let queued = rx.clone();
+        let name = match device {
+            Some(device) => format!("device {}", device),
+            None => "unknown device".to_string(),
+        };
+        self.console.queue(name, move || queued.len());
+        // each device's reads are timed on their own, for --buffer-size auto
+        let workers = Self {
+            options: Arc::new(HashOptions {
+                buffer: self.options.buffer.fresh(),
+                ..HashOptions::clone(&self.options)
+            }),
+            ..self.clone()
+        };
+        (0..jobs)
+            .map(|_| async_std::task::spawn(workers.clone().work(rx.clone())))
+            .collect()
+    }
+
+    /// Hashes files from `rx` until there are none left, or nobody's
+    /// writing the results
+    async fn work(self, rx: async_std::channel::Receiver<(usize, PathBuf)>) {
+        let Self {
+            options,
+            results: results_tx,
+            slots: (slots_tx, slots_rx),
+            budget,
+            not_started,
+            aborted,
+            count_bytes,
+            batch_small_files,
+            console: _,
+        } = self;
+        let mut batcher = batch_small_files.map(batch::Batcher::new);
+        while let Ok(first) = rx.recv().await {
+            let mut files = vec![first];
+            if let Some(batcher) = &batcher {
+                while files.len() < batcher.size() {
+                    match rx.try_recv() {
+                        Ok(file) => files.push(file),
+                        Err(_) => break,
+                    }
+                }
+            }
+            // every worker holds a sender, so this can't fail. A
+            // batch takes a single slot, its files waiting together
+            slots_rx.recv().await.ok();
+            let started = std::time::Instant::now();
+            let sizes: Vec<Option<u64>> = match &options.dirs {
+                Some(dirs) if batcher.is_some() => {
+                    let stats = files.iter().map(|(_, path)| dirs.len(path));
+                    futures::future::join_all(stats)
+                        .await
+                        .into_iter()
+                        .map(Result::ok)
+                        .collect()
+                }
+                _ if count_bytes => {
+                    let mut sizes = Vec::new();
+                    for (_, path) in &files {
+                        sizes.push(async_std::fs::metadata(path).await.ok().map(|m| m.len()));
+                    }
+                    sizes
+                }
+                _ => vec![None; files.len()],
+            };
+            let stat = started.elapsed();
+
+            let (mut small, mut rest) = (Vec::new(), Vec::new());
+            for ((index, path), size) in files.into_iter().zip(sizes) {
+                if !budget.admit(size.unwrap_or(0)) {
+                    not_started.lock().unwrap().push((index, path));
+                } else if batcher.is_some()
+                    && size.is_some_and(|size| size < options.small_file_size)
+                {
+                    small.push((index, path));
+                } else {
+                    rest.push((index, path));
+                }
+            }
+
+            let started = std::time::Instant::now();
+            let hashed =
+                futures::future::join_all(small.iter().map(|(_, path)| hash_file(path, &options)))
+                    .await;
+            if let Some(batcher) = &mut batcher {
+                if !small.is_empty() {
+                    batcher.observe(stat, started.elapsed());
+                }
+            }
+            let mut outcomes: Vec<_> = small.into_iter().zip(hashed).collect();
+            for (index, path) in rest {
+                let outcome = hash_file(&path, &options).await;
+                outcomes.push(((index, path), outcome));
+            }
+            slots_tx.try_send(()).ok();
+
+            let mut gone = false;
+            for ((index, path), outcome) in outcomes {
+                if let Some(progress) = &options.progress {
+                    progress.file_done();
+                }
+                if matches!(&outcome, Err(e) if e.is::<cancel::Cancelled>()) {
+                    aborted.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                    budget.abandoned();
+                    not_started.lock().unwrap().push((index, path));
+                    continue;
+                }
+                // as the writer counts them, skipped files aside
+                if matches!(&outcome, Err(e) if !e.is::<filter::TooLarge>()) {
+                    budget.failed();
+                }
+                if results_tx
+                    .send(output::FileResult {
+                        index,
+                        path,
+                        outcome,
+                        member_of: None,
+                    })
+                    .await
+                    .is_err()
+                {
+                    gone = true;
+                    break;
+                }
+            }
+            if gone {
+                // the writer is gone, nobody will see further results
+                break;
+            }
+        }
+    }
+}
+
+/// The inputs the feeder hands out, hashed as they're listed and walked
+struct Feed {
+    /// Given on the command line, which come first
+    files: Vec<PathBuf>,
+    /// The --files-from list
+    list: Option<PathBuf>,
+    null: bool,
+    walk: walk::Options,
+    ids: Arc<output::Ids>,
+}
+
+/// How many files wait for a device's workers before the inputs are read
+/// any further
+const FEED_CAPACITY: usize = 1024;
+
+/// Reads the inputs a file at a time, directories walked, and hands each
+/// file to its device's workers, starting them the first time the device
+/// comes up. Returns the workers, and how reading the list went.
+async fn feed(
+    workers: Workers,
+    device_jobs: DeviceJobs,
+    feed: Feed,
+) -> (
+    Vec<async_std::task::JoinHandle<()>>,
+    Result<(), eyre::Error>,
+) {
+    let mut feeder = Feeder {
+        workers,
+        device_jobs,
+        ids: feed.ids.clone(),
+        queues: HashMap::new(),
+        handles: Vec::new(),
+        next: 0,
+    };
+    let fed = feeder.run(feed).await;
+    (feeder.handles, fed)
+}
+
+struct Feeder {
+    workers: Workers,
+    device_jobs: DeviceJobs,
+    ids: Arc<output::Ids>,
+    /// Into each device's workers
+    queues: HashMap<Option<devices::DeviceId>, async_std::channel::Sender<(usize, PathBuf)>>,
+    handles: Vec<async_std::task::JoinHandle<()>>,
+    /// The index of the next file
+    next: usize,
+}
+
+impl Feeder {
+    async fn run(&mut self, feed: Feed) -> Result<(), eyre::Error> {
+        // opened first, so a list that isn't there fails the run before
+        // anything is hashed
+        let mut list = match &feed.list {
+            Some(path) => Some((path, open_files_from(path, feed.null).await?)),
+            None => None,
+        };
+        let mut files = feed.files;
+        if files.is_empty() && list.is_none() {
+            // like coreutils, read stdin when given nothing
+            files.push(PathBuf::from(open::STDIN));
+        }
+        let mut given = files.into_iter().map(|path| (None, path));
+        loop {
+            let (id, path) = match (given.next(), &mut list) {
+                (Some(input), _) => input,
+                (None, Some((path, list))) => match next_listed(path, list).await? {
+                    Some(input) => input,
+                    None => return Ok(()),
+                },
+                (None, None) => return Ok(()),
+            };
+            match async_std::fs::metadata(&path).await {
+                Ok(metadata) if metadata.is_dir() => {
+                    // dropped early, it stops the walk
+                    let found = walk::stream(&path, &feed.walk);
+                    while let Ok(file) = found.recv().await {
+                        if !self.send(id.clone(), file?).await {
+                            return Ok(());
+                        }
+                    }
+                }
+                // anything else, including errors, is reported when hashing
+                _ => {
+                    if !self.send(id, path).await {
+                        return Ok(());
+                    }
+                }
+            }
+        }
+    }
+
+    /// Hands `path` to its device's workers, returning whether to go on
+    async fn send(&mut self, id: Option<String>, path: PathBuf) -> bool {
+        // whatever is still queued is left for the next run, what's not
+        // read yet isn't
+        if cancel::requested() || self.workers.budget.exceeded().is_some() {
+            return false;
+        }
+        let index = self.next;
+        self.next += 1;
+        if let Some(id) = id {
+            self.ids.insert(index, id);
+        }
+        let device = devices::device_of(&path).await;
+        let (workers, device_jobs, handles) = (&self.workers, &self.device_jobs, &mut self.handles);
+        let tx = self.queues.entry(device).or_insert_with(|| {
+            let jobs = device_jobs.of(device);
+            tracing::debug!(?device, jobs, "starting device workers");
+            let (tx, rx) = async_std::channel::bounded(FEED_CAPACITY);
+            handles.extend(workers.start(device, rx, jobs));
+            tx
+        });
+        // workers only stop early once the writer is gone
+        tx.send((index, path)).await.is_ok()
+    }
+}
+
+/// The digests the --dedupe-against manifest at `path` lists for this
+/// run's algorithm
+async fn load_known(args: &Args, path: &Path) -> Result<dupes::Known, eyre::Error> {
+    let input = {
+        let path = path.to_owned();
+        async_std::task::spawn_blocking(move || compress::read_to_string(&path)).await?
+    };
+    let known = sums::parse(args.dedupe_format, &input, args.algorithm().name())
+        .and_then(|entries| dupes::Known::new(entries, args.algorithm()))
+        .map_err(|e| eyre!("in {}: {}", path.display(), e))?;
+    tracing::debug!(path = %path.display(), digests = known.len(), "loaded known digests");
+    Ok(known)
+}
+
+/// Refuses what --watch can't do: it hashes the inputs as given, again and
+/// again, so it can't take a list or a stream, and what's written once at
+/// the end of a run would only hold the last round
+fn check_watch(args: &Args) -> Result<(), eyre::Error> {
+    if !cfg!(target_os = "linux") {
+        return Err(eyre!("--watch is only supported on Linux"));
+    }
+    if args.command.is_some() || args.check.is_some() || args.verify_stream.is_some() {
+        return Err(eyre!("--watch only applies to hashing files"));
+    }
+    if args.files.is_empty() || args.files_from.is_some() {
+        return Err(eyre!("--watch needs the paths to watch"));
+    }
+    let once = [
+        ("--output", args.output.is_some()),
+        ("--console", args.console.is_some()),
+        ("--leftovers", args.leftovers.is_some()),
+        ("--bloom-out", args.bloom_out.is_some()),
+        ("--timings-out", args.timings_out.is_some()),
+        ("--metrics-out", args.metrics_out.is_some()),
+        ("--pushgateway", args.pushgateway.is_some()),
+        ("--record-run", args.record_run.is_some()),
+        ("--heatmap", args.heatmap.is_some()),
+        ("--tree-leaves", args.tree_leaves.is_some()),
+        ("--bad-ranges", args.bad_ranges.is_some()),
+    ];
+    match once.iter().find(|(_, given)| *given) {
+        Some((name, _)) => Err(eyre!("--watch can't be combined with {}", name)),
+        None => Ok(()),
+    }
+}
+
+/// Hashes files under the inputs again whenever they change, for good
+async fn watch(
+    args: &Args,
+    mut watcher: watch::Watcher,
+    files: Vec<PathBuf>,
+) -> Result<(), eyre::Error> {
+    let debounce = args.debounce.map_or(
+        std::time::Duration::from_millis(200),
+        |units::Duration(d)| d,
+    );
+    let mut known: HashSet<PathBuf> = files.into_iter().collect();
+    let options = walk_options(args)?;
+    loop {
+        let (returned, changed) = rt::spawn_blocking(move || {
+            let changed = watcher.changes(debounce);
+            (watcher, changed)
+        })
+        .await;
+        watcher = returned;
+        let changed = changed?;
+
+        // walked again, so new files go by the same rules as the first ones
+        let mut below = HashMap::new();
+        for root in &args.files {
+            if root.is_dir() && changed.iter().any(|path| path.starts_with(root)) {
+                below.insert(root.clone(), walk::files(root, &options).await?);
+            }
+        }
+        let wanted = watch::select(&args.files, &below, &changed, &known);
+        if wanted.is_empty() {
+            continue;
+        }
+        tracing::debug!(
+            changed = changed.len(),
+            hashing = wanted.len(),
+            "inputs changed"
+        );
+        for file in hash_inputs(args, Some(wanted)).await?.files {
+            if file.exists() {
+                known.insert(file);
+            } else {
+                known.remove(&file);
+            }
+        }
+    }
+}
+
+fn print_stats(
+    args: &Args,
+    stats: &stats::Stats,
+    metrics: &metrics::RunMetrics,
+    elapsed: std::time::Duration,
+) -> Result<(), eyre::Error> {
+    eprint!("{}", stats.render(args.stats_format, metrics, elapsed)?);
+    // JSON is read by programs, which would trip over another line
+    #[cfg(feature = "alloc-stats")]
+    if args.stats_format == stats::Format::Text {
+        let alloc = alloc::stats();
+        eprintln!(
+            "{} allocation(s), {} byte(s) allocated, {} byte(s) at peak",
+            alloc.allocations, alloc.allocated, alloc.peak
+        );
+    }
+    Ok(())
+}
+
+async fn compare_runs(args: &RunsCompareArgs) -> Result<(), eyre::Error> {
+    let runs = runs::load(&args.dir).await?;
+    let find = |id: &Option<String>, from_end: usize| match id {
+        Some(id) => runs
+            .iter()
+            .find(|(i, _)| i == id)
+            .ok_or_else(|| eyre!("no run {:?} in {}", id, args.dir.display())),
+        None => runs
+            .len()
+            .checked_sub(from_end)
+            .map(|i| &runs[i])
+            .ok_or_else(|| eyre!("{} has fewer than two runs", args.dir.display())),
+    };
+    let (from_id, from) = find(&args.from, 2)?;
+    let (to_id, to) = find(&args.to, 1)?;
+    println!("{} -> {}", from_id, to_id);
+    print!("{}", runs::compare(from, to));
+    Ok(())
+}
+
+/// Hashes every file under `root` with the algorithm in `options`, by
+/// manifest key, along with the ones that couldn't be read
+async fn integrity_entries(
+    args: &Args,
+    root: &Path,
+    options: HashOptions,
+) -> Result<
+    (
+        BTreeMap<String, integrity::Entry>,
+        Vec<(String, eyre::Error)>,
+    ),
+    eyre::Error,
+> {
+    use futures::stream::StreamExt;
+
+    let options = Arc::new(options);
+    let files = walk::files(root, &walk_options(args)?).await?;
+    let mut results = futures::stream::iter(files)
+        .map(|path| {
+            let (key, options) = (cargo_checksum::key(root, &path), options.clone());
+            async_std::task::spawn(async move {
+                let entry = async {
+                    let hashed = hash_file(&path, &options).await?;
+                    let metadata = async_std::fs::metadata(&path).await?;
+                    Ok::<_, eyre::Error>(integrity::Entry::new(&metadata, hex(&hashed.hash)))
+                };
+                (key, entry.await)
+            })
+        })
+        .buffered(jobs(args));
+
+    let (mut entries, mut unreadable) = (BTreeMap::new(), Vec::new());
+    while let Some((key, entry)) = results.next().await {
+        match entry {
+            Ok(entry) => {
+                entries.insert(key, entry);
+            }
+            Err(e) => unreadable.push((key, e)),
+        }
+    }
+    Ok((entries, unreadable))
+}
+
+async fn take_snapshot(args: &Args, snapshot: &SnapshotArgs) -> Result<(), eyre::Error> {
+    let (files, unreadable) = integrity_entries(args, &snapshot.root, hash_options(args)).await?;
+    for (key, e) in &unreadable {
+        print_status(key, &format!("FAILED ({})", e));
+    }
+    let manifest = integrity::Manifest::new(args.algorithm().name(), &snapshot.root, files);
+    manifest.save(&snapshot.output)?;
+    eprintln!(
+        "{} file(s) in {}",
+        manifest.files.len(),
+        snapshot.output.display()
+    );
+    if !unreadable.is_empty() {
+        return Err(eyre!(
+            "{} file(s) couldn't be read and were left out",
+            unreadable.len()
+        ));
+    }
+    Ok(())
+}
+
+async fn verify_snapshot(args: &Args, verify: &VerifyArgs) -> Result<(), eyre::Error> {
+    let manifest = integrity::Manifest::load(&verify.manifest)?;
+    let root = verify.root.as_ref().unwrap_or(&manifest.root);
+    let mut options = hash_options(args);
+    options.algorithm = manifest.algorithm.parse()?;
+    let (mut files, unreadable) = integrity_entries(args, root, options).await?;
+    // a file that couldn't be read is neither removed nor changed as far as
+    // anyone can tell
+    for (key, _) in &unreadable {
+        if let Some(entry) = manifest.files.get(key) {
+            files.insert(key.clone(), entry.clone());
+        }
+    }
+
+    let mut verdicts = verdicts(args)?;
+    let mut failed = unreadable.len();
+    for (key, e) in &unreadable {
+        let status = format!("FAILED ({})", e);
+        if verify.json {
+            let record =
+                serde_json::json!({ "path": key, "change": "unreadable", "error": e.to_string() });
+            println!("{}", record);
+        } else {
+            print_status(key, &status);
+        }
+        verdicts.record(&root.join(key), key, &status).await?;
+    }
+    for difference in integrity::diff(&manifest.files, &files) {
+        let key = &difference.path;
+        if difference.change.is_failure() {
+            failed += 1;
+        }
+        if verify.json {
+            println!("{}", serde_json::to_string(&difference)?);
+        } else {
+            let status = match difference.change {
+                integrity::Change::Added => "ADDED",
+                integrity::Change::Removed => "REMOVED",
+                integrity::Change::Modified => "MODIFIED",
+                integrity::Change::Touched => "TOUCHED",
+            };
+            print_status(key, status);
+        }
+        // in the terms --on-mismatch and --heatmap go by
+        let status = match difference.change {
+            integrity::Change::Removed => "MISSING",
+            integrity::Change::Modified => "FAILED",
+            _ => continue,
+        };
+        verdicts.record(&root.join(key), key, status).await?;
+    }
+    verdicts.finish(args)?;
+
+    if failed > 0 {
+        return Err(eyre!("{} file(s) did not verify", failed));
+    }
+    Ok(())
+}
+
+async fn verify_tree(args: &Args, verify: &TreeVerifyArgs) -> Result<(), eyre::Error> {
+    use async_std::io::{prelude::SeekExt, ReadExt};
+    use std::convert::TryFrom;
+
+    let record = merkle::load(&verify.leaves, &verify.file)?;
+    let algorithm: algo::Algorithm = record.algorithm.parse()?;
+    let prototype = match (&args.hmac_key, record.keyed) {
+        (Some(key), true) => algorithm.keyed_hasher(key.bytes())?,
+        (None, false) => algorithm.hasher(),
+        (None, true) => {
+            return Err(eyre!(
+                "these leaves are keyed, pass the key with --hmac-key"
+            ))
+        }
+        (Some(_), false) => return Err(eyre!("these leaves aren't keyed, drop --hmac-key")),
+    };
+    let leaves = record
+        .leaves
+        .iter()
+        .map(|leaf| {
+            unhex(leaf)
+                .ok_or_else(|| eyre!("{} has a leaf that isn't hex", verify.leaves.display()))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let units::ByteSize(offset) = verify.offset;
+    let index = offset / record.chunk_size;
+    let expected = usize::try_from(index)
+        .ok()
+        .and_then(|i| leaves.get(i))
+        .ok_or_else(|| {
+            eyre!(
+                "offset {} is past the end of {}, which had {} chunk(s) of {}",
+                offset,
+                record.path,
+                leaves.len(),
+                record.chunk_size
+            )
+        })?;
+    // the leaves have to add up to the root, or they prove nothing
+    if hex(&merkle::root(&prototype, &leaves)) != record.root {
+        return Err(eyre!(
+            "the leaves for {} don't add up to its root",
+            record.path
+        ));
+    }
+
+    let start = index * record.chunk_size;
+    let mut file = async_std::fs::File::open(&verify.file).await?;
+    file.seek(std::io::SeekFrom::Start(start)).await?;
+    let mut chunk = Vec::new();
+    file.take(record.chunk_size).read_to_end(&mut chunk).await?;
+    let leaf = merkle::leaf(&prototype, &chunk)
+        .ok_or_else(|| eyre!("{} can't hash leaves apart", algorithm))?;
+
+    let name = format!("{}@{}", verify.file.display(), start);
+    if &leaf == expected {
+        print_status(&name, "OK");
+        Ok(())
+    } else {
+        print_status(&name, "FAILED");
+        Err(eyre!(
+            "bytes {}..{} of {} did not verify",
+            start,
+            start + chunk.len() as u64,
+            verify.file.display()
+        ))
+    }
+}
+
+async fn self_test() -> Result<(), eyre::Error> {
+    let checks = surviving::selftest::run().await;
+    let mut failed = 0;
+    for check in &checks {
+        match &check.failure {
+            None => println!("ok   {}", check.name),
+            Some(failure) => {
+                failed += 1;
+                println!("FAIL {}: {}", check.name, failure);
+            }
+        }
+    }
+    if failed > 0 {
+        return Err(eyre!("{} of {} checks failed", failed, checks.len()));
+    }
+    println!("all {} checks passed", checks.len());
+    Ok(())
+}
+
+async fn run_bench(args: &BenchArgs) -> Result<(), eyre::Error> {
+    use std::convert::TryFrom;
+
+    let options = bench::Options {
+        size: usize::try_from(args.size.0)?,
+        work: if args.algo.is_empty() {
+            std::iter::once(bench::Work::Read)
+                .chain(algo::Algorithm::BUILT_IN.map(bench::Work::Hash))
+                .collect()
+        } else {
+            args.algo.clone()
+        },
+        buffer_sizes: if args.buffer_size.is_empty() {
+            vec![4 << 10, 64 << 10, 256 << 10]
+        } else {
+            args.buffer_size
+                .iter()
+                .map(|&units::ByteSize(n)| usize::try_from(n))
+                .collect::<Result<_, _>>()?
+        },
+        rounds: args.rounds,
+    };
+    if options.size == 0 || options.buffer_sizes.contains(&0) {
+        return Err(eyre!("--size and --buffer-size must be more than zero"));
+    }
+    if options.rounds == 0 {
+        return Err(eyre!("--rounds must be at least 1"));
+    }
+    let measurements = bench::run(&options).await?;
+
+    let mib_per_sec = |m: &bench::Measurement| m.bytes_per_sec(options.size) / (1 << 20) as f64;
+    print!("{:<9} {:>7}", "algo", "buffer");
+    for reader in bench::Reader::ALL {
+        print!(" {:>12}", format!("{} MiB/s", reader.name()));
+    }
+    // what the adapter costs, against the plain read loop
+    println!(" {:>9} {:>9}", "polled %", "boxed %");
+    // a row per algorithm and buffer size, readers in the order they ran
+    for row in measurements.chunks(bench::Reader::ALL.len()) {
+        let raw = mib_per_sec(&row[0]);
+        print!(
+            "{:<9} {:>6}K",
+            row[0].work.to_string(),
+            row[0].buffer_size >> 10
+        );
+        for m in row {
+            print!(" {:>12.1}", mib_per_sec(m));
+        }
+        for m in &row[1..] {
+            print!(" {:>+8.1}%", (mib_per_sec(m) / raw - 1.0) * 100.0);
+        }
+        println!();
+    }
+    Ok(())
+}
+
+/// The inputs, with directories replaced by the files below them
+async fn expand_inputs(args: &Args) -> Result<Vec<PathBuf>, eyre::Error> {
+    Ok(expand_inputs_with_ids(args).await?.0)
+}
+
+/// Like [`expand_inputs`], with the ID `--files-from` gave each file, if
+/// any. Files below a directory get the directory's.
+async fn expand_inputs_with_ids(
+    args: &Args,
+) -> Result<(Vec<PathBuf>, Vec<Option<String>>), eyre::Error> {
+    let options = walk_options(args)?;
+    let mut inputs: Vec<(Option<String>, PathBuf)> =
+        args.files.iter().map(|path| (None, path.clone())).collect();
+    if let Some(list) = &args.files_from {
+        inputs.extend(read_files_from(list, args.null).await?);
+    } else if inputs.is_empty() {
+        // like coreutils, read stdin when given nothing
+        inputs.push((None, PathBuf::from(open::STDIN)));
+    }
+    let mut groups = Vec::new();
+    for (id, path) in inputs {
+        match async_std::fs::metadata(&path).await {
+            Ok(metadata) if metadata.is_dir() => {
+                let below = walk::files(&path, &options).await?;
+                groups.push(below.into_iter().map(|file| (id.clone(), file)).collect());
+            }
+            // anything else, including errors, is reported when hashing
+            _ => groups.push(vec![(id, path)]),
+        }
+    }
+    let all = if args.fair {
+        walk::interleave(groups)
+    } else {
+        groups.into_iter().flatten().collect()
+    };
+    Ok(all.into_iter().map(|(id, file)| (file, id)).unzip())
+}
+
+/// The `--files-from` list at `path`, or on stdin for `-`
+async fn read_files_from(
+    path: &Path,
+    null: bool,
+) -> Result<Vec<(Option<String>, PathBuf)>, eyre::Error> {
+    let mut list = open_files_from(path, null).await?;
+    let mut inputs = Vec::new();
+    while let Some(input) = next_listed(path, &mut list).await? {
+        inputs.push(input);
+    }
+    Ok(inputs)
+}
+
+type ListReader =
+    walk::ListReader<futures::io::BufReader<Box<dyn futures::io::AsyncRead + Send + Unpin>>>;
+
+/// Opens the `--files-from` list at `path`, or stdin for `-`, to be read an
+/// entry at a time
+async fn open_files_from(path: &Path, null: bool) -> Result<ListReader, eyre::Error> {
+    let inner: Box<dyn futures::io::AsyncRead + Send + Unpin> = if open::is_stdin(path) {
+        Box::new(async_std::io::stdin())
+    } else {
+        Box::new(
+            async_std::fs::File::open(path)
+                .await
+                .map_err(|e| eyre!("can't read {}: {}", path.display(), e))?,
+        )
+    };
+    Ok(walk::ListReader::new(
+        futures::io::BufReader::new(inner),
+        null,
+    ))
+}
+
+/// The next entry of the `--files-from` list at `path`
+async fn next_listed(
+    path: &Path,
+    list: &mut ListReader,
+) -> Result<Option<(Option<String>, PathBuf)>, eyre::Error> {
+    let input = list
+        .next()
+        .await
+        .map_err(|e| eyre!("can't read {}: {}", path.display(), e))?;
+    match &input {
+        Some((_, input)) if open::is_stdin(path) && open::is_stdin(input) => Err(eyre!(
+            "--files-from - already reads stdin, it can't also be an input"
+        )),
+        _ => Ok(input),
+    }
+}
+
+/// Whether the inputs can be hashed as they're listed and walked, which they
+/// can't when something needs every file before the first is hashed
+fn streams_inputs(args: &Args) -> bool {
+    !args.fair
+        && !args.watch
+        && !args.progress
+        && !args.report_aliases
+        && args.bloom_out.is_none()
+        && args.shard_output_by_dir.is_none()
+        && args.leftovers.is_none()
+        && !matches!(args.max_errors, Some(budget::MaxErrors::Percent(_)))
+}
+
+/// How many files to hash at once
+fn jobs(args: &Args) -> usize {
+    args.jobs
+        .unwrap_or_else(|| std::thread::available_parallelism().map_or(1, |n| n.get()))
+}
+
+fn walk_options(args: &Args) -> Result<walk::Options, eyre::Error> {
+    let globs = |patterns: &[String]| match patterns {
+        [] => Ok(None),
+        patterns => filter::globs(patterns).map(Some),
+    };
+    Ok(walk::Options {
+        ignore_file: if args.no_ignore {
+            None
+        } else {
+            Some(walk::IGNORE_FILE.to_string())
+        },
+        include: globs(&args.include)?,
+        exclude: globs(&args.exclude)?,
+        follow_symlinks: args.follow_symlinks,
+        one_file_system: args.one_file_system && !args.cross_file_systems,
+    })
+}
+
+async fn convert_sums(args: &ConvertArgs) -> Result<(), eyre::Error> {
+    let input = match &args.input {
+        Some(path) if path != Path::new("-") => async_std::fs::read_to_string(path).await?,
+        _ => {
+            let mut input = String::new();
+            async_std::io::stdin().read_to_string(&mut input).await?;
+            input
+        }
+    };
+    // lines without `algo=` are from the default; gnu lines are left blank
+    // here, to be filled in below
+    let mut entries = match args.from {
+        sums::Format::Lines => sums::parse(args.from, &input, algo::Algorithm::Sha3_256.name())?,
+        format => sums::parse(format, &input, "")?,
+    };
+    let chosen = args
+        .algo
+        .as_deref()
+        .map(|name| (name, name.parse::<algo::Algorithm>().ok()));
+    for entry in entries
+        .iter_mut()
+        .filter(|entry| !args.from.records_algorithm() && entry.algorithm.is_empty())
+    {
+        let len = entry.digest.len() / 2;
+        entry.algorithm = match chosen {
+            // an algorithm this tool doesn't know is taken at its word
+            Some((name, None)) => name.to_string(),
+            Some((_, Some(algorithm))) => {
+                if algorithm.digest_len() != Some(len) {
+                    return Err(eyre!(
+                        "{}: its digest has {} bytes, not the {} of {}",
+                        entry.path,
+                        len,
+                        algorithm.digest_len().unwrap_or_default(),
+                        algorithm.name()
+                    ));
+                }
+                algorithm.name().to_string()
+            }
+            None => match algo::Algorithm::candidates(len)[..] {
+                [algorithm] => algorithm.name().to_string(),
+                [] => {
+                    return Err(eyre!(
+                        "{}: no built-in algorithm makes {}-byte digests, name it with --algo",
+                        entry.path,
+                        len
+                    ))
+                }
+                ref candidates => {
+                    let names: Vec<_> = candidates.iter().map(|a| a.name()).collect();
+                    let (last, rest) = names.split_last().unwrap();
+                    return Err(eyre!(
+                        "{}: {}-byte digests could be {} or {}, say which with --algo",
+                        entry.path,
+                        len,
+                        rest.join(", "),
+                        last
+                    ));
+                }
+            },
+        };
+    }
+
+    let stdout = std::io::stdout();
+    sums::write(args.to, &entries, &mut stdout.lock())?;
+    Ok(())
+}
+
+async fn crc_file(path: &Path, options: &HashOptions) -> Result<u32, eyre::Error> {
+    let (hasher, _) = feed_file(path, options, crc32fast::Hasher::new()).await?;
+    Ok(hasher.finalize())
+}
+
+async fn create_sfv(args: &Args, create: &SfvCreateArgs) -> Result<(), eyre::Error> {
+    use futures::stream::StreamExt;
+
+    let options = Arc::new(hash_options(args));
+    let mut results = futures::stream::iter(create.files.iter().cloned())
+        .map(|path| {
+            let options = options.clone();
+            async_std::task::spawn(async move {
+                let crc = crc_file(&path, &options).await;
+                (path, crc)
+            })
+        })
+        .buffered(jobs(args));
+
+    let mut entries = Vec::new();
+    while let Some((path, crc)) = results.next().await {
+        let path = path.display().to_string();
+        if path.contains(['\n', '\r']) {
+            return Err(eyre!(
+                "SFV files can't hold paths with line breaks: {:?}",
+                path
+            ));
+        }
+        let crc = crc.map_err(|e| eyre!("While hashing {}: {}", path, e))?;
+        entries.push(sfv::Entry { path, crc });
+    }
+
+    let stdout = std::io::stdout();
+    sfv::write(&entries, &mut stdout.lock())
+}
+
+async fn check_sfv(args: &Args, check: &SfvCheckArgs) -> Result<(), eyre::Error> {
+    use futures::stream::StreamExt;
+
+    let entries = sfv::parse(&async_std::fs::read_to_string(&check.sfv).await?)?;
+    let base = check
+        .sfv
+        .parent()
+        .unwrap_or_else(|| Path::new(""))
+        .to_owned();
+
+    let options = Arc::new(hash_options(args));
+    let mut results = futures::stream::iter(entries)
+        .map(|entry| {
+            let options = options.clone();
+            let path = base.join(&entry.path);
+            async_std::task::spawn(async move {
+                let crc = crc_file(&path, &options).await;
+                (entry, crc)
+            })
+        })
+        .buffered(jobs(args));
+
+    let mut verdicts = verdicts(args)?;
+    let mut failed = 0;
+    while let Some((entry, crc)) = results.next().await {
+        let status = match crc {
+            Ok(crc) if crc == entry.crc => "OK".to_string(),
+            Ok(_) => "FAILED".to_string(),
+            Err(e) => match e.downcast_ref::<std::io::Error>() {
+                Some(e) if e.kind() == std::io::ErrorKind::NotFound => "MISSING".to_string(),
+                _ => format!("FAILED ({})", e),
+            },
+        };
+        if status != "OK" {
+            failed += 1;
+        }
+        print_status(&entry.path, &status);
+        verdicts
+            .record(&base.join(&entry.path), &entry.path, &status)
+            .await?;
+    }
+    verdicts.finish(args)?;
+
+    if failed > 0 {
+        return Err(eyre!("{} file(s) did not verify", failed));
+    }
+    Ok(())
+}
+
+async fn create_parity(args: &ParityCreateArgs) -> Result<(), eyre::Error> {
+    let units::ByteSize(block_size) = args.block_size;
+    if block_size == 0 {
+        return Err(eyre!("--block-size must be at least one byte"));
+    }
+    for path in &args.files {
+        let volume = parity::create(path, args.redundancy, block_size as usize)
+            .await
+            .map_err(|e| eyre!("While protecting {}: {}", path.display(), e))?;
+        println!("{} {}", path.display(), volume.display());
+    }
+    Ok(())
+}
+
+async fn repair_parity(args: &ParityRepairArgs) -> Result<(), eyre::Error> {
+    let mut failed = 0;
+    for volume in &args.volumes {
+        match parity::repair(volume).await {
+            Ok(parity::Repair::Intact) => print_status(volume.display(), "OK"),
+            Ok(parity::Repair::Repaired(n)) => print_status(
+                volume.display(),
+                &format!("REPAIRED ({} blocks rebuilt)", n),
+            ),
+            Err(e) => {
+                failed += 1;
+                print_status(volume.display(), &format!("FAILED ({})", e));
+            }
+        }
+    }
+    if failed > 0 {
+        return Err(eyre!("{} file(s) could not be repaired", failed));
+    }
+    Ok(())
+}
+
+async fn make_zsync(args: &ZsyncArgs) -> Result<(), eyre::Error> {
+    let block_size = match args.block_size {
+        Some(units::ByteSize(n)) if !n.is_power_of_two() => {
+            return Err(eyre!("--block-size must be a power of two"))
+        }
+        Some(units::ByteSize(n)) => Some(n as usize),
+        None => None,
+    };
+    let name = args
+        .file
+        .file_name()
+        .ok_or_else(|| eyre!("{} has no file name", args.file.display()))?;
+    let url = args
+        .url
+        .clone()
+        .unwrap_or_else(|| name.to_string_lossy().into_owned());
+    let output = args.output.clone().unwrap_or_else(|| {
+        let mut name = name.to_owned();
+        name.push(".zsync");
+        args.file.with_file_name(name)
+    });
+
+    let mut out = async_std::io::BufWriter::new(async_std::fs::File::create(&output).await?);
+    zsync::make(&args.file, &zsync::Options { url, block_size }, &mut out).await?;
+    println!("{} {}", args.file.display(), output.display());
+    Ok(())
+}
+
+async fn sha256_file(path: &Path, options: &HashOptions) -> Result<String, eyre::Error> {
+    let (hasher, _) = feed_file(path, options, sha2::Sha256::new()).await?;
+    Ok(hex(&hasher.finalize()))
+}
+
+/// Hashes every file of a crate directory with SHA-256, by manifest key
+async fn hash_crate_dir(
+    args: &Args,
+    dir: &Path,
+) -> Result<Vec<(String, Result<String, eyre::Error>)>, eyre::Error> {
+    use futures::stream::StreamExt;
+
+    let options = Arc::new(hash_options(args));
+    let files = walk::files(dir, &Default::default()).await?;
+    let digests = futures::stream::iter(files)
+        .map(|path| (cargo_checksum::key(dir, &path), path))
+        .filter(|(key, _)| futures::future::ready(key != cargo_checksum::FILE_NAME))
+        .map(|(key, path)| {
+            let options = options.clone();
+            async_std::task::spawn(async move { (key, sha256_file(&path, &options).await) })
+        })
+        .buffered(jobs(args))
+        .collect()
+        .await;
+    Ok(digests)
+}
+
+async fn create_cargo_checksum(
+    args: &Args,
+    create: &CargoChecksumCreateArgs,
+) -> Result<(), eyre::Error> {
+    // regenerating keeps the package digest, the `.crate` is rarely at hand
+    let mut checksums = cargo_checksum::Checksums::load(&create.dir)
+        .await
+        .map(|old| cargo_checksum::Checksums {
+            files: Default::default(),
+            package: old.package,
+        })
+        .unwrap_or_default();
+    for (key, digest) in hash_crate_dir(args, &create.dir).await? {
+        let digest = digest.map_err(|e| eyre!("While hashing {}: {}", key, e))?;
+        checksums.files.insert(key, digest);
+    }
+    if let Some(crate_file) = &create.crate_file {
+        checksums.package = Some(sha256_file(crate_file, &hash_options(args)).await?);
+    }
+    checksums.save(&create.dir).await
+}
 
-use pin_project::pin_project;
-use tracing_subscriber::{prelude::*, Registry};
+async fn verify_cargo_checksum(
+    args: &Args,
+    verify: &CargoChecksumVerifyArgs,
+) -> Result<(), eyre::Error> {
+    let checksums = cargo_checksum::Checksums::load(&verify.dir).await?;
+    let mut actual: HashMap<_, _> = hash_crate_dir(args, &verify.dir)
+        .await?
+        .into_iter()
+        .collect();
 
-/// Prints the SHA3-256 hash of some files
-#[derive(FromArgs)]
-struct Args {
-    /// the files whose contents to hash and print
-    #[argh(positional)]
-    files: Vec<PathBuf>,
+    let mut verdicts = verdicts(args)?;
+    let mut failed = 0;
+    for (key, expected) in &checksums.files {
+        let status = match actual.remove(key) {
+            Some(Ok(digest)) if digest::ct_eq(digest.as_bytes(), expected.as_bytes()) => {
+                "OK".to_string()
+            }
+            Some(Ok(_)) => "FAILED".to_string(),
+            Some(Err(e)) => format!("FAILED ({})", e),
+            None => "MISSING".to_string(),
+        };
+        if status != "OK" {
+            failed += 1;
+        }
+        print_status(key, &status);
+        verdicts.record(&verify.dir.join(key), key, &status).await?;
+    }
+    // cargo itself ignores files the manifest doesn't list, so these are only
+    // worth a mention
+    let mut unlisted: Vec<_> = actual.into_keys().collect();
+    unlisted.sort();
+    for key in unlisted {
+        print_status(&key, "UNLISTED");
+        verdicts
+            .record(&verify.dir.join(&key), &key, "UNLISTED")
+            .await?;
+    }
+
+    if let Some(crate_file) = &verify.crate_file {
+        let digest = sha256_file(crate_file, &hash_options(args)).await?;
+        let status = match &checksums.package {
+            Some(expected) if *expected == digest => "OK",
+            Some(_) => "FAILED",
+            None => "FAILED (no package digest recorded)",
+        };
+        if status != "OK" {
+            failed += 1;
+        }
+        print_status(crate_file.display(), status);
+        let key = crate_file.display().to_string();
+        verdicts.record(crate_file, &key, status).await?;
+    }
+    verdicts.finish(args)?;
+
+    if failed > 0 {
+        return Err(eyre!("{} file(s) did not verify", failed));
+    }
+    Ok(())
 }
 
-#[async_std::main]
-#[tracing::instrument]
-async fn main() -> Result<(), eyre::Error> {
-    // let subscriber = Registry::default().with(HierarchicalLayer::new(2));
-    // tracing::subscriber::set_global_default(subscriber).unwrap();
+async fn verify_repo(args: &Args, verify: &RepoVerifyArgs) -> Result<(), eyre::Error> {
+    use futures::stream::StreamExt;
 
-    color_eyre::install().unwrap();
-    let args: Args = argh::from_env();
+    let kind = match verify.kind {
+        Some(kind) => kind,
+        None => repo::Kind::detect(&verify.index).ok_or_else(|| {
+            eyre!(
+                "can't tell what kind of index {} is, pass --kind",
+                verify.index.display()
+            )
+        })?,
+    };
+    let root = verify
+        .root
+        .clone()
+        .unwrap_or_else(|| kind.default_root(&verify.index));
+    let entries = repo::parse(kind, &repo::read(&verify.index)?)?;
 
-    let mut handles = Vec::new();
+    let options = Arc::new(hash_options(args));
+    let mut results = futures::stream::iter(entries)
+        .map(|entry| {
+            let options = options.clone();
+            let path = root.join(&entry.path);
+            async_std::task::spawn(async move {
+                let status = check_repo_entry(&path, &entry, &options).await;
+                (entry, status)
+            })
+        })
+        .buffered(verify.jobs.max(1));
 
-    for file in &args.files {
-        let file = file.clone();
-        let handle = async_std::task::spawn(async move {
-            let res = hash_file(&file).await;
-            if let Err(e) = res {
-                println!("While hashing {}: {}", file.display(), e);
-            }
-        });
-        handles.push(handle);
+    let mut verdicts = verdicts(args)?;
+    let mut failed = 0;
+    while let Some((entry, status)) = results.next().await {
+        let ok = match status.as_str() {
+            "OK" => true,
+            "MISSING" => verify.ignore_missing,
+            _ => false,
+        };
+        if !ok {
+            failed += 1;
+        }
+        print_status(&entry.path, &status);
+        verdicts
+            .record(&root.join(&entry.path), &entry.path, &status)
+            .await?;
     }
+    verdicts.finish(args)?;
 
-    for handle in handles {
-        handle.await;
+    if failed > 0 {
+        return Err(eyre!("{} file(s) did not verify", failed));
+    }
+    Ok(())
+}
+
+/// Checks every object a manifest lists under the upload's prefix, then
+/// mentions those it doesn't list
+async fn verify_upload(args: &Args, verify: &UploadVerifyArgs) -> Result<(), eyre::Error> {
+    use futures::stream::StreamExt;
+
+    if args.hmac_key.is_some() {
+        return Err(eyre!("upload-verify can't check keyed digests"));
+    }
+    let input = {
+        let manifest = verify.manifest.clone();
+        async_std::task::spawn_blocking(move || compress::read_to_string(&manifest)).await?
+    };
+    let watermark = watermark::Watermark::find(&input)
+        .map_err(|e| eyre!("in {}: {}", verify.manifest.display(), e))?;
+    if let Some(watermark) = &watermark {
+        eprintln!("{} {}", verify.manifest.display(), watermark.describe());
+        if watermark.keyed {
+            return Err(eyre!(
+                "the manifest was made with --hmac-key, which upload-verify can't check"
+            ));
+        }
+    }
+    let algorithm = match (&watermark, args.algo()) {
+        (Some(watermark), None) => watermark.algorithm.as_str(),
+        _ => args.algorithm().name(),
+    };
+    let entries = sums::parse(verify.manifest_format, &input, algorithm)
+        .map_err(|e| eyre!("in {}: {}", verify.manifest.display(), e))?;
+
+    let default_region = || {
+        ["AWS_REGION", "AWS_DEFAULT_REGION"]
+            .iter()
+            .find_map(|name| std::env::var(name).ok().filter(|v| !v.is_empty()))
+            .unwrap_or_else(|| "us-east-1".to_string())
+    };
+    let region = verify.region.clone().unwrap_or_else(default_region);
+    let endpoint = match &verify.endpoint {
+        Some(endpoint) => endpoint.clone(),
+        None => std::env::var("AWS_ENDPOINT_URL")
+            .ok()
+            .filter(|v| !v.is_empty())
+            .unwrap_or_else(|| format!("https://s3.{}.amazonaws.com", region)),
+    };
+    let client = Arc::new(s3::Client::new(
+        &endpoint,
+        region,
+        s3::Credentials::from_env(),
+    )?);
+    let mut objects: HashMap<_, _> = client
+        .list(&verify.dest)
+        .await?
+        .into_iter()
+        .map(|object| (object.key.clone(), object))
+        .collect();
+    tracing::debug!(dest = %verify.dest, objects = objects.len(), "listed the upload");
+
+    // each entry takes its object out, so what's left isn't listed
+    let checks: Vec<_> = entries
+        .into_iter()
+        .map(|entry| {
+            let key = upload_key(&entry.path, verify.root.as_deref()).map(|k| verify.dest.key(&k));
+            let object = key.as_ref().ok().and_then(|key| objects.remove(key));
+            (entry, key, object)
+        })
+        .collect();
+    let download = verify.download;
+    let mut results = futures::stream::iter(checks)
+        .map(|(entry, key, object)| {
+            let client = client.clone();
+            let bucket = verify.dest.bucket.clone();
+            async_std::task::spawn(async move {
+                let status = match (&key, &object) {
+                    (Err(e), _) => format!("FAILED ({})", e),
+                    (Ok(_), None) => "MISSING".to_string(),
+                    (Ok(_), Some(object)) => {
+                        check_upload_entry(&client, &bucket, object, &entry, download).await
+                    }
+                };
+                (entry, status)
+            })
+        })
+        .buffered(verify.jobs.max(1));
+
+    let (mut ok, mut failed, mut missing) = (0, 0, 0);
+    while let Some((entry, status)) = results.next().await {
+        match status.as_str() {
+            "OK" => ok += 1,
+            "MISSING" => missing += 1,
+            _ => failed += 1,
+        }
+        print_status(&entry.path, &status);
+    }
+    // uploads often land next to others, so these are only worth a mention
+    let mut unlisted: Vec<_> = objects.into_keys().collect();
+    unlisted.sort();
+    for key in &unlisted {
+        print_status(format!("s3://{}/{}", verify.dest.bucket, key), "UNLISTED");
     }
 
+    let lang = LANG.get().copied().unwrap_or_default();
+    eprintln!(
+        "{} {}, {} {}, {} {}, {} {}",
+        ok,
+        lang.status("OK"),
+        failed,
+        lang.status("FAILED"),
+        missing,
+        lang.status("MISSING"),
+        unlisted.len(),
+        lang.status("UNLISTED")
+    );
+    if failed + missing > 0 {
+        return Err(eyre!("{} file(s) did not verify", failed + missing));
+    }
     Ok(())
 }
 
-async fn hash_file(path: &Path) -> Result<(), eyre::Error> {
-    let file = File::open(path).await?;
-    let file = TracingReader { inner: file };
-    let mut file = SimpleAsyncReader {
-        state: State::Idle(file, Default::default()),
+/// The key below the prefix a manifest's `path` was uploaded as, relative
+/// to `root` if it's given
+fn upload_key(path: &str, root: Option<&Path>) -> Result<String, eyre::Error> {
+    let path = Path::new(path);
+    let relative = match root {
+        Some(root) => path
+            .strip_prefix(root)
+            .map_err(|_| eyre!("not under {}", root.display()))?,
+        None => path,
+    };
+    let parts: Vec<_> = relative
+        .components()
+        .filter_map(|component| match component {
+            std::path::Component::Normal(part) => Some(Ok(part.to_string_lossy())),
+            std::path::Component::CurDir => None,
+            _ => Some(Err(eyre!("not relative to the prefix"))),
+        })
+        .collect::<Result<_, _>>()?;
+    Ok(parts.join("/"))
+}
+
+/// Checks an uploaded object's size, then its digest, returning its status
+async fn check_upload_entry(
+    client: &s3::Client,
+    bucket: &str,
+    object: &s3::Object,
+    entry: &sums::Entry,
+    download: bool,
+) -> String {
+    if entry.size.is_some_and(|size| size != object.size) {
+        return format!(
+            "FAILED (size {}, expected {})",
+            object.size,
+            entry.size.unwrap()
+        );
+    }
+    let expected = match unhex(&entry.digest) {
+        Some(expected) => expected,
+        None => return format!("FAILED ({:?} is not a hex digest)", entry.digest),
     };
+    let algorithm: algo::Algorithm = match entry.algorithm.parse() {
+        Ok(algorithm) => algorithm,
+        Err(e) => return format!("FAILED ({})", e),
+    };
+    if algorithm == algo::Algorithm::Sha256 && !download {
+        match client.sha256(bucket, &object.key).await {
+            Ok(Some(digest)) if digest::ct_eq(&digest, &expected) => return "OK".to_string(),
+            Ok(Some(_)) => return "FAILED".to_string(),
+            // uploaded without one, or in parts
+            Ok(None) => tracing::debug!(key = %object.key, "no SHA-256 kept, downloading"),
+            Err(e) => return format!("FAILED ({})", e),
+        }
+    }
+    match client.hash(bucket, &object.key, algorithm).await {
+        Ok(digest) if digest::ct_eq(&digest, &expected) => "OK".to_string(),
+        Ok(_) => "FAILED".to_string(),
+        Err(e) => format!("FAILED ({})", e),
+    }
+}
 
-    let mut hasher = sha3::Sha3_256::new();
+/// Verifies the files listed in a checksum manifest, relative to the
+/// current directory like `sha256sum --check`, then prints how many passed
+async fn check_manifest(args: &Args, manifest: &Path) -> Result<(), eyre::Error> {
+    use futures::stream::StreamExt;
 
-    let mut buf = vec![0u8; 256 * 1024];
-    loop {
-        let n = file.read(&mut buf[..]).await?;
-        match n {
-            0 => break,
-            n => hasher.update(&buf[..n]),
+    // read once, so what's verified is what's checked
+    let bytes = async_std::fs::read(manifest)
+        .await
+        .map_err(|e| eyre!("can't read {}: {}", manifest.display(), e))?;
+    if let Some(key) = &args.verify_signature {
+        verify_signature(key, manifest, &bytes).await?;
+    }
+    let input = {
+        let manifest = manifest.to_owned();
+        async_std::task::spawn_blocking(move || compress::decode(&manifest, bytes)).await?
+    };
+    let watermark = watermark::Watermark::find(&input)
+        .map_err(|e| eyre!("in {}: {}", manifest.display(), e))?;
+    if let Some(watermark) = &watermark {
+        eprintln!("{} {}", manifest.display(), watermark.describe());
+        check_watermark(args, watermark)?;
+    }
+    // a watermark says which algorithm made digests that don't say it
+    // themselves
+    let algorithm = match (&watermark, args.algo()) {
+        (Some(watermark), None) => watermark.algorithm.as_str(),
+        _ => args.algorithm().name(),
+    };
+    let entries = sums::parse(args.check_format, &input, algorithm)
+        .map_err(|e| eyre!("in {}: {}", manifest.display(), e))?;
+
+    // digests alone don't say which algorithm made them
+    let infer =
+        args.algo().is_none() && watermark.is_none() && !args.check_format.records_algorithm();
+    let options = Arc::new(hash_options(args));
+    let mut results = futures::stream::iter(entries)
+        .map(|entry| {
+            let options = options.clone();
+            async_std::task::spawn(async move {
+                let (status, matched) = check_sums_entry(&entry, &options, infer).await;
+                (entry, status, matched)
+            })
+        })
+        .buffered(jobs(args));
+
+    let mut verdicts = verdicts(args)?;
+    let (mut ok, mut failed, mut missing) = (0, 0, 0);
+    while let Some((entry, status, matched)) = results.next().await {
+        match status.as_str() {
+            "OK" => ok += 1,
+            "MISSING" => missing += 1,
+            _ => failed += 1,
         }
+        match matched {
+            Some(algorithm) => print_status(&entry.path, &format!("{} ({})", status, algorithm)),
+            None => print_status(&entry.path, &status),
+        }
+        verdicts
+            .record(Path::new(&entry.path), &entry.path, &status)
+            .await?;
     }
 
-    let hash = hasher.finalize();
-    print!("{} ", path.display());
-    for x in hash {
-        print!("{:02x}", x);
+    verdicts.finish(args)?;
+
+    let lang = LANG.get().copied().unwrap_or_default();
+    eprintln!(
+        "{} {}, {} {}, {} {}",
+        ok,
+        lang.status("OK"),
+        failed,
+        lang.status("FAILED"),
+        missing,
+        lang.status("MISSING")
+    );
+    if failed + missing > 0 {
+        return Err(eyre!("{} file(s) did not verify", failed + missing));
     }
-    println!();
+    Ok(())
+}
 
+/// Fails unless `manifest`, read from `path`, is signed with `key`, as it's
+/// written: compressed, if it is
+async fn verify_signature(
+    key: &sign::PublicKey,
+    path: &Path,
+    manifest: &[u8],
+) -> Result<(), eyre::Error> {
+    let signature = sign::signature_path(path);
+    let text = async_std::fs::read_to_string(&signature)
+        .await
+        .map_err(|e| eyre!("can't read {}: {}", signature.display(), e))?;
+    let trusted_comment = key
+        .verify(&text, manifest)
+        .map_err(|e| eyre!("bad signature for {}: {}", path.display(), e))?;
+    eprintln!(
+        "{} signature verified, trusted comment: {}",
+        path.display(),
+        trusted_comment
+    );
     Ok(())
 }
 
-use futures::{io::AsyncRead, Future};
-use std::{
-    io,
-    pin::Pin,
-    task::{Context, Poll},
-};
+/// Refuses to check a manifest with other settings than made it, which
+/// would fail every entry for no fault of the files
+fn check_watermark(args: &Args, watermark: &watermark::Watermark) -> Result<(), eyre::Error> {
+    if args.algo().is_some() && args.algorithm().name() != watermark.algorithm {
+        return Err(eyre!(
+            "the manifest was made with {}, not {}",
+            watermark.algorithm,
+            args.algorithm().name()
+        ));
+    }
+    if watermark.keyed && args.hmac_key.is_none() {
+        return Err(eyre!(
+            "the manifest was made with --hmac-key, which checking it needs"
+        ));
+    }
+    Ok(())
+}
+
+/// Checks one manifest entry's size, if listed, then its digest, returning
+/// its status. With `infer`, the entry doesn't say which algorithm made its
+/// digest, so every one that gives digests of its length is tried, and the
+/// one that matched is returned too.
+async fn check_sums_entry(
+    entry: &sums::Entry,
+    options: &HashOptions,
+    infer: bool,
+) -> (String, Option<algo::Algorithm>) {
+    let failed = |status: String| (status, None);
+    let candidates = if infer {
+        algo::Algorithm::candidates(entry.digest.len() / 2)
+    } else {
+        // manifests can't name external commands, but entries in formats
+        // that don't record an algorithm take --algo's
+        match entry.algorithm.parse() {
+            _ if entry.algorithm == options.algorithm.name() => vec![options.algorithm],
+            Ok(algorithm) => vec![algorithm],
+            Err(_) => {
+                return failed(format!(
+                    "FAILED (unsupported algorithm {})",
+                    entry.algorithm
+                ))
+            }
+        }
+    };
+    if candidates.is_empty() {
+        return failed(format!(
+            "FAILED (no algorithm gives {}-byte digests)",
+            entry.digest.len() / 2
+        ));
+    }
+    let path = Path::new(&entry.path);
+    let len = match async_std::fs::metadata(path).await {
+        Ok(metadata) => metadata.len(),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return failed("MISSING".to_string()),
+        Err(e) => return failed(format!("FAILED ({})", e)),
+    };
+    if entry.size.is_some_and(|size| size != len) {
+        return failed(format!(
+            "FAILED (size {}, expected {})",
+            len,
+            entry.size.unwrap()
+        ));
+    }
+    let expected = match unhex(&entry.digest) {
+        Some(expected) => expected,
+        None => return failed(format!("FAILED ({:?} is not a hex digest)", entry.digest)),
+    };
+    match match_candidates(path, options, &candidates, &expected).await {
+        Ok(Some(algorithm)) => ("OK".to_string(), Some(algorithm).filter(|_| infer)),
+        Ok(None) => failed("FAILED".to_string()),
+        Err(e) => failed(format!("FAILED ({})", e)),
+    }
+}
+
+/// Hashes `path` once with every algorithm in `candidates`, returning the
+/// first whose digest is `expected`
+async fn match_candidates(
+    path: &Path,
+    options: &HashOptions,
+    candidates: &[algo::Algorithm],
+    expected: &[u8],
+) -> Result<Option<algo::Algorithm>, eyre::Error> {
+    let (hasher, _) = feed_file(
+        path,
+        options,
+        algo::Several::new(candidates, options.key.as_ref().map(|k| k.bytes())),
+    )
+    .await?;
+    Ok(hasher
+        .finalize()
+        .into_iter()
+        .find(|(_, digest)| digest::ct_eq(digest, expected))
+        .map(|(algorithm, _)| algorithm))
+}
 
-// Generate projection types
-struct TracingReader<R>
-where
-    R: AsyncRead,
-{
-    inner: R,
-}
-
-use async_trait::async_trait;
-use tracing_tree::HierarchicalLayer;
-
-#[async_trait]
-trait SimpleRead {
-    async fn simple_read(&mut self, buf: &mut [u8]) -> io::Result<usize>;
-}
-
-#[async_trait]
-impl<R> SimpleRead for TracingReader<R>
-where
-    R: AsyncRead + Send + Unpin,
-{
-    #[tracing::instrument(skip(self, buf))]
-    async fn simple_read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        use futures_timer::Delay;
-        use std::time::Duration;
-
-        // artificial slowdown
-        tracing::debug!("doing delay...");
-        Delay::new(Duration::from_millis(50)).await;
-        tracing::debug!("doing delay...done!");
-
-        // reading
-        tracing::debug!("doing read...");
-        let res = self.inner.read(buf).await;
-        tracing::debug!("doing read...done!");
-        res
-    }
-}
-
-#[pin_project]
-struct SimpleAsyncReader<R>
-where
-    R: SimpleRead,
-{
-    state: State<R>,
-}
-
-type BoxFut<T> = Pin<Box<dyn Future<Output = T> + Send>>;
-
-enum State<R> {
-    Idle(R, Vec<u8>),
-    Pending(BoxFut<(R, Vec<u8>, io::Result<usize>)>),
-    Transitional,
-}
-
-impl<R> AsyncRead for SimpleAsyncReader<R>
-where
-    R: SimpleRead + Send + 'static,
-{
-    #[tracing::instrument(skip(self, buf))]
-    fn poll_read(
-        self: Pin<&mut Self>,
-        cx: &mut Context<'_>,
-        buf: &mut [u8],
-    ) -> Poll<io::Result<usize>> {
-        let proj = self.project();
-        let mut state = State::Transitional;
-        std::mem::swap(proj.state, &mut state);
-
-        let mut fut = match state {
-            State::Idle(mut inner, mut internal_buf) => {
-                tracing::debug!("getting new future...");
-                internal_buf.clear();
-                internal_buf.reserve(buf.len());
-                unsafe { internal_buf.set_len(buf.len()) }
-
-                Box::pin(async move {
-                    let res = inner.simple_read(&mut internal_buf[..]).await;
-                    (inner, internal_buf, res)
+/// One `--expect`: a digest, the algorithms that might have made it, and
+/// the input it's for, if it names one
+struct Expected {
+    path: Option<PathBuf>,
+    candidates: Vec<algo::Algorithm>,
+    bytes: Vec<u8>,
+}
+
+impl Expected {
+    fn parse(s: &str, algo: Option<algo::Algorithm>) -> Result<Self, eyre::Error> {
+        let s = s.trim();
+        let whole = match Self::digest(s, algo) {
+            Ok(expected) => return Ok(expected),
+            Err(e) => e,
+        };
+        // paths may have an `=` of their own, base64 only ends with them
+        s.match_indices('=')
+            .find_map(|(i, _)| {
+                let expected = Self::digest(&s[i + 1..], algo).ok()?;
+                Some(Self {
+                    path: Some(PathBuf::from(&s[..i])),
+                    ..expected
                 })
+                .filter(|_| i > 0)
+            })
+            .ok_or(whole)
+    }
+
+    fn digest(s: &str, algo: Option<algo::Algorithm>) -> Result<Self, eyre::Error> {
+        let (named, bytes) = digest::parse(s)?;
+        let candidates = match named.or(algo) {
+            Some(algorithm) => vec![algorithm],
+            None => algo::Algorithm::candidates(bytes.len()),
+        };
+        if candidates.is_empty() {
+            return Err(eyre!(
+                "no supported algorithm gives {}-byte digests",
+                bytes.len()
+            ));
+        }
+        Ok(Self {
+            path: None,
+            candidates,
+            bytes,
+        })
+    }
+}
+
+/// Why --expect failed, which picks its exit status
+#[derive(Debug)]
+struct Unverified {
+    mismatched: usize,
+    unreadable: usize,
+}
+
+impl Unverified {
+    /// 2 when any input has another digest, which is worse news than one
+    /// that couldn't be read at all, 3
+    fn code(&self) -> i32 {
+        if self.mismatched > 0 {
+            2
+        } else {
+            3
+        }
+    }
+}
+
+impl std::fmt::Display for Unverified {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} file(s) did not match, {} couldn't be read",
+            self.mismatched, self.unreadable
+        )
+    }
+}
+
+impl std::error::Error for Unverified {}
+
+/// Why --max-errors stopped the run
+#[derive(Debug)]
+struct TooManyErrors {
+    errors: u64,
+}
+
+impl std::fmt::Display for TooManyErrors {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "gave up after {} file(s) failed", self.errors)
+    }
+}
+
+impl std::error::Error for TooManyErrors {}
+
+/// What an interrupted run got through
+#[derive(Debug)]
+struct Interrupted {
+    hashed: u64,
+    failed: u64,
+    /// Started, but not finished
+    aborted: usize,
+    not_started: usize,
+}
+
+impl std::fmt::Display for Interrupted {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "interrupted: {} file(s) hashed, {} failed, {} aborted, {} not started",
+            self.hashed, self.failed, self.aborted, self.not_started
+        )
+    }
+}
+
+impl std::error::Error for Interrupted {}
+
+/// Hashes each input, telling which of them have a digest --expect gives
+/// and, when the digest doesn't say and --algo isn't given, which algorithm
+/// made it
+async fn check_expected(args: &Args) -> Result<(), eyre::Error> {
+    use futures::stream::StreamExt;
+
+    let expected = args
+        .expect
+        .iter()
+        .map(|s| Expected::parse(s, args.algo()))
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut tried: Vec<_> = Vec::new();
+    for e in expected.iter().filter(|e| e.candidates.len() > 1) {
+        for algorithm in &e.candidates {
+            if !tried.contains(&algorithm.name()) {
+                tried.push(algorithm.name());
+            }
+        }
+    }
+    if !tried.is_empty() {
+        eprintln!("trying {}", tried.join(", "));
+    }
+
+    let named: Vec<_> = expected.iter().filter_map(|e| e.path.clone()).collect();
+    let files = if args.files.is_empty() && args.files_from.is_none() && !named.is_empty() {
+        let mut files = named.clone();
+        files.dedup();
+        files
+    } else {
+        expand_inputs(args).await?
+    };
+    if let Some(path) = named.iter().find(|path| !files.contains(path)) {
+        return Err(eyre!(
+            "--expect names {}, which isn't an input",
+            path.display()
+        ));
+    }
+    let expected = Arc::new(expected);
+    // each input is checked against the digests given for it, if any, and
+    // against the bare ones otherwise
+    let mut jobs_for = Vec::new();
+    for path in files {
+        let mine: Vec<usize> = match (0..expected.len())
+            .filter(|&i| expected[i].path.as_ref() == Some(&path))
+            .collect::<Vec<_>>()
+        {
+            mine if !mine.is_empty() => mine,
+            _ => (0..expected.len())
+                .filter(|&i| expected[i].path.is_none())
+                .collect(),
+        };
+        if mine.is_empty() {
+            return Err(eyre!(
+                "nothing to check {} against: give a bare --expect or {0}=DIGEST",
+                path.display()
+            ));
+        }
+        jobs_for.push((path, mine));
+    }
+
+    let options = Arc::new(hash_options(args));
+    let mut results = futures::stream::iter(jobs_for)
+        .map(|(path, mine)| {
+            let (options, expected) = (options.clone(), expected.clone());
+            async_std::task::spawn(async move {
+                let mine: Vec<_> = mine.iter().map(|&i| &expected[i]).collect();
+                let matched = match_any(&path, &options, &mine).await;
+                (path, matched)
+            })
+        })
+        .buffered(jobs(args));
+
+    let mut verdicts = verdicts(args)?;
+    let (mut mismatched, mut unreadable) = (0, 0);
+    while let Some((path, matched)) = results.next().await {
+        let (status, shown) = match matched {
+            Ok(Some((algorithm, true))) => ("OK".to_string(), format!("OK ({})", algorithm)),
+            Ok(Some(_)) => ("OK".to_string(), "OK".to_string()),
+            Ok(None) => {
+                mismatched += 1;
+                ("FAILED".to_string(), "FAILED".to_string())
+            }
+            Err(e)
+                if e.downcast_ref::<std::io::Error>()
+                    .is_some_and(|e| e.kind() == std::io::ErrorKind::NotFound) =>
+            {
+                unreadable += 1;
+                ("MISSING".to_string(), "MISSING".to_string())
             }
-            State::Pending(fut) => {
-                tracing::debug!("polling existing future...");
-                fut
+            Err(e) => {
+                unreadable += 1;
+                let status = format!("FAILED ({})", e);
+                (status.clone(), status)
             }
-            State::Transitional => unreachable!(),
         };
+        print_status(path.display(), &shown);
+        verdicts
+            .record(&path, &path.display().to_string(), &status)
+            .await?;
+    }
 
-        match fut.as_mut().poll(cx) {
-            Poll::Ready((inner, mut internal_buf, result)) => {
-                tracing::debug!("future was ready!");
-                if let Ok(n) = &result {
-                    let n = *n;
-                    unsafe { internal_buf.set_len(n) }
+    verdicts.finish(args)?;
+    if mismatched + unreadable > 0 {
+        return Err(Unverified {
+            mismatched,
+            unreadable,
+        }
+        .into());
+    }
+    Ok(())
+}
 
-                    let dst = &mut buf[..n];
-                    let src = &internal_buf[..];
-                    dst.copy_from_slice(src);
-                } else {
-                    unsafe { internal_buf.set_len(0) }
-                }
-                *proj.state = State::Idle(inner, internal_buf);
-                Poll::Ready(result)
+/// Hashes `path` once with every algorithm any of `expected` might be
+/// from, returning the one that made a digest among them, and whether it
+/// had to be guessed
+async fn match_any(
+    path: &Path,
+    options: &HashOptions,
+    expected: &[&Expected],
+) -> Result<Option<(algo::Algorithm, bool)>, eyre::Error> {
+    let mut candidates: Vec<_> = expected.iter().flat_map(|e| e.candidates.clone()).collect();
+    candidates.sort_by_key(|a| a.name());
+    candidates.dedup();
+    let (hasher, _) = feed_file(
+        path,
+        options,
+        algo::Several::new(&candidates, options.key.as_ref().map(|k| k.bytes())),
+    )
+    .await?;
+    let digests = hasher.finalize();
+    Ok(expected.iter().find_map(|e| {
+        digests
+            .iter()
+            .find(|(algorithm, digest)| {
+                e.candidates.contains(algorithm) && digest::ct_eq(digest, &e.bytes)
+            })
+            .map(|(algorithm, _)| (*algorithm, e.candidates.len() > 1))
+    }))
+}
+
+/// Checks one file's size, then its digest, returning its status
+async fn check_repo_entry(path: &Path, entry: &repo::Entry, options: &HashOptions) -> String {
+    let len = match async_std::fs::metadata(path).await {
+        Ok(metadata) => metadata.len(),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return "MISSING".to_string(),
+        Err(e) => return format!("FAILED ({})", e),
+    };
+    if entry.size.is_some_and(|size| size != len) {
+        return format!("FAILED (size {}, expected {})", len, entry.size.unwrap());
+    }
+    match feed_file(path, options, entry.algorithm.hasher()).await {
+        Ok((hasher, _)) => if unhex(&entry.digest)
+            .is_some_and(|expected| digest::ct_eq(&hasher.finalize(), &expected))
+        {
+            "OK"
+        } else {
+            "FAILED"
+        }
+        .to_string(),
+        Err(e) => format!("FAILED ({})", e),
+    }
+}
+
+/// Links `farm.out/<digest>` to each file under `farm.dir`. When several files
+/// have the same contents, the first one in path order gets the link, and a
+/// link left by an earlier run is kept.
+#[cfg(unix)]
+async fn make_link_farm(args: &Args, farm: &LinkFarmArgs) -> Result<(), eyre::Error> {
+    use futures::stream::StreamExt;
+
+    let dir = async_std::fs::canonicalize(&farm.dir).await?;
+    async_std::fs::create_dir_all(&farm.out).await?;
+    let options = Arc::new(hash_options(args));
+    let files = walk::files(dir.as_ref(), &walk_options(args)?).await?;
+    let mut results = futures::stream::iter(files)
+        .map(|path| {
+            let options = options.clone();
+            async_std::task::spawn(async move {
+                let hashed = hash_file(&path, &options).await;
+                (path, hashed)
+            })
+        })
+        .buffered(jobs(args));
+
+    let mut failed = 0;
+    while let Some((path, hashed)) = results.next().await {
+        let hashed = match hashed {
+            Ok(hashed) => hashed,
+            Err(e) => {
+                eprintln!("While hashing {}: {}", path.display(), e);
+                failed += 1;
+                continue;
+            }
+        };
+        let link = farm.out.join(hex(&hashed.hash));
+        match async_std::os::unix::fs::symlink(&path, &link).await {
+            Ok(()) => println!("{} -> {}", link.display(), path.display()),
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {}
+            Err(e) => return Err(eyre!("can't create {}: {}", link.display(), e)),
+        }
+    }
+    if failed > 0 {
+        return Err(eyre!("{} file(s) could not be hashed", failed));
+    }
+    Ok(())
+}
+
+/// Prints the clusters of files with the same contents under `dupes.paths`
+async fn find_dupes(args: &Args, dupes: &DupesArgs) -> Result<(), eyre::Error> {
+    use futures::stream::StreamExt;
+
+    if dupes.paths.is_empty() {
+        return Err(eyre!("dupes needs the directories to look through"));
+    }
+    let options = walk_options(args)?;
+    // once each, however many of the paths they're under
+    let mut files = std::collections::BTreeSet::new();
+    for path in &dupes.paths {
+        if async_std::fs::metadata(path).await?.is_dir() {
+            files.extend(walk::files(path, &options).await?);
+        } else {
+            files.insert(path.clone());
+        }
+    }
+
+    let mut failed = 0;
+    let mut sizes = futures::stream::iter(files)
+        .map(|path| async move {
+            let size = async_std::fs::metadata(&path).await.map(|m| m.len());
+            (path, size)
+        })
+        .buffer_unordered(jobs(args));
+    let mut sized = Vec::new();
+    while let Some((path, size)) = sizes.next().await {
+        match size {
+            Ok(size) => sized.push((path, size)),
+            Err(e) => {
+                eprintln!("While reading {}: {}", path.display(), e);
+                failed += 1;
+            }
+        }
+    }
+
+    let candidates = dupes::same_size(sized);
+    tracing::debug!(
+        sizes = candidates.len(),
+        files = candidates.values().map(Vec::len).sum::<usize>(),
+        "hashing files that share a size"
+    );
+    let options = Arc::new(hash_options(args));
+    let mut results = futures::stream::iter(candidates.into_values().flatten())
+        .map(|path| {
+            let options = options.clone();
+            async_std::task::spawn(async move {
+                let hashed = hash_file(&path, &options).await;
+                (path, hashed)
+            })
+        })
+        .buffer_unordered(jobs(args));
+    let mut clusters = dupes::Clusters::default();
+    while let Some((path, hashed)) = results.next().await {
+        match hashed {
+            // the size it had when it was read, should it have changed since
+            Ok(hashed) => clusters.add(hashed.size, hashed.hash, path),
+            Err(e) => {
+                eprintln!("While hashing {}: {}", path.display(), e);
+                failed += 1;
+            }
+        }
+    }
+
+    let clusters = clusters.finish();
+    if dupes.json {
+        let records: Vec<_> = clusters
+            .iter()
+            .map(|cluster| dupes::Record::new(args.algorithm().name(), cluster))
+            .collect();
+        println!("{}", serde_json::to_string_pretty(&records)?);
+    } else {
+        for cluster in &clusters {
+            println!(
+                "{} ({} files, {} bytes each)",
+                cluster.digest,
+                cluster.paths.len(),
+                cluster.size
+            );
+            for path in &cluster.paths {
+                println!("  {}", args.quote.path(path));
+            }
+            println!();
+        }
+    }
+    if failed > 0 {
+        return Err(eyre!("{} file(s) could not be compared", failed));
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+async fn make_link_farm(_args: &Args, _farm: &LinkFarmArgs) -> Result<(), eyre::Error> {
+    Err(eyre!("link-farm is only supported on Unix"))
+}
+
+/// What `--lang` says statuses are printed in, set once arguments are parsed
+static LANG: OnceLock<messages::Lang> = OnceLock::new();
+
+/// Prints a verifier's `path: STATUS` line in the `--lang` language, and
+/// logs anything that isn't OK, in English
+fn print_status(path: impl std::fmt::Display, status: &str) {
+    match status {
+        // an inferred algorithm follows in parentheses
+        s if s == "OK" || s.starts_with("OK (") => {}
+        s if s.starts_with("FAILED") => {
+            tracing::error!(path = %path, status, "verification failed")
+        }
+        _ => tracing::warn!(path = %path, status, "verification issue"),
+    }
+    let lang = LANG.get().copied().unwrap_or_default();
+    println!("{}: {}", path, lang.status(status));
+}
+
+/// Where verifiers send each file's status once it's printed
+struct Verdicts {
+    remedy: remedy::Remedy,
+    heatmap: Option<heatmap::Heatmap>,
+}
+
+fn verdicts(args: &Args) -> Result<Verdicts, eyre::Error> {
+    Ok(Verdicts {
+        remedy: remedy::Remedy::new(args.on_mismatch.clone(), args.mismatch_journal.as_deref())?,
+        heatmap: args.heatmap.as_ref().map(|_| Default::default()),
+    })
+}
+
+impl Verdicts {
+    /// Counts the status of the file at `path`, listed as `key`, towards
+    /// `--heatmap`, and applies `--on-mismatch` to it
+    async fn record(&mut self, path: &Path, key: &str, status: &str) -> Result<(), eyre::Error> {
+        if let (Some(heatmap), Some(outcome)) =
+            (&mut self.heatmap, heatmap::Outcome::from_status(status))
+        {
+            heatmap.record(path, outcome);
+        }
+        if let Some(done) = self.remedy.apply(path, key, status).await? {
+            print_status(key, &done);
+        }
+        Ok(())
+    }
+
+    fn finish(self, args: &Args) -> Result<(), eyre::Error> {
+        write_heatmap(args, self.heatmap.as_ref())
+    }
+}
+
+fn write_heatmap(args: &Args, heatmap: Option<&heatmap::Heatmap>) -> Result<(), eyre::Error> {
+    if let (Some(path), Some(heatmap)) = (&args.heatmap, heatmap) {
+        let report = heatmap.render(args.heatmap_format, args.heatmap_depth)?;
+        std::fs::write(path, report)
+            .map_err(|e| eyre!("can't write heatmap {}: {}", path.display(), e))?;
+    }
+    Ok(())
+}
+
+fn hash_options(args: &Args) -> HashOptions {
+    HashOptions {
+        algorithm: args.algorithm(),
+        noatime: args.noatime,
+        buffer: tune::Buffer::new(args.buffer_size()),
+        inspect: Default::default(),
+        trace_sample: args.trace_sample.map(|s| s.with_seed(args.seed)),
+        console: None,
+        progress: None,
+        audit: args.audit || args.audit_against.is_some(),
+        checkpoint_every: args.checkpoint_every.map(|units::ByteSize(n)| n),
+        max_file_size: args
+            .max_file_size
+            .map(|units::ByteSize(n)| (n, args.oversize)),
+        changing_files: args.changing_files,
+        reads: args.reads,
+        drop_cache: args.drop_cache,
+        evict_after_read: args.evict_after_read,
+        populate_cache: args.populate_cache,
+        salvage: args.salvage,
+        backend: args.backend,
+        dirs: args
+            .batch_small_files
+            .map(|_| Arc::new(open::Dirs::default())),
+        timeout: args.timeout.map(|units::Duration(d)| d),
+        retry: (args.retries.is_some() || args.read_timeout.is_some()).then(|| {
+            let mut policy = retry::Policy::new(args.retries.unwrap_or(0));
+            if let Some(units::Duration(backoff)) = args.retry_backoff {
+                policy.backoff = backoff;
+            }
+            policy.read_timeout = args.read_timeout.map(|units::Duration(d)| d);
+            policy
+        }),
+        archive: args.archive,
+        limit_rate: rate_limit(args),
+        also: args.also(),
+        resume_dir: args.resume_dir().map(|dir| {
+            Arc::new(state::ResumeDir {
+                dir: dir.to_owned(),
+                every: args
+                    .state_every
+                    .map_or(state::DEFAULT_EVERY, |units::ByteSize(n)| n),
+            })
+        }),
+        faults: fault::Faults {
+            latency: args
+                .throttle
+                .map(|units::Duration(d)| d)
+                .or(args.inject_faults.and_then(|f| f.latency)),
+            ..args.inject_faults.unwrap_or_default()
+        },
+        seed: args.seed,
+        key: args.hmac_key.clone(),
+        cache: None,
+        tree: args.tree.map(|units::ByteSize(n)| n),
+        threads: args.threads,
+        pool: hash_pool(args),
+        small_file_size: args
+            .small_file_size
+            .map_or(pool::DEFAULT_SMALL_FILE_SIZE, |units::ByteSize(n)| n),
+    }
+}
+
+/// What `--limit-rate` keeps reads to, shared by every file
+fn rate_limit(args: &Args) -> Option<Arc<throttle::Bucket>> {
+    static BUCKET: OnceLock<Option<Arc<throttle::Bucket>>> = OnceLock::new();
+    BUCKET
+        .get_or_init(|| {
+            args.limit_rate
+                .map(|units::ByteSize(n)| Arc::new(throttle::Bucket::new(n)))
+        })
+        .clone()
+}
+
+/// The threads hashers run on, shared by every file, if any
+fn hash_pool(args: &Args) -> Option<Arc<pool::Pool>> {
+    static POOL: OnceLock<Option<Arc<pool::Pool>>> = OnceLock::new();
+    POOL.get_or_init(|| {
+        let threads = args
+            .hash_threads
+            .unwrap_or_else(|| std::thread::available_parallelism().map_or(1, |n| n.get()));
+        (threads > 0).then(|| Arc::new(pool::Pool::new(threads, args.pin_hash_threads)))
+    })
+    .clone()
+}
+
+/// Hashes one piece of a larger object, starting from and/or ending with a
+/// saved hasher state
+async fn hash_with_state(args: &Args) -> Result<(), eyre::Error> {
+    let path = match &args.files[..] {
+        [path] => path,
+        _ => {
+            return Err(eyre!(
+                "--resume-state and --emit-state take exactly one input"
+            ))
+        }
+    };
+    if args.hmac_key.is_some() {
+        return Err(eyre!(
+            "--resume-state and --emit-state can't save a keyed hasher's state"
+        ));
+    }
+    let options = hash_options(args);
+
+    let saved = match args.resume_file() {
+        Some(state) => Some(state::HasherState::load(state).await?),
+        None => None,
+    };
+    // a saved state says which algorithm it's for, unless --algo does
+    let algorithm = match (&saved, args.algo()) {
+        (Some(saved), None) => saved.algorithm()?,
+        _ => args.algorithm(),
+    };
+    let (hasher, offset) = match &saved {
+        Some(saved) => (saved.restore(algorithm)?, saved.offset),
+        None => (algorithm.hasher(), 0),
+    };
+    if hasher.serialize().is_none() {
+        return Err(eyre!(
+            "--resume-state and --emit-state don't support {}, whose state can't be saved",
+            algorithm
+        ));
+    }
+    let (hasher, fed) = feed_file(path, &options, hasher).await?;
+    let offset = offset + fed.size;
+
+    match &args.emit_state {
+        Some(state) => {
+            state::HasherState::capture(&hasher, algorithm, offset)?
+                .save(state)
+                .await?
+        }
+        None => println!("{} {}", path.display(), hex(&hasher.finalize())),
+    }
+    Ok(())
+}
+
+/// Copies stdin to stdout while hashing it, failing at the end unless the
+/// digest is `expected`
+async fn verify_stream(args: &Args, expected: &str) -> Result<(), eyre::Error> {
+    use algo::Update;
+    use async_std::io::WriteExt;
+
+    let expected = digest::Digest::parse_as(args.algorithm(), expected.trim())?;
+    let mut input = async_std::io::stdin();
+    let mut output = async_std::io::stdout();
+    let mut hasher = args.hasher()?;
+    let mut buf = vec![0u8; tune::Buffer::new(args.buffer_size()).size()];
+    loop {
+        let n = input.read(&mut buf).await?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+        output.write_all(&buf[..n]).await?;
+    }
+    output.flush().await?;
+
+    hasher.finish()?;
+    let actual = digest::Digest {
+        algorithm: args.algorithm(),
+        bytes: hasher.finalize(),
+    };
+    if actual.ct_eq(&expected) {
+        return Ok(());
+    }
+    if args.truncate_on_mismatch {
+        truncate_stdout()?;
+    }
+    Err(eyre!("stream digest is {}, expected {}", actual, expected))
+}
+
+/// Empties stdout if it's a regular file, so a bad copy doesn't stay behind
+#[cfg(unix)]
+fn truncate_stdout() -> Result<(), eyre::Error> {
+    use std::os::unix::io::FromRawFd;
+
+    // borrowed, not owned: stdout must stay open
+    let stdout = std::mem::ManuallyDrop::new(unsafe { std::fs::File::from_raw_fd(1) });
+    if !stdout.metadata()?.is_file() {
+        return Err(eyre!(
+            "--truncate-on-mismatch needs stdout to be a regular file"
+        ));
+    }
+    stdout.set_len(0)?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn truncate_stdout() -> Result<(), eyre::Error> {
+    Err(eyre!("--truncate-on-mismatch is only supported on Unix"))
+}
+
+/// Hashes every attachment of every message in the inputs
+async fn hash_attachments(args: &Args) -> Result<(), eyre::Error> {
+    let mut failed = 0;
+    for path in &args.files {
+        let attachments = async_std::fs::read(path)
+            .await
+            .map_err(eyre::Error::from)
+            .and_then(|input| {
+                mime::messages(&input)
+                    .into_iter()
+                    .enumerate()
+                    .map(|(i, message)| mime::attachments(i, message))
+                    .collect::<Result<Vec<_>, _>>()
+            });
+        let attachments = match attachments {
+            Ok(attachments) => attachments,
+            Err(e) => {
+                tracing::error!(path = %path.display(), error = %e, "hashing failed");
+                eprintln!("While hashing {}: {}", path.display(), e);
+                failed += 1;
+                continue;
             }
-            Poll::Pending => {
-                tracing::debug!("future was pending!");
-                *proj.state = State::Pending(fut);
-                Poll::Pending
+        };
+
+        for attachment in attachments.into_iter().flatten() {
+            let mut hasher = args.hasher()?;
+            algo::Update::update(&mut hasher, &attachment.data);
+            algo::Update::finish(&mut hasher)?;
+            println!(
+                "{} {} message={} message-id={} filename={}",
+                path.display(),
+                hex(&hasher.finalize()),
+                attachment.message,
+                attachment.message_id.as_deref().unwrap_or("-"),
+                attachment
+                    .filename
+                    .map(|name| format!("{:?}", name))
+                    .unwrap_or_else(|| "-".to_string()),
+            );
+        }
+    }
+    if failed > 0 {
+        return Err(eyre!("{} file(s) couldn't be hashed", failed));
+    }
+    Ok(())
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+
+    /// An empty directory of its own for each test
+    fn scratch(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("surviving-main-{}-{}", std::process::id(), name));
+        std::fs::remove_dir_all(&dir).ok();
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn args(argv: &[&str]) -> Args {
+        Args::from_args(&["surviving"], argv).unwrap()
+    }
+
+    /// Confines a thread of its own, since Landlock only restricts the
+    /// calling thread, and tries creating `outputs` from it
+    fn confined(args: Args, outputs: Vec<PathBuf>) -> Option<Result<(), eyre::Error>> {
+        std::thread::spawn(move || {
+            match sandbox(&args) {
+                Err(e) if e.to_string().contains("can't confine processes") => return None,
+                Err(e) => return Some(Err(e)),
+                Ok(()) => {}
             }
+            Some(
+                outputs
+                    .iter()
+                    .try_for_each(|path| compress::Output::create(path, None).map(drop)),
+            )
+        })
+        .join()
+        .unwrap()
+    }
+
+    #[test]
+    fn confined_output_is_writable() {
+        let dir = scratch("confined-output");
+        let (input, output) = (dir.join("a"), dir.join("out.txt"));
+        std::fs::write(&input, "a").unwrap();
+        let args = args(&[
+            "--confine-to",
+            dir.to_str().unwrap(),
+            "-o",
+            output.to_str().unwrap(),
+            input.to_str().unwrap(),
+        ]);
+        match confined(args, vec![output.clone()]) {
+            None => eprintln!("skipped, this kernel has no Landlock"),
+            Some(res) => res.unwrap(),
+        }
+        assert!(output.exists());
+        // anything else stays read-only
+        let args = self::args(&[
+            "--confine-to",
+            dir.to_str().unwrap(),
+            input.to_str().unwrap(),
+        ]);
+        if let Some(res) = confined(args, vec![dir.join("other")]) {
+            assert!(res.is_err());
         }
     }
+
+    #[test]
+    fn confined_sidecar_is_writable() {
+        let dir = scratch("confined-sidecar");
+        let (input, cache) = (dir.join("a"), dir.join("cache").join("a.json"));
+        std::fs::write(&input, "a").unwrap();
+        std::fs::create_dir_all(cache.parent().unwrap()).unwrap();
+        let sidecar = format!("sidecar:{}", cache.display());
+        let args = args(&[
+            "--confine-to",
+            input.to_str().unwrap(),
+            "--cache",
+            &sidecar,
+            input.to_str().unwrap(),
+        ]);
+        let mut partial = cache.into_os_string();
+        partial.push(".partial");
+        if let Some(res) = confined(args, vec![partial.into()]) {
+            res.unwrap();
+        }
+
+        let args = self::args(&["--confine-to", input.to_str().unwrap(), "--cache", "xattr"]);
+        let e = sandbox(&args).unwrap_err();
+        assert!(e.to_string().contains("--cache xattr"), "{}", e);
+    }
 }
@@ -0,0 +1,174 @@
+//! Merkle trees over fixed-size chunks, for `--tree`: a root digest for
+//! the whole file, and leaves that let any one chunk be checked later
+//! without reading the rest.
+//!
+//! Leaves and interior nodes are hashed with different prefixes, as in
+//! RFC 6962, so a leaf can't pass for a node. A node left without a
+//! sibling moves up a level unchanged.
+
+use crate::algo::{Hasher, Update};
+use color_eyre::eyre::{self, eyre};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+const LEAF: u8 = 0x00;
+const NODE: u8 = 0x01;
+
+/// Hashes a stream as the leaves of a tree
+pub struct Tree {
+    /// A fresh hasher, copied for each leaf and node
+    prototype: Hasher,
+    chunk_size: u64,
+    chunk: Hasher,
+    /// How much of the current chunk was fed
+    filled: u64,
+    leaves: Vec<Vec<u8>>,
+}
+
+impl Tree {
+    /// `None` for hashers that can't be copied, which external commands
+    /// can't
+    pub fn new(prototype: Hasher, chunk_size: u64) -> Option<Self> {
+        let chunk = leaf_hasher(&prototype)?;
+        Some(Self {
+            prototype,
+            chunk_size,
+            chunk,
+            filled: 0,
+            leaves: Vec::new(),
+        })
+    }
+
+    /// The root, and every leaf in order. An empty stream has a single
+    /// leaf, of no bytes.
+    pub fn finalize(mut self) -> (Vec<u8>, Vec<Vec<u8>>) {
+        if self.filled > 0 || self.leaves.is_empty() {
+            self.leaves.push(self.chunk.finalize());
+        }
+        (root(&self.prototype, &self.leaves), self.leaves)
+    }
+}
+
+impl Update for Tree {
+    fn update(&mut self, mut data: &[u8]) {
+        while !data.is_empty() {
+            if self.filled == self.chunk_size {
+                let next = leaf_hasher(&self.prototype).expect("the prototype was copied before");
+                self.leaves
+                    .push(std::mem::replace(&mut self.chunk, next).finalize());
+                self.filled = 0;
+            }
+            let take = ((self.chunk_size - self.filled) as usize).min(data.len());
+            self.chunk.update(&data[..take]);
+            self.filled += take as u64;
+            data = &data[take..];
+        }
+    }
+}
+
+fn leaf_hasher(prototype: &Hasher) -> Option<Hasher> {
+    let mut hasher = prototype.try_clone()?;
+    hasher.update(&[LEAF]);
+    Some(hasher)
+}
+
+/// The leaf for one chunk
+pub fn leaf(prototype: &Hasher, chunk: &[u8]) -> Option<Vec<u8>> {
+    let mut hasher = leaf_hasher(prototype)?;
+    hasher.update(chunk);
+    Some(hasher.finalize())
+}
+
+/// The root over `leaves`
+pub fn root(prototype: &Hasher, leaves: &[Vec<u8>]) -> Vec<u8> {
+    let mut level = leaves.to_vec();
+    while level.len() > 1 {
+        level = level
+            .chunks(2)
+            .map(|pair| match pair {
+                [left, right] => {
+                    let mut hasher = prototype.try_clone().expect("leaves were made with it");
+                    hasher.update(&[NODE]);
+                    hasher.update(left);
+                    hasher.update(right);
+                    hasher.finalize()
+                }
+                [alone] => alone.clone(),
+                _ => unreachable!(),
+            })
+            .collect();
+    }
+    level.pop().unwrap_or_default()
+}
+
+/// One file's line in a `--tree-leaves` sidecar
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Leaves {
+    pub path: String,
+    pub algorithm: String,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub keyed: bool,
+    pub chunk_size: u64,
+    /// In hex, like the leaves
+    pub root: String,
+    pub leaves: Vec<String>,
+}
+
+/// The record for `path` in the sidecar at `sidecar`, the last one if it
+/// was hashed more than once
+pub fn load(sidecar: &Path, path: &Path) -> Result<Leaves, eyre::Error> {
+    let text = std::fs::read_to_string(sidecar)
+        .map_err(|e| eyre!("can't read {}: {}", sidecar.display(), e))?;
+    let wanted = path.display().to_string();
+    let mut found = None;
+    for (i, line) in text.lines().enumerate().filter(|(_, l)| !l.is_empty()) {
+        let leaves: Leaves = serde_json::from_str(line)
+            .map_err(|e| eyre!("{} line {}: {}", sidecar.display(), i + 1, e))?;
+        if leaves.path == wanted {
+            found = Some(leaves);
+        }
+    }
+    found.ok_or_else(|| eyre!("{} has no leaves for {}", sidecar.display(), wanted))
+}
+
+/// A `--tree-leaves` sidecar being written, one JSON line per file
+pub struct Sidecar {
+    out: std::io::BufWriter<std::fs::File>,
+    chunk_size: u64,
+}
+
+impl Sidecar {
+    pub fn create(path: &Path, chunk_size: u64) -> Result<Self, eyre::Error> {
+        let file = std::fs::File::create(path)
+            .map_err(|e| eyre!("can't create {}: {}", path.display(), e))?;
+        Ok(Self {
+            out: std::io::BufWriter::new(file),
+            chunk_size,
+        })
+    }
+
+    pub fn write(
+        &mut self,
+        path: &Path,
+        algorithm: crate::Algorithm,
+        keyed: bool,
+        root: &[u8],
+        leaves: &[Vec<u8>],
+    ) -> Result<(), eyre::Error> {
+        let record = Leaves {
+            path: path.display().to_string(),
+            algorithm: algorithm.name().to_owned(),
+            keyed,
+            chunk_size: self.chunk_size,
+            root: crate::hex(root),
+            leaves: leaves.iter().map(|leaf| crate::hex(leaf)).collect(),
+        };
+        serde_json::to_writer(&mut self.out, &record)?;
+        std::io::Write::write_all(&mut self.out, b"\n")?;
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> std::io::Result<()> {
+        std::io::Write::flush(&mut self.out)
+    }
+}
@@ -0,0 +1,92 @@
+//! The status words verifiers print, for `--lang`. Only what people read
+//! is translated: JSON, journals, heatmaps and `--on-mismatch` keep going
+//! by the English words, as do the details after them.
+
+use color_eyre::eyre::{self, eyre};
+use std::{borrow::Cow, str::FromStr};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Lang {
+    #[default]
+    En,
+    De,
+    Fr,
+    Es,
+}
+
+impl FromStr for Lang {
+    type Err = eyre::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "en" => Ok(Self::En),
+            "de" => Ok(Self::De),
+            "fr" => Ok(Self::Fr),
+            "es" => Ok(Self::Es),
+            _ => Err(eyre!("expected `en`, `de`, `fr` or `es`, got {:?}", s)),
+        }
+    }
+}
+
+/// Each status word with its German, French and Spanish, longer ones
+/// ahead of any they start with
+const WORDS: &[(&str, [&str; 3])] = &[
+    ("OK", ["OK", "OK", "OK"]),
+    ("FAILED", ["FEHLER", "ÉCHEC", "FALLO"]),
+    ("MISSING", ["FEHLT", "MANQUANT", "FALTA"]),
+    (
+        "UNLISTED",
+        ["NICHT GELISTET", "NON RÉPERTORIÉ", "NO LISTADO"],
+    ),
+    ("REPAIRED", ["REPARIERT", "RÉPARÉ", "REPARADO"]),
+    ("ADDED", ["HINZUGEFÜGT", "AJOUTÉ", "AÑADIDO"]),
+    ("REMOVED", ["ENTFERNT", "DISPARU", "ELIMINADO"]),
+    ("MODIFIED", ["GEÄNDERT", "MODIFIÉ", "MODIFICADO"]),
+    (
+        "TOUCHED",
+        ["ZEIT GEÄNDERT", "DATE CHANGÉE", "FECHA CAMBIADA"],
+    ),
+    ("DELETED", ["GELÖSCHT", "EFFACÉ", "BORRADO"]),
+    (
+        "QUARANTINED to",
+        [
+            "IN QUARANTÄNE NACH",
+            "EN QUARANTAINE DANS",
+            "EN CUARENTENA EN",
+        ],
+    ),
+    (
+        "NOT DELETED",
+        ["NICHT GELÖSCHT", "NON EFFACÉ", "NO BORRADO"],
+    ),
+    (
+        "NOT QUARANTINED",
+        [
+            "NICHT IN QUARANTÄNE",
+            "NON MIS EN QUARANTAINE",
+            "NO EN CUARENTENA",
+        ],
+    ),
+];
+
+impl Lang {
+    /// `status` with its leading status word in this language. A status
+    /// this table doesn't know is shown as it is.
+    pub fn status(self, status: &str) -> Cow<'_, str> {
+        let column = match self {
+            Self::En => return Cow::Borrowed(status),
+            Self::De => 0,
+            Self::Fr => 1,
+            Self::Es => 2,
+        };
+        let found = WORDS.iter().find_map(|(word, translations)| {
+            let rest = status.strip_prefix(word)?;
+            // a whole word, not the start of a longer one
+            (rest.is_empty() || rest.starts_with(' ')).then(|| (translations[column], rest))
+        });
+        match found {
+            Some((word, rest)) => Cow::Owned(format!("{}{}", word, rest)),
+            None => Cow::Borrowed(status),
+        }
+    }
+}
@@ -0,0 +1,108 @@
+//! Run totals in the Prometheus text format, for node_exporter's textfile
+//! collector or a Pushgateway, since a one-shot run has no endpoint to
+//! scrape.
+
+use crate::algo::Algorithm;
+use color_eyre::eyre::{self, eyre};
+use std::{
+    fmt::Write as _,
+    path::Path,
+    time::{Duration, SystemTime},
+};
+
+/// Job name results are pushed under
+const JOB: &str = "surviving";
+
+/// What a run did, as counted by the output writer
+#[derive(Debug, Default, Clone, Copy)]
+pub struct RunMetrics {
+    pub files: u64,
+    pub errors: u64,
+    /// Files over `--max-file-size` that weren't read
+    pub skipped: u64,
+    /// Files whose `--reads` didn't all give the same digest
+    pub disagreed: u64,
+    pub bytes: u64,
+}
+
+impl RunMetrics {
+    pub fn render(&self, algorithm: Algorithm, elapsed: Duration, finished: SystemTime) -> String {
+        let finished = finished
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default();
+        // external algorithms are named after their command
+        let name = algorithm.name().replace('\\', "\\\\").replace('"', "\\\"");
+        let labels = format!("{{algorithm=\"{}\"}}", name);
+        let mut out = String::new();
+        let mut gauge = |name: &str, help: &str, value: String| {
+            writeln!(out, "# HELP surviving_{} {}", name, help).unwrap();
+            writeln!(out, "# TYPE surviving_{} gauge", name).unwrap();
+            writeln!(out, "surviving_{}{} {}", name, labels, value).unwrap();
+        };
+        gauge(
+            "last_run_files_hashed",
+            "Files hashed by the last run.",
+            self.files.to_string(),
+        );
+        gauge(
+            "last_run_files_failed",
+            "Files the last run couldn't hash.",
+            self.errors.to_string(),
+        );
+        gauge(
+            "last_run_files_skipped",
+            "Files the last run skipped for being too large.",
+            self.skipped.to_string(),
+        );
+        gauge(
+            "last_run_files_disagreed",
+            "Files the last run read several times with different results.",
+            self.disagreed.to_string(),
+        );
+        gauge(
+            "last_run_bytes_hashed",
+            "Bytes hashed by the last run.",
+            self.bytes.to_string(),
+        );
+        gauge(
+            "last_run_duration_seconds",
+            "How long the last run took.",
+            format!("{:.3}", elapsed.as_secs_f64()),
+        );
+        gauge(
+            "last_run_timestamp_seconds",
+            "When the last run finished, as a Unix timestamp.",
+            format!("{:.3}", finished.as_secs_f64()),
+        );
+        gauge(
+            "last_run_success",
+            "Whether every file of the last run was hashed.",
+            ((self.errors == 0) as u8).to_string(),
+        );
+        out
+    }
+}
+
+/// Writes `metrics` to `path` through a rename, so the textfile collector
+/// never reads a half-written file
+pub async fn write_textfile(path: &Path, metrics: &str) -> Result<(), eyre::Error> {
+    let mut partial = path.as_os_str().to_owned();
+    partial.push(".partial");
+    async_std::fs::write(&partial, metrics).await?;
+    async_std::fs::rename(&partial, path).await?;
+    Ok(())
+}
+
+/// Replaces this job's metrics on the Pushgateway at `url`
+pub async fn push(url: &str, metrics: &str) -> Result<(), eyre::Error> {
+    let url = format!("{}/metrics/job/{}", url.trim_end_matches('/'), JOB);
+    let response = surf::put(&url)
+        .body(metrics)
+        .content_type("text/plain; version=0.0.4")
+        .await
+        .map_err(|e| eyre!("pushing to {}: {}", url, e))?;
+    if !response.status().is_success() {
+        return Err(eyre!("pushing to {}: {}", url, response.status()));
+    }
+    Ok(())
+}
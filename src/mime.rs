@@ -0,0 +1,70 @@
+//! Pulling attachments out of email, so each one can be hashed on its own.
+
+use color_eyre::eyre;
+use mailparse::{DispositionType, MailHeaderMap};
+
+/// One attached file, decoded from its transfer encoding
+pub struct Attachment {
+    /// Position of the message in its mailbox, counting from 0
+    pub message: usize,
+    pub message_id: Option<String>,
+    pub filename: Option<String>,
+    pub data: Vec<u8>,
+}
+
+/// Splits an mbox into its messages, or returns a lone .eml message as-is
+pub fn messages(input: &[u8]) -> Vec<&[u8]> {
+    if !input.starts_with(b"From ") {
+        return vec![input];
+    }
+    let mut starts: Vec<usize> = vec![0];
+    starts.extend(
+        input
+            .windows(6)
+            .enumerate()
+            .filter(|(_, w)| *w == b"\nFrom ")
+            .map(|(i, _)| i + 1),
+    );
+    starts
+        .iter()
+        .enumerate()
+        .map(|(i, &start)| {
+            let end = starts.get(i + 1).copied().unwrap_or(input.len());
+            let message = &input[start..end];
+            // drop the `From ` separator line itself
+            match message.iter().position(|&b| b == b'\n') {
+                Some(nl) => &message[nl + 1..],
+                None => &[],
+            }
+        })
+        .collect()
+}
+
+/// Every part of `message` that is an attachment or carries a file name
+pub fn attachments(index: usize, message: &[u8]) -> Result<Vec<Attachment>, eyre::Error> {
+    let mail = mailparse::parse_mail(message)?;
+    let message_id = mail.headers.get_first_value("Message-ID");
+
+    let mut attachments = Vec::new();
+    for part in mail.parts() {
+        if part.ctype.mimetype.starts_with("multipart/") {
+            continue;
+        }
+        let disposition = part.get_content_disposition();
+        let filename = disposition
+            .params
+            .get("filename")
+            .or_else(|| part.ctype.params.get("name"))
+            .cloned();
+        if disposition.disposition != DispositionType::Attachment && filename.is_none() {
+            continue;
+        }
+        attachments.push(Attachment {
+            message: index,
+            message_id: message_id.clone(),
+            filename,
+            data: part.get_body_raw()?,
+        });
+    }
+    Ok(attachments)
+}
@@ -0,0 +1,271 @@
+//! Opening input files for reading.
+
+use async_std::fs::{File, OpenOptions};
+use color_eyre::eyre::{self, eyre};
+use std::{io, path::Path, str::FromStr, sync::Arc};
+
+#[cfg(target_os = "linux")]
+use std::{
+    collections::HashMap,
+    ffi::{CStr, CString},
+    os::unix::io::OwnedFd,
+    path::PathBuf,
+    sync::Mutex,
+};
+
+/// What to do about files that change while they're read, like logs or live
+/// databases, whose digest would otherwise describe a torn read
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Changing {
+    /// Mark the result as `unstable`
+    Flag,
+    /// Hash the file again, a few times, before marking it
+    Retry,
+    /// Hold a shared `flock` while reading, so writers that take the lock
+    /// wait, and mark the result if it changed anyway
+    Lock,
+}
+
+impl FromStr for Changing {
+    type Err = eyre::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "flag" => Ok(Self::Flag),
+            "retry" => Ok(Self::Retry),
+            "lock" => Ok(Self::Lock),
+            _ => Err(eyre!("expected `flag`, `retry` or `lock`, got {:?}", s)),
+        }
+    }
+}
+
+/// The path that stands for stdin, as with coreutils. A file by that name
+/// can still be given as `./-`.
+pub const STDIN: &str = "-";
+
+pub fn is_stdin(path: &Path) -> bool {
+    path == Path::new(STDIN)
+}
+
+/// Opens `path` read-only. The descriptor is always close-on-exec, so it
+/// never leaks into helpers we spawn. With `noatime`, reads don't update the
+/// inode's access time where the platform supports it — the kernel only
+/// allows that for the file's owner, so we quietly fall back to a regular
+/// open otherwise.
+pub async fn open(path: &Path, noatime: bool) -> io::Result<File> {
+    if noatime {
+        match open_with_flags(path, NOATIME).await {
+            Err(e) if e.kind() == io::ErrorKind::PermissionDenied => {
+                tracing::debug!(path = %path.display(), "not the owner, opening without O_NOATIME");
+            }
+            res => return res,
+        }
+    }
+    open_with_flags(path, 0).await
+}
+
+#[cfg(target_os = "linux")]
+const NOATIME: i32 = libc::O_NOATIME;
+
+#[cfg(not(target_os = "linux"))]
+const NOATIME: i32 = 0;
+
+#[cfg(unix)]
+async fn open_with_flags(path: &Path, flags: i32) -> io::Result<File> {
+    use async_std::os::unix::fs::OpenOptionsExt;
+
+    OpenOptions::new()
+        .read(true)
+        .custom_flags(libc::O_CLOEXEC | flags)
+        .open(path)
+        .await
+}
+
+#[cfg(not(unix))]
+async fn open_with_flags(path: &Path, _flags: i32) -> io::Result<File> {
+    // handles are not inheritable by default on Windows
+    OpenOptions::new().read(true).open(path).await
+}
+
+/// Takes a shared advisory lock on `file`, waiting for any exclusive one to
+/// be released. It's held until the file is closed.
+#[cfg(unix)]
+pub async fn lock_shared(file: &File) -> io::Result<()> {
+    use std::os::unix::io::{AsFd, AsRawFd};
+
+    // a descriptor of its own, so one dropped mid-wait, by a timeout or an
+    // interrupt, can't be closed and its number reused under the flock.
+    // Both share the open file, and with it the lock.
+    let fd = file.as_fd().try_clone_to_owned()?;
+    crate::rt::spawn_blocking(move || {
+        if unsafe { libc::flock(fd.as_raw_fd(), libc::LOCK_SH) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    })
+    .await
+}
+
+/// Evicts `path`'s pages from the page cache, so the next read comes from
+/// storage. Pages not yet written back stay.
+#[cfg(target_os = "linux")]
+pub async fn drop_cache(path: &Path) -> io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+
+    let file = open(path, false).await?;
+    match unsafe { libc::posix_fadvise(file.as_raw_fd(), 0, 0, libc::POSIX_FADV_DONTNEED) } {
+        0 => Ok(()),
+        // it returns the error rather than setting errno
+        e => Err(io::Error::from_raw_os_error(e)),
+    }
+}
+
+/// Asks the kernel to start reading all of `file` into the page cache,
+/// without waiting for it
+#[cfg(target_os = "linux")]
+pub fn will_need(file: &File) -> io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+
+    match unsafe { libc::posix_fadvise(file.as_raw_fd(), 0, 0, libc::POSIX_FADV_WILLNEED) } {
+        0 => Ok(()),
+        e => Err(io::Error::from_raw_os_error(e)),
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn will_need(_file: &File) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Other,
+        "reading ahead is only supported on Linux",
+    ))
+}
+
+#[cfg(not(target_os = "linux"))]
+pub async fn drop_cache(_path: &Path) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Other,
+        "dropping cached pages is only supported on Linux",
+    ))
+}
+
+#[cfg(not(unix))]
+pub async fn lock_shared(_file: &File) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Other,
+        "locking is only supported on Unix",
+    ))
+}
+
+/// How many directories [`Dirs`] keeps open before starting over
+const KEPT_DIRS: usize = 64;
+
+/// Directories kept open, for `--batch-small-files`, so files in them are
+/// stat'd and opened relative to a handle instead of by their whole path,
+/// which costs a lookup per component on network filesystems whose
+/// clients don't cache them for long
+#[derive(Default)]
+pub struct Dirs {
+    #[cfg(target_os = "linux")]
+    open: Mutex<HashMap<PathBuf, Arc<OwnedFd>>>,
+}
+
+impl Dirs {
+    /// Like [`open`], from the handle for `path`'s directory
+    #[cfg(target_os = "linux")]
+    pub async fn open(self: &Arc<Self>, path: &Path, noatime: bool) -> io::Result<File> {
+        let (dir, name) = match self.dir(path).await {
+            Some(found) => found,
+            None => return open(path, noatime).await,
+        };
+        let file = crate::rt::spawn_blocking(move || {
+            if noatime {
+                match openat(&dir, &name, libc::O_RDONLY | NOATIME) {
+                    Err(e) if e.kind() == io::ErrorKind::PermissionDenied => {}
+                    res => return res,
+                }
+            }
+            openat(&dir, &name, libc::O_RDONLY)
+        })
+        .await?;
+        Ok(File::from(file))
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub async fn open(self: &Arc<Self>, path: &Path, noatime: bool) -> io::Result<File> {
+        open(path, noatime).await
+    }
+
+    /// The size of `path`, from the handle for its directory
+    #[cfg(target_os = "linux")]
+    pub async fn len(self: &Arc<Self>, path: &Path) -> io::Result<u64> {
+        use std::os::unix::io::AsRawFd;
+
+        let (dir, name) = match self.dir(path).await {
+            Some(found) => found,
+            None => return Ok(async_std::fs::metadata(path).await?.len()),
+        };
+        crate::rt::spawn_blocking(move || {
+            let mut stat: libc::stat = unsafe { std::mem::zeroed() };
+            if unsafe { libc::fstatat(dir.as_raw_fd(), name.as_ptr(), &mut stat, 0) } != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(stat.st_size as u64)
+        })
+        .await
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub async fn len(self: &Arc<Self>, path: &Path) -> io::Result<u64> {
+        Ok(async_std::fs::metadata(path).await?.len())
+    }
+
+    /// The handle for `path`'s directory, opening it if it isn't yet, and
+    /// the name to look up there. `None` for paths with no file name, or
+    /// in directories that can't be opened, which are then opened by path
+    /// and fail there if they must.
+    #[cfg(target_os = "linux")]
+    async fn dir(self: &Arc<Self>, path: &Path) -> Option<(Arc<OwnedFd>, CString)> {
+        use std::os::unix::{ffi::OsStrExt, io::FromRawFd};
+
+        let name = CString::new(path.file_name()?.as_bytes()).ok()?;
+        let parent = match path.parent()? {
+            parent if parent.as_os_str().is_empty() => Path::new("."),
+            parent => parent,
+        };
+        if let Some(dir) = self.open.lock().unwrap().get(parent) {
+            return Some((dir.clone(), name));
+        }
+        let parent = parent.to_owned();
+        let c_parent = CString::new(parent.as_os_str().as_bytes()).ok()?;
+        let dirs = self.clone();
+        crate::rt::spawn_blocking(move || {
+            // only to look names up in, not to list
+            let flags = libc::O_PATH | libc::O_DIRECTORY | libc::O_CLOEXEC;
+            let fd = unsafe { libc::open(c_parent.as_ptr(), flags) };
+            if fd < 0 {
+                return None;
+            }
+            let dir = Arc::new(unsafe { OwnedFd::from_raw_fd(fd) });
+            let mut open = dirs.open.lock().unwrap();
+            if open.len() >= KEPT_DIRS {
+                // files come in walk order, so the directories already done
+                // with are most of these
+                open.clear();
+            }
+            open.insert(parent, dir.clone());
+            Some((dir, name))
+        })
+        .await
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn openat(dir: &OwnedFd, name: &CStr, flags: i32) -> io::Result<std::fs::File> {
+    use std::os::unix::io::{AsRawFd, FromRawFd};
+
+    let fd = unsafe { libc::openat(dir.as_raw_fd(), name.as_ptr(), flags | libc::O_CLOEXEC) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(unsafe { std::fs::File::from_raw_fd(fd) })
+}
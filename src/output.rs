@@ -0,0 +1,1018 @@
+//! Turning per-file results into output, from a single task.
+
+use crate::{bloom::Bloom, Hashed};
+use async_std::channel::Receiver;
+use color_eyre::eyre::{self, eyre};
+use serde::Serialize;
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fmt::Write as _,
+    io::{self, Write},
+    path::PathBuf,
+    str::FromStr,
+};
+
+/// How many finished results may wait for the writer before workers block
+pub const RESULTS_CAPACITY: usize = 1024;
+
+/// The version of the JSON formats, in every record and group. Fields are
+/// only ever added, and parsers should ignore those they don't know; this
+/// is bumped if one ever changes meaning or goes away.
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// The outcome of hashing one input
+pub struct FileResult {
+    /// Where the input is in the list of files to hash
+    pub index: usize,
+    pub path: PathBuf,
+    pub outcome: Result<Hashed, eyre::Error>,
+    /// With `--archive`, for a member, the archive it's in
+    pub member_of: Option<PathBuf>,
+}
+
+/// Everything the writer needs to format and record results
+pub struct Writer {
+    /// Mark digests as `seen` or `new` against this filter
+    pub bloom_check: Option<Bloom>,
+    /// Insert every digest into this filter
+    pub bloom_out: Option<Bloom>,
+    /// Mark files as duplicates of archived or earlier ones, or as new
+    pub dedupe: Option<crate::dupes::Known>,
+    /// Leave out the files `dedupe` says are duplicates
+    pub only_new: bool,
+    /// Print each distinct digest only once
+    pub unique: Option<Unique>,
+    /// Write lines to per-directory manifests instead of stdout
+    pub shards: Option<crate::shard::Shards>,
+    /// Drop results that don't match
+    pub filter: crate::filter::Filter,
+    /// Totals, counted before filtering
+    pub metrics: crate::metrics::RunMetrics,
+    /// Per-file phase timings, for every file that hashed
+    pub timings: Option<TimingsFile>,
+    /// Per-file bytes, reads and throughput, for `--stats`
+    pub stats: Option<crate::stats::Stats>,
+    pub format: Format,
+    /// How paths are written, except in JSON
+    pub quote: crate::quote::Quote,
+    /// With `--null`, whether records end with a NUL rather than a newline,
+    /// except in JSON
+    pub null: bool,
+    /// Owners and modes from an earlier run, to flag changes against
+    pub previous_audit: Option<HashMap<PathBuf, crate::audit::Audit>>,
+    /// Named in the group formats, and in lines unless it's the default
+    pub algorithm: crate::algo::Algorithm,
+    /// Whether digests are keyed, with `--hmac-key`
+    pub keyed: bool,
+    /// With `--tree`, the chunk size, named in lines
+    pub tree: Option<u64>,
+    /// Each file's leaves, with `--tree-leaves`
+    pub leaves: Option<crate::merkle::Sidecar>,
+    /// Where damaged files couldn't be read, with `--bad-ranges`
+    pub bad_ranges: Option<crate::salvage::Map>,
+    /// Paths by digest, for the group formats
+    pub groups: BTreeMap<Vec<u8>, Vec<PathBuf>>,
+    /// Read errors by directory and device, for `--heatmap`
+    pub heatmap: Option<crate::heatmap::Heatmap>,
+    /// How many records the `json` array has so far
+    pub records: usize,
+    /// Hold results back until every input before them is written
+    pub ordered: Option<Ordered>,
+    /// Bars to take down while writing, taken down for good once every
+    /// result is in
+    pub progress: Option<std::sync::Arc<crate::progress::Progress>>,
+    /// What callers call each input, by index, echoed in JSON records
+    pub ids: std::sync::Arc<Ids>,
+    /// With `--report-aliases`, the input each one is a symlink to, by
+    /// index
+    pub aliases: Vec<Option<PathBuf>>,
+    /// Where results go instead of stdout, with `--output`
+    pub output: Option<crate::compress::Output>,
+    /// With `--watermark`, the comment line to start with
+    pub watermark: Option<String>,
+    /// With `--sort`, the results held back to be written in order
+    pub sort: Option<crate::sort::Sorter>,
+}
+
+/// The IDs `--files-from` gave inputs, by index, filled in as the list is
+/// read, which can be while earlier inputs are written already
+#[derive(Default)]
+pub struct Ids(std::sync::Mutex<HashMap<usize, String>>);
+
+impl Ids {
+    pub fn insert(&self, index: usize, id: String) {
+        self.0.lock().unwrap().insert(index, id);
+    }
+
+    fn get(&self, index: usize) -> Option<String> {
+        self.0.lock().unwrap().get(&index).cloned()
+    }
+}
+
+/// Results that finished before an earlier input, for `--ordered`
+#[derive(Default)]
+pub struct Ordered {
+    /// The index of the next result to write
+    next: usize,
+    pending: BTreeMap<usize, FileResult>,
+}
+
+impl Ordered {
+    /// Takes in a result, returning those that can now be written, in order
+    fn push(&mut self, result: FileResult) -> Vec<FileResult> {
+        self.pending.insert(result.index, result);
+        let mut ready = Vec::new();
+        while let Some(result) = self.pending.remove(&self.next) {
+            ready.push(result);
+            self.next += 1;
+        }
+        ready
+    }
+}
+
+/// The `--timings-out` file
+pub struct TimingsFile(csv::Writer<std::fs::File>);
+
+impl TimingsFile {
+    pub fn create(path: impl AsRef<std::path::Path>) -> Result<Self, eyre::Error> {
+        let mut writer = csv::Writer::from_path(path)?;
+        writer.write_record(["path", "open_us", "read_us", "hash_us", "output_us"])?;
+        Ok(Self(writer))
+    }
+
+    fn write(&mut self, path: &std::path::Path, timings: &crate::Timings) -> csv::Result<()> {
+        let us = |d: std::time::Duration| d.as_micros().to_string();
+        self.0.write_record([
+            path.display().to_string(),
+            us(timings.open),
+            us(timings.read),
+            us(timings.hash),
+            us(timings.output),
+        ])
+    }
+}
+
+/// How results are laid out
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// One `<path> <digest>` line per file, as soon as it's hashed
+    Lines,
+    /// `<digest>  <path>`, as `sha256sum` prints, with errors on stderr
+    Gnu,
+    /// `ALGO (<path>) = <digest>`, as `sha256sum --tag` prints, with errors
+    /// on stderr
+    Bsd,
+    /// A JSON array with a record per file
+    Json,
+    /// A JSON record per line, as soon as each file is hashed
+    JsonLines,
+    /// Paths grouped under each distinct digest, once everything is hashed
+    Groups,
+    /// Like `groups`, as a JSON array
+    GroupsJson,
+}
+
+impl Format {
+    pub const ALL: [Self; 7] = [
+        Self::Lines,
+        Self::Gnu,
+        Self::Bsd,
+        Self::Json,
+        Self::JsonLines,
+        Self::Groups,
+        Self::GroupsJson,
+    ];
+
+    /// How `--format` spells it
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Lines => "lines",
+            Self::Gnu => "gnu",
+            Self::Bsd => "bsd",
+            Self::Json => "json",
+            Self::JsonLines => "json-lines",
+            Self::Groups => "groups",
+            Self::GroupsJson => "groups-json",
+        }
+    }
+}
+
+impl FromStr for Format {
+    type Err = eyre::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|format| format.name() == s)
+            .ok_or_else(|| {
+                eyre!(
+                    "expected `lines`, `gnu`, `bsd`, `json`, `json-lines`, `groups` or `groups-json`, got {:?}",
+                    s
+                )
+            })
+    }
+}
+
+/// One file in the JSON formats, with either a digest or an error. Keep
+/// [`schema`] in step with it.
+#[derive(Serialize, Default)]
+struct Record<'a> {
+    schema_version: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    id: Option<String>,
+    path: String,
+    /// Another record's path, naming the same file without the symlinks
+    #[serde(skip_serializing_if = "Option::is_none")]
+    alias_of: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    algorithm: Option<&'a str>,
+    /// Whether the digest is an HMAC or a keyed BLAKE3 hash
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    keyed: bool,
+    /// With `--tree`, the chunk size, the digest being the Merkle root
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tree_chunk_size: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    digest: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    size: Option<u64>,
+    /// With `--max-file-size`, the size of a file only its first `size`
+    /// bytes were hashed of
+    #[serde(skip_serializing_if = "Option::is_none")]
+    truncated_from: Option<u64>,
+    /// Whether the file changed while it was read
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    unstable: bool,
+    /// With `--reads`, how many of them agreed on the digest, and out of
+    /// how many, when they didn't all
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reads: Option<(usize, usize)>,
+    /// With `--salvage`, the start and end of each range hashed as zeros
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    unreadable: Vec<(u64, u64)>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    elapsed_secs: Option<f64>,
+    /// With `--dedupe-against`, `duplicate` or `new`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    dedupe: Option<&'static str>,
+    /// The archived or earlier path a duplicate has the contents of
+    #[serde(skip_serializing_if = "Option::is_none")]
+    duplicate_of: Option<String>,
+    /// With `--archive`, for a member, the archive it's in
+    #[serde(skip_serializing_if = "Option::is_none")]
+    member_of: Option<String>,
+    /// With `--bloom-check`, `seen` or `new`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    bloom: Option<&'static str>,
+    /// With `--detect-type`
+    #[serde(rename = "type", skip_serializing_if = "Option::is_none")]
+    file_type: Option<&'static str>,
+    /// With `--entropy`, in bits per byte
+    #[serde(skip_serializing_if = "Option::is_none")]
+    entropy: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    zero_runs: Option<ZeroRuns>,
+    /// With `--audit`, `UID:GID`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    owner: Option<String>,
+    /// With `--audit`, the permission bits in octal
+    #[serde(skip_serializing_if = "Option::is_none")]
+    mode: Option<String>,
+    /// With `--audit-against`, the owner and mode before, if they changed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    previous_owner: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    previous_mode: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+/// With `--detect-zero-runs`, the all-zero regions found
+#[derive(Serialize)]
+struct ZeroRuns {
+    count: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    longest_offset: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    longest_len: Option<u64>,
+}
+
+impl Record<'_> {
+    fn failed(path: &std::path::Path, error: &eyre::Error) -> Self {
+        Self {
+            schema_version: SCHEMA_VERSION,
+            path: path.display().to_string(),
+            error: Some(error.to_string()),
+            ..Default::default()
+        }
+    }
+}
+
+/// A JSON Schema for the `json`, `json-lines` and `groups-json` formats,
+/// for `--schema`
+pub fn schema() -> serde_json::Value {
+    let counts = serde_json::json!({ "type": "integer", "minimum": 0 });
+    let hex = serde_json::json!({ "type": "string", "pattern": "^([0-9a-f]{2})*$" });
+    serde_json::json!({
+        "$schema": "https://json-schema.org/draft/2020-12/schema",
+        "title": "surviving JSON output",
+        "description": "A record per file with `json-lines`, an array of them with `json`, \
+    or an array of groups with `groups-json`. Fields are only added within a \
+    schema version; ignore unknown ones.",
+        "oneOf": [
+            { "$ref": "#/$defs/record" },
+            { "type": "array", "items": { "$ref": "#/$defs/record" } },
+            { "type": "array", "items": { "$ref": "#/$defs/group" } },
+        ],
+        "$defs": {
+            "schema_version": { "const": SCHEMA_VERSION },
+            "record": {
+                "description": "One file, with a digest or an error",
+                "type": "object",
+                "required": ["schema_version", "path"],
+                "properties": {
+                    "schema_version": { "$ref": "#/$defs/schema_version" },
+                    "id": { "type": "string", "description": "The caller's ID for the input" },
+                    "path": { "type": "string" },
+                    "alias_of": {
+                        "type": "string",
+                        "description": "Another record's path, naming the same file",
+                    },
+                    "algorithm": { "type": "string" },
+                    "keyed": { "type": "boolean", "description": "Present and true for HMACs and keyed BLAKE3" },
+                    "tree_chunk_size": {
+                        "type": "integer",
+                        "description": "With --tree, the chunk size; the digest is then a Merkle root",
+                    },
+                    "digest": hex,
+                    "size": counts,
+                    "truncated_from": {
+                        "type": "integer",
+                        "description": "With --max-file-size, the size of a file only `size` bytes of were hashed",
+                    },
+                    "unstable": { "type": "boolean", "description": "Present and true if the file changed while it was read" },
+                    "reads": {
+                        "type": "array",
+                        "description": "With --reads, how many reads agreed on the digest and out of how many, when they didn't all",
+                        "items": counts,
+                        "minItems": 2,
+                        "maxItems": 2,
+                    },
+                    "unreadable": {
+                        "type": "array",
+                        "description": "With --salvage, [start, end) ranges hashed as zeros",
+                        "items": { "type": "array", "items": counts, "minItems": 2, "maxItems": 2 },
+                    },
+                    "elapsed_secs": { "type": "number", "minimum": 0 },
+                    "dedupe": {
+                        "enum": ["duplicate", "new"],
+                        "description": "With --dedupe-against, whether the contents were known already",
+                    },
+                    "duplicate_of": {
+                        "type": "string",
+                        "description": "The archived or earlier path a duplicate has the contents of",
+                    },
+                    "member_of": {
+                        "type": "string",
+                        "description": "With --archive, the archive a member's in, its path being ARCHIVE!PATH",
+                    },
+                    "bloom": {
+                        "enum": ["seen", "new"],
+                        "description": "With --bloom-check, whether the filter may have had the digest",
+                    },
+                    "type": { "type": "string", "description": "With --detect-type, what the contents look like" },
+                    "entropy": {
+                        "type": "number",
+                        "minimum": 0,
+                        "maximum": 8,
+                        "description": "With --entropy, in bits per byte",
+                    },
+                    "zero_runs": {
+                        "type": "object",
+                        "description": "With --detect-zero-runs, how many all-zero regions there are, and where the longest is",
+                        "required": ["count"],
+                        "properties": {
+                            "count": counts,
+                            "longest_offset": counts,
+                            "longest_len": counts,
+                        },
+                    },
+                    "owner": { "type": "string", "pattern": "^[0-9]+:[0-9]+$", "description": "With --audit, UID:GID" },
+                    "mode": { "type": "string", "pattern": "^[0-7]{4}$", "description": "With --audit, the permission bits" },
+                    "previous_owner": {
+                        "type": "string",
+                        "pattern": "^[0-9]+:[0-9]+$",
+                        "description": "With --audit-against, the owner before, if it changed",
+                    },
+                    "previous_mode": {
+                        "type": "string",
+                        "pattern": "^[0-7]{4}$",
+                        "description": "With --audit-against, the mode before, if it changed",
+                    },
+                    "error": { "type": "string" },
+                },
+                "oneOf": [
+                    { "required": ["digest"] },
+                    { "required": ["error"] },
+                ],
+            },
+            "group": {
+                "description": "Every path that hashed to one digest",
+                "type": "object",
+                "required": ["schema_version", "algorithm", "digest", "paths"],
+                "properties": {
+                    "schema_version": { "$ref": "#/$defs/schema_version" },
+                    "algorithm": { "type": "string" },
+                    "digest": hex,
+                    "paths": { "type": "array", "items": { "type": "string" } },
+                },
+            },
+        },
+    })
+}
+
+#[derive(Serialize)]
+struct Group<'a> {
+    schema_version: u32,
+    algorithm: &'a str,
+    digest: String,
+    paths: Vec<String>,
+}
+
+/// Which paths to print for a digest that was seen more than once
+#[derive(Debug, Clone, Copy)]
+pub enum UniqueMode {
+    /// Only the first path, as soon as it's hashed
+    First,
+    /// All of them, next to each other, once everything is hashed
+    All,
+}
+
+impl FromStr for UniqueMode {
+    type Err = eyre::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "first" => Ok(Self::First),
+            "all" => Ok(Self::All),
+            _ => Err(eyre!("expected `first` or `all`, got {:?}", s)),
+        }
+    }
+}
+
+/// Deduplication state for `--unique`
+pub enum Unique {
+    First(HashSet<Vec<u8>>),
+    All {
+        index: HashMap<Vec<u8>, usize>,
+        /// Output lines, grouped by digest in first-seen order
+        groups: Vec<Vec<String>>,
+    },
+}
+
+impl Unique {
+    pub fn new(mode: UniqueMode) -> Self {
+        match mode {
+            UniqueMode::First => Self::First(Default::default()),
+            UniqueMode::All => Self::All {
+                index: Default::default(),
+                groups: Default::default(),
+            },
+        }
+    }
+}
+
+impl Writer {
+    /// Writes results to stdout, or `--output`, until every sender is gone,
+    /// then returns itself so the caller can persist what was collected.
+    pub async fn run(mut self, results: Receiver<FileResult>) -> Result<Self, eyre::Error> {
+        match self.output.take() {
+            Some(output) => {
+                let mut out = io::BufWriter::new(output);
+                self.drain(results, &mut out).await?;
+                out.into_inner().map_err(|e| e.into_error())?.finish()?;
+            }
+            None => {
+                self.drain(results, &mut io::BufWriter::new(io::stdout()))
+                    .await?
+            }
+        }
+        Ok(self)
+    }
+
+    async fn drain(
+        &mut self,
+        results: Receiver<FileResult>,
+        out: &mut impl Write,
+    ) -> Result<(), eyre::Error> {
+        if let Some(line) = &self.watermark {
+            write!(out, "{}{}", line, self.end())?;
+        }
+        while let Ok(result) = results.recv().await {
+            let ready = match &mut self.ordered {
+                Some(ordered) => ordered.push(result),
+                None => vec![result],
+            };
+            // keep output flowing when results trickle in, and out from
+            // under the bars
+            let flush = results.is_empty() || self.progress.is_some();
+            match self.progress.clone() {
+                Some(progress) => progress.suspend(|| self.emit(ready, flush, out))?,
+                None => self.emit(ready, flush, out)?,
+            }
+        }
+        if let Some(progress) = &self.progress {
+            progress.finish();
+        }
+        if let Some(Unique::All { groups, .. }) = &self.unique {
+            for line in groups.iter().flatten() {
+                out.write_all(line.as_bytes())?;
+            }
+        }
+        self.write_end(out)?;
+        out.flush()?;
+        if let Some(shards) = &mut self.shards {
+            shards.finish()?;
+        }
+        if let Some(TimingsFile(file)) = &mut self.timings {
+            file.flush()?;
+        }
+        if let Some(sidecar) = &mut self.leaves {
+            sidecar.flush()?;
+        }
+        if let Some(map) = &mut self.bad_ranges {
+            map.flush()?;
+        }
+        Ok(())
+    }
+
+    fn emit(
+        &mut self,
+        ready: Vec<FileResult>,
+        flush: bool,
+        out: &mut impl Write,
+    ) -> Result<(), eyre::Error> {
+        for result in ready {
+            self.handle(result, out)?;
+        }
+        if flush {
+            out.flush()?;
+        }
+        Ok(())
+    }
+
+    fn handle(&mut self, mut result: FileResult, out: &mut impl Write) -> Result<(), eyre::Error> {
+        let path = result.path.clone();
+        let timings = result.outcome.as_ref().ok().map(|hashed| hashed.timings);
+        let members = match &mut result.outcome {
+            Ok(hashed) => std::mem::take(&mut hashed.members),
+            Err(_) => Vec::new(),
+        };
+        let index = result.index;
+        let start = std::time::Instant::now();
+        self.place(result, out)?;
+        // right after the archive, each as a file of its own
+        for member in members {
+            let member = FileResult {
+                index,
+                path: crate::archive::member_path(&path, &member.name),
+                outcome: Ok(member.into_hashed()),
+                member_of: Some(path.clone()),
+            };
+            self.place(member, out)?;
+        }
+        if let (Some(file), Some(mut timings)) = (&mut self.timings, timings) {
+            timings.output = start.elapsed();
+            file.write(&path, &timings)?;
+        }
+        if let Some(shards) = &mut self.shards {
+            shards.done(&path)?;
+        }
+        Ok(())
+    }
+
+    /// Writes `result` out, or with `--sort`, formats it to be written in
+    /// its place once every result is in
+    fn place(&mut self, result: FileResult, out: &mut impl Write) -> Result<(), eyre::Error> {
+        let by = match &self.sort {
+            Some(sorter) => sorter.by,
+            None => return self.write(result, out),
+        };
+        let digest = result.outcome.as_ref().ok().map(|hashed| &hashed.hash[..]);
+        let key = by.key(&result.path, digest);
+        let mut formatted = Vec::new();
+        self.write(result, &mut formatted)?;
+        if let Some(sorter) = self.sort.as_mut().filter(|_| !formatted.is_empty()) {
+            sorter.push(key, formatted)?;
+        }
+        Ok(())
+    }
+
+    fn write(&mut self, result: FileResult, out: &mut impl Write) -> Result<(), eyre::Error> {
+        let hashed = match result.outcome {
+            Ok(hashed) => hashed,
+            Err(e) if e.is::<crate::filter::TooLarge>() => {
+                self.metrics.skipped += 1;
+                tracing::warn!(path = %result.path.display(), reason = %e, "skipped");
+                let line = format!("Skipped {}: {}", self.quote.path(&result.path), e);
+                return self.write_failure(result.index, &result.path, &e, line, out);
+            }
+            Err(e) => {
+                self.metrics.errors += 1;
+                if let Some(heatmap) = &mut self.heatmap {
+                    heatmap.record(&result.path, crate::heatmap::Outcome::ReadError);
+                }
+                tracing::error!(path = %result.path.display(), error = %e, "hashing failed");
+                let line = format!("While hashing {}: {}", self.quote.path(&result.path), e);
+                return self.write_failure(result.index, &result.path, &e, line, out);
+            }
+        };
+        self.metrics.files += 1;
+        self.metrics.bytes += hashed.size;
+        if let Some(stats) = &mut self.stats {
+            stats.record(
+                &result.path,
+                hashed.size,
+                hashed.timings.read_calls,
+                hashed.elapsed,
+            );
+        }
+        if let Some((agreeing, reads)) = hashed.disagreement {
+            self.metrics.disagreed += 1;
+            tracing::error!(path = %result.path.display(), agreeing, reads, "reads disagreed");
+        }
+        if let Some(heatmap) = &mut self.heatmap {
+            let outcome = match hashed.disagreement {
+                Some(_) => crate::heatmap::Outcome::Mismatch,
+                None => crate::heatmap::Outcome::Ok,
+            };
+            heatmap.record(&result.path, outcome);
+        }
+        if !self.filter.matches(&result.path, &hashed) {
+            return Ok(());
+        }
+        let path = &result.path;
+        let verdict = self
+            .dedupe
+            .as_mut()
+            .map(|known| known.check(&hashed.hash, path));
+        if self.only_new && matches!(verdict, Some(crate::dupes::Verdict::Duplicate(_))) {
+            return Ok(());
+        }
+        if let Some(bloom) = &mut self.bloom_out {
+            bloom.insert(&hashed.hash);
+        }
+        if let Some(sidecar) = &mut self.leaves {
+            sidecar.write(
+                &result.path,
+                self.algorithm,
+                self.keyed,
+                &hashed.hash,
+                &hashed.leaves,
+            )?;
+        }
+        if let Some(map) = self
+            .bad_ranges
+            .as_mut()
+            .filter(|_| !hashed.unreadable.is_empty())
+        {
+            map.write(&result.path, hashed.size, &hashed.unreadable)?;
+        }
+
+        // with several --algo, a result for each, all from the same read
+        let digests: Vec<_> = std::iter::once((self.algorithm, &hashed.hash))
+            .chain(
+                hashed
+                    .also
+                    .iter()
+                    .map(|(algorithm, digest)| (*algorithm, digest)),
+            )
+            .collect();
+        match self.format {
+            Format::Lines => {}
+            Format::Groups | Format::GroupsJson => {
+                self.groups
+                    .entry(hashed.hash)
+                    .or_default()
+                    .push(result.path);
+                return Ok(());
+            }
+            Format::Gnu | Format::Bsd => {
+                let format = if self.format == Format::Gnu {
+                    crate::sums::Format::Gnu
+                } else {
+                    crate::sums::Format::Bsd
+                };
+                let path = result.path.display().to_string();
+                let entries: Vec<_> = digests
+                    .iter()
+                    .map(|(algorithm, digest)| crate::sums::Entry {
+                        path: path.clone(),
+                        algorithm: algorithm.name().to_string(),
+                        digest: crate::hex(digest),
+                        size: Some(hashed.size),
+                    })
+                    .collect();
+                if self.null {
+                    return crate::sums::write_null(format, &entries, out);
+                }
+                return crate::sums::write(format, &entries, out);
+            }
+            Format::Json | Format::JsonLines => {
+                let previous = match (&hashed.audit, &self.previous_audit) {
+                    (Some(_), Some(previous)) => previous.get(&result.path).copied(),
+                    _ => None,
+                };
+                let owner = |audit: &crate::audit::Audit| format!("{}:{}", audit.uid, audit.gid);
+                let mode = |audit: &crate::audit::Audit| format!("{:04o}", audit.mode);
+                for (algorithm, digest) in digests {
+                    let record = Record {
+                        schema_version: SCHEMA_VERSION,
+                        id: self.id(result.index),
+                        path: result.path.display().to_string(),
+                        alias_of: self.alias_of(result.index),
+                        algorithm: Some(algorithm.name()),
+                        keyed: self.keyed,
+                        tree_chunk_size: self.tree,
+                        digest: Some(crate::hex(digest)),
+                        size: Some(hashed.size),
+                        truncated_from: hashed.truncated,
+                        unstable: hashed.unstable,
+                        reads: hashed.disagreement,
+                        unreadable: hashed.unreadable.iter().map(|r| (r.start, r.end)).collect(),
+                        elapsed_secs: Some(hashed.elapsed.as_secs_f64()),
+                        dedupe: verdict.as_ref().map(|verdict| match verdict {
+                            crate::dupes::Verdict::Duplicate(_) => "duplicate",
+                            crate::dupes::Verdict::New => "new",
+                        }),
+                        duplicate_of: match &verdict {
+                            Some(crate::dupes::Verdict::Duplicate(of)) => Some(of.clone()),
+                            _ => None,
+                        },
+                        member_of: result.member_of.as_ref().map(|p| p.display().to_string()),
+                        bloom: self.bloom_check.as_ref().map(|bloom| {
+                            if bloom.contains(&hashed.hash) {
+                                "seen"
+                            } else {
+                                "new"
+                            }
+                        }),
+                        file_type: hashed.report.file_type,
+                        entropy: hashed.report.entropy,
+                        zero_runs: hashed.report.zero_runs.map(|runs| ZeroRuns {
+                            count: runs.count,
+                            longest_offset: runs.longest.map(|(offset, _)| offset),
+                            longest_len: runs.longest.map(|(_, len)| len),
+                        }),
+                        owner: hashed.audit.as_ref().map(owner),
+                        mode: hashed.audit.as_ref().map(mode),
+                        previous_owner: previous
+                            .as_ref()
+                            .filter(|p| {
+                                hashed
+                                    .audit
+                                    .is_some_and(|a| (p.uid, p.gid) != (a.uid, a.gid))
+                            })
+                            .map(owner),
+                        previous_mode: previous
+                            .as_ref()
+                            .filter(|p| hashed.audit.is_some_and(|a| p.mode != a.mode))
+                            .map(mode),
+                        error: None,
+                    };
+                    self.write_record(&record, out)?;
+                }
+                return Ok(());
+            }
+        }
+
+        let mut line = String::new();
+        for (algorithm, digest) in digests {
+            let mut one = self.format(&result.path, algorithm, digest, &hashed, verdict.as_ref());
+            if let Some(target) = self.aliases.get(result.index).and_then(Option::as_ref) {
+                // after the first line, which is the only one with words
+                let end = one.find(self.end()).unwrap_or(one.len());
+                one.insert_str(end, &format!(" alias-of={}", self.quote.path(target)));
+            }
+            line += &one;
+        }
+        if let Some(shards) = &mut self.shards {
+            return Ok(shards.write(&result.path, &line)?);
+        }
+        match &mut self.unique {
+            None => out.write_all(line.as_bytes())?,
+            Some(Unique::First(seen)) => {
+                if seen.insert(hashed.hash) {
+                    out.write_all(line.as_bytes())?;
+                }
+            }
+            Some(Unique::All { index, groups }) => {
+                let i = *index.entry(hashed.hash).or_insert_with(|| {
+                    groups.push(Vec::new());
+                    groups.len() - 1
+                });
+                groups[i].push(line);
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes a file that couldn't be hashed, as `line` to stderr unless
+    /// the format has a record for it
+    fn write_failure(
+        &mut self,
+        index: usize,
+        path: &std::path::Path,
+        error: &eyre::Error,
+        line: String,
+        out: &mut impl Write,
+    ) -> Result<(), eyre::Error> {
+        match self.format {
+            Format::Json | Format::JsonLines => {
+                let record = Record {
+                    id: self.id(index),
+                    alias_of: self.alias_of(index),
+                    ..Record::failed(path, error)
+                };
+                self.write_record(&record, out)?
+            }
+            // keep what's written only results, which a failure in a
+            // pipeline would otherwise end up among
+            Format::Lines | Format::Gnu | Format::Bsd | Format::Groups | Format::GroupsJson => {
+                eprintln!("{}", line)
+            }
+        }
+        Ok(())
+    }
+
+    /// The caller's ID for the input at `index`, if it gave one
+    fn id(&self, index: usize) -> Option<String> {
+        self.ids.get(index)
+    }
+
+    /// The input the one at `index` is an alias of, if it's one
+    fn alias_of(&self, index: usize) -> Option<String> {
+        let target = self.aliases.get(index)?.as_ref()?;
+        Some(target.display().to_string())
+    }
+
+    fn write_record(&mut self, record: &Record, out: &mut impl Write) -> Result<(), eyre::Error> {
+        if self.format == Format::Json {
+            // sorted records are only put in the array as they're written out
+            if self.sort.is_none() {
+                out.write_all(if self.records == 0 {
+                    b"[\n  "
+                } else {
+                    b",\n  "
+                })?;
+            }
+            serde_json::to_writer(&mut *out, record)?;
+        } else {
+            serde_json::to_writer(&mut *out, record)?;
+            writeln!(out)?;
+        }
+        self.records += 1;
+        Ok(())
+    }
+
+    /// Writes what can only be written once every file is done
+    fn write_end(&mut self, out: &mut impl Write) -> Result<(), eyre::Error> {
+        if let Some(sorter) = self.sort.take() {
+            let json = self.format == Format::Json;
+            let mut first = true;
+            sorter.finish(|formatted| {
+                if json {
+                    out.write_all(if first { b"[\n  " } else { b",\n  " })?;
+                }
+                first = false;
+                out.write_all(formatted)
+            })?;
+        }
+        // workers finish in any order, keep the output stable between runs
+        for paths in self.groups.values_mut() {
+            paths.sort();
+        }
+        match self.format {
+            Format::Lines | Format::Gnu | Format::Bsd | Format::JsonLines => {}
+            Format::Json if self.records == 0 => writeln!(out, "[]")?,
+            Format::Json => writeln!(out, "\n]")?,
+            Format::Groups => {
+                for (digest, paths) in &self.groups {
+                    let plural = if paths.len() == 1 { "" } else { "s" };
+                    writeln!(
+                        out,
+                        "{} ({} file{})",
+                        crate::hex(digest),
+                        paths.len(),
+                        plural
+                    )?;
+                    for path in paths {
+                        writeln!(out, "  {}", self.quote.path(path))?;
+                    }
+                    writeln!(out)?;
+                }
+            }
+            Format::GroupsJson => {
+                let groups: Vec<_> = self
+                    .groups
+                    .iter()
+                    .map(|(digest, paths)| Group {
+                        schema_version: SCHEMA_VERSION,
+                        algorithm: self.algorithm.name(),
+                        digest: crate::hex(digest),
+                        paths: paths.iter().map(|p| p.display().to_string()).collect(),
+                    })
+                    .collect();
+                serde_json::to_writer_pretty(&mut *out, &groups)?;
+                writeln!(out)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// The line for `path`'s `digest` by `algorithm`, with what else was
+    /// learned about it
+    fn format(
+        &self,
+        path: &std::path::Path,
+        algorithm: crate::algo::Algorithm,
+        digest: &[u8],
+        hashed: &Hashed,
+        verdict: Option<&crate::dupes::Verdict>,
+    ) -> String {
+        let mut line = format!("{} {}", self.quote.path(path), crate::hex(digest));
+        // the default is left out, so SHA3-256 lines read as they always have
+        if algorithm != crate::algo::Algorithm::Sha3_256 {
+            write!(line, " algo={}", algorithm).unwrap();
+        }
+        if self.keyed {
+            line += " keyed";
+        }
+        if let Some(chunk_size) = self.tree {
+            write!(line, " tree={}", chunk_size).unwrap();
+        }
+        if let Some(size) = hashed.truncated {
+            write!(line, " truncated={} size={}", hashed.size, size).unwrap();
+        }
+        if hashed.unstable {
+            line += " unstable";
+        }
+        if !hashed.unreadable.is_empty() {
+            let bytes: u64 = hashed.unreadable.iter().map(|r| r.end - r.start).sum();
+            write!(line, " unreadable={}", bytes).unwrap();
+        }
+        if let Some((agreeing, reads)) = hashed.disagreement {
+            write!(line, " reads={}/{}", agreeing, reads).unwrap();
+        }
+        if let Some(bloom) = &self.bloom_check {
+            let status = if bloom.contains(&hashed.hash) {
+                "seen"
+            } else {
+                "new"
+            };
+            write!(line, " {}", status).unwrap();
+        }
+        match verdict {
+            Some(crate::dupes::Verdict::Duplicate(of)) => {
+                let of = self.quote.path(std::path::Path::new(of));
+                write!(line, " duplicate-of={}", of).unwrap();
+            }
+            Some(crate::dupes::Verdict::New) => line += " new",
+            None => {}
+        }
+        if let Some(file_type) = hashed.report.file_type {
+            write!(line, " type={}", file_type).unwrap();
+        }
+        if let Some(entropy) = hashed.report.entropy {
+            write!(line, " entropy={:.3}", entropy).unwrap();
+            if entropy >= crate::inspect::HIGH_ENTROPY {
+                line += " high-entropy";
+            }
+        }
+        if let Some(zero_runs) = hashed.report.zero_runs {
+            write!(line, " zero-runs={}", zero_runs.count).unwrap();
+            if let Some((offset, len)) = zero_runs.longest {
+                write!(line, " longest-zero-run={}@{}", len, offset).unwrap();
+            }
+        }
+        if let Some(audit) = &hashed.audit {
+            let previous = self.previous_audit.as_ref().and_then(|p| p.get(path));
+            line += &audit.words(previous);
+        }
+        line.push(self.end());
+        for (offset, digest) in &hashed.checkpoints {
+            write!(line, "  {} {}{}", offset, crate::hex(digest), self.end()).unwrap();
+        }
+        line
+    }
+
+    /// What ends each line
+    fn end(&self) -> char {
+        if self.null {
+            '\0'
+        } else {
+            '\n'
+        }
+    }
+}
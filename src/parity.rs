@@ -0,0 +1,260 @@
+//! Reed-Solomon recovery data, so a corrupted file can be repaired rather than
+//! just reported.
+//!
+//! Each file gets a `.svpar` volume next to it: a JSON manifest with the
+//! digest of every block, followed by the parity blocks themselves. Files are
+//! cut into stripes of up to [`STRIPE_BLOCKS`] blocks, and each stripe can
+//! lose as many blocks as it has parity blocks.
+
+use async_std::{
+    fs::{File, OpenOptions},
+    io::{prelude::*, SeekFrom},
+};
+use color_eyre::eyre::{self, eyre};
+use reed_solomon_erasure::galois_8::ReedSolomon;
+use serde::{Deserialize, Serialize};
+use sha3::Digest;
+use std::{
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+const MAGIC: &[u8; 8] = b"SVPAR\x00\x00\x01";
+
+/// Most data blocks in one stripe, leaving room for as many parity blocks in
+/// GF(2^8)'s 256 shards
+pub const STRIPE_BLOCKS: usize = 128;
+
+/// How much recovery data to produce, as a fraction of the data
+#[derive(Debug, Clone, Copy)]
+pub struct Redundancy(pub f64);
+
+impl FromStr for Redundancy {
+    type Err = eyre::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let fraction = match s.strip_suffix('%') {
+            Some(percent) => percent.trim().parse::<f64>().map(|p| p / 100.0),
+            None => s.parse::<f64>(),
+        }
+        .map_err(|_| eyre!("expected a redundancy like `5%` or `0.05`, got {:?}", s))?;
+        if !(fraction > 0.0 && fraction <= 1.0) {
+            return Err(eyre!("redundancy must be above 0% and at most 100%"));
+        }
+        Ok(Self(fraction))
+    }
+}
+
+/// Where the recovery volume for `path` goes
+pub fn volume_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_owned();
+    name.push(".svpar");
+    path.with_file_name(name)
+}
+
+#[derive(Serialize, Deserialize)]
+struct Manifest {
+    /// File name, relative to the volume's directory
+    path: String,
+    size: u64,
+    block_size: usize,
+    algorithm: String,
+    digest: String,
+    stripes: Vec<Stripe>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Stripe {
+    data_digests: Vec<String>,
+    parity_digests: Vec<String>,
+}
+
+impl Manifest {
+    /// Where the parity blocks of `stripe` start in the volume
+    fn parity_offset(&self, header_len: u64, stripe: usize) -> u64 {
+        let before: usize = self.stripes[..stripe]
+            .iter()
+            .map(|s| s.parity_digests.len())
+            .sum();
+        header_len + (before * self.block_size) as u64
+    }
+}
+
+fn digest(block: &[u8]) -> String {
+    crate::hex(&sha3::Sha3_256::digest(block))
+}
+
+/// Reads up to `buf.len()` bytes, stopping early only at the end of the file
+async fn read_block(file: &mut File, buf: &mut [u8]) -> Result<usize, eyre::Error> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match file.read(&mut buf[filled..]).await? {
+            0 => break,
+            n => filled += n,
+        }
+    }
+    Ok(filled)
+}
+
+/// Writes `path`'s recovery volume, returning where it went
+pub async fn create(
+    path: &Path,
+    redundancy: Redundancy,
+    block_size: usize,
+) -> Result<PathBuf, eyre::Error> {
+    let mut file = File::open(path).await?;
+    let size = file.metadata().await?.len();
+    let blocks = size.div_ceil(block_size as u64) as usize;
+
+    let mut whole = sha3::Sha3_256::new();
+    let mut stripes = Vec::new();
+    let mut parity = Vec::new();
+    let mut remaining = blocks;
+    while remaining > 0 {
+        let data = remaining.min(STRIPE_BLOCKS);
+        remaining -= data;
+        let parity_count = ((data as f64 * redundancy.0).ceil() as usize).max(1);
+
+        let mut shards = Vec::with_capacity(data + parity_count);
+        for _ in 0..data {
+            let mut block = vec![0u8; block_size];
+            let n = read_block(&mut file, &mut block).await?;
+            whole.update(&block[..n]);
+            shards.push(block);
+        }
+        shards.resize(data + parity_count, vec![0u8; block_size]);
+        ReedSolomon::new(data, parity_count)?.encode(&mut shards)?;
+
+        stripes.push(Stripe {
+            data_digests: shards[..data].iter().map(|b| digest(b)).collect(),
+            parity_digests: shards[data..].iter().map(|b| digest(b)).collect(),
+        });
+        parity.extend(shards.drain(data..));
+    }
+
+    let manifest = Manifest {
+        path: path
+            .file_name()
+            .ok_or_else(|| eyre!("{} has no file name", path.display()))?
+            .to_string_lossy()
+            .into_owned(),
+        size,
+        block_size,
+        algorithm: "SHA3-256".to_string(),
+        digest: crate::hex(&whole.finalize()),
+        stripes,
+    };
+
+    let volume = volume_path(path);
+    let mut out = async_std::io::BufWriter::new(File::create(&volume).await?);
+    let json = serde_json::to_vec(&manifest)?;
+    out.write_all(MAGIC).await?;
+    out.write_all(&(json.len() as u64).to_be_bytes()).await?;
+    out.write_all(&json).await?;
+    for block in parity {
+        out.write_all(&block).await?;
+    }
+    out.flush().await?;
+    Ok(volume)
+}
+
+/// What `repair` did to a file
+#[derive(Debug)]
+pub enum Repair {
+    /// Every block matched its digest
+    Intact,
+    /// This many blocks were rebuilt from parity, and the file was cut back
+    /// to its original size if needed
+    Repaired(usize),
+}
+
+/// Checks the file described by `volume` block by block, rewriting any
+/// corrupt or missing blocks from the parity data
+pub async fn repair(volume: &Path) -> Result<Repair, eyre::Error> {
+    let mut vol = File::open(volume).await?;
+    let mut header = [0u8; 16];
+    vol.read_exact(&mut header).await?;
+    if &header[..8] != MAGIC {
+        return Err(eyre!("{} is not a recovery volume", volume.display()));
+    }
+    let mut len = [0u8; 8];
+    len.copy_from_slice(&header[8..]);
+    let mut json = vec![0u8; u64::from_be_bytes(len) as usize];
+    vol.read_exact(&mut json).await?;
+    let manifest: Manifest = serde_json::from_slice(&json)?;
+    let header_len = (header.len() + json.len()) as u64;
+
+    let path = volume.with_file_name(&manifest.path);
+    let mut file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .open(&path)
+        .await?;
+    let block_size = manifest.block_size;
+    let resized = file.metadata().await?.len() != manifest.size;
+
+    let mut repaired = 0;
+    let mut offset = 0u64;
+    for (i, stripe) in manifest.stripes.iter().enumerate() {
+        let data = stripe.data_digests.len();
+        let mut shards: Vec<Option<Vec<u8>>> = Vec::with_capacity(data);
+        file.seek(SeekFrom::Start(offset)).await?;
+        for expected in &stripe.data_digests {
+            let mut block = vec![0u8; block_size];
+            read_block(&mut file, &mut block).await?;
+            shards.push(if digest(&block) == *expected {
+                Some(block)
+            } else {
+                None
+            });
+        }
+        let damaged: Vec<usize> = (0..data).filter(|&j| shards[j].is_none()).collect();
+
+        if !damaged.is_empty() {
+            vol.seek(SeekFrom::Start(manifest.parity_offset(header_len, i)))
+                .await?;
+            for expected in &stripe.parity_digests {
+                let mut block = vec![0u8; block_size];
+                let n = read_block(&mut vol, &mut block).await?;
+                shards.push(if n == block_size && digest(&block) == *expected {
+                    Some(block)
+                } else {
+                    None
+                });
+            }
+            let lost = shards.iter().filter(|s| s.is_none()).count();
+            if lost > stripe.parity_digests.len() {
+                return Err(eyre!(
+                    "stripe {} of {} lost {} blocks, only {} can be recovered",
+                    i,
+                    path.display(),
+                    lost,
+                    stripe.parity_digests.len()
+                ));
+            }
+            ReedSolomon::new(data, stripe.parity_digests.len())?.reconstruct_data(&mut shards)?;
+
+            for j in damaged {
+                let start = offset + (j * block_size) as u64;
+                let len = (manifest.size - start).min(block_size as u64) as usize;
+                let block = shards[j].as_ref().expect("reconstructed");
+                file.seek(SeekFrom::Start(start)).await?;
+                file.write_all(&block[..len]).await?;
+                repaired += 1;
+            }
+        }
+        offset += (data * block_size) as u64;
+    }
+
+    // blocks only cover the expected size, drop anything past it
+    file.set_len(manifest.size).await?;
+    file.flush().await?;
+    file.sync_all().await?;
+
+    if repaired == 0 && !resized {
+        Ok(Repair::Intact)
+    } else {
+        Ok(Repair::Repaired(repaired))
+    }
+}
@@ -0,0 +1,106 @@
+//! Threads that feed hashers, apart from the async executor's, so a slow
+//! hash function in software can't hold up I/O for every other file.
+//!
+//! Every thread takes jobs from the same queue, so an idle one picks up the
+//! next chunk whichever file it belongs to. Small files are read into
+//! buffers the pool hands out and takes back, and hashed in a single job.
+
+use crate::affinity;
+use async_std::channel::{self, Receiver, Sender};
+use std::sync::Mutex;
+
+type Job = Box<dyn FnOnce() + Send>;
+
+/// How many chunks may wait for a thread before readers wait too
+const QUEUE_CAPACITY: usize = 64;
+
+/// Files smaller than this are hashed in one go unless told otherwise
+pub const DEFAULT_SMALL_FILE_SIZE: u64 = 64 << 10;
+
+/// How many buffers for small files are kept around between files
+const SPARE_BUFFERS: usize = 2 * QUEUE_CAPACITY;
+
+pub struct Pool {
+    jobs: Sender<Job>,
+    /// Buffers small files were read into, for the next ones
+    buffers: Mutex<Vec<Vec<u8>>>,
+}
+
+impl Pool {
+    /// Starts `threads` threads, which stop once the pool is dropped. With
+    /// `pin`, each is kept on one of the CPUs the process may use, in turn.
+    pub fn new(threads: usize, pin: bool) -> Self {
+        let cpus = if pin {
+            affinity::allowed_cpus()
+                .map_err(|e| tracing::warn!(error = %e, "not pinning hashing threads"))
+                .ok()
+                .filter(|cpus| !cpus.0.is_empty())
+        } else {
+            None
+        };
+        let (jobs, queue) = channel::bounded::<Job>(QUEUE_CAPACITY);
+        for i in 0..threads.max(1) {
+            let queue = queue.clone();
+            let cpu = cpus.as_ref().map(|cpus| cpus.0[i % cpus.0.len()]);
+            std::thread::Builder::new()
+                .name(format!("hash-{}", i))
+                .spawn(move || {
+                    if let Some(cpu) = cpu {
+                        if let Err(e) = affinity::pin_to_cpus(&affinity::CpuList(vec![cpu])) {
+                            tracing::warn!(cpu, error = %e, "can't pin hashing thread");
+                        }
+                    }
+                    while let Ok(job) = queue.recv_blocking() {
+                        job();
+                    }
+                })
+                .expect("can't start hashing threads");
+        }
+        Self {
+            jobs,
+            buffers: Default::default(),
+        }
+    }
+
+    /// Queues `f`, waiting for room in the queue, and returns a handle to
+    /// its result
+    pub async fn spawn<T: Send + 'static>(
+        &self,
+        f: impl FnOnce() -> T + Send + 'static,
+    ) -> Task<T> {
+        let (tx, rx) = channel::bounded(1);
+        let job: Job = Box::new(move || {
+            tx.try_send(f()).ok();
+        });
+        // the threads only stop once we're dropped
+        self.jobs.send(job).await.ok();
+        Task(rx)
+    }
+
+    /// How many jobs wait for a thread
+    pub fn queued(&self) -> usize {
+        self.jobs.len()
+    }
+
+    /// A buffer for a small file, from an earlier one if there's any left
+    pub fn buffer(&self) -> Vec<u8> {
+        self.buffers.lock().unwrap().pop().unwrap_or_default()
+    }
+
+    /// Keeps `buffer` for [`Pool::buffer`]
+    pub fn recycle(&self, buffer: Vec<u8>) {
+        let mut buffers = self.buffers.lock().unwrap();
+        if buffers.len() < SPARE_BUFFERS {
+            buffers.push(buffer);
+        }
+    }
+}
+
+/// A job on the pool
+pub struct Task<T>(Receiver<T>);
+
+impl<T> Task<T> {
+    pub async fn join(self) -> T {
+        self.0.recv().await.expect("hashing job panicked")
+    }
+}
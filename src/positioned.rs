@@ -0,0 +1,91 @@
+//! Positioned reads (`pread` / `seek_read`) over a shared file handle.
+//! They block, so they're only made from threads of their own, like
+//! `--threads`' segments.
+
+use std::{fs::File, io, ops::Range, path::Path, sync::Arc};
+
+/// Reads a range of a file through positioned reads, so any number of
+/// readers can share one handle without opening the file again or fighting
+/// over a cursor.
+#[derive(Clone)]
+pub struct PositionedReader {
+    file: Arc<File>,
+    offset: u64,
+    end: u64,
+}
+
+impl PositionedReader {
+    /// Opens `path` for reading, covering the whole file
+    pub fn open(path: &Path) -> io::Result<Self> {
+        Self::from_file(File::open(path)?)
+    }
+
+    /// Covers the whole of an already-open file
+    pub fn from_file(file: File) -> io::Result<Self> {
+        let end = file.metadata()?.len();
+        Ok(Self {
+            file: Arc::new(file),
+            offset: 0,
+            end,
+        })
+    }
+
+    /// Returns a reader over `range` of the same file handle
+    pub fn range(&self, range: Range<u64>) -> Self {
+        Self {
+            file: self.file.clone(),
+            offset: range.start,
+            end: range.end,
+        }
+    }
+
+    /// Splits the remaining range into consecutive readers of at most
+    /// `chunk_size` bytes each, for handing out to a pool of workers.
+    pub fn chunks(&self, chunk_size: u64) -> impl Iterator<Item = Self> + '_ {
+        assert!(chunk_size > 0, "chunk size must be non-zero");
+        let end = self.end;
+        (self.offset..end)
+            .step_by(chunk_size as usize)
+            .map(move |start| self.range(start..(start + chunk_size).min(end)))
+    }
+
+    /// Reads into all of `buf`, or as much of it as the range covers,
+    /// blocking until it's done. Returns how much was read, less only if
+    /// the file ended first.
+    pub fn fill(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = (buf.len() as u64).min(self.remaining()) as usize;
+        let mut filled = 0;
+        while filled < len {
+            match read_at(&self.file, &mut buf[filled..len], self.offset) {
+                Ok(0) => break,
+                Ok(n) => {
+                    filled += n;
+                    self.offset += n as u64;
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(filled)
+    }
+
+    /// Offset of the next byte this reader will return
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// Number of bytes left in this reader's range
+    pub fn remaining(&self) -> u64 {
+        self.end.saturating_sub(self.offset)
+    }
+}
+
+#[cfg(unix)]
+fn read_at(file: &File, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+    std::os::unix::fs::FileExt::read_at(file, buf, offset)
+}
+
+#[cfg(windows)]
+fn read_at(file: &File, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+    std::os::windows::fs::FileExt::seek_read(file, buf, offset)
+}
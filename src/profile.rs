@@ -0,0 +1,83 @@
+//! Named presets over the tunables, for `--profile`: one for sharing a
+//! busy host, one for having it to ourselves.
+//!
+//! A preset only fills in what the command line left unset, so
+//! `--profile low-impact --jobs 4` still runs four jobs.
+
+use crate::{tune::BufferSize, units::ByteSize};
+use color_eyre::eyre::{self, eyre};
+use std::{str::FromStr, time::Duration};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Profile {
+    /// Stay out of the way of everything else running
+    LowImpact,
+    /// Use every CPU and all the bandwidth there is
+    MaxThroughput,
+}
+
+/// What a profile sets; `None` and `false` leave a tunable at its default
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Preset {
+    pub jobs: Option<usize>,
+    pub per_device_jobs: Option<usize>,
+    pub hash_threads: Option<usize>,
+    pub pin_hash_threads: bool,
+    pub buffer_size: Option<BufferSize>,
+    pub small_file_size: Option<ByteSize>,
+    /// Waiting before each read, which caps how fast each job reads
+    pub throttle: Option<Duration>,
+    pub idle_priority: bool,
+    pub evict_after_read: bool,
+}
+
+impl Profile {
+    pub const ALL: [Self; 2] = [Self::LowImpact, Self::MaxThroughput];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::LowImpact => "low-impact",
+            Self::MaxThroughput => "max-throughput",
+        }
+    }
+
+    /// The settings this profile stands for, on a host with `cpus` CPUs
+    pub fn preset(self, cpus: usize) -> Preset {
+        let linux = cfg!(target_os = "linux");
+        match self {
+            // 64K reads 5ms apart keep a job under ~12MiB/s
+            Self::LowImpact => Preset {
+                jobs: Some(cpus.clamp(1, 2)),
+                per_device_jobs: Some(1),
+                hash_threads: Some(1),
+                buffer_size: Some(BufferSize::Fixed(64 << 10)),
+                throttle: Some(Duration::from_millis(5)),
+                idle_priority: linux,
+                evict_after_read: linux,
+                ..Default::default()
+            },
+            // more jobs than CPUs, so there's always a read in flight while
+            // the hashers are busy
+            Self::MaxThroughput => Preset {
+                jobs: Some(cpus * 2),
+                hash_threads: Some(cpus),
+                pin_hash_threads: linux,
+                buffer_size: Some(BufferSize::Auto),
+                small_file_size: Some(ByteSize(256 << 10)),
+                ..Default::default()
+            },
+        }
+    }
+}
+
+impl FromStr for Profile {
+    type Err = eyre::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|p| p.name() == s)
+            .ok_or_else(|| eyre!("expected `low-impact` or `max-throughput`, got {:?}", s))
+    }
+}
@@ -0,0 +1,404 @@
+//! How far along a run is, drawn on stderr while it goes: a bar for each
+//! file in flight, and one for the whole run with its throughput.
+//!
+//! Bytes are counted as they come out of each file, by a reader wrapped
+//! around it. Anything else that prints while the bars are up goes through
+//! [`Progress::suspend`], so results never end up mixed into a bar. On
+//! anything but a terminal, a plain line is printed every few seconds.
+
+use futures::io::AsyncRead;
+use pin_project::pin_project;
+use std::{
+    collections::BTreeMap,
+    fmt::Write as _,
+    io::{self, Write as _},
+    path::{Path, PathBuf},
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+
+/// How often bars are redrawn on a terminal
+const REDRAW_EVERY: Duration = Duration::from_millis(100);
+
+/// How often a line is printed when stderr isn't a terminal
+const PLAIN_EVERY: Duration = Duration::from_secs(5);
+
+/// How many files get a bar of their own, the rest are counted
+const MAX_BARS: usize = 8;
+
+/// How much of the throughput shown comes from the last redraw, the rest
+/// being the previous estimate
+const SMOOTHING: f64 = 0.2;
+
+/// A size no file has, for files whose size isn't known
+const UNKNOWN: u64 = u64::MAX;
+
+/// Bytes read from a file so far, and the size it had when opened
+struct Counter {
+    read: AtomicU64,
+    size: AtomicU64,
+}
+
+struct InFlight {
+    path: PathBuf,
+    since: Instant,
+    counter: Arc<Counter>,
+}
+
+/// What the last redraw left on screen
+struct Screen {
+    /// Bar lines below the cursor's line, which have to be cleared first
+    lines: usize,
+    drawn: bool,
+    /// The last point throughput was measured at
+    sampled: (Instant, u64),
+    rate: f64,
+}
+
+/// Shared state for progress reporting, updated by workers as they go
+pub struct Progress {
+    /// Whether stderr is a terminal bars can be redrawn on
+    terminal: bool,
+    started: Instant,
+    files: usize,
+    /// The size of every file together, as they were before the run
+    bytes: u64,
+    done: AtomicUsize,
+    read: AtomicU64,
+    next: AtomicU64,
+    in_flight: Mutex<BTreeMap<u64, InFlight>>,
+    screen: Mutex<Screen>,
+    finished: AtomicBool,
+}
+
+impl Progress {
+    /// Starts reporting on a run over `files` files totalling `bytes` bytes,
+    /// until [`Progress::finish`]
+    pub fn start(files: usize, bytes: u64) -> Arc<Self> {
+        let started = Instant::now();
+        let progress = Arc::new(Self {
+            terminal: unsafe { libc::isatty(libc::STDERR_FILENO) } == 1,
+            started,
+            files,
+            bytes,
+            done: AtomicUsize::new(0),
+            read: AtomicU64::new(0),
+            next: AtomicU64::new(0),
+            in_flight: Default::default(),
+            screen: Mutex::new(Screen {
+                lines: 0,
+                drawn: false,
+                sampled: (started, 0),
+                rate: 0.0,
+            }),
+            finished: AtomicBool::new(false),
+        });
+        let every = if progress.terminal {
+            REDRAW_EVERY
+        } else {
+            PLAIN_EVERY
+        };
+        let drawing = progress.clone();
+        std::thread::Builder::new()
+            .name("progress".to_string())
+            .spawn(move || {
+                while !drawing.finished.load(Ordering::Relaxed) {
+                    std::thread::sleep(every);
+                    drawing.redraw();
+                }
+            })
+            .expect("can't start progress thread");
+        progress
+    }
+
+    /// Starts counting what is read from `path`, until the returned handle
+    /// is dropped. `size` is `None` when it isn't known up front.
+    pub fn track(self: &Arc<Self>, path: &Path, size: Option<u64>) -> Tracked {
+        let id = self.next.fetch_add(1, Ordering::Relaxed);
+        let counter = Arc::new(Counter {
+            read: AtomicU64::new(0),
+            size: AtomicU64::new(size.unwrap_or(UNKNOWN)),
+        });
+        self.in_flight.lock().unwrap().insert(
+            id,
+            InFlight {
+                path: path.to_owned(),
+                since: Instant::now(),
+                counter: counter.clone(),
+            },
+        );
+        Tracked {
+            progress: self.clone(),
+            id,
+            counter,
+        }
+    }
+
+    /// Counts a file as done, however it went
+    pub fn file_done(&self) {
+        self.done.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Takes the bars down while `f` prints, then puts them back up
+    pub fn suspend<T>(&self, f: impl FnOnce() -> T) -> T {
+        let mut screen = self.screen.lock().unwrap();
+        if self.terminal {
+            clear(&mut screen);
+        }
+        let res = f();
+        if self.terminal && !self.finished.load(Ordering::Relaxed) {
+            self.draw(&mut screen);
+        }
+        res
+    }
+
+    /// Takes the bars down for good, leaving a summary of the run
+    pub fn finish(&self) {
+        let mut screen = self.screen.lock().unwrap();
+        if self.finished.swap(true, Ordering::Relaxed) {
+            return;
+        }
+        if self.terminal {
+            clear(&mut screen);
+        }
+        let elapsed = self.started.elapsed();
+        let read = self.read.load(Ordering::Relaxed);
+        eprintln!(
+            "{} file(s), {} in {:.1}s ({}/s)",
+            self.done.load(Ordering::Relaxed),
+            bytes(read),
+            elapsed.as_secs_f64(),
+            bytes((read as f64 / elapsed.as_secs_f64().max(f64::EPSILON)) as u64)
+        );
+    }
+
+    fn redraw(&self) {
+        let mut screen = self.screen.lock().unwrap();
+        if self.finished.load(Ordering::Relaxed) {
+            return;
+        }
+        if self.terminal {
+            clear(&mut screen);
+            self.draw(&mut screen);
+        } else {
+            let line = self.summary(&mut screen);
+            eprintln!("{}", line);
+        }
+    }
+
+    fn draw(&self, screen: &mut Screen) {
+        let width = terminal_width();
+        let mut lines = Vec::new();
+        {
+            let in_flight = self.in_flight.lock().unwrap();
+            for file in in_flight.values().take(MAX_BARS) {
+                lines.push(file_line(file, width));
+            }
+            if in_flight.len() > MAX_BARS {
+                lines.push(format!("… and {} more", in_flight.len() - MAX_BARS));
+            }
+        }
+        lines.push(self.summary(screen));
+
+        let mut text = String::new();
+        for (i, line) in lines.iter().enumerate() {
+            if i > 0 {
+                text.push('\n');
+            }
+            text.extend(line.chars().take(width.saturating_sub(1)));
+        }
+        let mut stderr = io::stderr();
+        // a terminal that went away only misses its bars
+        let _ = stderr
+            .write_all(text.as_bytes())
+            .and_then(|_| stderr.flush());
+        screen.lines = lines.len() - 1;
+        screen.drawn = true;
+    }
+
+    /// The line for the whole run, updating the throughput estimate
+    fn summary(&self, screen: &mut Screen) -> String {
+        let now = Instant::now();
+        let read = self.read.load(Ordering::Relaxed);
+        let (then, then_read) = screen.sampled;
+        let secs = now.duration_since(then).as_secs_f64();
+        if secs > 0.0 {
+            let rate = read.saturating_sub(then_read) as f64 / secs;
+            screen.rate = if then == self.started {
+                rate
+            } else {
+                SMOOTHING * rate + (1.0 - SMOOTHING) * screen.rate
+            };
+            screen.sampled = (now, read);
+        }
+
+        let mut line = format!(
+            "{}/{} file(s) {} {}/{} {}/s",
+            self.done.load(Ordering::Relaxed),
+            self.files,
+            bar(read, self.bytes),
+            bytes(read),
+            bytes(self.bytes),
+            bytes(screen.rate as u64)
+        );
+        if screen.rate >= 1.0 && read < self.bytes {
+            let eta = (self.bytes - read) as f64 / screen.rate;
+            write!(line, " eta {}", duration(eta)).unwrap();
+        }
+        line
+    }
+}
+
+/// A file being read, forgotten once dropped
+pub struct Tracked {
+    progress: Arc<Progress>,
+    id: u64,
+    counter: Arc<Counter>,
+}
+
+impl Tracked {
+    fn add(&self, n: usize) {
+        self.counter.read.fetch_add(n as u64, Ordering::Relaxed);
+        self.progress.read.fetch_add(n as u64, Ordering::Relaxed);
+    }
+}
+
+impl Drop for Tracked {
+    fn drop(&mut self) {
+        self.progress.in_flight.lock().unwrap().remove(&self.id);
+    }
+}
+
+/// Wraps a reader, counting everything read from it
+#[pin_project]
+pub struct CountingReader<R> {
+    #[pin]
+    inner: R,
+    tracked: Tracked,
+}
+
+impl<R> CountingReader<R> {
+    pub fn new(inner: R, tracked: Tracked) -> Self {
+        Self { inner, tracked }
+    }
+}
+
+impl<R: AsyncRead> AsyncRead for CountingReader<R> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let proj = self.project();
+        let res = proj.inner.poll_read(cx, buf);
+        if let Poll::Ready(Ok(n)) = &res {
+            proj.tracked.add(*n);
+        }
+        res
+    }
+}
+
+/// Erases the bars, leaving the cursor where the first one started
+fn clear(screen: &mut Screen) {
+    if !screen.drawn {
+        return;
+    }
+    let mut text = String::from("\r\x1b[K");
+    for _ in 0..screen.lines {
+        text.push_str("\x1b[1A\x1b[K");
+    }
+    let mut stderr = io::stderr();
+    let _ = stderr
+        .write_all(text.as_bytes())
+        .and_then(|_| stderr.flush());
+    screen.lines = 0;
+    screen.drawn = false;
+}
+
+fn file_line(file: &InFlight, width: usize) -> String {
+    let read = file.counter.read.load(Ordering::Relaxed);
+    let size = file.counter.size.load(Ordering::Relaxed);
+    let rate = read as f64 / file.since.elapsed().as_secs_f64().max(f64::EPSILON);
+    let stats = if size == UNKNOWN {
+        format!("{} {}/s", bytes(read), bytes(rate as u64))
+    } else {
+        format!(
+            "{} {}/{} {}/s",
+            bar(read, size),
+            bytes(read),
+            bytes(size),
+            bytes(rate as u64)
+        )
+    };
+    // the path gets whatever room the numbers leave, keeping its end
+    let room = width.saturating_sub(stats.chars().count() + 2).max(10);
+    let path = file.path.display().to_string();
+    let count = path.chars().count();
+    let path = if count > room {
+        let tail: String = path.chars().skip(count - room + 1).collect();
+        format!("…{}", tail)
+    } else {
+        path
+    };
+    format!("{:<room$} {}", path, stats, room = room)
+}
+
+/// `[#####-----]  50%`
+fn bar(done: u64, total: u64) -> String {
+    const WIDTH: u64 = 20;
+    let (filled, percent) = match total {
+        0 => (WIDTH, 100),
+        total => {
+            let done = done.min(total) as u128;
+            (
+                (done * WIDTH as u128 / total as u128) as u64,
+                done * 100 / total as u128,
+            )
+        }
+    };
+    let mut bar = String::from("[");
+    for i in 0..WIDTH {
+        bar.push(if i < filled { '#' } else { '-' });
+    }
+    write!(bar, "] {:>3}%", percent).unwrap();
+    bar
+}
+
+/// `1.5 GiB`, in the binary units `--buffer-size` and friends take
+fn bytes(n: u64) -> String {
+    const UNITS: [&str; 5] = ["KiB", "MiB", "GiB", "TiB", "PiB"];
+    if n < 1024 {
+        return format!("{} B", n);
+    }
+    let mut value = n as f64 / 1024.0;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", value, UNITS[unit])
+}
+
+/// `1h02m`, `3m05s` or `42s`
+fn duration(secs: f64) -> String {
+    let secs = secs.round() as u64;
+    match secs {
+        0..=59 => format!("{}s", secs),
+        60..=3599 => format!("{}m{:02}s", secs / 60, secs % 60),
+        _ => format!("{}h{:02}m", secs / 3600, secs / 60 % 60),
+    }
+}
+
+fn terminal_width() -> usize {
+    let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+    let res = unsafe { libc::ioctl(libc::STDERR_FILENO, libc::TIOCGWINSZ, &mut size) };
+    match res {
+        0 if size.ws_col > 0 => size.ws_col as usize,
+        _ => 80,
+    }
+}
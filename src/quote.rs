@@ -0,0 +1,87 @@
+//! Quoting paths in output lines, so names with spaces, quotes or control
+//! characters can't be mistaken for the rest of the line and can be pasted
+//! back into a shell or a C program as is.
+
+use color_eyre::eyre::{self, eyre};
+use std::{fmt::Write as _, path::Path, str::FromStr};
+
+/// How paths are written
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Quote {
+    /// As they are
+    None,
+    /// For POSIX shells: bare when they only hold safe characters, in single
+    /// quotes otherwise, and in `$'...'` when they hold control characters
+    /// or bytes that aren't UTF-8
+    Shell,
+    /// As a C string literal, with everything outside printable ASCII
+    /// escaped, so the output is the same in every locale
+    C,
+}
+
+impl FromStr for Quote {
+    type Err = eyre::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Self::None),
+            "shell" => Ok(Self::Shell),
+            "c" => Ok(Self::C),
+            _ => Err(eyre!("expected `shell`, `c` or `none`, got {:?}", s)),
+        }
+    }
+}
+
+#[cfg(unix)]
+fn bytes(path: &Path) -> std::borrow::Cow<'_, [u8]> {
+    use std::os::unix::ffi::OsStrExt;
+    path.as_os_str().as_bytes().into()
+}
+
+#[cfg(not(unix))]
+fn bytes(path: &Path) -> std::borrow::Cow<'_, [u8]> {
+    path.to_string_lossy().into_owned().into_bytes().into()
+}
+
+impl Quote {
+    pub fn path(self, path: &Path) -> String {
+        match self {
+            Self::None => path.display().to_string(),
+            Self::Shell => shell(&bytes(path)),
+            Self::C => format!("\"{}\"", c_escape(&bytes(path), false)),
+        }
+    }
+}
+
+fn shell(path: &[u8]) -> String {
+    let safe = |b: &u8| b.is_ascii_alphanumeric() || b"_@%+=:,./-".contains(b);
+    if !path.is_empty() && path.iter().all(safe) {
+        return String::from_utf8_lossy(path).into_owned();
+    }
+    match std::str::from_utf8(path) {
+        Ok(text) if !text.chars().any(char::is_control) => {
+            format!("'{}'", text.replace('\'', "'\\''"))
+        }
+        _ => format!("$'{}'", c_escape(path, true)),
+    }
+}
+
+/// Escapes `path` for the inside of a C string literal, or of a shell's
+/// `$'...'` when `single_quoted`. Octal escapes always take three digits, so
+/// a digit after one can't be read as part of it.
+fn c_escape(path: &[u8], single_quoted: bool) -> String {
+    let mut out = String::new();
+    for &b in path {
+        match b {
+            b'\\' => out += "\\\\",
+            b'"' if !single_quoted => out += "\\\"",
+            b'\'' if single_quoted => out += "\\'",
+            b'\n' => out += "\\n",
+            b'\r' => out += "\\r",
+            b'\t' => out += "\\t",
+            0x20..=0x7e => out.push(b as char),
+            _ => write!(out, "\\{:03o}", b).unwrap(),
+        }
+    }
+    out
+}
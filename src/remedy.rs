@@ -0,0 +1,172 @@
+//! Dealing with files that failed verification, so remediation pipelines
+//! can move corrupted or unexpected files out of the way without a script
+//! parsing the output, with a journal of what was done to which file.
+
+use color_eyre::eyre::{self, eyre};
+use serde::Serialize;
+use std::{
+    io::{self, Write},
+    path::{Component, Path, PathBuf},
+    str::FromStr,
+    time::SystemTime,
+};
+
+/// What to do with a file that failed verification or isn't listed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OnMismatch {
+    /// Only print its status
+    Report,
+    /// Move it below this directory, at the path it's listed under
+    Quarantine(PathBuf),
+    Delete,
+}
+
+impl FromStr for OnMismatch {
+    type Err = eyre::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "report" => Ok(Self::Report),
+            "delete" => Ok(Self::Delete),
+            _ => match s.strip_prefix("quarantine:") {
+                Some(dir) if !dir.is_empty() => Ok(Self::Quarantine(dir.into())),
+                _ => Err(eyre!(
+                    "expected `report`, `delete` or `quarantine:DIR`, got {:?}",
+                    s
+                )),
+            },
+        }
+    }
+}
+
+/// One journal line
+#[derive(Serialize)]
+struct Action<'a> {
+    /// Unix timestamp
+    time: u64,
+    action: &'a str,
+    path: &'a Path,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    to: Option<&'a Path>,
+    /// The verification status that called for it
+    status: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+pub struct Remedy {
+    on_mismatch: OnMismatch,
+    /// JSON lines, appended to
+    journal: Option<std::fs::File>,
+}
+
+impl Remedy {
+    /// Acts according to `on_mismatch`, recording actions in `journal`, or
+    /// in the quarantine directory's `journal.jsonl` if not given
+    pub fn new(on_mismatch: OnMismatch, journal: Option<&Path>) -> Result<Self, eyre::Error> {
+        let journal = match (&on_mismatch, journal) {
+            (_, Some(path)) => Some(path.to_owned()),
+            (OnMismatch::Quarantine(dir), None) => {
+                std::fs::create_dir_all(dir)?;
+                Some(dir.join("journal.jsonl"))
+            }
+            _ => None,
+        };
+        let journal = journal
+            .map(|path| {
+                std::fs::OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(&path)
+                    .map_err(|e| eyre!("can't open journal {}: {}", path.display(), e))
+            })
+            .transpose()?;
+        Ok(Self {
+            on_mismatch,
+            journal,
+        })
+    }
+
+    /// Acts on the file at `path`, listed as `key`, if its `status` calls
+    /// for it: anything `FAILED` or `UNLISTED`. Returns the status of the
+    /// action, failing only if the journal can't be written.
+    pub async fn apply(
+        &mut self,
+        path: &Path,
+        key: &str,
+        status: &str,
+    ) -> Result<Option<String>, eyre::Error> {
+        if !(status.starts_with("FAILED") || status == "UNLISTED") {
+            return Ok(None);
+        }
+        let (action, to, res) = match &self.on_mismatch {
+            OnMismatch::Report => return Ok(None),
+            OnMismatch::Delete => ("delete", None, async_std::fs::remove_file(path).await),
+            OnMismatch::Quarantine(dir) => {
+                let to = destination(dir, key);
+                let res = quarantine(path, &to).await;
+                ("quarantine", Some(to), res)
+            }
+        };
+
+        if let Some(journal) = &mut self.journal {
+            let line = Action {
+                time: SystemTime::now()
+                    .duration_since(SystemTime::UNIX_EPOCH)
+                    .map(|d| d.as_secs())
+                    .unwrap_or(0),
+                action,
+                path,
+                to: to.as_deref(),
+                status,
+                error: res.as_ref().err().map(|e| e.to_string()),
+            };
+            let mut line = serde_json::to_vec(&line)?;
+            line.push(b'\n');
+            // one write per line, so an interrupted run never leaves half of one
+            journal.write_all(&line)?;
+        }
+
+        Ok(Some(match (res, to) {
+            (Ok(()), Some(to)) => format!("QUARANTINED to {}", to.display()),
+            (Ok(()), None) => "DELETED".to_string(),
+            (Err(e), Some(_)) => format!("NOT QUARANTINED ({})", e),
+            (Err(e), None) => format!("NOT DELETED ({})", e),
+        }))
+    }
+}
+
+/// Where `key` goes below `dir`, keeping its relative path so files with
+/// the same name don't collide, and never above `dir`
+fn destination(dir: &Path, key: &str) -> PathBuf {
+    let relative: PathBuf = Path::new(key)
+        .components()
+        .filter(|c| matches!(c, Component::Normal(_)))
+        .collect();
+    let base = dir.join(relative);
+    let mut to = base.clone();
+    // an earlier run may have quarantined another copy
+    for n in 1.. {
+        if std::fs::symlink_metadata(&to).is_err() {
+            break;
+        }
+        let mut name = base.as_os_str().to_owned();
+        name.push(format!(".{}", n));
+        to = name.into();
+    }
+    to
+}
+
+async fn quarantine(from: &Path, to: &Path) -> io::Result<()> {
+    if let Some(parent) = to.parent() {
+        async_std::fs::create_dir_all(parent).await?;
+    }
+    match async_std::fs::rename(from, to).await {
+        // the quarantine may be on another filesystem
+        Err(e) if e.raw_os_error() == Some(libc::EXDEV) => {
+            async_std::fs::copy(from, to).await?;
+            async_std::fs::remove_file(from).await
+        }
+        res => res,
+    }
+}
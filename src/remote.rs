@@ -0,0 +1,64 @@
+//! Inputs given as `http://` or `https://` URLs, hashed as the body
+//! arrives, so it's never held in memory whole.
+
+use color_eyre::eyre::{self, eyre};
+use futures::io::AsyncRead;
+use std::path::Path;
+
+/// Whether `path` is a URL to fetch rather than a file. A file whose name
+/// looks like one can still be given as `./http://…`.
+pub fn is_url(path: &Path) -> bool {
+    path.to_str()
+        .is_some_and(|s| s.starts_with("http://") || s.starts_with("https://"))
+}
+
+/// How many redirects to follow before giving up
+const MAX_REDIRECTS: usize = 5;
+
+/// The body of `url`, following redirects, and its length if the server
+/// gave one
+pub async fn fetch(
+    url: &Path,
+) -> Result<(impl AsyncRead + Send + Unpin + 'static, Option<u64>), eyre::Error> {
+    let mut url: surf::Url = url
+        .to_str()
+        .and_then(|url| url.parse().ok())
+        .ok_or_else(|| eyre!("{} is not a valid URL", url.display()))?;
+    // surf's own redirect middleware fetches the whole body to find out
+    // whether there's a redirect, then again to return it
+    let client = surf::client();
+    for _ in 0..=MAX_REDIRECTS {
+        let mut response = client
+            .get(url.clone())
+            .await
+            .map_err(|e| eyre!("fetching {}: {}", url, e))?;
+        let status = response.status();
+        if status.is_redirection() {
+            if let Some(location) = response.header(surf::http::headers::LOCATION) {
+                let next = url
+                    .join(location.last().as_str())
+                    .map_err(|e| eyre!("{} redirected to a bad location: {}", url, e))?;
+                // what's left of a body would be read as the next response
+                // on the same connection
+                response
+                    .body_bytes()
+                    .await
+                    .map_err(|e| eyre!("fetching {}: {}", url, e))?;
+                tracing::debug!(%url, %next, "redirected");
+                url = next;
+                continue;
+            }
+        }
+        if !status.is_success() {
+            return Err(eyre!("fetching {}: {}", url, status));
+        }
+        let len = response.len().map(|len| len as u64);
+        tracing::debug!(%url, ?len, "fetching");
+        return Ok((response.take_body(), len));
+    }
+    Err(eyre!(
+        "fetching {}: more than {} redirects",
+        url,
+        MAX_REDIRECTS
+    ))
+}
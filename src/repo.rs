@@ -0,0 +1,258 @@
+//! Package repository metadata: the files an APT `Release` or `Packages`
+//! index, or a YUM `repomd.xml` or `primary.xml`, say should exist, with
+//! their sizes and digests.
+
+use color_eyre::eyre::{self, eyre};
+use std::{
+    io::Read,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+/// Which kind of index a metadata file is
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    /// APT `Release` or `InRelease`, listing the indices of a suite
+    Release,
+    /// APT `Packages`, listing `.deb` files
+    Packages,
+    /// YUM `repomd.xml`, listing the other metadata files
+    Repomd,
+    /// YUM `primary.xml`, listing `.rpm` files
+    Primary,
+}
+
+impl FromStr for Kind {
+    type Err = eyre::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "release" => Ok(Self::Release),
+            "packages" => Ok(Self::Packages),
+            "repomd" => Ok(Self::Repomd),
+            "primary" => Ok(Self::Primary),
+            _ => Err(eyre!(
+                "expected `release`, `packages`, `repomd` or `primary`, got {:?}",
+                s
+            )),
+        }
+    }
+}
+
+impl Kind {
+    /// Guesses the kind from the usual file names
+    pub fn detect(path: &Path) -> Option<Self> {
+        let name = path.file_name()?.to_str()?;
+        if name == "Release" || name == "InRelease" {
+            Some(Self::Release)
+        } else if name.starts_with("Packages") {
+            Some(Self::Packages)
+        } else if name == "repomd.xml" {
+            Some(Self::Repomd)
+        } else if name.contains("primary.xml") {
+            Some(Self::Primary)
+        } else {
+            None
+        }
+    }
+
+    /// The directory paths in an index of this kind are relative to
+    pub fn default_root(self, index: &Path) -> PathBuf {
+        let dir = index.parent().unwrap_or_else(|| Path::new(""));
+        match self {
+            Self::Release => dir.to_owned(),
+            // `Filename:` fields start at the top of the archive, above `dists/`
+            Self::Packages => dir
+                .ancestors()
+                .find(|a| a.file_name().is_some_and(|n| n == "dists"))
+                .and_then(Path::parent)
+                .unwrap_or(dir)
+                .to_owned(),
+            // locations start next to `repodata/`
+            Self::Repomd | Self::Primary => dir.parent().unwrap_or(dir).to_owned(),
+        }
+    }
+}
+
+/// Digest algorithms found in repository metadata, weakest first
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Algorithm {
+    Md5,
+    Sha1,
+    Sha256,
+    Sha512,
+}
+
+impl Algorithm {
+    /// Parses APT field names and YUM `type` attributes
+    fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "md5sum" | "md5" => Some(Self::Md5),
+            "sha1" | "sha" => Some(Self::Sha1),
+            "sha256" => Some(Self::Sha256),
+            "sha512" => Some(Self::Sha512),
+            _ => None,
+        }
+    }
+
+    pub fn hasher(self) -> Box<dyn sha2::digest::DynDigest + Send> {
+        match self {
+            Self::Md5 => Box::new(md5::Md5::default()),
+            Self::Sha1 => Box::new(sha1::Sha1::default()),
+            Self::Sha256 => Box::new(sha2::Sha256::default()),
+            Self::Sha512 => Box::new(sha2::Sha512::default()),
+        }
+    }
+}
+
+/// A file the index refers to
+#[derive(Debug, Clone)]
+pub struct Entry {
+    /// Path as written in the index, relative to the repository root
+    pub path: String,
+    pub size: Option<u64>,
+    pub algorithm: Algorithm,
+    /// The strongest digest listed for the file, in lowercase hex
+    pub digest: String,
+}
+
+/// Reads an index, decompressing it if its name ends in `.gz`
+pub fn read(path: &Path) -> Result<String, eyre::Error> {
+    let file = std::fs::File::open(path)?;
+    let mut text = String::new();
+    if path.extension().is_some_and(|e| e == "gz") {
+        flate2::read::MultiGzDecoder::new(file).read_to_string(&mut text)?;
+    } else {
+        std::io::BufReader::new(file).read_to_string(&mut text)?;
+    }
+    Ok(text)
+}
+
+pub fn parse(kind: Kind, input: &str) -> Result<Vec<Entry>, eyre::Error> {
+    match kind {
+        Kind::Release => parse_release(&strip_signature(input)),
+        Kind::Packages => parse_packages(input),
+        Kind::Repomd => parse_xml(input, "data"),
+        Kind::Primary => parse_xml(input, "package"),
+    }
+}
+
+/// The signed text of an `InRelease` file, or the input if it isn't signed
+fn strip_signature(input: &str) -> String {
+    if !input.starts_with("-----BEGIN PGP SIGNED MESSAGE-----") {
+        return input.to_string();
+    }
+    input
+        .lines()
+        // armor headers end at the first blank line
+        .skip_while(|line| !line.is_empty())
+        .skip(1)
+        .take_while(|line| *line != "-----BEGIN PGP SIGNATURE-----")
+        .map(|line| line.strip_prefix("- ").unwrap_or(line))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Splits a deb822 paragraph into fields, joining continuation lines
+fn fields(paragraph: &str) -> Vec<(&str, Vec<&str>)> {
+    let mut fields: Vec<(&str, Vec<&str>)> = Vec::new();
+    for line in paragraph.lines() {
+        if line.starts_with([' ', '\t']) {
+            if let Some((_, value)) = fields.last_mut() {
+                value.push(line.trim());
+            }
+        } else if let Some((name, value)) = line.split_once(':') {
+            let value = value.trim();
+            let value = if value.is_empty() {
+                vec![]
+            } else {
+                vec![value]
+            };
+            fields.push((name, value));
+        }
+    }
+    fields
+}
+
+fn parse_release(input: &str) -> Result<Vec<Entry>, eyre::Error> {
+    let mut entries: Vec<Entry> = Vec::new();
+    for (name, lines) in fields(input) {
+        let algorithm = match Algorithm::from_name(name) {
+            Some(algorithm) => algorithm,
+            None => continue,
+        };
+        for line in lines {
+            let mut words = line.split_whitespace();
+            let (digest, size, path) = match (words.next(), words.next(), words.next()) {
+                (Some(digest), Some(size), Some(path)) => (digest, size, path),
+                _ => return Err(eyre!("malformed {} line: {:?}", name, line)),
+            };
+            let entry = Entry {
+                path: path.to_string(),
+                size: Some(size.parse()?),
+                algorithm,
+                digest: digest.to_ascii_lowercase(),
+            };
+            // every algorithm lists the same files, keep the strongest digest
+            match entries.iter_mut().find(|e| e.path == entry.path) {
+                Some(e) if e.algorithm < algorithm => *e = entry,
+                Some(_) => {}
+                None => entries.push(entry),
+            }
+        }
+    }
+    Ok(entries)
+}
+
+fn parse_packages(input: &str) -> Result<Vec<Entry>, eyre::Error> {
+    let mut entries = Vec::new();
+    for paragraph in input.split("\n\n").filter(|p| !p.trim().is_empty()) {
+        let fields = fields(paragraph);
+        let value = |name: &str| {
+            fields
+                .iter()
+                .find(|(n, _)| n.eq_ignore_ascii_case(name))
+                .and_then(|(_, v)| v.first().copied())
+        };
+        let path = value("Filename")
+            .ok_or_else(|| eyre!("package without a Filename: {:?}", value("Package")))?;
+        let (algorithm, digest) = fields
+            .iter()
+            .filter_map(|(n, v)| Some((Algorithm::from_name(n)?, *v.first()?)))
+            .max_by_key(|(algorithm, _)| *algorithm)
+            .ok_or_else(|| eyre!("{} has no digest", path))?;
+        entries.push(Entry {
+            path: path.to_string(),
+            size: value("Size").map(str::parse).transpose()?,
+            algorithm,
+            digest: digest.to_ascii_lowercase(),
+        });
+    }
+    Ok(entries)
+}
+
+/// Reads the `<checksum>`, `<location>` and `<size>` of every `element`
+fn parse_xml(input: &str, element: &str) -> Result<Vec<Entry>, eyre::Error> {
+    let doc = roxmltree::Document::parse(input)?;
+    let mut entries = Vec::new();
+    for node in doc.descendants().filter(|n| n.tag_name().name() == element) {
+        let child = |name: &str| node.children().find(|c| c.tag_name().name() == name);
+        let path = child("location")
+            .and_then(|l| l.attribute("href"))
+            .ok_or_else(|| eyre!("<{}> without a location", element))?;
+        let checksum = child("checksum").ok_or_else(|| eyre!("{} has no checksum", path))?;
+        let algorithm = checksum
+            .attribute("type")
+            .and_then(Algorithm::from_name)
+            .ok_or_else(|| eyre!("{} has an unknown checksum type", path))?;
+        // `repomd.xml` has the size as text, `primary.xml` as an attribute
+        let size = child("size").and_then(|s| s.attribute("package").or_else(|| s.text()));
+        entries.push(Entry {
+            path: path.to_string(),
+            size: size.map(|s| s.trim().parse()).transpose()?,
+            algorithm,
+            digest: checksum.text().unwrap_or("").trim().to_ascii_lowercase(),
+        });
+    }
+    Ok(entries)
+}
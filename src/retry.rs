@@ -0,0 +1,209 @@
+//! Riding out flaky storage, for `--retries`: a read that fails with what
+//! looks like a transient error, or that stalls past `--read-timeout`, is
+//! tried again after a backoff, on the file opened afresh and sought back
+//! to the last byte read, so a brief NFS hiccup doesn't cost a whole file
+//! of a long run. Only once the retries are spent does the error go up, to
+//! `--salvage` if it's on.
+
+use futures::{
+    future::BoxFuture,
+    io::{AsyncRead, AsyncSeek},
+};
+use std::{
+    future::Future,
+    io::{self, SeekFrom},
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+/// The longest a backoff grows to
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// How many times to try again, and after how long
+#[derive(Debug, Clone, Copy)]
+pub struct Policy {
+    /// Tries after the first, for each failure in a row
+    pub retries: u32,
+    /// The wait before the first retry, doubled for each one after it
+    pub backoff: Duration,
+    /// How long a read may take before it's taken for a stall and tried
+    /// again, if at all
+    pub read_timeout: Option<Duration>,
+    /// Which errors are worth another try
+    pub retryable: fn(&io::Error) -> bool,
+}
+
+impl Policy {
+    pub fn new(retries: u32) -> Self {
+        Self {
+            retries,
+            backoff: Duration::from_millis(200),
+            read_timeout: None,
+            retryable: transient,
+        }
+    }
+
+    /// The wait before the `attempt`th retry, counting from 1
+    fn backoff(&self, attempt: u32) -> Duration {
+        let factor = 1u32.checked_shl(attempt - 1).unwrap_or(u32::MAX);
+        self.backoff.saturating_mul(factor).min(MAX_BACKOFF)
+    }
+}
+
+/// Whether `e` is what network filesystems fail with while they reconnect,
+/// rather than something another try won't change
+pub fn transient(e: &io::Error) -> bool {
+    use io::ErrorKind::*;
+
+    if matches!(
+        e.kind(),
+        TimedOut | Interrupted | WouldBlock | ConnectionReset | ConnectionAborted | NotConnected
+    ) {
+        return true;
+    }
+    #[cfg(unix)]
+    if let Some(errno) = e.raw_os_error() {
+        return [
+            libc::EIO,
+            libc::ESTALE,
+            libc::ETIMEDOUT,
+            libc::ENETDOWN,
+            libc::ENETUNREACH,
+            libc::EHOSTUNREACH,
+        ]
+        .contains(&errno);
+    }
+    false
+}
+
+/// Opens the source again, at the offset it's given
+pub type Reopen<R> = Box<dyn FnMut(u64) -> BoxFuture<'static, io::Result<R>> + Send>;
+
+enum State<R> {
+    Reading,
+    /// Out the backoff before a retry
+    Waiting(BoxFuture<'static, ()>),
+    Reopening(BoxFuture<'static, io::Result<R>>),
+}
+
+/// Retries the reads of the reader it wraps as `policy` says, reopening it
+/// with `reopen` each time
+pub struct RetryingReader<R> {
+    inner: R,
+    reopen: Reopen<R>,
+    policy: Policy,
+    /// Just past the last byte handed out
+    offset: u64,
+    /// Failures in a row
+    failures: u32,
+    state: State<R>,
+    /// When the read in flight is taken for a stall
+    deadline: Option<BoxFuture<'static, ()>>,
+}
+
+impl<R> RetryingReader<R> {
+    /// Over `inner`, which is at `offset`
+    pub fn new(inner: R, offset: u64, policy: Policy, reopen: Reopen<R>) -> Self {
+        Self {
+            inner,
+            reopen,
+            policy,
+            offset,
+            failures: 0,
+            state: State::Reading,
+            deadline: None,
+        }
+    }
+
+    /// Waits to try again after `e`, or hands it back if it's not worth it
+    fn failed(&mut self, e: io::Error) -> io::Result<()> {
+        self.deadline = None;
+        if self.failures >= self.policy.retries || !(self.policy.retryable)(&e) {
+            self.failures = 0;
+            return Err(e);
+        }
+        self.failures += 1;
+        let wait = self.policy.backoff(self.failures);
+        tracing::warn!(
+            offset = self.offset,
+            attempt = self.failures,
+            wait_ms = wait.as_millis() as u64,
+            error = %e,
+            "read failed, retrying"
+        );
+        self.state = State::Waiting(Box::pin(crate::rt::sleep(wait)));
+        Ok(())
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for RetryingReader<R> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        loop {
+            match &mut this.state {
+                State::Waiting(wait) => {
+                    futures::ready!(wait.as_mut().poll(cx));
+                    this.state = State::Reopening((this.reopen)(this.offset));
+                }
+                State::Reopening(reopen) => {
+                    let res = futures::ready!(reopen.as_mut().poll(cx));
+                    this.state = State::Reading;
+                    match res {
+                        Ok(inner) => {
+                            tracing::debug!(offset = this.offset, "reopened");
+                            this.inner = inner;
+                        }
+                        Err(e) => this.failed(e)?,
+                    }
+                }
+                State::Reading => {
+                    match Pin::new(&mut this.inner).poll_read(cx, buf) {
+                        Poll::Ready(Ok(n)) => {
+                            this.deadline = None;
+                            this.failures = 0;
+                            this.offset += n as u64;
+                            return Poll::Ready(Ok(n));
+                        }
+                        Poll::Ready(Err(e)) => this.failed(e)?,
+                        Poll::Pending => {
+                            let timeout = match this.policy.read_timeout {
+                                Some(timeout) => timeout,
+                                None => return Poll::Pending,
+                            };
+                            let deadline = this
+                                .deadline
+                                .get_or_insert_with(|| Box::pin(crate::rt::sleep(timeout)));
+                            futures::ready!(deadline.as_mut().poll(cx));
+                            // the stalled read is left to finish on its own,
+                            // on the file it was made on
+                            this.failed(io::Error::new(
+                                io::ErrorKind::TimedOut,
+                                format!("no data in {:.3}s", timeout.as_secs_f64()),
+                            ))?;
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Seeks the reader it wraps, for `--salvage` and resumes, keeping track
+/// of where a retry picks up
+impl<R: AsyncSeek + Unpin> AsyncSeek for RetryingReader<R> {
+    fn poll_seek(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        pos: SeekFrom,
+    ) -> Poll<io::Result<u64>> {
+        let this = self.get_mut();
+        let offset = futures::ready!(Pin::new(&mut this.inner).poll_seek(cx, pos))?;
+        this.offset = offset;
+        Poll::Ready(Ok(offset))
+    }
+}
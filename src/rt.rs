@@ -0,0 +1,110 @@
+//! What the library asks of an async runtime: running blocking work off
+//! the executor, spawning tasks and sleeping. That's async-std's by
+//! default, and tokio's with the `rt-tokio` feature, so a tokio
+//! application embedding the library doesn't grow a second pool of
+//! threads for it.
+//!
+//! async-std's files, sockets and channels don't need its executor, and
+//! work from any runtime as they are; [`Compat`] lets tokio's readers be
+//! hashed like any other.
+
+use std::time::Duration;
+
+/// Runs `f` where blocking is fine, returning what it did
+#[cfg(not(feature = "rt-tokio"))]
+pub async fn spawn_blocking<F, T>(f: F) -> T
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    async_std::task::spawn_blocking(f).await
+}
+
+/// Runs `future` in the background, detached
+#[cfg(not(feature = "rt-tokio"))]
+pub fn spawn<F>(future: F)
+where
+    F: std::future::Future<Output = ()> + Send + 'static,
+{
+    async_std::task::spawn(future);
+}
+
+#[cfg(not(feature = "rt-tokio"))]
+pub async fn sleep(duration: Duration) {
+    async_std::task::sleep(duration).await
+}
+
+#[cfg(feature = "rt-tokio")]
+mod tokio_rt {
+    use std::sync::OnceLock;
+
+    /// The runtime of whoever's calling, or else one of the library's own,
+    /// for callers that aren't on a tokio runtime at all
+    pub fn handle() -> tokio::runtime::Handle {
+        static FALLBACK: OnceLock<tokio::runtime::Runtime> = OnceLock::new();
+        tokio::runtime::Handle::try_current().unwrap_or_else(|_| {
+            FALLBACK
+                .get_or_init(|| {
+                    // a thread of its own to drive timers and tasks, which
+                    // nobody else would
+                    tokio::runtime::Builder::new_multi_thread()
+                        .worker_threads(1)
+                        .enable_time()
+                        .build()
+                        .expect("can't start a tokio runtime")
+                })
+                .handle()
+                .clone()
+        })
+    }
+}
+
+#[cfg(feature = "rt-tokio")]
+pub async fn spawn_blocking<F, T>(f: F) -> T
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    match tokio_rt::handle().spawn_blocking(f).await {
+        Ok(value) => value,
+        // as if it had been called here
+        Err(e) => std::panic::resume_unwind(e.into_panic()),
+    }
+}
+
+#[cfg(feature = "rt-tokio")]
+pub fn spawn<F>(future: F)
+where
+    F: std::future::Future<Output = ()> + Send + 'static,
+{
+    tokio_rt::handle().spawn(future);
+}
+
+#[cfg(feature = "rt-tokio")]
+pub async fn sleep(duration: Duration) {
+    // the timer has to be made inside the runtime, wherever it's awaited
+    let sleep = {
+        let handle = tokio_rt::handle();
+        let _entered = handle.enter();
+        tokio::time::sleep(duration)
+    };
+    sleep.await
+}
+
+/// A tokio reader as a `futures` one, for [`crate::hash_reader`] and the
+/// rest of the library
+#[cfg(feature = "rt-tokio")]
+pub struct Compat<R>(pub R);
+
+#[cfg(feature = "rt-tokio")]
+impl<R: tokio::io::AsyncRead + Unpin> futures::io::AsyncRead for Compat<R> {
+    fn poll_read(
+        mut self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+        buf: &mut [u8],
+    ) -> std::task::Poll<std::io::Result<usize>> {
+        let mut buf = tokio::io::ReadBuf::new(buf);
+        futures::ready!(std::pin::Pin::new(&mut self.0).poll_read(cx, &mut buf))?;
+        std::task::Poll::Ready(Ok(buf.filled().len()))
+    }
+}
@@ -0,0 +1,167 @@
+//! A record of every run: what it was asked to do and how it went, one JSON
+//! file per run in a directory, so nightly scans can be compared to spot
+//! performance or coverage regressions.
+
+use crate::metrics::RunMetrics;
+use color_eyre::eyre::{self, eyre};
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt::Write as _,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+/// One run, as written to its sidecar file
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Run {
+    /// When the run started, as a Unix timestamp
+    pub started: u64,
+    pub host: String,
+    pub algorithm: String,
+    /// The command line, minus the program name
+    pub arguments: Vec<String>,
+    /// How long the run took, in seconds
+    pub duration: f64,
+    pub files: u64,
+    pub errors: u64,
+    pub bytes: u64,
+}
+
+impl Run {
+    pub fn new(
+        algorithm: crate::algo::Algorithm,
+        started: SystemTime,
+        elapsed: Duration,
+        metrics: &RunMetrics,
+    ) -> Self {
+        Self {
+            started: started
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0),
+            host: hostname(),
+            algorithm: algorithm.name().to_string(),
+            arguments: std::env::args().skip(1).collect(),
+            duration: elapsed.as_secs_f64(),
+            files: metrics.files,
+            errors: metrics.errors,
+            bytes: metrics.bytes,
+        }
+    }
+
+    /// Bytes hashed per second
+    fn throughput(&self) -> f64 {
+        self.bytes as f64 / self.duration.max(f64::EPSILON)
+    }
+}
+
+pub fn hostname() -> String {
+    let mut buf = [0u8; 256];
+    if unsafe { libc::gethostname(buf.as_mut_ptr() as *mut libc::c_char, buf.len()) } != 0 {
+        return "unknown".to_string();
+    }
+    let len = buf.iter().position(|&b| b == 0).unwrap_or(buf.len());
+    String::from_utf8_lossy(&buf[..len]).into_owned()
+}
+
+/// Writes `run` to `dir`, named after when it started, returning its id
+pub async fn record(dir: &Path, run: &Run) -> Result<String, eyre::Error> {
+    async_std::fs::create_dir_all(dir).await?;
+    let id = format!("{}-{}", run.started, std::process::id());
+    let path = dir.join(format!("{}.json", id));
+    let partial = dir.join(format!("{}.json.partial", id));
+    async_std::fs::write(&partial, serde_json::to_vec_pretty(run)?).await?;
+    async_std::fs::rename(&partial, &path).await?;
+    Ok(id)
+}
+
+/// Every run recorded in `dir` with its id, oldest first
+pub async fn load(dir: &Path) -> Result<Vec<(String, Run)>, eyre::Error> {
+    let mut runs = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let path: PathBuf = entry?.path();
+        if path.extension().is_none_or(|e| e != "json") {
+            continue;
+        }
+        let id = match path.file_stem().and_then(|s| s.to_str()) {
+            Some(id) => id.to_string(),
+            None => continue,
+        };
+        let text = async_std::fs::read(&path).await?;
+        let run: Run =
+            serde_json::from_slice(&text).map_err(|e| eyre!("in {}: {}", path.display(), e))?;
+        runs.push((id, run));
+    }
+    runs.sort_by(|(a_id, a), (b_id, b)| (a.started, a_id).cmp(&(b.started, b_id)));
+    Ok(runs)
+}
+
+/// One line per run
+pub fn list(runs: &[(String, Run)]) -> String {
+    let mut out = String::new();
+    for (id, run) in runs {
+        writeln!(
+            out,
+            "{} {} {} {} files={} failed={} bytes={} duration={:.3}s",
+            id,
+            crate::zsync::rfc2822(SystemTime::UNIX_EPOCH + Duration::from_secs(run.started)),
+            run.host,
+            run.algorithm,
+            run.files,
+            run.errors,
+            run.bytes,
+            run.duration
+        )
+        .unwrap();
+    }
+    out
+}
+
+/// What changed from run `a` to run `b`
+pub fn compare(a: &Run, b: &Run) -> String {
+    let mut out = String::new();
+    let mut text = |name: &str, a: &str, b: &str| {
+        let mark = if a == b { "" } else { " (changed)" };
+        writeln!(out, "{}: {} -> {}{}", name, a, b, mark).unwrap();
+    };
+    text("host", &a.host, &b.host);
+    text("algorithm", &a.algorithm, &b.algorithm);
+    text("arguments", &a.arguments.join(" "), &b.arguments.join(" "));
+
+    let mut count = |name: &str, a: u64, b: u64| {
+        writeln!(
+            out,
+            "{}: {} -> {} ({:+})",
+            name,
+            a,
+            b,
+            b as i128 - a as i128
+        )
+        .unwrap();
+    };
+    count("files", a.files, b.files);
+    count("failed", a.errors, b.errors);
+    count("bytes", a.bytes, b.bytes);
+
+    let mut rate = |name: &str, unit: &str, a: f64, b: f64| {
+        let change = if a > 0.0 {
+            format!(" ({:+.1}%)", (b - a) / a * 100.0)
+        } else {
+            String::new()
+        };
+        writeln!(
+            out,
+            "{}: {:.3}{} -> {:.3}{}{}",
+            name, a, unit, b, unit, change
+        )
+        .unwrap();
+    };
+    rate("duration", "s", a.duration, b.duration);
+    rate(
+        "throughput",
+        "MB/s",
+        a.throughput() / 1e6,
+        b.throughput() / 1e6,
+    );
+    out
+}
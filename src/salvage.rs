@@ -0,0 +1,125 @@
+//! Reading past damage, for `--salvage`: a read that fails is tried again
+//! a block at a time, and each block that still can't be read is skipped
+//! and hashed as zeros. The digest is then only a best effort, but the
+//! blocks it's missing are known exactly, for `--bad-ranges`.
+
+use color_eyre::eyre::{self, eyre};
+use futures::io::{AsyncRead, AsyncSeek};
+use serde::Serialize;
+use std::{
+    io::{self, SeekFrom},
+    ops::Range,
+    path::Path,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    task::{Context, Poll},
+};
+
+/// How much is given up on at a time, and what a failed read is narrowed
+/// down to
+pub const BLOCK: u64 = 4096;
+
+/// What unreadable bytes are hashed as
+pub const PLACEHOLDER: u8 = 0;
+
+const NOWHERE: u64 = u64::MAX;
+
+/// Where the file under a [`Skipping`] reader should pick up from, set
+/// from above once a block is given up on
+#[derive(Clone)]
+pub struct Resume(Arc<AtomicU64>);
+
+impl Default for Resume {
+    fn default() -> Self {
+        Self(Arc::new(AtomicU64::new(NOWHERE)))
+    }
+}
+
+impl Resume {
+    pub fn at(&self, offset: u64) {
+        self.0.store(offset, Ordering::SeqCst);
+    }
+}
+
+/// A file that seeks past skipped blocks before its next read
+pub struct Skipping<F> {
+    inner: F,
+    resume: Resume,
+}
+
+impl<F> Skipping<F> {
+    pub fn new(inner: F, resume: Resume) -> Self {
+        Self { inner, resume }
+    }
+}
+
+impl<F: AsyncRead + AsyncSeek + Unpin> AsyncRead for Skipping<F> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let offset = self.resume.0.load(Ordering::SeqCst);
+        if offset != NOWHERE {
+            // an absolute seek, so polling it again after it was pending
+            // lands in the same place
+            futures::ready!(Pin::new(&mut self.inner).poll_seek(cx, SeekFrom::Start(offset)))?;
+            self.resume.0.store(NOWHERE, Ordering::SeqCst);
+        }
+        Pin::new(&mut self.inner).poll_read(cx, buf)
+    }
+}
+
+/// Adds `range` to `ranges`, merging it into the last one if they touch
+pub fn record(ranges: &mut Vec<Range<u64>>, range: Range<u64>) {
+    match ranges.last_mut() {
+        Some(last) if last.end == range.start => last.end = range.end,
+        _ => ranges.push(range),
+    }
+}
+
+/// One damaged file's line in a `--bad-ranges` map
+#[derive(Debug, Serialize)]
+struct Damage {
+    path: String,
+    size: u64,
+    /// The byte hashed in place of each unreadable one
+    placeholder: u8,
+    /// Start and end offsets, the end excluded
+    ranges: Vec<(u64, u64)>,
+}
+
+/// A `--bad-ranges` map being written, one JSON line per damaged file
+pub struct Map(std::io::BufWriter<std::fs::File>);
+
+impl Map {
+    pub fn create(path: &Path) -> Result<Self, eyre::Error> {
+        let file = std::fs::File::create(path)
+            .map_err(|e| eyre!("can't create {}: {}", path.display(), e))?;
+        Ok(Self(std::io::BufWriter::new(file)))
+    }
+
+    pub fn write(
+        &mut self,
+        path: &Path,
+        size: u64,
+        ranges: &[Range<u64>],
+    ) -> Result<(), eyre::Error> {
+        let damage = Damage {
+            path: path.display().to_string(),
+            size,
+            placeholder: PLACEHOLDER,
+            ranges: ranges.iter().map(|r| (r.start, r.end)).collect(),
+        };
+        serde_json::to_writer(&mut self.0, &damage)?;
+        std::io::Write::write_all(&mut self.0, b"\n")?;
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> std::io::Result<()> {
+        std::io::Write::flush(&mut self.0)
+    }
+}
@@ -0,0 +1,239 @@
+//! Giving up privileges before hashing, for runs that start as root so they
+//! can read everything but have no business keeping root while they parse
+//! untrusted files.
+//!
+//! Everything here happens once, before the async runtime starts its
+//! threads, in the order `lookup`, `chroot`, `confine`, then `drop_to`: the
+//! user has to be looked up while `/etc/passwd` is still reachable, and
+//! only root may chroot.
+
+use color_eyre::eyre::{self, eyre};
+use std::{
+    ffi::CString,
+    io,
+    os::unix::ffi::OsStrExt,
+    path::{Path, PathBuf},
+};
+
+/// The account to switch to, resolved from a name or a numeric uid
+#[derive(Debug, Clone, Copy)]
+pub struct User {
+    uid: libc::uid_t,
+    gid: libc::gid_t,
+}
+
+/// Looks `user` up in the password database
+pub fn lookup(user: &str) -> Result<User, eyre::Error> {
+    let name = CString::new(user)?;
+    let mut pwd: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut buf = vec![0 as libc::c_char; 16 << 10];
+    let mut found = std::ptr::null_mut();
+    let res = match user.parse::<libc::uid_t>() {
+        Ok(uid) => unsafe {
+            libc::getpwuid_r(uid, &mut pwd, buf.as_mut_ptr(), buf.len(), &mut found)
+        },
+        Err(_) => unsafe {
+            libc::getpwnam_r(
+                name.as_ptr(),
+                &mut pwd,
+                buf.as_mut_ptr(),
+                buf.len(),
+                &mut found,
+            )
+        },
+    };
+    if res != 0 {
+        return Err(eyre!(
+            "can't look up user {:?}: {}",
+            user,
+            io::Error::from_raw_os_error(res)
+        ));
+    }
+    if found.is_null() {
+        return Err(eyre!("no such user: {:?}", user));
+    }
+    Ok(User {
+        uid: pwd.pw_uid,
+        gid: pwd.pw_gid,
+    })
+}
+
+/// Makes `dir` the root directory. Paths given afterwards, including the
+/// inputs, are resolved inside it.
+pub fn chroot(dir: &Path) -> Result<(), eyre::Error> {
+    let path = CString::new(dir.as_os_str().as_bytes())?;
+    if unsafe { libc::chroot(path.as_ptr()) } != 0 {
+        return Err(eyre!(
+            "can't chroot to {}: {}",
+            dir.display(),
+            io::Error::last_os_error()
+        ));
+    }
+    std::env::set_current_dir("/")?;
+    Ok(())
+}
+
+/// Switches to `user` for good, dropping supplementary groups first
+pub fn drop_to(user: User) -> Result<(), eyre::Error> {
+    let fail = |what: &str| eyre!("can't {}: {}", what, io::Error::last_os_error());
+    unsafe {
+        if libc::setgroups(0, std::ptr::null()) != 0 {
+            return Err(fail("drop supplementary groups"));
+        }
+        if libc::setgid(user.gid) != 0 {
+            return Err(fail("set group id"));
+        }
+        if libc::setuid(user.uid) != 0 {
+            return Err(fail("set user id"));
+        }
+        // a saved set-user-ID of 0 would let the process get root back
+        if user.uid != 0 && libc::setuid(0) == 0 {
+            return Err(eyre!("still able to regain root after dropping privileges"));
+        }
+    }
+    Ok(())
+}
+
+// Landlock's ABI, from linux/landlock.h
+const CREATE_RULESET_VERSION: u32 = 1 << 0;
+const RULE_PATH_BENEATH: libc::c_int = 1;
+
+const ACCESS_EXECUTE: u64 = 1 << 0;
+const ACCESS_WRITE_FILE: u64 = 1 << 1;
+const ACCESS_READ_FILE: u64 = 1 << 2;
+const ACCESS_READ_DIR: u64 = 1 << 3;
+const ACCESS_REMOVE_DIR: u64 = 1 << 4;
+const ACCESS_REMOVE_FILE: u64 = 1 << 5;
+const ACCESS_MAKE_DIR: u64 = 1 << 7;
+const ACCESS_MAKE_REG: u64 = 1 << 8;
+/// Every filesystem right of ABI version 1
+const ACCESS_V1: u64 = (1 << 13) - 1;
+/// Linking and renaming across directories, from ABI version 2
+const ACCESS_REFER: u64 = 1 << 13;
+/// Truncating, from ABI version 3
+const ACCESS_TRUNCATE: u64 = 1 << 14;
+
+#[repr(C)]
+struct RulesetAttr {
+    handled_access_fs: u64,
+}
+
+#[repr(C, packed)]
+struct PathBeneathAttr {
+    allowed_access: u64,
+    parent_fd: i32,
+}
+
+/// Denies all filesystem access except reading below `read` and writing
+/// below `write`, for this process and everything it starts, using Landlock.
+pub fn confine(read: &[PathBuf], write: &[PathBuf]) -> Result<(), eyre::Error> {
+    let abi = unsafe {
+        libc::syscall(
+            libc::SYS_landlock_create_ruleset,
+            std::ptr::null::<RulesetAttr>(),
+            0,
+            CREATE_RULESET_VERSION,
+        )
+    };
+    if abi < 1 {
+        return Err(eyre!(
+            "this kernel can't confine processes with Landlock: {}",
+            io::Error::last_os_error()
+        ));
+    }
+    let mut handled = ACCESS_V1;
+    if abi >= 2 {
+        handled |= ACCESS_REFER;
+    }
+    if abi >= 3 {
+        handled |= ACCESS_TRUNCATE;
+    }
+
+    let attr = RulesetAttr {
+        handled_access_fs: handled,
+    };
+    let ruleset = unsafe {
+        libc::syscall(
+            libc::SYS_landlock_create_ruleset,
+            &attr,
+            std::mem::size_of::<RulesetAttr>(),
+            0,
+        )
+    };
+    if ruleset < 0 {
+        return Err(eyre!(
+            "can't create a Landlock ruleset: {}",
+            io::Error::last_os_error()
+        ));
+    }
+    let ruleset = ruleset as libc::c_int;
+
+    let res = (|| {
+        let reading = ACCESS_READ_FILE | ACCESS_READ_DIR;
+        let writing = reading
+            | ACCESS_WRITE_FILE
+            | ACCESS_REMOVE_DIR
+            | ACCESS_REMOVE_FILE
+            | ACCESS_MAKE_DIR
+            | ACCESS_MAKE_REG
+            | (handled & ACCESS_TRUNCATE);
+        for path in read {
+            allow(ruleset, path, reading)?;
+        }
+        for path in write {
+            allow(ruleset, path, writing)?;
+        }
+
+        // without this, only CAP_SYS_ADMIN may restrict itself
+        if unsafe { libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) } != 0 {
+            return Err(eyre!(
+                "can't set no_new_privs: {}",
+                io::Error::last_os_error()
+            ));
+        }
+        if unsafe { libc::syscall(libc::SYS_landlock_restrict_self, ruleset, 0) } != 0 {
+            return Err(eyre!(
+                "can't enforce the Landlock ruleset: {}",
+                io::Error::last_os_error()
+            ));
+        }
+        Ok(())
+    })();
+    unsafe { libc::close(ruleset) };
+    res
+}
+
+fn allow(ruleset: libc::c_int, path: &Path, mut access: u64) -> Result<(), eyre::Error> {
+    let name = CString::new(path.as_os_str().as_bytes())?;
+    let fd = unsafe { libc::open(name.as_ptr(), libc::O_PATH | libc::O_CLOEXEC) };
+    if fd < 0 {
+        return Err(eyre!(
+            "can't open {} for confinement: {}",
+            path.display(),
+            io::Error::last_os_error()
+        ));
+    }
+    // directory rights are rejected on anything that isn't a directory
+    if !path.is_dir() {
+        access &= ACCESS_READ_FILE | ACCESS_WRITE_FILE | ACCESS_TRUNCATE | ACCESS_EXECUTE;
+    }
+    let rule = PathBeneathAttr {
+        allowed_access: access,
+        parent_fd: fd,
+    };
+    let res = unsafe {
+        libc::syscall(
+            libc::SYS_landlock_add_rule,
+            ruleset,
+            RULE_PATH_BENEATH,
+            &rule,
+            0,
+        )
+    };
+    let err = io::Error::last_os_error();
+    unsafe { libc::close(fd) };
+    if res != 0 {
+        return Err(eyre!("can't allow access to {}: {}", path.display(), err));
+    }
+    Ok(())
+}
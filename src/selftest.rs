@@ -0,0 +1,227 @@
+//! Known answers for every built-in algorithm, for `surviving self-test`,
+//! so an installation can be validated before its digests are trusted.
+//!
+//! Each vector is hashed in one go, and again through the same reader
+//! stack files go through, with short reads injected, so a bug in how
+//! reads are buffered shows up as well as one in the algorithm.
+
+use crate::{
+    algo::{Algorithm, Update},
+    fault::{FaultInjectingReader, Faults},
+    hex, Position, SimpleAsyncReader, SimpleAsyncWriter, SimpleWrite, TracingReader,
+};
+use async_std::io::ReadExt;
+use async_trait::async_trait;
+use futures::io::AsyncWriteExt;
+use std::{borrow::Cow, io, path::Path};
+
+/// What a vector hashes
+#[derive(Debug, Clone, Copy)]
+enum Input {
+    Bytes(&'static [u8]),
+    /// One byte, this many times
+    Repeat(u8, usize),
+    /// 0, 1, .. 250, 0, 1, .., as the BLAKE3 reference vectors use
+    Pattern(usize),
+}
+
+impl Input {
+    fn bytes(self) -> Cow<'static, [u8]> {
+        match self {
+            Self::Bytes(bytes) => Cow::Borrowed(bytes),
+            Self::Repeat(byte, len) => Cow::Owned(vec![byte; len]),
+            Self::Pattern(len) => Cow::Owned((0..len).map(|i| (i % 251) as u8).collect()),
+        }
+    }
+
+    fn describe(self) -> String {
+        match self {
+            Self::Bytes(bytes) => format!("{:?}", String::from_utf8_lossy(bytes)),
+            Self::Repeat(byte, len) => format!("{} x {:?}", len, byte as char),
+            Self::Pattern(len) => format!("{} pattern bytes", len),
+        }
+    }
+}
+
+struct Vector {
+    algorithm: Algorithm,
+    input: Input,
+    /// Hex
+    digest: &'static str,
+}
+
+const fn vector(algorithm: Algorithm, input: Input, digest: &'static str) -> Vector {
+    Vector {
+        algorithm,
+        input,
+        digest,
+    }
+}
+
+const ABC: Input = Input::Bytes(b"abc");
+const EMPTY: Input = Input::Bytes(b"");
+const MILLION_A: Input = Input::Repeat(b'a', 1_000_000);
+
+/// From FIPS 180 and 202 examples for the SHA families, the BLAKE3
+/// reference vectors, RFC 1320 for ED2K's MD4 and THEX for TTH
+const VECTORS: &[Vector] = &[
+    vector(Algorithm::Sha3_256, EMPTY, "a7ffc6f8bf1ed76651c14756a061d662f580ff4de43b49fa82d80a4b80f8434a"),
+    vector(Algorithm::Sha3_256, ABC, "3a985da74fe225b2045c172d6bd390bd855f086e3e9d525b46bfe24511431532"),
+    vector(Algorithm::Sha3_256, MILLION_A, "5c8875ae474a3634ba4fd55ec85bffd661f32aca75c6d699d0cdcb6c115891c1"),
+    vector(Algorithm::Sha3_512, EMPTY, "a69f73cca23a9ac5c8b567dc185a756e97c982164fe25859e0d1dcc1475c80a615b2123af1f5f94c11e3e9402c3ac558f500199d95b6d3e301758586281dcd26"),
+    vector(Algorithm::Sha3_512, ABC, "b751850b1a57168a5693cd924b6b096e08f621827444f70d884f5d0240d2712e10e116e9192af3c91a7ec57647e3934057340b4cf408d5a56592f8274eec53f0"),
+    vector(Algorithm::Sha3_512, MILLION_A, "3c3a876da14034ab60627c077bb98f7e120a2a5370212dffb3385a18d4f38859ed311d0a9d5141ce9cc5c66ee689b266a8aa18ace8282a0e0db596c90b0a7b87"),
+    vector(Algorithm::Sha256, EMPTY, "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"),
+    vector(Algorithm::Sha256, ABC, "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"),
+    vector(Algorithm::Sha256, Input::Bytes(b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq"), "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1"),
+    vector(Algorithm::Sha256, MILLION_A, "cdc76e5c9914fb9281a1c7e284d73e67f1809a48a497200e046d39ccc7112cd0"),
+    vector(Algorithm::Sha512, EMPTY, "cf83e1357eefb8bdf1542850d66d8007d620e4050b5715dc83f4a921d36ce9ce47d0d13c5d85f2b0ff8318d2877eec2f63b931bd47417a81a538327af927da3e"),
+    vector(Algorithm::Sha512, ABC, "ddaf35a193617abacc417349ae20413112e6fa4e89a97ea20a9eeee64b55d39a2192992a274fc1a836ba3c23a3feebbd454d4423643ce80e2a9ac94fa54ca49f"),
+    vector(Algorithm::Sha512, MILLION_A, "e718483d0ce769644e2e42c7bc15b4638e1f98b13b2044285632a803afa973ebde0ff244877ea60a4cb0432ce577c31beb009c5c2c49aa2e4eadb217ad8cc09b"),
+    vector(Algorithm::Blake3, Input::Pattern(0), "af1349b9f5f9a1a6a0404dea36dcc9499bcb25c9adc112b7cc9a93cae41f3262"),
+    vector(Algorithm::Blake3, Input::Pattern(1), "2d3adedff11b61f14c886e35afa036736dcd87a74d27b5c1510225d0f592e213"),
+    vector(Algorithm::Blake3, Input::Pattern(1024), "42214739f095a406f3fc83deb889744ac00df831c10daa55189b5d121c855af7"),
+    vector(Algorithm::Blake3, Input::Pattern(1025), "d00278ae47eb27b34faecf67b4fe263f82d5412916c1ffd97c8cb7fb814b8444"),
+    vector(Algorithm::Blake3, Input::Pattern(2048), "e776b6028c7cd22a4d0ba182a8bf62205d2ef576467e838ed6f2529b85fba24a"),
+    vector(Algorithm::Ed2k, EMPTY, "31d6cfe0d16ae931b73c59d7e0c089c0"),
+    vector(Algorithm::Ed2k, ABC, "a448017aaf21d8525fc10ae87aa6729d"),
+    vector(Algorithm::Tth, EMPTY, "5d9ed00a030e638bdb753a6a24fb900e5a63b8e73e6c25b6"),
+    vector(Algorithm::Tth, Input::Repeat(b'A', 1024), "5fbd0e62ad016d596b77d1d28883b94fed78ecbaf4640914"),
+];
+
+const JEFE: Input = Input::Bytes(b"what do ya want for nothing?");
+const ELVISH: &[u8] = b"whats the Elvish word for friend";
+
+/// Keys and what each algorithm makes of a message with them: RFC 4231's
+/// second HMAC case, run through every HMAC, and the BLAKE3 reference
+/// vectors' keyed hashes
+const KEYED: &[(&[u8], Vector)] = &[
+    (b"Jefe", vector(Algorithm::Sha256, JEFE, "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843")),
+    (b"Jefe", vector(Algorithm::Sha512, JEFE, "164b7a7bfcf819e2e395fbe73b56e0a387bd64222e831fd610270cd7ea2505549758bf75c05a994a6d034f65f8f0e6fdcaeab1a34d4a6b4b636e070a38bce737")),
+    (b"Jefe", vector(Algorithm::Sha3_256, JEFE, "c7d4072e788877ae3596bbb0da73b887c9171f93095b294ae857fbe2645e1ba5")),
+    (b"Jefe", vector(Algorithm::Sha3_512, JEFE, "5a4bfeab6166427c7a3647b747292b8384537cdb89afb3bf5665e4c5e709350b287baec921fd7ca0ee7a0c31d022a95e1fc92ba9d77df883960275beb4e62024")),
+    (ELVISH, vector(Algorithm::Blake3, Input::Pattern(0), "92b2b75604ed3c761f9d6f62392c8a9227ad0ea3f09573e783f1498a4ed60d26")),
+    (ELVISH, vector(Algorithm::Blake3, Input::Pattern(1), "6d7878dfff2f485635d39013278ae14f1454b8c0a3a2d34bc1ab38228a80c95b")),
+];
+
+/// One thing the self-test looked at
+#[derive(Debug)]
+pub struct Check {
+    pub name: String,
+    /// What went wrong, if anything did
+    pub failure: Option<String>,
+}
+
+/// Runs every check, passed or not
+pub async fn run() -> Vec<Check> {
+    let mut checks = Vec::new();
+    for (i, vector) in VECTORS.iter().enumerate() {
+        let input = vector.input.bytes();
+        let name = format!("{} {}", vector.algorithm.name(), vector.input.describe());
+
+        let mut hasher = vector.algorithm.hasher();
+        hasher.update(&input);
+        checks.push(Check {
+            failure: mismatch(vector.digest, &hasher.finalize()),
+            name: name.clone(),
+        });
+
+        checks.push(Check {
+            failure: match through_reader(vector.algorithm, &input, i as u64).await {
+                Ok(hash) => mismatch(vector.digest, &hash),
+                Err(e) => Some(e.to_string()),
+            },
+            name: format!("{}, read in short pieces", name),
+        });
+    }
+
+    for (key, vector) in KEYED {
+        let name = format!(
+            "{} keyed with {:?}, {}",
+            vector.algorithm.name(),
+            String::from_utf8_lossy(key),
+            vector.input.describe()
+        );
+        checks.push(Check {
+            failure: match vector.algorithm.keyed_hasher(key) {
+                Ok(mut hasher) => {
+                    hasher.update(&vector.input.bytes());
+                    mismatch(vector.digest, &hasher.finalize())
+                }
+                Err(e) => Some(e.to_string()),
+            },
+            name,
+        });
+    }
+
+    let reference = Input::Pattern(100_000).bytes();
+    checks.push(Check {
+        name: "writer, in short pieces".to_owned(),
+        failure: match through_writer(&reference).await {
+            Ok(written) if written == *reference => None,
+            Ok(written) => Some(format!(
+                "wrote {} bytes that differ from the {} given",
+                written.len(),
+                reference.len()
+            )),
+            Err(e) => Some(e.to_string()),
+        },
+    });
+    checks
+}
+
+fn mismatch(expected: &str, got: &[u8]) -> Option<String> {
+    let got = hex(got);
+    (got != expected).then(|| format!("expected {}, got {}", expected, got))
+}
+
+/// Hashes `input` through the reader stack, with half the reads cut short
+async fn through_reader(algorithm: Algorithm, input: &[u8], seed: u64) -> io::Result<Vec<u8>> {
+    let path = Path::new("self-test");
+    let faults = Faults {
+        short: 0.5,
+        ..Default::default()
+    };
+    let inner = TracingReader::new(
+        futures::io::Cursor::new(input.to_vec()),
+        Position::new(path),
+    );
+    let mut reader = SimpleAsyncReader::with_capacity(
+        FaultInjectingReader::new(inner, faults, seed, path),
+        Position::new(path),
+        4096,
+    );
+    let mut hasher = algorithm.hasher();
+    // odd-sized reads, so they straddle blocks and the reader's buffer
+    let mut buf = vec![0u8; 1031];
+    loop {
+        let n = reader.read(&mut buf).await?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+    Ok(hasher.finalize())
+}
+
+/// Takes at most this much per write
+struct ShortSink(Vec<u8>);
+
+#[async_trait]
+impl SimpleWrite for ShortSink {
+    async fn simple_write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = buf.len().min(1000);
+        self.0.extend_from_slice(&buf[..n]);
+        Ok(n)
+    }
+}
+
+/// What comes out of the writer adapter when `input` goes in
+async fn through_writer(input: &[u8]) -> io::Result<Vec<u8>> {
+    let mut writer = SimpleAsyncWriter::new(ShortSink(Vec::new()));
+    for piece in input.chunks(4099) {
+        writer.write_all(piece).await?;
+    }
+    writer.close().await?;
+    Ok(writer.into_inner().map(|sink| sink.0).unwrap_or_default())
+}
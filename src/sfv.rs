@@ -0,0 +1,49 @@
+//! Simple File Verification (`.sfv`) files: one `<filename> <CRC32>` line per
+//! file, with `;` starting comment lines.
+
+use color_eyre::eyre::{self, eyre};
+use std::io::Write;
+
+/// One file listed in an SFV file
+#[derive(Debug, Clone)]
+pub struct Entry {
+    pub path: String,
+    pub crc: u32,
+}
+
+pub fn parse(input: &str) -> Result<Vec<Entry>, eyre::Error> {
+    let mut entries = Vec::new();
+    for (i, line) in input.lines().enumerate() {
+        let line = line.trim_end();
+        if line.is_empty() || line.starts_with(';') {
+            continue;
+        }
+        // filenames may contain spaces, the checksum is always the last word
+        let (path, crc) = line
+            .rsplit_once([' ', '\t'])
+            .ok_or_else(|| eyre!("line {}: expected `<filename> <CRC32>`", i + 1))?;
+        if crc.len() != 8 {
+            return Err(eyre!("line {}: {:?} is not a CRC32", i + 1, crc));
+        }
+        let crc = u32::from_str_radix(crc, 16)
+            .map_err(|_| eyre!("line {}: {:?} is not a CRC32", i + 1, crc))?;
+        entries.push(Entry {
+            path: path.trim_end().to_string(),
+            crc,
+        });
+    }
+    Ok(entries)
+}
+
+pub fn write(entries: &[Entry], out: &mut impl Write) -> Result<(), eyre::Error> {
+    writeln!(
+        out,
+        "; Generated by surviving {}",
+        env!("CARGO_PKG_VERSION")
+    )?;
+    writeln!(out, ";")?;
+    for entry in entries {
+        writeln!(out, "{} {:08X}", entry.path, entry.crc)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,125 @@
+//! Splitting output into one manifest per directory, so huge trees can be
+//! verified and diffed piecewise.
+//!
+//! Each manifest is written under a temporary name and renamed into place
+//! once every file of its shard has a result, so a manifest that exists is
+//! always complete — even while the run goes on, or after it crashed.
+
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::{Component, Path, PathBuf},
+};
+
+/// Name of the manifest written in each shard's directory
+pub const SHARD_FILE: &str = "surviving.manifest";
+
+/// Suffix of manifests that are still being written
+const PARTIAL_SUFFIX: &str = ".partial";
+
+/// Open manifests, keyed by directory prefix
+pub struct Shards {
+    dir: PathBuf,
+    depth: usize,
+    files: HashMap<PathBuf, BufWriter<File>>,
+    /// How many results each unfinished shard is still waiting for
+    remaining: HashMap<PathBuf, usize>,
+}
+
+impl Shards {
+    /// Shards `inputs` by their first `depth` directories, writing the
+    /// manifests under `dir`
+    pub fn new(dir: PathBuf, depth: usize, inputs: &[PathBuf]) -> Self {
+        let mut shards = Self {
+            dir,
+            depth,
+            files: Default::default(),
+            remaining: Default::default(),
+        };
+        for path in inputs {
+            *shards.remaining.entry(shards.key(path)).or_default() += 1;
+        }
+        shards
+    }
+
+    /// The shard `path` belongs to: its first `depth` directories, or all of
+    /// them for shallower paths
+    pub fn key(&self, path: &Path) -> PathBuf {
+        let parent = path.parent().unwrap_or_else(|| Path::new(""));
+        parent
+            .components()
+            .filter(|c| matches!(c, Component::Normal(_)))
+            .take(self.depth)
+            .collect()
+    }
+
+    /// Where the manifest for `key` goes
+    pub fn manifest_path(&self, key: &Path) -> PathBuf {
+        self.dir.join(key).join(SHARD_FILE)
+    }
+
+    fn partial_path(&self, key: &Path) -> PathBuf {
+        let mut name = self.manifest_path(key).into_os_string();
+        name.push(PARTIAL_SUFFIX);
+        name.into()
+    }
+
+    fn file(&mut self, key: &Path) -> io::Result<&mut BufWriter<File>> {
+        if !self.files.contains_key(key) {
+            let partial = self.partial_path(key);
+            std::fs::create_dir_all(partial.parent().unwrap())?;
+            self.files
+                .insert(key.to_owned(), BufWriter::new(File::create(partial)?));
+        }
+        Ok(self.files.get_mut(key).unwrap())
+    }
+
+    /// Appends a line for `path` to its shard's manifest
+    pub fn write(&mut self, path: &Path, line: &str) -> io::Result<()> {
+        let key = self.key(path);
+        self.file(&key)?.write_all(line.as_bytes())
+    }
+
+    /// Records that `path` has its result, whether or not a line was written
+    /// for it, and publishes its shard if that was the last one
+    pub fn done(&mut self, path: &Path) -> io::Result<()> {
+        let key = self.key(path);
+        let remaining = match self.remaining.get_mut(&key) {
+            Some(remaining) => remaining,
+            None => return Ok(()),
+        };
+        *remaining -= 1;
+        if *remaining == 0 {
+            self.remaining.remove(&key);
+            self.publish(&key)?;
+        }
+        Ok(())
+    }
+
+    /// Publishes every shard still open
+    pub fn finish(&mut self) -> io::Result<()> {
+        let keys: Vec<_> = self.remaining.drain().map(|(key, _)| key).collect();
+        for key in keys {
+            self.publish(&key)?;
+        }
+        Ok(())
+    }
+
+    fn publish(&mut self, key: &Path) -> io::Result<()> {
+        // shards where nothing was written still get an (empty) manifest,
+        // so its presence always means "done"
+        self.file(key)?;
+        let file = self.files.remove(key).unwrap();
+        let file = file.into_inner().map_err(|e| e.into_error())?;
+        file.sync_all()?;
+        drop(file);
+
+        let manifest = self.manifest_path(key);
+        std::fs::rename(self.partial_path(key), &manifest)?;
+        // make the rename itself durable
+        #[cfg(unix)]
+        File::open(manifest.parent().unwrap())?.sync_all()?;
+        Ok(())
+    }
+}
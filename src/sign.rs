@@ -0,0 +1,274 @@
+//! Signing manifests, for `--sign` and `--verify-signature`, so whoever
+//! checks one can tell it's the one its maker wrote. Keys and signatures
+//! are minisign's: Ed25519 over the manifest's BLAKE2b-512 digest, in a
+//! `.minisig` file next to it, which `minisign -V` verifies too, and keys
+//! `minisign -G -W` makes.
+
+use crate::{
+    blake2b::{self, Blake2b},
+    digest::{base64, unbase64},
+};
+use color_eyre::eyre::{self, eyre};
+use ring::signature::{self, Ed25519KeyPair};
+use std::{
+    convert::TryInto,
+    ffi::OsString,
+    fmt,
+    io::{self, Read},
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::Arc,
+};
+
+/// What signatures made over the whole manifest start with
+const LEGACY: &[u8; 2] = b"Ed";
+/// What signatures made over its BLAKE2b-512 digest start with
+const PREHASHED: &[u8; 2] = b"ED";
+/// The only checksum minisign keeps of secret keys
+const CHECKSUM: &[u8; 2] = b"B2";
+
+/// Where the signature of the manifest at `path` goes
+pub fn signature_path(path: &Path) -> PathBuf {
+    let mut name = OsString::from(path.as_os_str());
+    name.push(".minisig");
+    PathBuf::from(name)
+}
+
+/// The BLAKE2b-512 digest signatures are made over
+pub fn digest(mut reader: impl Read) -> io::Result<Vec<u8>> {
+    let mut hasher = Blake2b::new(64);
+    let mut buf = vec![0; 64 * 1024];
+    loop {
+        match reader.read(&mut buf)? {
+            0 => return Ok(hasher.finalize()),
+            n => hasher.update(&buf[..n]),
+        }
+    }
+}
+
+/// The base64 line of a key or signature file, after its untrusted comment
+fn payload<'a>(lines: &mut impl Iterator<Item = &'a str>) -> Result<Vec<u8>, eyre::Error> {
+    let line = lines
+        .map(str::trim)
+        .find(|line| !line.is_empty() && !line.starts_with("untrusted comment:"))
+        .ok_or_else(|| eyre!("it's empty"))?;
+    unbase64(line).ok_or_else(|| eyre!("{:?} isn't base64", line))
+}
+
+/// How minisign shows key IDs
+fn key_id(id: &[u8; 8]) -> String {
+    format!("{:016X}", u64::from_le_bytes(*id))
+}
+
+/// A minisign secret key, read from its file, which mustn't have a
+/// password
+#[derive(Clone)]
+pub struct SecretKey {
+    id: [u8; 8],
+    pair: Arc<Ed25519KeyPair>,
+}
+
+/// Never shows the key itself
+impl fmt::Debug for SecretKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "SecretKey({})", key_id(&self.id))
+    }
+}
+
+impl FromStr for SecretKey {
+    type Err = eyre::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let text = std::fs::read_to_string(s).map_err(|e| eyre!("can't read {}: {}", s, e))?;
+        Self::parse(&text).map_err(|e| eyre!("in {}: {}", s, e))
+    }
+}
+
+impl SecretKey {
+    /// The algorithms, the KDF's salt and limits, then the key ID, secret
+    /// key and checksum
+    fn parse(text: &str) -> Result<Self, eyre::Error> {
+        let bytes = payload(&mut text.lines())?;
+        if bytes.len() != 158 {
+            return Err(eyre!("expected a minisign secret key"));
+        }
+        let (algorithms, rest) = bytes.split_at(6);
+        if &algorithms[..2] != LEGACY || &algorithms[4..] != CHECKSUM {
+            return Err(eyre!("expected an Ed25519 key with a BLAKE2b checksum"));
+        }
+        if algorithms[2..4] != [0, 0] {
+            return Err(eyre!(
+                "the key is encrypted, which isn't supported: make one without a password, with `minisign -G -W`"
+            ));
+        }
+        let keynum = &rest[48..];
+        let (id, secret, checksum) = (&keynum[..8], &keynum[8..72], &keynum[72..]);
+        if blake2b::digest(32, &[&algorithms[..2], id, secret].concat()) != checksum {
+            return Err(eyre!("the key's checksum doesn't match, it's damaged"));
+        }
+        // libsodium keeps the seed, then the public key
+        let pair = Ed25519KeyPair::from_seed_and_public_key(&secret[..32], &secret[32..])
+            .map_err(|e| eyre!("the key is invalid: {}", e))?;
+        Ok(Self {
+            id: id.try_into().unwrap(),
+            pair: Arc::new(pair),
+        })
+    }
+
+    /// A signature file for the manifest whose BLAKE2b-512 digest is
+    /// `digest`, vouching for `trusted_comment` too
+    pub fn sign(&self, digest: &[u8], trusted_comment: &str) -> String {
+        let signature = self.pair.sign(digest);
+        let global = self
+            .pair
+            .sign(&[signature.as_ref(), trusted_comment.as_bytes()].concat());
+        format!(
+            "untrusted comment: signature from surviving secret key\n{}\ntrusted comment: {}\n{}\n",
+            base64(&[&PREHASHED[..], &self.id, signature.as_ref()].concat()),
+            trusted_comment,
+            base64(global.as_ref())
+        )
+    }
+}
+
+/// A minisign public key: the key file, or the base64 line in it
+#[derive(Debug, Clone)]
+pub struct PublicKey {
+    id: [u8; 8],
+    key: [u8; 32],
+}
+
+impl FromStr for PublicKey {
+    type Err = eyre::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (text, name) = if Path::new(s).is_file() {
+            let text = std::fs::read_to_string(s).map_err(|e| eyre!("can't read {}: {}", s, e))?;
+            (text, s)
+        } else {
+            (s.to_string(), "the public key")
+        };
+        let bytes = payload(&mut text.lines()).map_err(|e| eyre!("in {}: {}", name, e))?;
+        if bytes.len() != 42 || &bytes[..2] != LEGACY {
+            return Err(eyre!(
+                "expected a minisign public key file, or the key in base64"
+            ));
+        }
+        Ok(Self {
+            id: bytes[2..10].try_into().unwrap(),
+            key: bytes[10..].try_into().unwrap(),
+        })
+    }
+}
+
+impl PublicKey {
+    /// Checks the signature file `signature` against `manifest`, returning
+    /// its trusted comment
+    pub fn verify(&self, signature: &str, mut manifest: impl Read) -> Result<String, eyre::Error> {
+        let mut lines = signature.lines();
+        let bytes = payload(&mut lines)?;
+        if bytes.len() != 74 {
+            return Err(eyre!("expected a minisign signature"));
+        }
+        let (algorithm, id, sig) = (&bytes[..2], &bytes[2..10], &bytes[10..]);
+        if id != self.id {
+            return Err(eyre!(
+                "it was made with key {}, not {}",
+                key_id(id.try_into().unwrap()),
+                key_id(&self.id)
+            ));
+        }
+        let trusted_comment = lines
+            .next()
+            .and_then(|line| line.strip_prefix("trusted comment: "))
+            .ok_or_else(|| eyre!("it has no trusted comment"))?;
+        let global = payload(&mut lines)?;
+
+        let key = signature::UnparsedPublicKey::new(&signature::ED25519, &self.key);
+        let message = match algorithm {
+            _ if algorithm == PREHASHED => digest(manifest)?,
+            _ if algorithm == LEGACY => {
+                let mut message = Vec::new();
+                manifest.read_to_end(&mut message)?;
+                message
+            }
+            _ => return Err(eyre!("expected an Ed25519 signature")),
+        };
+        key.verify(&message, sig)
+            .map_err(|_| eyre!("the signature doesn't match the manifest"))?;
+        key.verify(&[sig, trusted_comment.as_bytes()].concat(), &global)
+            .map_err(|_| eyre!("the trusted comment was tampered with"))?;
+        Ok(trusted_comment.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Made with `minisign -G -W`, without a password
+    const SECRET: &str = "untrusted comment: minisign secret key
+RWQAAEIyAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA/W2BF0eJkhCEfY1AO7P5IyNHdz/0Lk7iL1W9QFYZ2wVcx3dprYy8LaSFpbyQ6R6bH2yt9QXUeuiscSwKC7ZB61vwKpxrDHzOmT63BpKIBWBzzb799CJG6C9bOmNmkmig0MpLw88xvsQ=
+";
+    const PUBLIC: &str = "RWT9bYEXR4mSEKSFpbyQ6R6bH2yt9QXUeuiscSwKC7ZB61vwKpxrDHzO";
+    const MANIFEST: &[u8] = b"a.txt 0123456789abcdef0123456789abcdef\n";
+
+    fn signed() -> String {
+        let key = SecretKey::parse(SECRET).unwrap();
+        key.sign(&digest(MANIFEST).unwrap(), "timestamp:1700000000")
+    }
+
+    #[test]
+    fn reads_minisign_keys() {
+        let secret = SecretKey::parse(SECRET).unwrap();
+        assert_eq!(format!("{:?}", secret), "SecretKey(1092894717816DFD)");
+        let public: PublicKey = PUBLIC.parse().unwrap();
+        assert_eq!(key_id(&public.id), "1092894717816DFD");
+        // the public half of the secret key is the public key
+        assert_eq!(
+            public.key.as_ref(),
+            ring::signature::KeyPair::public_key(&*secret.pair).as_ref()
+        );
+    }
+
+    #[test]
+    fn rejects_damaged_secret_keys() {
+        let damaged = SECRET.replace("mT63", "mT64");
+        let e = SecretKey::parse(&damaged).unwrap_err();
+        assert!(e.to_string().contains("checksum"), "{}", e);
+    }
+
+    #[test]
+    fn verifies_what_it_signs() {
+        let public: PublicKey = PUBLIC.parse().unwrap();
+        let comment = public.verify(&signed(), MANIFEST).unwrap();
+        assert_eq!(comment, "timestamp:1700000000");
+    }
+
+    #[test]
+    fn rejects_a_changed_manifest() {
+        let public: PublicKey = PUBLIC.parse().unwrap();
+        let e = public.verify(&signed(), &b"a.txt 00"[..]).unwrap_err();
+        assert!(
+            e.to_string().contains("doesn't match the manifest"),
+            "{}",
+            e
+        );
+    }
+
+    #[test]
+    fn rejects_a_changed_trusted_comment() {
+        let public: PublicKey = PUBLIC.parse().unwrap();
+        let forged = signed().replace("timestamp:1700000000", "timestamp:1800000000");
+        let e = public.verify(&forged, MANIFEST).unwrap_err();
+        assert!(e.to_string().contains("tampered"), "{}", e);
+    }
+
+    #[test]
+    fn rejects_other_keys() {
+        let mut public: PublicKey = PUBLIC.parse().unwrap();
+        public.id = [0; 8];
+        let e = public.verify(&signed(), MANIFEST).unwrap_err();
+        assert!(e.to_string().contains("1092894717816DFD"), "{}", e);
+    }
+}
@@ -0,0 +1,156 @@
+//! Taking filesystem snapshots for the length of a run, with each
+//! filesystem's own tools, so there's no need for a pair of hooks in the
+//! common cases.
+
+use crate::hooks::check;
+use color_eyre::eyre::{self, eyre};
+use std::{path::PathBuf, process::Command, str::FromStr};
+
+/// A snapshot to take before hashing and drop afterwards
+#[derive(Debug, Clone)]
+pub enum Snapshot {
+    /// `btrfs:SOURCE:DEST`, a read-only snapshot of the subvolume `SOURCE`
+    /// at `DEST`
+    Btrfs { source: PathBuf, dest: PathBuf },
+    /// `zfs:DATASET@NAME`, readable below the dataset's mountpoint in
+    /// `.zfs/snapshot/NAME`
+    Zfs { name: String },
+    /// `lvm:VG/LV:SIZE:MOUNTPOINT`, a snapshot volume `LV-surviving` with
+    /// room for `SIZE` of changes, mounted read-only at `MOUNTPOINT`
+    Lvm {
+        volume: String,
+        size: String,
+        mountpoint: PathBuf,
+    },
+}
+
+impl FromStr for Snapshot {
+    type Err = eyre::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let bad = || {
+            eyre!(
+                "expected `btrfs:SOURCE:DEST`, `zfs:DATASET@NAME` or \
+                 `lvm:VG/LV:SIZE:MOUNTPOINT`, got {:?}",
+                s
+            )
+        };
+        let (kind, rest) = s.split_once(':').ok_or_else(bad)?;
+        match kind {
+            "btrfs" => {
+                let (source, dest) = rest.split_once(':').ok_or_else(bad)?;
+                Ok(Self::Btrfs {
+                    source: source.into(),
+                    dest: dest.into(),
+                })
+            }
+            "zfs" if rest.contains('@') => Ok(Self::Zfs { name: rest.into() }),
+            "lvm" => match rest.splitn(3, ':').collect::<Vec<_>>()[..] {
+                [volume, size, mountpoint] if volume.contains('/') => Ok(Self::Lvm {
+                    volume: volume.into(),
+                    size: size.into(),
+                    mountpoint: mountpoint.into(),
+                }),
+                _ => Err(bad()),
+            },
+            _ => Err(bad()),
+        }
+    }
+}
+
+impl Snapshot {
+    /// The snapshot volume, for LVM
+    fn snapshot_volume(volume: &str) -> String {
+        format!("{}-surviving", volume)
+    }
+
+    pub fn create(&self) -> Result<(), eyre::Error> {
+        tracing::info!(snapshot = ?self, "taking snapshot");
+        match self {
+            Self::Btrfs { source, dest } => check(
+                Command::new("btrfs")
+                    .args(["subvolume", "snapshot", "-r"])
+                    .arg(source)
+                    .arg(dest),
+            ),
+            Self::Zfs { name } => check(Command::new("zfs").arg("snapshot").arg(name)),
+            Self::Lvm {
+                volume,
+                size,
+                mountpoint,
+            } => {
+                let (group, name) = volume.split_once('/').unwrap();
+                check(
+                    Command::new("lvcreate")
+                        .args(["--snapshot", "--size", size, "--name"])
+                        .arg(Self::snapshot_volume(name))
+                        .arg(volume),
+                )?;
+                let device = format!("/dev/{}/{}", group, Self::snapshot_volume(name));
+                let mounted = check(
+                    Command::new("mount")
+                        .args(["-o", "ro", &device])
+                        .arg(mountpoint),
+                );
+                if mounted.is_err() {
+                    let _ = check(Command::new("lvremove").args(["-y", &device]));
+                }
+                mounted
+            }
+        }
+    }
+
+    pub fn destroy(&self) -> Result<(), eyre::Error> {
+        tracing::info!(snapshot = ?self, "dropping snapshot");
+        match self {
+            Self::Btrfs { dest, .. } => check(
+                Command::new("btrfs")
+                    .args(["subvolume", "delete"])
+                    .arg(dest),
+            ),
+            Self::Zfs { name } => check(Command::new("zfs").arg("destroy").arg(name)),
+            Self::Lvm {
+                volume, mountpoint, ..
+            } => {
+                let (group, name) = volume.split_once('/').unwrap();
+                check(Command::new("umount").arg(mountpoint))?;
+                check(
+                    Command::new("lvremove")
+                        .args(["-y", &format!("{}/{}", group, Self::snapshot_volume(name))]),
+                )
+            }
+        }
+    }
+}
+
+/// Takes every snapshot in order, runs `f`, then drops them in reverse,
+/// whether `f` succeeded or not. Failures to drop one are printed, since
+/// it's left behind, and returned unless `f` failed first.
+pub fn around(
+    snapshots: &[Snapshot],
+    f: impl FnOnce() -> Result<(), eyre::Error>,
+) -> Result<(), eyre::Error> {
+    let mut taken = Vec::new();
+    let mut res = Ok(());
+    for snapshot in snapshots {
+        match snapshot.create() {
+            Ok(()) => taken.push(snapshot),
+            Err(e) => {
+                res = Err(e);
+                break;
+            }
+        }
+    }
+    if res.is_ok() {
+        res = f();
+    }
+    for snapshot in taken.into_iter().rev() {
+        if let Err(e) = snapshot.destroy() {
+            eprintln!("Couldn't drop snapshot {:?}: {}", snapshot, e);
+            if res.is_ok() {
+                res = Err(e);
+            }
+        }
+    }
+    res
+}
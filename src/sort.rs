@@ -0,0 +1,241 @@
+//! Sorting the output, for `--sort`, in bounded memory: results are kept
+//! until there are `--sort-memory` bytes of them, sorted, and spilled to a
+//! temporary file, and the files are merged once every result is in. A
+//! manifest of hundreds of millions of files comes out in digest order
+//! with only one batch, and a record of each spill, in memory at a time.
+
+use color_eyre::eyre::{self, eyre};
+use std::{
+    cmp::Reverse,
+    collections::BinaryHeap,
+    convert::TryFrom,
+    fs::File,
+    io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+/// How much output is kept in memory without `--sort-memory`
+pub const DEFAULT_MEMORY: u64 = 256 << 20;
+
+/// How many spills are merged at once; past that they're merged into one
+/// first, so a run never holds more files open
+const FAN_IN: usize = 64;
+
+/// What each result costs besides its key and output, for the vector and
+/// the two allocations
+const OVERHEAD: usize = 64;
+
+/// What `--sort` orders results by
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum By {
+    /// The digest, then the path, so files with the same contents sit
+    /// together, and failures last
+    Hash,
+    Path,
+}
+
+impl By {
+    /// What `path`, hashed to `digest` or not at all, sorts as
+    pub fn key(self, path: &Path, digest: Option<&[u8]>) -> Vec<u8> {
+        let path = path.display().to_string().into_bytes();
+        match (self, digest) {
+            (Self::Path, _) => path,
+            (Self::Hash, Some(digest)) => [&[0], digest, &path].concat(),
+            (Self::Hash, None) => [&[1], &path[..]].concat(),
+        }
+    }
+}
+
+impl FromStr for By {
+    type Err = eyre::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "hash" => Ok(Self::Hash),
+            "path" => Ok(Self::Path),
+            _ => Err(eyre!("expected `hash` or `path`, got {:?}", s)),
+        }
+    }
+}
+
+/// Results waiting to be written in order, each as its key and the output
+/// it was formatted to
+pub struct Sorter {
+    pub by: By,
+    memory: usize,
+    /// Where spills go
+    dir: PathBuf,
+    batch: Vec<(Vec<u8>, Vec<u8>)>,
+    /// What `batch` takes up, roughly
+    held: usize,
+    spills: Vec<Run>,
+}
+
+impl Sorter {
+    /// Keeping up to `memory` bytes of output before spilling it to a file
+    /// under `dir`
+    pub fn new(by: By, memory: u64, dir: PathBuf) -> Self {
+        Self {
+            by,
+            memory: usize::try_from(memory).unwrap_or(usize::MAX),
+            dir,
+            batch: Vec::new(),
+            held: 0,
+            spills: Vec::new(),
+        }
+    }
+
+    pub fn push(&mut self, key: Vec<u8>, output: Vec<u8>) -> io::Result<()> {
+        self.held += key.len() + output.len() + OVERHEAD;
+        self.batch.push((key, output));
+        if self.held >= self.memory {
+            self.spill()?;
+        }
+        Ok(())
+    }
+
+    /// Sorts the batch into a spill of its own
+    fn spill(&mut self) -> io::Result<()> {
+        if self.spills.len() >= FAN_IN {
+            let spills = std::mem::take(&mut self.spills);
+            let mut merged = Spill::create(&self.dir)?;
+            merge(spills, Vec::new(), |key, output| merged.write(key, output))?;
+            self.spills.push(merged.rewind()?);
+        }
+        let mut batch = std::mem::take(&mut self.batch);
+        self.held = 0;
+        // stable, so results with the same key stay in the order they came
+        batch.sort_by(|a, b| a.0.cmp(&b.0));
+        let mut spill = Spill::create(&self.dir)?;
+        for (key, output) in &batch {
+            spill.write(key, output)?;
+        }
+        tracing::debug!(
+            results = batch.len(),
+            spills = self.spills.len() + 1,
+            "spilled sorted output"
+        );
+        self.spills.push(spill.rewind()?);
+        Ok(())
+    }
+
+    /// Hands every result's output to `write`, in order
+    pub fn finish(mut self, mut write: impl FnMut(&[u8]) -> io::Result<()>) -> io::Result<()> {
+        let mut batch = std::mem::take(&mut self.batch);
+        batch.sort_by(|a, b| a.0.cmp(&b.0));
+        merge(std::mem::take(&mut self.spills), batch, |_, output| {
+            write(output)
+        })
+    }
+}
+
+/// Merges `runs` and the sorted `batch` into `write`, taking runs in the
+/// order they were made on a tie, and the batch last
+fn merge(
+    runs: Vec<Run>,
+    batch: Vec<(Vec<u8>, Vec<u8>)>,
+    mut write: impl FnMut(&[u8], &[u8]) -> io::Result<()>,
+) -> io::Result<()> {
+    let mut readers: Vec<_> = runs.iter().map(|run| BufReader::new(&run.file)).collect();
+    let mut batch = batch.into_iter();
+    let mut heap = BinaryHeap::new();
+    for (i, reader) in readers.iter_mut().enumerate() {
+        if let Some((key, output)) = read(reader)? {
+            heap.push(Reverse((key, i, output)));
+        }
+    }
+    if let Some((key, output)) = batch.next() {
+        heap.push(Reverse((key, runs.len(), output)));
+    }
+    while let Some(Reverse((key, i, output))) = heap.pop() {
+        write(&key, &output)?;
+        let next = match readers.get_mut(i) {
+            Some(reader) => read(reader)?,
+            None => batch.next(),
+        };
+        if let Some((key, output)) = next {
+            heap.push(Reverse((key, i, output)));
+        }
+    }
+    Ok(())
+}
+
+/// The next key and output in a run, if it has more
+fn read(reader: &mut impl Read) -> io::Result<Option<(Vec<u8>, Vec<u8>)>> {
+    let mut field = |first: bool| -> io::Result<Option<Vec<u8>>> {
+        let mut len = [0; 4];
+        match reader.read_exact(&mut len) {
+            Err(e) if first && e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            res => res?,
+        }
+        let mut bytes = vec![0; u32::from_le_bytes(len) as usize];
+        reader.read_exact(&mut bytes)?;
+        Ok(Some(bytes))
+    };
+    let key = match field(true)? {
+        Some(key) => key,
+        None => return Ok(None),
+    };
+    let output = field(false)?.ok_or_else(|| io::Error::from(io::ErrorKind::UnexpectedEof))?;
+    Ok(Some((key, output)))
+}
+
+/// A run being written to a temporary file, each result as its key's
+/// length and key, then its output's length and output
+struct Spill(BufWriter<File>, PathBuf);
+
+impl Spill {
+    fn create(dir: &Path) -> io::Result<Self> {
+        use std::sync::atomic::{AtomicU64, Ordering};
+        static NEXT: AtomicU64 = AtomicU64::new(0);
+
+        let name = format!(
+            "surviving-sort-{}-{}",
+            std::process::id(),
+            NEXT.fetch_add(1, Ordering::Relaxed)
+        );
+        let path = dir.join(name);
+        let file = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(&path)?;
+        // gone as soon as it's closed, even if the run is killed
+        #[cfg(unix)]
+        std::fs::remove_file(&path)?;
+        Ok(Self(BufWriter::new(file), path))
+    }
+
+    fn write(&mut self, key: &[u8], output: &[u8]) -> io::Result<()> {
+        for bytes in [key, output] {
+            let len = u32::try_from(bytes.len())
+                .map_err(|_| io::Error::other("a result is too large to sort"))?;
+            self.0.write_all(&len.to_le_bytes())?;
+            self.0.write_all(bytes)?;
+        }
+        Ok(())
+    }
+
+    /// Done writing, back at the start for reading
+    fn rewind(self) -> io::Result<Run> {
+        let Self(file, path) = self;
+        let mut file = file.into_inner().map_err(|e| e.into_error())?;
+        file.seek(SeekFrom::Start(0))?;
+        Ok(Run { file, path })
+    }
+}
+
+/// A sorted run, spilled
+struct Run {
+    file: File,
+    path: PathBuf,
+}
+
+impl Drop for Run {
+    fn drop(&mut self) {
+        // on Unix, it was removed when it was created
+        #[cfg(not(unix))]
+        std::fs::remove_file(&self.path).ok();
+    }
+}
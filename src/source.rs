@@ -0,0 +1,274 @@
+//! Where the bytes being hashed come from, for `--backend`: files read a
+//! buffer at a time, or mapped into memory and copied out of the page
+//! cache without a system call or a trip to a blocking thread per read.
+//! Everything above the source, from `--progress` to `--inject-faults`,
+//! sees the same reads either way.
+
+use crate::{open, remote, retry, salvage, HashOptions};
+use async_std::fs::File;
+use color_eyre::eyre::{self, eyre};
+use futures::io::{AsyncRead, AsyncSeek, AsyncSeekExt};
+use std::{
+    convert::TryFrom,
+    io::{self, SeekFrom},
+    path::Path,
+    pin::Pin,
+    str::FromStr,
+    task::{Context, Poll},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Backend {
+    /// `read` calls into a buffer
+    #[default]
+    Read,
+    /// `mmap`, for files; stdin and URLs are still read
+    Mmap,
+}
+
+impl FromStr for Backend {
+    type Err = eyre::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "read" => Ok(Self::Read),
+            "mmap" => Ok(Self::Mmap),
+            _ => Err(eyre!("expected `read` or `mmap`, got {:?}", s)),
+        }
+    }
+}
+
+/// An input, opened
+pub struct ByteSource {
+    pub reader: Box<dyn AsyncRead + Send + Unpin>,
+    /// For files, as they were once opened
+    pub metadata: Option<std::fs::Metadata>,
+    /// For URLs, the size the response gave, if it did
+    pub len: Option<u64>,
+}
+
+impl ByteSource {
+    /// Opens `path` with the backend `options` ask for. With `--salvage` or
+    /// a `--resume-state` directory, files seek to where `resume` says
+    /// before their next read. With `--retries`, files that are read are
+    /// reopened to retry a failed read.
+    pub async fn open(
+        path: &Path,
+        options: &HashOptions,
+        resume: &salvage::Resume,
+    ) -> Result<Self, eyre::Error> {
+        // stdin's size isn't known up front, and nothing can change under it
+        // and a URL's only from its response, if at all
+        if open::is_stdin(path) {
+            return Ok(Self {
+                reader: Box::new(async_std::io::stdin()),
+                metadata: None,
+                len: None,
+            });
+        }
+        if remote::is_url(path) {
+            let (body, len) = remote::fetch(path).await?;
+            return Ok(Self {
+                reader: Box::new(body),
+                metadata: None,
+                len,
+            });
+        }
+        let file = open_file(path, options).await?;
+        let metadata = file.metadata().await?;
+        let reader: Box<dyn AsyncRead + Send + Unpin> = match options.backend {
+            Backend::Mmap => Box::new(Mapped::new(file, metadata.len())?),
+            Backend::Read => match options.retry {
+                Some(policy) => {
+                    let reopen = reopen(path, options, &metadata);
+                    let file = retry::RetryingReader::new(file, 0, policy, reopen);
+                    skipping(file, options, resume)
+                }
+                None => skipping(file, options, resume),
+            },
+        };
+        Ok(Self {
+            reader,
+            metadata: Some(metadata),
+            len: None,
+        })
+    }
+}
+
+/// Opens the file at `path` as `options` ask, locked and read ahead if
+/// they say so
+pub async fn open_file(path: &Path, options: &HashOptions) -> Result<File, eyre::Error> {
+    let file = match &options.dirs {
+        Some(dirs) => dirs.open(path, options.noatime).await?,
+        None => open::open(path, options.noatime).await?,
+    };
+    if options.changing_files == open::Changing::Lock {
+        open::lock_shared(&file).await?;
+    }
+    if options.populate_cache {
+        // only a hint, reading works as well without it
+        if let Err(e) = open::will_need(&file) {
+            tracing::debug!(path = %path.display(), error = %e, "can't read ahead");
+        }
+    }
+    Ok(file)
+}
+
+/// `file`, seeking to where `resume` says first if it might have to
+fn skipping<F>(
+    file: F,
+    options: &HashOptions,
+    resume: &salvage::Resume,
+) -> Box<dyn AsyncRead + Send + Unpin>
+where
+    F: AsyncRead + AsyncSeek + Send + Unpin + 'static,
+{
+    if options.salvage || options.resume_dir.is_some() {
+        Box::new(salvage::Skipping::new(file, resume.clone()))
+    } else {
+        Box::new(file)
+    }
+}
+
+/// Opens `path` again the way it was first, at an offset, for a retry. A
+/// file that was replaced since isn't the one being hashed, so it fails.
+fn reopen(path: &Path, options: &HashOptions, metadata: &std::fs::Metadata) -> retry::Reopen<File> {
+    let path = path.to_owned();
+    let dirs = options.dirs.clone();
+    let noatime = options.noatime;
+    let lock = options.changing_files == open::Changing::Lock;
+    #[cfg(unix)]
+    let id = {
+        use std::os::unix::fs::MetadataExt;
+        (metadata.dev(), metadata.ino())
+    };
+    Box::new(move |offset| {
+        let path = path.clone();
+        let dirs = dirs.clone();
+        Box::pin(async move {
+            let mut file = match &dirs {
+                Some(dirs) => dirs.open(&path, noatime).await?,
+                None => open::open(&path, noatime).await?,
+            };
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::MetadataExt;
+                let metadata = file.metadata().await?;
+                if (metadata.dev(), metadata.ino()) != id {
+                    return Err(io::Error::other("replaced since it was opened"));
+                }
+            }
+            if lock {
+                open::lock_shared(&file).await?;
+            }
+            file.seek(SeekFrom::Start(offset)).await?;
+            Ok(file)
+        })
+    })
+}
+
+/// A file mapped whole, read by copying out of the mapping
+pub struct Mapped {
+    map: Map,
+    offset: usize,
+    /// Kept open for as long as it's read, for `--changing-files lock`
+    _file: File,
+}
+
+impl Mapped {
+    pub fn new(file: File, len: u64) -> io::Result<Self> {
+        let len = usize::try_from(len)
+            .map_err(|_| io::Error::other("too large to map on this platform"))?;
+        Ok(Self {
+            map: Map::new(&file, len)?,
+            offset: 0,
+            _file: file,
+        })
+    }
+}
+
+impl AsyncRead for Mapped {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let rest = &self.map.bytes()[self.offset..];
+        let n = rest.len().min(buf.len());
+        buf[..n].copy_from_slice(&rest[..n]);
+        self.offset += n;
+        Poll::Ready(Ok(n))
+    }
+}
+
+/// A read-only, private mapping. Bytes past the end of a file that shrank
+/// since it was mapped can't be read, and touching them is fatal, which is
+/// what `--changing-files lock` is for.
+struct Map {
+    ptr: *const u8,
+    len: usize,
+}
+
+// SAFETY: the mapping is read-only and owned by this value alone
+unsafe impl Send for Map {}
+
+impl Map {
+    #[cfg(unix)]
+    fn new(file: &File, len: usize) -> io::Result<Self> {
+        use std::os::unix::io::AsRawFd;
+
+        // there's nothing to map, and mmap refuses a length of zero
+        if len == 0 {
+            return Ok(Self {
+                ptr: std::ptr::NonNull::dangling().as_ptr(),
+                len,
+            });
+        }
+        // SAFETY: a fresh mapping of an open descriptor, which the kernel
+        // places wherever it likes
+        let ptr = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                len,
+                libc::PROT_READ,
+                libc::MAP_PRIVATE,
+                file.as_raw_fd(),
+                0,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        // only a hint, the mapping works as well without it
+        // SAFETY: the range is the mapping just made
+        unsafe { libc::madvise(ptr, len, libc::MADV_SEQUENTIAL) };
+        Ok(Self {
+            ptr: ptr as *const u8,
+            len,
+        })
+    }
+
+    #[cfg(not(unix))]
+    fn new(_file: &File, _len: usize) -> io::Result<Self> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "--backend mmap is only supported on Unix",
+        ))
+    }
+
+    fn bytes(&self) -> &[u8] {
+        // SAFETY: `ptr` is valid for `len` bytes, or dangling with a length
+        // of zero, until the mapping is dropped
+        unsafe { std::slice::from_raw_parts(self.ptr, self.len) }
+    }
+}
+
+impl Drop for Map {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        if self.len > 0 {
+            // SAFETY: unmaps exactly what `new` mapped, once
+            unsafe { libc::munmap(self.ptr as *mut libc::c_void, self.len) };
+        }
+    }
+}
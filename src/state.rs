@@ -0,0 +1,250 @@
+//! Saving a hasher's internal state, so hashing can pick up where it left off
+//! — later, or on another machine.
+//!
+//! With a `--resume-state` directory, every file being hashed has its state
+//! saved there as it goes, so a file of hundreds of gigabytes whose read
+//! was cut short, by a flaky mount or an interrupt, continues from its last
+//! save on the next run rather than from the start. A save only holds for
+//! the same file, unchanged since, and is removed once the file is hashed.
+
+use crate::{
+    algo::{Algorithm, Hasher, Update},
+    cache::Stamp,
+};
+use color_eyre::eyre::{self, eyre};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+/// A snapshot of a hasher after it has consumed `offset` bytes
+#[derive(Serialize, Deserialize)]
+pub struct HasherState {
+    pub algorithm: String,
+    pub offset: u64,
+    /// The serialized hasher, in hex
+    pub state: String,
+}
+
+impl HasherState {
+    /// Fails for algorithms whose state can't be saved
+    pub fn capture(
+        hasher: &Hasher,
+        algorithm: Algorithm,
+        offset: u64,
+    ) -> Result<Self, eyre::Error> {
+        let state = hasher
+            .serialize()
+            .ok_or_else(|| eyre!("{}'s state can't be saved", algorithm))?;
+        Ok(Self {
+            algorithm: algorithm.name().to_string(),
+            offset,
+            state: crate::hex(&state),
+        })
+    }
+
+    /// The algorithm the state was saved by
+    pub fn algorithm(&self) -> Result<Algorithm, eyre::Error> {
+        self.algorithm.parse().map_err(|_| {
+            eyre!(
+                "state was saved by {}, which isn't built in",
+                self.algorithm
+            )
+        })
+    }
+
+    pub fn restore(&self, algorithm: Algorithm) -> Result<Hasher, eyre::Error> {
+        if self.algorithm != algorithm.name() {
+            return Err(eyre!(
+                "state was saved by {}, which can't be resumed as {}",
+                self.algorithm,
+                algorithm
+            ));
+        }
+        let bytes =
+            crate::unhex(&self.state).ok_or_else(|| eyre!("hasher state is not valid hex"))?;
+        Hasher::deserialize(algorithm, &bytes).ok_or_else(|| eyre!("hasher state is corrupt"))
+    }
+
+    pub async fn load(path: &Path) -> Result<Self, eyre::Error> {
+        let json = async_std::fs::read_to_string(path).await?;
+        Ok(serde_json::from_str(&json)?)
+    }
+
+    pub async fn save(&self, path: &Path) -> Result<(), eyre::Error> {
+        let json = serde_json::to_string_pretty(self)?;
+        async_std::fs::write(path, json + "\n").await?;
+        Ok(())
+    }
+}
+
+/// How often a file's state is saved without `--state-every`
+pub const DEFAULT_EVERY: u64 = 1 << 30;
+
+/// Where each file's state is saved, and how often
+pub struct ResumeDir {
+    pub dir: PathBuf,
+    pub every: u64,
+}
+
+/// A file's state, as saved in a [`ResumeDir`]
+#[derive(Serialize, Deserialize)]
+struct Saved {
+    path: String,
+    stamp: Stamp,
+    hasher: HasherState,
+}
+
+impl ResumeDir {
+    /// Where `path`'s state goes, named after the digest of its absolute
+    /// path, so any path fits and two files never share one
+    pub fn file(&self, path: &Path) -> PathBuf {
+        let path = std::path::absolute(path).unwrap_or_else(|_| path.to_owned());
+        let name = crate::hex(&<sha3::Sha3_256 as sha3::Digest>::digest(
+            path.display().to_string().as_bytes(),
+        ));
+        self.dir.join(name + ".json")
+    }
+
+    /// The hasher and offset saved for `path` in `file`, if it's still the
+    /// file that was saved, as it was then
+    pub async fn load(
+        &self,
+        file: &Path,
+        path: &Path,
+        stamp: &Stamp,
+        algorithm: Algorithm,
+    ) -> Option<(Hasher, u64)> {
+        let json = async_std::fs::read_to_string(file).await.ok()?;
+        let saved: Saved = match serde_json::from_str(&json) {
+            Ok(saved) => saved,
+            Err(e) => {
+                tracing::warn!(state = %file.display(), error = %e, "can't read saved state, starting over");
+                return None;
+            }
+        };
+        if saved.stamp != *stamp {
+            tracing::debug!(path = %path.display(), "changed since its state was saved, starting over");
+            return None;
+        }
+        if saved.hasher.algorithm != algorithm.name() {
+            tracing::debug!(path = %path.display(), algorithm = %saved.hasher.algorithm, "state saved for another algorithm, starting over");
+            return None;
+        }
+        let hasher = crate::unhex(&saved.hasher.state)
+            .and_then(|bytes| Hasher::deserialize(algorithm, &bytes));
+        match hasher {
+            Some(hasher) => {
+                tracing::debug!(path = %path.display(), offset = saved.hasher.offset, "resuming");
+                Some((hasher, saved.hasher.offset))
+            }
+            None => {
+                tracing::warn!(state = %file.display(), "saved state is corrupt, starting over");
+                None
+            }
+        }
+    }
+
+    /// Forgets the state saved in `file`, once its file is hashed
+    pub async fn done(&self, file: &Path) {
+        match async_std::fs::remove_file(file).await {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                tracing::warn!(state = %file.display(), error = %e, "can't remove saved state")
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Wraps a hasher that has consumed `offset` bytes of a file, saving its
+/// state to `file` every time another `every` bytes went through it
+pub struct Saving {
+    hasher: Hasher,
+    algorithm: Algorithm,
+    fed: u64,
+    every: u64,
+    file: PathBuf,
+    path: String,
+    stamp: Stamp,
+    /// Whether a save failed, so it's only warned about once
+    failed: bool,
+}
+
+impl Saving {
+    /// Fails for algorithms whose state can't be saved
+    pub fn new(
+        hasher: Hasher,
+        algorithm: Algorithm,
+        offset: u64,
+        resume: &ResumeDir,
+        file: PathBuf,
+        path: &Path,
+        stamp: Stamp,
+    ) -> Result<Self, eyre::Error> {
+        if hasher.serialize().is_none() {
+            return Err(eyre!("{}'s state can't be saved to resume from", algorithm));
+        }
+        Ok(Self {
+            hasher,
+            algorithm,
+            fed: offset,
+            every: resume.every,
+            file,
+            path: path.display().to_string(),
+            stamp,
+            failed: false,
+        })
+    }
+
+    pub fn finalize(self) -> Vec<u8> {
+        self.hasher.finalize()
+    }
+
+    /// Writes the state to a file next to `file` and renames it over, so
+    /// a save cut short leaves the last one whole. Blocking, but only once
+    /// every `every` bytes.
+    fn save(&mut self) {
+        let saved = Saved {
+            path: self.path.clone(),
+            stamp: self.stamp,
+            hasher: HasherState {
+                algorithm: self.algorithm.name().to_string(),
+                offset: self.fed,
+                state: crate::hex(&self.hasher.serialize().unwrap_or_default()),
+            },
+        };
+        let partial = self.file.with_extension("json.partial");
+        let res = serde_json::to_string_pretty(&saved)
+            .map_err(std::io::Error::from)
+            .and_then(|json| std::fs::write(&partial, json + "\n"))
+            .and_then(|()| std::fs::rename(&partial, &self.file));
+        match res {
+            Ok(()) => tracing::debug!(path = %self.path, offset = self.fed, "state saved"),
+            Err(e) if !self.failed => {
+                self.failed = true;
+                tracing::warn!(state = %self.file.display(), error = %e, "can't save state");
+            }
+            Err(_) => {}
+        }
+    }
+}
+
+impl Update for Saving {
+    fn update(&mut self, mut data: &[u8]) {
+        loop {
+            let until = self.every - self.fed % self.every;
+            if until > data.len() as u64 {
+                break;
+            }
+            let (before, after) = data.split_at(until as usize);
+            self.hasher.update(before);
+            self.fed += until;
+            self.save();
+            data = after;
+        }
+        self.fed += data.len() as u64;
+        self.hasher.update(data);
+    }
+
+    fn finish(&mut self) -> std::io::Result<()> {
+        self.hasher.finish()
+    }
+}
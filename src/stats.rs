@@ -0,0 +1,314 @@
+//! What `--stats` reports: how many bytes each file took, how many reads,
+//! how long and how fast, and the run's totals with the slowest file, as
+//! text to read or JSON to feed a dashboard. With `--report-by`, totals
+//! for each extension or top-level directory too, to tell what a share is
+//! full of.
+
+use crate::metrics::RunMetrics;
+use color_eyre::eyre::{self, eyre};
+use futures::io::AsyncRead;
+use serde::Serialize;
+use std::{
+    collections::BTreeMap,
+    fmt::Write as _,
+    io,
+    path::{Path, PathBuf},
+    pin::Pin,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    task::{Context, Poll},
+    time::Duration,
+};
+
+/// How the stats are printed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Text,
+    Json,
+}
+
+impl FromStr for Format {
+    type Err = eyre::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(Self::Text),
+            "json" => Ok(Self::Json),
+            _ => Err(eyre!("expected `text` or `json`, got {:?}", s)),
+        }
+    }
+}
+
+/// What `--report-by` groups files by
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum By {
+    /// Their extension, lowercased
+    Ext,
+    /// The directory right below the input they were found under
+    Dir,
+}
+
+impl By {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Ext => "ext",
+            Self::Dir => "dir",
+        }
+    }
+
+    /// The group `path` is in, found under one of `roots`
+    fn key(self, roots: &[PathBuf], path: &Path) -> String {
+        match self {
+            Self::Ext => match path.extension() {
+                Some(ext) => ext.to_string_lossy().to_lowercase(),
+                None => "(none)".to_string(),
+            },
+            Self::Dir => top_dir(roots, path).display().to_string(),
+        }
+    }
+}
+
+impl FromStr for By {
+    type Err = eyre::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ext" => Ok(Self::Ext),
+            "dir" => Ok(Self::Dir),
+            _ => Err(eyre!("expected `ext` or `dir`, got {:?}", s)),
+        }
+    }
+}
+
+/// The directory right below the root `path` was found under, or the root
+/// itself for files directly in it. Files given as inputs themselves are
+/// grouped under the directory they're in.
+fn top_dir(roots: &[PathBuf], path: &Path) -> PathBuf {
+    for root in roots {
+        let rest = match path.strip_prefix(root) {
+            Ok(rest) if !rest.as_os_str().is_empty() => rest,
+            _ => continue,
+        };
+        let mut components = rest.components();
+        return match (components.next(), components.next()) {
+            (Some(first), Some(_)) => root.join(first),
+            _ => root.clone(),
+        };
+    }
+    path.parent().map(Path::to_owned).unwrap_or_default()
+}
+
+/// The totals of the files in a group
+#[derive(Debug, Clone, Serialize)]
+pub struct Group {
+    pub key: String,
+    pub files: u64,
+    pub bytes: u64,
+    /// Spent on its files, which can overlap
+    pub seconds: f64,
+    pub mb_per_s: f64,
+}
+
+/// Counts the reads made of the reader it wraps, into a counter that
+/// outlives it
+pub struct ReadCounter<R> {
+    inner: R,
+    reads: Arc<AtomicU64>,
+}
+
+impl<R> ReadCounter<R> {
+    pub fn new(inner: R, reads: Arc<AtomicU64>) -> Self {
+        Self { inner, reads }
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for ReadCounter<R> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let res = Pin::new(&mut self.inner).poll_read(cx, buf);
+        // a read that's still pending hasn't been made yet
+        if res.is_ready() {
+            self.reads.fetch_add(1, Ordering::Relaxed);
+        }
+        res
+    }
+}
+
+/// `bytes` over `elapsed` in MB/s, or zero when no time was measured
+fn rate(bytes: u64, elapsed: Duration) -> f64 {
+    match elapsed.as_secs_f64() {
+        secs if secs > 0.0 => bytes as f64 / secs / 1e6,
+        _ => 0.0,
+    }
+}
+
+/// One file that hashed
+#[derive(Debug, Clone, Serialize)]
+pub struct File {
+    pub path: PathBuf,
+    pub bytes: u64,
+    pub reads: u64,
+    pub seconds: f64,
+    pub mb_per_s: f64,
+}
+
+/// Every file that hashed, in the order they were written
+#[derive(Debug, Default)]
+pub struct Stats {
+    files: Vec<File>,
+    /// With `--report-by`, what to group them by, and the inputs
+    by: Option<(By, Vec<PathBuf>)>,
+}
+
+impl Stats {
+    /// Stats that also total the files `by` something, `roots` being the
+    /// inputs they were found under
+    pub fn grouped(by: By, roots: Vec<PathBuf>) -> Self {
+        Self {
+            files: Vec::new(),
+            by: Some((by, roots)),
+        }
+    }
+
+    pub fn record(&mut self, path: &Path, bytes: u64, reads: u64, elapsed: Duration) {
+        self.files.push(File {
+            path: path.to_owned(),
+            bytes,
+            reads,
+            seconds: elapsed.as_secs_f64(),
+            mb_per_s: rate(bytes, elapsed),
+        });
+    }
+
+    /// The one that took longest, the first of them on a tie
+    pub fn slowest(&self) -> Option<&File> {
+        self.files.iter().reduce(|slowest, file| {
+            if file.seconds > slowest.seconds {
+                file
+            } else {
+                slowest
+            }
+        })
+    }
+
+    /// The totals for each group, the most bytes first, if the files are
+    /// grouped
+    pub fn groups(&self) -> Option<Vec<Group>> {
+        let (by, roots) = self.by.as_ref()?;
+        let mut groups: BTreeMap<String, (u64, u64, f64)> = BTreeMap::new();
+        for file in &self.files {
+            let group = groups.entry(by.key(roots, &file.path)).or_default();
+            group.0 += 1;
+            group.1 += file.bytes;
+            group.2 += file.seconds;
+        }
+        let mut groups: Vec<_> = groups
+            .into_iter()
+            .map(|(key, (files, bytes, seconds))| Group {
+                key,
+                files,
+                bytes,
+                seconds,
+                mb_per_s: rate(bytes, Duration::from_secs_f64(seconds)),
+            })
+            .collect();
+        groups.sort_by(|a, b| b.bytes.cmp(&a.bytes).then_with(|| a.key.cmp(&b.key)));
+        Some(groups)
+    }
+
+    /// The files, then the run's totals, which count the bytes of files
+    /// the writer filtered out too, then the groups'
+    pub fn render(
+        &self,
+        format: Format,
+        metrics: &RunMetrics,
+        elapsed: Duration,
+    ) -> Result<String, eyre::Error> {
+        match format {
+            Format::Json => {
+                #[derive(Serialize)]
+                struct Report<'a> {
+                    schema_version: u32,
+                    files: &'a [File],
+                    total: Total<'a>,
+                    #[serde(skip_serializing_if = "Option::is_none")]
+                    report_by: Option<&'static str>,
+                    #[serde(skip_serializing_if = "Option::is_none")]
+                    groups: Option<Vec<Group>>,
+                }
+                #[derive(Serialize)]
+                struct Total<'a> {
+                    files: u64,
+                    failed: u64,
+                    bytes: u64,
+                    seconds: f64,
+                    mb_per_s: f64,
+                    slowest: Option<&'a File>,
+                }
+                let report = Report {
+                    schema_version: crate::output::SCHEMA_VERSION,
+                    files: &self.files,
+                    total: Total {
+                        files: metrics.files,
+                        failed: metrics.errors,
+                        bytes: metrics.bytes,
+                        seconds: elapsed.as_secs_f64(),
+                        mb_per_s: rate(metrics.bytes, elapsed),
+                        slowest: self.slowest(),
+                    },
+                    report_by: self.by.as_ref().map(|(by, _)| by.name()),
+                    groups: self.groups(),
+                };
+                Ok(serde_json::to_string_pretty(&report)? + "\n")
+            }
+            Format::Text => {
+                let mut out = String::new();
+                for file in &self.files {
+                    writeln!(
+                        out,
+                        "{}: {} byte(s), {} read(s) in {:.3}s, {:.1} MB/s",
+                        file.path.display(),
+                        file.bytes,
+                        file.reads,
+                        file.seconds,
+                        file.mb_per_s
+                    )?;
+                }
+                writeln!(
+                    out,
+                    "{} file(s), {} failed, {} byte(s) in {:.3}s, {:.1} MB/s",
+                    metrics.files,
+                    metrics.errors,
+                    metrics.bytes,
+                    elapsed.as_secs_f64(),
+                    rate(metrics.bytes, elapsed)
+                )?;
+                if let Some(file) = self.slowest() {
+                    writeln!(
+                        out,
+                        "slowest: {} in {:.3}s",
+                        file.path.display(),
+                        file.seconds
+                    )?;
+                }
+                if let (Some((by, _)), Some(groups)) = (&self.by, self.groups()) {
+                    writeln!(out, "by {}:", by.name())?;
+                    for group in groups {
+                        writeln!(
+                            out,
+                            "  {}: {} file(s), {} byte(s) in {:.3}s, {:.1} MB/s",
+                            group.key, group.files, group.bytes, group.seconds, group.mb_per_s
+                        )?;
+                    }
+                }
+                Ok(out)
+            }
+        }
+    }
+}
@@ -0,0 +1,595 @@
+//! Reading and writing checksum manifests in the formats other tools use.
+
+use crate::{algo::Algorithm, filter::MIN_DIGEST_LEN};
+use color_eyre::eyre::{self, eyre};
+use serde::{Deserialize, Serialize};
+use std::{fmt, io::Write, str::FromStr};
+
+/// A checksum manifest format
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// `<digest>  <path>`, as written by `sha256sum` and friends
+    Gnu,
+    /// `ALGO (<path>) = <digest>`, as written by `sha256sum --tag` or BSD `sha256`
+    Bsd,
+    /// A JSON array of entries
+    Json,
+    /// `algorithm,digest,size,path` rows with a header
+    Csv,
+    /// The `hashdeep` audit format
+    Hashdeep,
+    /// `<path> <digest>[ algo=NAME]`, as this tool prints, with paths
+    /// unquoted. Error lines, checkpoints and other words are skipped.
+    Lines,
+}
+
+impl FromStr for Format {
+    type Err = eyre::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|format| format.name() == s)
+            .ok_or_else(|| {
+                eyre!(
+                    "unknown format {:?} (expected gnu, bsd, json, csv, hashdeep or lines)",
+                    s
+                )
+            })
+    }
+}
+
+impl Format {
+    pub const ALL: [Self; 6] = [
+        Self::Gnu,
+        Self::Bsd,
+        Self::Json,
+        Self::Csv,
+        Self::Hashdeep,
+        Self::Lines,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Gnu => "gnu",
+            Self::Bsd => "bsd",
+            Self::Json => "json",
+            Self::Csv => "csv",
+            Self::Hashdeep => "hashdeep",
+            Self::Lines => "lines",
+        }
+    }
+
+    /// Whether entries say which algorithm made them. Lines without
+    /// `algo=` are from the default, plain gnu lines could be from any.
+    pub fn records_algorithm(self) -> bool {
+        self != Self::Gnu
+    }
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// One line of a checksum manifest
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Entry {
+    pub path: String,
+    pub algorithm: String,
+    /// Lowercase hex
+    pub digest: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+}
+
+/// Parses a manifest. `algorithm` is used for formats that don't record one.
+pub fn parse(format: Format, input: &str, algorithm: &str) -> Result<Vec<Entry>, eyre::Error> {
+    match format {
+        Format::Gnu => parse_lines(input, |line| parse_gnu_line(line, algorithm)),
+        Format::Bsd => parse_lines(input, parse_bsd_line),
+        Format::Json => Ok(serde_json::from_str(input)?),
+        Format::Csv => parse_csv(input),
+        Format::Hashdeep => parse_hashdeep(input),
+        Format::Lines => parse_own(input, algorithm),
+    }
+}
+
+fn parse_lines(
+    input: &str,
+    f: impl Fn(&str) -> Result<Entry, eyre::Error>,
+) -> Result<Vec<Entry>, eyre::Error> {
+    input
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty() && !crate::watermark::is_watermark(line))
+        .map(|(i, line)| f(line).map_err(|e| eyre!("line {}: {}", i + 1, e)))
+        .collect()
+}
+
+fn parse_gnu_line(line: &str, algorithm: &str) -> Result<Entry, eyre::Error> {
+    // coreutils prefixes lines whose path needed escaping with a backslash
+    let (escaped, line) = match line.strip_prefix('\\') {
+        Some(rest) => (true, rest),
+        None => (false, line),
+    };
+    let (digest, rest) = line
+        .split_once(' ')
+        .ok_or_else(|| eyre!("expected `<digest>  <path>`"))?;
+    // the second separator character is ' ' for text mode and '*' for binary mode
+    let path = rest
+        .strip_prefix(' ')
+        .or_else(|| rest.strip_prefix('*'))
+        .ok_or_else(|| eyre!("expected `<digest>  <path>`"))?;
+    let path = if escaped {
+        unescape(path)?
+    } else {
+        path.to_string()
+    };
+    Ok(Entry {
+        path,
+        algorithm: algorithm.to_string(),
+        digest: parse_hex(digest)?,
+        size: None,
+    })
+}
+
+fn parse_own(input: &str, algorithm: &str) -> Result<Vec<Entry>, eyre::Error> {
+    let mut entries = Vec::new();
+    for (i, line) in input.lines().enumerate() {
+        // checkpoints are indented, and files that couldn't be hashed get a
+        // line saying why
+        if line.trim().is_empty()
+            || crate::watermark::is_watermark(line)
+            || line.starts_with(' ')
+            || line.starts_with("While hashing ")
+            || line.starts_with("Skipped ")
+        {
+            continue;
+        }
+        entries.push(parse_own_line(line, algorithm).map_err(|e| eyre!("line {}: {}", i + 1, e))?);
+    }
+    Ok(entries)
+}
+
+fn parse_own_line(line: &str, algorithm: &str) -> Result<Entry, eyre::Error> {
+    // the digest follows the path, which may itself contain spaces
+    let (i, _) = line
+        .match_indices(' ')
+        .find(|&(i, _)| {
+            line[i + 1..]
+                .split(' ')
+                .next()
+                .is_some_and(|w| w.len() >= MIN_DIGEST_LEN && parse_hex(w).is_ok())
+        })
+        .ok_or_else(|| eyre!("expected `<path> <digest>`"))?;
+    let mut words = line[i + 1..].split(' ');
+    let digest = words.next().unwrap();
+    let mut algorithm = algorithm.to_string();
+    for word in words {
+        if let Some(name) = word.strip_prefix("algo=") {
+            algorithm = name.to_string();
+        } else if let Some(len) = word.strip_prefix("truncated=") {
+            return Err(eyre!("the digest only covers the first {} bytes", len));
+        }
+    }
+    Ok(Entry {
+        path: line[..i].to_string(),
+        algorithm,
+        digest: parse_hex(digest)?,
+        size: None,
+    })
+}
+
+fn parse_bsd_line(line: &str) -> Result<Entry, eyre::Error> {
+    let (escaped, line) = match line.strip_prefix('\\') {
+        Some(rest) => (true, rest),
+        None => (false, line),
+    };
+    let err = || eyre!("expected `ALGO (<path>) = <digest>`");
+    let (algorithm, rest) = line.split_once(" (").ok_or_else(err)?;
+    let (path, digest) = rest.rsplit_once(") = ").ok_or_else(err)?;
+    let path = if escaped {
+        unescape(path)?
+    } else {
+        path.to_string()
+    };
+    Ok(Entry {
+        path,
+        algorithm: algorithm.to_string(),
+        digest: parse_hex(digest)?,
+        size: None,
+    })
+}
+
+const CSV_HEADER: [&str; 4] = ["algorithm", "digest", "size", "path"];
+
+fn parse_csv(input: &str) -> Result<Vec<Entry>, eyre::Error> {
+    let mut reader = csv::Reader::from_reader(input.as_bytes());
+    if reader.headers()?.iter().ne(CSV_HEADER.iter().copied()) {
+        return Err(eyre!("expected a `{}` header", CSV_HEADER.join(",")));
+    }
+    let mut entries = Vec::new();
+    for record in reader.records() {
+        let record = record?;
+        entries.push(Entry {
+            algorithm: record[0].to_string(),
+            digest: parse_hex(&record[1])?,
+            size: match &record[2] {
+                "" => None,
+                size => Some(size.parse()?),
+            },
+            path: record[3].to_string(),
+        });
+    }
+    Ok(entries)
+}
+
+fn parse_hashdeep(input: &str) -> Result<Vec<Entry>, eyre::Error> {
+    let mut lines = input.lines();
+    if lines.next() != Some("%%%% HASHDEEP-1.0") {
+        return Err(eyre!("missing `%%%% HASHDEEP-1.0` header"));
+    }
+    let columns: Vec<&str> = lines
+        .next()
+        .and_then(|l| l.strip_prefix("%%%% "))
+        .ok_or_else(|| eyre!("missing `%%%% <columns>` header"))?
+        .split(',')
+        .collect();
+    if columns.last() != Some(&"filename") {
+        return Err(eyre!("the last hashdeep column must be `filename`"));
+    }
+    let size_col = columns.iter().position(|&c| c == "size");
+    let (digest_col, algorithm) = columns
+        .iter()
+        .enumerate()
+        .find(|(_, &c)| c != "size" && c != "filename")
+        .ok_or_else(|| eyre!("hashdeep header lists no digest column"))?;
+    // `sha256` and the like are this tool's `SHA-256`
+    let algorithm = match algorithm.parse::<Algorithm>() {
+        Ok(algorithm) => algorithm.name().to_string(),
+        Err(_) => algorithm.to_string(),
+    };
+
+    let mut entries = Vec::new();
+    for line in lines {
+        if line.starts_with('#') || line.trim().is_empty() {
+            continue;
+        }
+        // filenames may contain commas, so only split off the leading columns
+        let fields: Vec<&str> = line.splitn(columns.len(), ',').collect();
+        if fields.len() != columns.len() {
+            return Err(eyre!("expected {} columns in {:?}", columns.len(), line));
+        }
+        entries.push(Entry {
+            path: fields[columns.len() - 1].to_string(),
+            algorithm: algorithm.clone(),
+            digest: parse_hex(fields[digest_col])?,
+            size: size_col.map(|i| fields[i].parse()).transpose()?,
+        });
+    }
+    Ok(entries)
+}
+
+/// Writes a manifest
+pub fn write(format: Format, entries: &[Entry], out: &mut impl Write) -> Result<(), eyre::Error> {
+    match format {
+        Format::Gnu => {
+            for entry in entries {
+                let (prefix, path) = escape(&entry.path);
+                writeln!(out, "{}{}  {}", prefix, entry.digest, path)?;
+            }
+        }
+        Format::Bsd => {
+            for entry in entries {
+                let (prefix, path) = escape(&entry.path);
+                writeln!(
+                    out,
+                    "{}{} ({}) = {}",
+                    prefix, entry.algorithm, path, entry.digest
+                )?;
+            }
+        }
+        Format::Json => {
+            serde_json::to_writer_pretty(&mut *out, entries)?;
+            writeln!(out)?;
+        }
+        Format::Csv => {
+            let mut writer = csv::Writer::from_writer(out);
+            writer.write_record(CSV_HEADER)?;
+            for entry in entries {
+                let size = entry.size.map(|s| s.to_string()).unwrap_or_default();
+                writer.write_record([
+                    entry.algorithm.as_str(),
+                    entry.digest.as_str(),
+                    size.as_str(),
+                    entry.path.as_str(),
+                ])?;
+            }
+            writer.flush()?;
+        }
+        Format::Hashdeep => {
+            let algorithm = match entries.first() {
+                Some(entry) => hashdeep_name(&entry.algorithm),
+                None => hashdeep_name(Algorithm::Sha3_256.name()),
+            };
+            if let Some(other) = entries
+                .iter()
+                .find(|e| hashdeep_name(&e.algorithm) != algorithm)
+            {
+                return Err(eyre!(
+                    "hashdeep manifests hold a single algorithm, found both {} and {}",
+                    algorithm,
+                    other.algorithm
+                ));
+            }
+            if let Some(entry) = entries.iter().find(|e| e.path.contains(['\n', '\r'])) {
+                return Err(eyre!(
+                    "hashdeep manifests can't hold paths with line breaks: {:?}",
+                    entry.path
+                ));
+            }
+            // hashdeep wants sizes, but manifests converted from formats that
+            // don't carry them can only list digests
+            let with_size = entries.iter().all(|e| e.size.is_some());
+
+            writeln!(out, "%%%% HASHDEEP-1.0")?;
+            if with_size {
+                writeln!(out, "%%%% size,{},filename", algorithm)?;
+            } else {
+                writeln!(out, "%%%% {},filename", algorithm)?;
+            }
+            writeln!(out, "## Converted by surviving")?;
+            writeln!(out, "##")?;
+            for entry in entries {
+                match entry.size {
+                    Some(size) if with_size => {
+                        writeln!(out, "{},{},{}", size, entry.digest, entry.path)?
+                    }
+                    _ => writeln!(out, "{},{}", entry.digest, entry.path)?,
+                }
+            }
+        }
+        Format::Lines => {
+            if let Some(entry) = entries.iter().find(|e| e.path.contains(['\n', '\r'])) {
+                return Err(eyre!(
+                    "lines manifests can't hold paths with line breaks: {:?}",
+                    entry.path
+                ));
+            }
+            for entry in entries {
+                if entry.algorithm.eq_ignore_ascii_case("sha3-256") {
+                    writeln!(out, "{} {}", entry.path, entry.digest)?;
+                } else {
+                    writeln!(
+                        out,
+                        "{} {} algo={}",
+                        entry.path, entry.digest, entry.algorithm
+                    )?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// What hashdeep calls `algorithm` in its header: `sha256` rather than
+/// `SHA-256`. Those it doesn't know are lowercased.
+fn hashdeep_name(algorithm: &str) -> String {
+    match algorithm.parse::<Algorithm>() {
+        Ok(Algorithm::Sha256) => "sha256".to_string(),
+        Ok(Algorithm::Sha512) => "sha512".to_string(),
+        _ => algorithm.to_lowercase(),
+    }
+}
+
+fn parse_hex(digest: &str) -> Result<String, eyre::Error> {
+    if digest.is_empty()
+        || !digest.len().is_multiple_of(2)
+        || !digest.bytes().all(|b| b.is_ascii_hexdigit())
+    {
+        return Err(eyre!("{:?} is not a hex digest", digest));
+    }
+    Ok(digest.to_ascii_lowercase())
+}
+
+/// Writes a gnu or bsd manifest with each entry ending in a NUL rather
+/// than a newline, and paths as they are, as `sha256sum -z` does
+pub fn write_null(
+    format: Format,
+    entries: &[Entry],
+    out: &mut impl Write,
+) -> Result<(), eyre::Error> {
+    for entry in entries {
+        match format {
+            Format::Gnu => write!(out, "{}  {}\0", entry.digest, entry.path)?,
+            Format::Bsd => write!(
+                out,
+                "{} ({}) = {}\0",
+                entry.algorithm, entry.path, entry.digest
+            )?,
+            _ => return Err(eyre!("{} manifests can't be NUL-delimited", format)),
+        }
+    }
+    Ok(())
+}
+
+/// Escapes a path the way coreutils does, returning the line prefix to use
+fn escape(path: &str) -> (&'static str, String) {
+    if !path.contains(['\\', '\n', '\r']) {
+        return ("", path.to_string());
+    }
+    let escaped = path
+        .replace('\\', "\\\\")
+        .replace('\n', "\\n")
+        .replace('\r', "\\r");
+    ("\\", escaped)
+}
+
+fn unescape(path: &str) -> Result<String, eyre::Error> {
+    let mut out = String::with_capacity(path.len());
+    let mut chars = path.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('\\') => out.push('\\'),
+            Some('n') => out.push('\n'),
+            Some('r') => out.push('\r'),
+            other => return Err(eyre!("invalid escape sequence \\{:?} in path", other)),
+        }
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entries() -> Vec<Entry> {
+        vec![
+            Entry {
+                path: "a b/c.txt".to_string(),
+                algorithm: "SHA-256".to_string(),
+                digest: "ab".repeat(32),
+                size: Some(3),
+            },
+            Entry {
+                path: "d,e".to_string(),
+                algorithm: "SHA-256".to_string(),
+                digest: "0f".repeat(32),
+                size: Some(0),
+            },
+        ]
+    }
+
+    fn carries_size(format: Format) -> bool {
+        matches!(format, Format::Json | Format::Csv | Format::Hashdeep)
+    }
+
+    fn round_trip(format: Format, entries: &[Entry]) -> Vec<Entry> {
+        let mut out = Vec::new();
+        write(format, entries, &mut out).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        parse(format, &text, "SHA-256").unwrap_or_else(|e| panic!("{}: {}\n{}", format, e, text))
+    }
+
+    #[test]
+    fn every_format_converts_to_every_other() {
+        for from in Format::ALL {
+            for to in Format::ALL {
+                let converted = round_trip(to, &round_trip(from, &entries()));
+                let expected: Vec<Entry> = entries()
+                    .into_iter()
+                    .map(|entry| Entry {
+                        size: entry
+                            .size
+                            .filter(|_| carries_size(from) && carries_size(to)),
+                        ..entry
+                    })
+                    .collect();
+                assert_eq!(converted, expected, "from {} to {}", from, to);
+            }
+        }
+    }
+
+    #[test]
+    fn hashdeep_names_algorithms_its_way() {
+        let mut out = Vec::new();
+        write(Format::Hashdeep, &entries(), &mut out).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        assert!(text.contains("\n%%%% size,sha256,filename\n"), "{}", text);
+    }
+
+    #[test]
+    fn lines_without_algo_are_the_default() {
+        let digest = "ab".repeat(32);
+        let entries = parse(Format::Lines, &format!("a {}\n", digest), "SHA3-256").unwrap();
+        assert_eq!(entries[0].algorithm, "SHA3-256");
+        let mut out = Vec::new();
+        write(Format::Lines, &entries, &mut out).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), format!("a {}\n", digest));
+    }
+
+    #[test]
+    fn parses_what_coreutils_writes() {
+        let digest = "ab".repeat(32);
+        let input = format!(
+            "{d}  plain name\n{d} *binary mode\n\\{d}  back\\\\slash\\nnewline\n\n",
+            d = digest
+        );
+        let paths: Vec<_> = parse(Format::Gnu, &input, "SHA-256")
+            .unwrap()
+            .into_iter()
+            .map(|e| e.path)
+            .collect();
+        assert_eq!(paths, ["plain name", "binary mode", "back\\slash\nnewline"]);
+
+        let bsd = format!("SHA256 (a (1).txt) = {}\n", digest.to_uppercase());
+        let entries = parse(Format::Bsd, &bsd, "").unwrap();
+        assert_eq!(entries[0].path, "a (1).txt");
+        assert_eq!(entries[0].algorithm, "SHA256");
+        assert_eq!(entries[0].digest, digest);
+
+        let e = parse(Format::Gnu, &format!("{} only-one-space\n", digest), "").unwrap_err();
+        assert!(e.to_string().starts_with("line 1:"), "{}", e);
+    }
+
+    #[test]
+    fn parses_what_hashdeep_writes() {
+        let digest = "0f".repeat(32);
+        let input = format!(
+            "%%%% HASHDEEP-1.0\n\
+             %%%% size,sha256,filename\n\
+             ## Invoked from: /home/user\n\
+             ## $ hashdeep -c sha256 -r .\n\
+             ##\n\
+             12,{d},./a,b.txt\n",
+            d = digest
+        );
+        let entries = parse(Format::Hashdeep, &input, "").unwrap();
+        assert_eq!(
+            entries,
+            [Entry {
+                path: "./a,b.txt".to_string(),
+                algorithm: "SHA-256".to_string(),
+                digest,
+                size: Some(12),
+            }]
+        );
+        let e = parse(
+            Format::Hashdeep,
+            "%%%% HASHDEEP-1.0\n%%%% size,filename\n",
+            "",
+        )
+        .unwrap_err();
+        assert!(e.to_string().contains("no digest column"), "{}", e);
+    }
+
+    #[test]
+    fn parses_what_this_tool_prints() {
+        let digest = "cd".repeat(32);
+        let input = format!(
+            "a file {d}\n\
+             \x20 1048576 {d}\n\
+             While hashing b: Permission denied\n\
+             Skipped c: not a regular file\n\
+             d {d} algo=BLAKE3\n",
+            d = digest
+        );
+        let entries = parse(Format::Lines, &input, "SHA3-256").unwrap();
+        let got: Vec<_> = entries
+            .iter()
+            .map(|e| (e.path.as_str(), e.algorithm.as_str()))
+            .collect();
+        assert_eq!(got, [("a file", "SHA3-256"), ("d", "BLAKE3")]);
+
+        let truncated = format!("e {} truncated=4096\n", digest);
+        let e = parse(Format::Lines, &truncated, "SHA3-256").unwrap_err();
+        assert!(e.to_string().contains("first 4096 bytes"), "{}", e);
+    }
+}
@@ -0,0 +1,108 @@
+//! Copying while hashing, for `surviving copy`: a single read of the source
+//! feeds both the hasher and the destination.
+
+use crate::{
+    algo::Update, fault, open, remote, HashOptions, Position, SimpleAsyncReader, TracingReader,
+};
+use async_std::io::{ReadExt, WriteExt};
+use color_eyre::eyre::{self, eyre};
+use futures::io::AsyncRead;
+use std::path::{Path, PathBuf};
+
+/// What a copy wrote
+pub struct Copied {
+    /// Where it went, which may be below the destination given
+    pub dest: PathBuf,
+    pub hash: Vec<u8>,
+    pub size: u64,
+}
+
+/// Copies `src`, stdin or a URL, to `dest`, or into it if it's a directory,
+/// replacing whatever was there
+pub async fn copy(src: &Path, dest: &Path, options: &HashOptions) -> Result<Copied, eyre::Error> {
+    let dest = if async_std::fs::metadata(dest)
+        .await
+        .is_ok_and(|m| m.is_dir())
+    {
+        let name = src
+            .file_name()
+            .filter(|_| !open::is_stdin(src))
+            .ok_or_else(|| {
+                eyre!(
+                    "{} is a directory, name the file to copy to",
+                    dest.display()
+                )
+            })?;
+        dest.join(name)
+    } else {
+        dest.to_owned()
+    };
+
+    let mut position = Position::new(src);
+    let file: Box<dyn AsyncRead + Send + Unpin> = if open::is_stdin(src) {
+        Box::new(async_std::io::stdin())
+    } else if remote::is_url(src) {
+        let (body, len) = remote::fetch(src).await?;
+        position.size = len.unwrap_or(0);
+        Box::new(body)
+    } else {
+        let file = open::open(src, options.noatime).await?;
+        let metadata = file.metadata().await?;
+        // creating the destination would truncate the source before it's read
+        if same_file(&metadata, &dest).await {
+            return Err(eyre!(
+                "{} and {} are the same file",
+                src.display(),
+                dest.display()
+            ));
+        }
+        position.size = metadata.len();
+        Box::new(file)
+    };
+    let file = TracingReader::new(file, position.clone());
+    let file = fault::FaultInjectingReader::new(file, options.faults, options.seed, src);
+    let mut file = SimpleAsyncReader::new(file, position);
+
+    let mut out = async_std::fs::File::create(&dest)
+        .await
+        .map_err(|e| eyre!("can't create {}: {}", dest.display(), e))?;
+    let mut hasher = options.hasher()?;
+    let mut buf = vec![0u8; options.buffer.size()];
+    let mut size = 0;
+    loop {
+        let n = file.read(&mut buf).await?;
+        if n == 0 {
+            break;
+        }
+        // the write is handed to a blocking thread on its first poll, so the
+        // chunk is hashed while it's being written
+        let chunk = &buf[..n];
+        let (written, ()) = futures::join!(out.write_all(chunk), async { hasher.update(chunk) });
+        written.map_err(|e| eyre!("can't write {}: {}", dest.display(), e))?;
+        size += n as u64;
+    }
+    out.flush()
+        .await
+        .map_err(|e| eyre!("can't write {}: {}", dest.display(), e))?;
+    hasher.finish()?;
+    Ok(Copied {
+        dest,
+        hash: hasher.finalize(),
+        size,
+    })
+}
+
+#[cfg(unix)]
+async fn same_file(src: &std::fs::Metadata, dest: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+
+    match async_std::fs::metadata(dest).await {
+        Ok(dest) => dest.dev() == src.dev() && dest.ino() == src.ino(),
+        Err(_) => false,
+    }
+}
+
+#[cfg(not(unix))]
+async fn same_file(_src: &std::fs::Metadata, _dest: &Path) -> bool {
+    false
+}
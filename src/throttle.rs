@@ -0,0 +1,100 @@
+//! Capping how fast files are read, for `--limit-rate`, so a scan on a
+//! production host leaves the disk to everything else. Every read in the
+//! run draws from one token bucket, and a read that overdraws it makes the
+//! next one wait until the bucket is back in credit. Wrapped in a
+//! [`TracingReader`], a read's span covers the wait before it.
+//!
+//! [`TracingReader`]: crate::TracingReader
+
+use futures::io::AsyncRead;
+use std::{
+    future::Future,
+    io,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+
+/// Bytes a second, shared by every read
+pub struct Bucket {
+    rate: u64,
+    level: Mutex<Level>,
+}
+
+struct Level {
+    /// Can go below zero, for what the next reads have to wait out
+    tokens: f64,
+    at: Instant,
+}
+
+impl Bucket {
+    /// Filling at `rate` bytes a second, holding up to a second's worth,
+    /// and full to begin with
+    pub fn new(rate: u64) -> Self {
+        Self {
+            rate,
+            level: Mutex::new(Level {
+                tokens: rate as f64,
+                at: Instant::now(),
+            }),
+        }
+    }
+
+    /// Takes out `n` bytes, returning how long until the bucket is back in
+    /// credit
+    pub fn take(&self, n: u64) -> Duration {
+        let rate = self.rate as f64;
+        let mut level = self.level.lock().unwrap();
+        let now = Instant::now();
+        let refill = now.duration_since(level.at).as_secs_f64() * rate;
+        level.tokens = (level.tokens + refill).min(rate) - n as f64;
+        level.at = now;
+        if level.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-level.tokens / rate)
+        }
+    }
+}
+
+/// Keeps the reads of the reader it wraps to what `bucket` allows
+pub struct ThrottledReader<R> {
+    inner: R,
+    bucket: Arc<Bucket>,
+    /// Before the next read
+    wait: Option<Pin<Box<dyn Future<Output = ()> + Send>>>,
+}
+
+impl<R> ThrottledReader<R> {
+    pub fn new(inner: R, bucket: Arc<Bucket>) -> Self {
+        Self {
+            inner,
+            bucket,
+            wait: None,
+        }
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for ThrottledReader<R> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        if let Some(wait) = &mut self.wait {
+            futures::ready!(wait.as_mut().poll(cx));
+            self.wait = None;
+        }
+        let res = futures::ready!(Pin::new(&mut self.inner).poll_read(cx, buf));
+        if let Ok(n) = &res {
+            // what was read is handed out now, it's the next read that waits
+            let wait = self.bucket.take(*n as u64);
+            if !wait.is_zero() {
+                tracing::debug!(wait_us = wait.as_micros() as u64, "throttled");
+                self.wait = Some(Box::pin(crate::rt::sleep(wait)));
+            }
+        }
+        Poll::Ready(res)
+    }
+}
@@ -0,0 +1,71 @@
+//! The Tiger Tree Hash used by Direct Connect and Gnutella: a Merkle tree of
+//! Tiger digests over 1 KiB leaves, as described by THEX.
+
+use tiger::{Digest, Tiger};
+
+/// Size of the leaves of the tree
+pub const LEAF_SIZE: usize = 1024;
+
+/// Incremental TTH hasher
+#[derive(Default, Clone)]
+pub struct Tth {
+    leaf: Vec<u8>,
+    /// Roots of the complete subtrees so far, with their height, largest first
+    stack: Vec<(u32, Vec<u8>)>,
+    /// Whether any leaf was hashed, since an empty file still has one
+    any: bool,
+}
+
+impl Tth {
+    pub fn update(&mut self, mut data: &[u8]) {
+        while !data.is_empty() {
+            let take = (LEAF_SIZE - self.leaf.len()).min(data.len());
+            self.leaf.extend_from_slice(&data[..take]);
+            data = &data[take..];
+            if self.leaf.len() == LEAF_SIZE {
+                self.push_leaf();
+            }
+        }
+    }
+
+    pub fn finalize(mut self) -> Vec<u8> {
+        if !self.leaf.is_empty() || !self.any {
+            self.push_leaf();
+        }
+        // a node without a sibling is promoted as-is, which amounts to
+        // folding what's left from the right
+        let (_, mut root) = self.stack.pop().expect("at least one leaf");
+        while let Some((_, left)) = self.stack.pop() {
+            root = node(&left, &root);
+        }
+        root
+    }
+
+    fn push_leaf(&mut self) {
+        let mut hasher = Tiger::new();
+        hasher.update([0u8]);
+        hasher.update(&self.leaf);
+        self.leaf.clear();
+        self.any = true;
+
+        let mut height = 0;
+        let mut hash = hasher.finalize().to_vec();
+        while let Some((h, _)) = self.stack.last() {
+            if *h != height {
+                break;
+            }
+            let (_, left) = self.stack.pop().unwrap();
+            hash = node(&left, &hash);
+            height += 1;
+        }
+        self.stack.push((height, hash));
+    }
+}
+
+fn node(left: &[u8], right: &[u8]) -> Vec<u8> {
+    let mut hasher = Tiger::new();
+    hasher.update([1u8]);
+    hasher.update(left);
+    hasher.update(right);
+    hasher.finalize().to_vec()
+}
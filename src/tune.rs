@@ -0,0 +1,157 @@
+//! Picking a read buffer size by measuring, rather than guessing.
+
+use crate::units::ByteSize;
+use color_eyre::eyre;
+use std::{
+    str::FromStr,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// The read buffer size unless told otherwise
+pub const DEFAULT_BUFFER_SIZE: usize = 256 << 10;
+
+/// How large a buffer to read files with
+#[derive(Debug, Clone, Copy)]
+pub enum BufferSize {
+    Fixed(usize),
+    /// Probe a few sizes at the start of the run, then stick with the fastest
+    Auto,
+}
+
+impl FromStr for BufferSize {
+    type Err = eyre::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "auto" {
+            return Ok(Self::Auto);
+        }
+        let ByteSize(size) = s.parse()?;
+        if size == 0 {
+            return Err(eyre::eyre!("buffer size must be non-zero"));
+        }
+        Ok(Self::Fixed(size as usize))
+    }
+}
+
+/// Decides the size of each read from one device
+#[derive(Clone)]
+pub enum Buffer {
+    Fixed(usize),
+    Tuned(Tuner),
+}
+
+impl Buffer {
+    pub fn new(size: BufferSize) -> Self {
+        match size {
+            BufferSize::Fixed(size) => Self::Fixed(size),
+            BufferSize::Auto => Self::Tuned(Default::default()),
+        }
+    }
+
+    /// One that starts over the way this one did, for another device
+    pub fn fresh(&self) -> Self {
+        match self {
+            Self::Fixed(size) => Self::Fixed(*size),
+            Self::Tuned(_) => Self::Tuned(Default::default()),
+        }
+    }
+
+    /// The buffer size to use for the next read
+    pub fn size(&self) -> usize {
+        match self {
+            Self::Fixed(size) => *size,
+            Self::Tuned(tuner) => tuner.buffer_size(),
+        }
+    }
+
+    /// Records how long a read with a buffer of `size` took to return `bytes`
+    pub fn record(&self, size: usize, bytes: usize, elapsed: Duration) {
+        if let Self::Tuned(tuner) = self {
+            tuner.record(size, bytes, elapsed);
+        }
+    }
+}
+
+const CANDIDATES: [usize; 5] = [64 << 10, 128 << 10, 256 << 10, 1 << 20, 4 << 20];
+
+/// How long to keep trying candidates before locking one in
+const PROBE_WINDOW: Duration = Duration::from_secs(3);
+
+/// Finds the best-performing buffer size for one device. Reads round-robin
+/// through the candidate sizes until the probe window has elapsed and every
+/// candidate has been measured, then the one with the highest throughput is
+/// used for the rest of the run.
+#[derive(Default)]
+pub struct Tuner {
+    state: Mutex<TunerState>,
+}
+
+impl Clone for Tuner {
+    fn clone(&self) -> Self {
+        Self {
+            state: Mutex::new(self.state.lock().unwrap().clone()),
+        }
+    }
+}
+
+#[derive(Default, Clone)]
+struct TunerState {
+    started: Option<Instant>,
+    next: usize,
+    samples: [(u64, Duration); CANDIDATES.len()],
+    locked: Option<usize>,
+}
+
+impl Tuner {
+    /// The buffer size to use for the next read
+    pub fn buffer_size(&self) -> usize {
+        let mut state = self.state.lock().unwrap();
+        if let Some(size) = state.locked {
+            return size;
+        }
+        state.started.get_or_insert_with(Instant::now);
+        let size = CANDIDATES[state.next % CANDIDATES.len()];
+        state.next += 1;
+        size
+    }
+
+    /// Records how long a read with a buffer of `size` took to return `bytes`
+    pub fn record(&self, size: usize, bytes: usize, elapsed: Duration) {
+        let mut state = self.state.lock().unwrap();
+        if state.locked.is_some() || bytes == 0 {
+            return;
+        }
+        let i = match CANDIDATES.iter().position(|&c| c == size) {
+            Some(i) => i,
+            None => return,
+        };
+        state.samples[i].0 += bytes as u64;
+        state.samples[i].1 += elapsed;
+
+        let probed_long_enough = state
+            .started
+            .map(|t| t.elapsed() >= PROBE_WINDOW)
+            .unwrap_or(false);
+        if !probed_long_enough || state.samples.iter().any(|(bytes, _)| *bytes == 0) {
+            return;
+        }
+
+        let throughput = |(bytes, elapsed): &(u64, Duration)| {
+            *bytes as f64 / elapsed.as_secs_f64().max(f64::EPSILON)
+        };
+        let (best, _) = state
+            .samples
+            .iter()
+            .enumerate()
+            .max_by(|(_, a), (_, b)| throughput(a).total_cmp(&throughput(b)))
+            .unwrap();
+        let size = CANDIDATES[best];
+        tracing::info!(
+            size,
+            mb_per_sec = throughput(&state.samples[best]) / 1e6,
+            "locked in buffer size"
+        );
+        state.locked = Some(size);
+    }
+}
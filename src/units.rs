@@ -0,0 +1,63 @@
+//! Parsing human-friendly quantities from the command line.
+
+use color_eyre::eyre::{self, eyre};
+use std::str::FromStr;
+
+/// A number of bytes, written like `4096`, `256K`, `1M`, `2GiB`.
+/// Suffixes are binary (`K` = 1024).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ByteSize(pub u64);
+
+impl FromStr for ByteSize {
+    type Err = eyre::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+        let (digits, suffix) = s.split_at(split);
+        let n: u64 = digits
+            .parse()
+            .map_err(|_| eyre!("{:?} is not a size in bytes", s))?;
+        let shift = match suffix.trim().to_ascii_uppercase().as_str() {
+            "" | "B" => 0,
+            "K" | "KB" | "KIB" => 10,
+            "M" | "MB" | "MIB" => 20,
+            "G" | "GB" | "GIB" => 30,
+            "T" | "TB" | "TIB" => 40,
+            _ => return Err(eyre!("unknown size suffix in {:?}", s)),
+        };
+        n.checked_mul(1 << shift)
+            .map(ByteSize)
+            .ok_or_else(|| eyre!("{:?} is too large", s))
+    }
+}
+
+/// A length of time, written like `250ms`, `2s`, `1.5m` or `1h`. A bare
+/// number is in seconds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Duration(pub std::time::Duration);
+
+impl FromStr for Duration {
+    type Err = eyre::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let split = s
+            .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+            .unwrap_or(s.len());
+        let (number, suffix) = s.split_at(split);
+        let n: f64 = number
+            .parse()
+            .map_err(|_| eyre!("{:?} is not a duration", s))?;
+        let scale = match suffix.trim() {
+            "ms" => 0.001,
+            "" | "s" => 1.0,
+            "m" => 60.0,
+            "h" => 3600.0,
+            _ => return Err(eyre!("unknown duration suffix in {:?}", s)),
+        };
+        std::time::Duration::try_from_secs_f64(n * scale)
+            .map(Duration)
+            .map_err(|_| eyre!("{:?} is out of range", s))
+    }
+}
@@ -0,0 +1,679 @@
+//! Listing the files under a directory.
+
+use color_eyre::eyre::{self, eyre};
+use futures::io::{AsyncBufRead, AsyncBufReadExt};
+use globset::GlobSet;
+use ignore::{gitignore::Gitignore, Match};
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+/// Per-directory exclusions, in gitignore syntax
+pub const IGNORE_FILE: &str = ".survivingignore";
+
+/// What to list while walking
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+    /// Each directory may hold a file of this name whose gitignore-style
+    /// patterns exclude paths below it, deeper files taking precedence
+    pub ignore_file: Option<String>,
+    /// Only list files matching one of these
+    pub include: Option<GlobSet>,
+    /// Skip files and directories matching one of these
+    pub exclude: Option<GlobSet>,
+    /// Walk into symlinked directories and list symlinked files, instead of
+    /// skipping symlinks
+    pub follow_symlinks: bool,
+    /// Skip directories on another filesystem than the root, mount points
+    /// included
+    pub one_file_system: bool,
+}
+
+impl Options {
+    /// Whether `--include` or `--exclude` leave `path` out. Patterns are
+    /// matched against both the whole path and the file name, so
+    /// `node_modules` and `*.jpg` work at any depth.
+    fn skips(&self, path: &Path, is_dir: bool) -> bool {
+        let matches = |set: &GlobSet| {
+            set.is_match(path) || path.file_name().is_some_and(|name| set.is_match(name))
+        };
+        if self.exclude.as_ref().is_some_and(matches) {
+            return true;
+        }
+        !is_dir && self.include.as_ref().is_some_and(|set| !matches(set))
+    }
+}
+
+/// The inputs in a `--files-from` list: one path per line, each optionally
+/// prefixed by `ID<TAB>`. The ID is whatever comes before the first tab, so
+/// a path with tabs in it needs one, even an empty one. Blank lines are
+/// skipped.
+pub fn parse_list(list: &[u8]) -> Vec<(Option<String>, PathBuf)> {
+    list.split(|&b| b == b'\n')
+        .filter_map(|line| parse_entry(line, false))
+        .collect()
+}
+
+/// One entry of a `--files-from` list, without its delimiter, or `None` if
+/// it's blank. With `null`, entries are NUL-delimited, as `find -print0`
+/// writes them, and taken whole: paths can hold tabs and line breaks.
+pub fn parse_entry(entry: &[u8], null: bool) -> Option<(Option<String>, PathBuf)> {
+    if null {
+        return (!entry.is_empty()).then(|| (None, path_from_bytes(entry)));
+    }
+    let line = entry.strip_suffix(b"\r").unwrap_or(entry);
+    if line.is_empty() {
+        return None;
+    }
+    Some(match line.iter().position(|&b| b == b'\t') {
+        Some(tab) => (
+            Some(String::from_utf8_lossy(&line[..tab]).into_owned()),
+            path_from_bytes(&line[tab + 1..]),
+        ),
+        None => (None, path_from_bytes(line)),
+    })
+}
+
+/// A `--files-from` list read an entry at a time, so one of any length can
+/// be hashed as it comes in
+pub struct ListReader<R> {
+    inner: R,
+    null: bool,
+    entry: Vec<u8>,
+}
+
+impl<R: AsyncBufRead + Unpin> ListReader<R> {
+    /// Over `inner`, NUL-delimited with `null`, or else a line per entry
+    pub fn new(inner: R, null: bool) -> Self {
+        Self {
+            inner,
+            null,
+            entry: Vec::new(),
+        }
+    }
+
+    /// The next entry, blank ones skipped, or `None` at the end
+    pub async fn next(&mut self) -> std::io::Result<Option<(Option<String>, PathBuf)>> {
+        let delimiter = if self.null { b'\0' } else { b'\n' };
+        loop {
+            self.entry.clear();
+            if self.inner.read_until(delimiter, &mut self.entry).await? == 0 {
+                return Ok(None);
+            }
+            let entry = self.entry.strip_suffix(&[delimiter]).unwrap_or(&self.entry);
+            if let Some(parsed) = parse_entry(entry, self.null) {
+                return Ok(Some(parsed));
+            }
+        }
+    }
+}
+
+#[cfg(unix)]
+fn path_from_bytes(bytes: &[u8]) -> PathBuf {
+    use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+
+    PathBuf::from(OsStr::from_bytes(bytes))
+}
+
+#[cfg(not(unix))]
+fn path_from_bytes(bytes: &[u8]) -> PathBuf {
+    PathBuf::from(String::from_utf8_lossy(bytes).into_owned())
+}
+
+/// For each of `files` that goes through a symlink, the other one it's an
+/// alias of: the one naming the same file without any. Everything else
+/// gets `None`, including symlinks whose target isn't among `files`.
+pub fn aliases(files: &[PathBuf]) -> Vec<Option<PathBuf>> {
+    let resolved: Vec<Option<PathBuf>> = files
+        .iter()
+        .map(|path| std::fs::canonicalize(path).ok())
+        .collect();
+    // a path that already is where it resolves to has no symlink in it
+    let mut targets: HashMap<&Path, &Path> = HashMap::new();
+    for (path, resolved) in files.iter().zip(&resolved) {
+        if let Some(resolved) = resolved {
+            if std::path::absolute(path).is_ok_and(|path| path == *resolved) {
+                targets.entry(resolved).or_insert(path);
+            }
+        }
+    }
+    files
+        .iter()
+        .zip(&resolved)
+        .map(|(path, resolved)| {
+            let resolved = resolved.as_deref()?;
+            if std::path::absolute(path).is_ok_and(|path| path == resolved) {
+                return None;
+            }
+            targets.get(resolved).map(|target| target.to_path_buf())
+        })
+        .collect()
+}
+
+/// One from each of `groups` in turn, until they're all used up, so the
+/// first few items come from every group
+pub fn interleave<T>(groups: Vec<Vec<T>>) -> Vec<T> {
+    let mut groups: Vec<_> = groups.into_iter().map(Vec::into_iter).collect();
+    let mut all = Vec::new();
+    while !groups.is_empty() {
+        groups.retain_mut(|group| match group.next() {
+            Some(item) => {
+                all.push(item);
+                true
+            }
+            None => false,
+        });
+    }
+    all
+}
+
+/// How far a walk gets ahead of whoever takes the files it finds
+const CAPACITY: usize = 1024;
+
+/// Every regular file under `root`, sorted.
+///
+/// On Linux, each directory is opened relative to its parent's descriptor
+/// and, unless following symlinks, never through a symlink, so renaming a
+/// directory or swapping one for a symlink in the middle of a long scan
+/// can't lead the walk out of `root`.
+pub async fn files(root: &Path, options: &Options) -> Result<Vec<PathBuf>, eyre::Error> {
+    let found = stream(root, options);
+    let mut files = Vec::new();
+    while let Ok(file) = found.recv().await {
+        files.push(file?);
+    }
+    // only what's reached through symlinks is out of order
+    files.sort();
+    Ok(files)
+}
+
+/// Like [`files`], handing each file out as it's found, so a tree of any
+/// size can be hashed while it's walked. They come sorted, but for those
+/// reached through symlinks, which come last. The walk stops once the
+/// receiver is dropped, and ends with its error if it fails.
+pub fn stream(
+    root: &Path,
+    options: &Options,
+) -> async_std::channel::Receiver<Result<PathBuf, eyre::Error>> {
+    let (tx, rx) = async_std::channel::bounded(CAPACITY);
+    let root = root.to_owned();
+    let options = options.clone();
+    #[cfg(target_os = "linux")]
+    crate::rt::spawn(async move {
+        crate::rt::spawn_blocking(move || {
+            let found = |file| tx.send_blocking(Ok(file)).is_ok();
+            if let Err(e) = at::walk(&root, &options, found) {
+                let _ = tx.send_blocking(Err(e));
+            }
+        })
+        .await
+    });
+    #[cfg(not(target_os = "linux"))]
+    crate::rt::spawn(async move {
+        if let Err(e) = by_path::walk(&root, &options, &tx).await {
+            let _ = tx.send(Err(e)).await;
+        }
+    });
+    rx
+}
+
+#[cfg(target_os = "linux")]
+mod at {
+    use super::*;
+    use std::{
+        ffi::{CStr, CString, OsStr},
+        io::{self, Read},
+        os::unix::{ffi::OsStrExt, io::FromRawFd},
+        rc::Rc,
+    };
+
+    /// An open directory, closed once dropped
+    struct Dir(*mut libc::DIR);
+
+    impl Dir {
+        /// Opens `name`, relative to `parent` unless it's `AT_FDCWD`
+        fn open(parent: libc::c_int, name: &CStr, flags: libc::c_int) -> io::Result<Self> {
+            let flags = libc::O_RDONLY | libc::O_DIRECTORY | libc::O_CLOEXEC | flags;
+            let fd = unsafe { libc::openat(parent, name.as_ptr(), flags) };
+            if fd < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let dir = unsafe { libc::fdopendir(fd) };
+            if dir.is_null() {
+                let err = io::Error::last_os_error();
+                unsafe { libc::close(fd) };
+                return Err(err);
+            }
+            Ok(Self(dir))
+        }
+
+        fn fd(&self) -> libc::c_int {
+            unsafe { libc::dirfd(self.0) }
+        }
+
+        /// Every entry but `.` and `..`, with its `d_type`
+        fn entries(&self) -> io::Result<Vec<(CString, u8)>> {
+            let mut entries = Vec::new();
+            loop {
+                // readdir only sets errno on failure, not at the end
+                unsafe { *libc::__errno_location() = 0 };
+                let entry = unsafe { libc::readdir(self.0) };
+                if entry.is_null() {
+                    let err = io::Error::last_os_error();
+                    return match err.raw_os_error() {
+                        Some(0) | None => Ok(entries),
+                        _ => Err(err),
+                    };
+                }
+                let (name, kind) =
+                    unsafe { (CStr::from_ptr((*entry).d_name.as_ptr()), (*entry).d_type) };
+                if name.to_bytes() != b"." && name.to_bytes() != b".." {
+                    entries.push((name.to_owned(), kind));
+                }
+            }
+        }
+
+        /// The `d_type` of `name`, for filesystems that don't fill it in, or
+        /// of what it points to with `follow`
+        fn file_type(&self, name: &CStr, follow: bool) -> io::Result<u8> {
+            let flags = if follow { 0 } else { libc::AT_SYMLINK_NOFOLLOW };
+            let mut stat: libc::stat = unsafe { std::mem::zeroed() };
+            let res = unsafe { libc::fstatat(self.fd(), name.as_ptr(), &mut stat, flags) };
+            if res != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(match stat.st_mode & libc::S_IFMT {
+                libc::S_IFDIR => libc::DT_DIR,
+                libc::S_IFREG => libc::DT_REG,
+                libc::S_IFLNK => libc::DT_LNK,
+                _ => libc::DT_UNKNOWN,
+            })
+        }
+
+        /// The device `name` is on, or what it points to with `follow`,
+        /// without mounting it if it's an automount point
+        fn device(&self, name: &CStr, follow: bool) -> io::Result<u64> {
+            let mut flags = libc::AT_NO_AUTOMOUNT;
+            if !follow {
+                flags |= libc::AT_SYMLINK_NOFOLLOW;
+            }
+            let mut stat: libc::stat = unsafe { std::mem::zeroed() };
+            let res = unsafe { libc::fstatat(self.fd(), name.as_ptr(), &mut stat, flags) };
+            if res != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(stat.st_dev)
+        }
+
+        /// Device and inode, to recognize a directory reached twice
+        fn id(&self) -> io::Result<(u64, u64)> {
+            let mut stat: libc::stat = unsafe { std::mem::zeroed() };
+            if unsafe { libc::fstat(self.fd(), &mut stat) } != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok((stat.st_dev, stat.st_ino))
+        }
+
+        /// The contents of the file `name`, if there is one
+        fn read(&self, name: &CStr) -> io::Result<Option<String>> {
+            let fd =
+                unsafe { libc::openat(self.fd(), name.as_ptr(), libc::O_RDONLY | libc::O_CLOEXEC) };
+            if fd < 0 {
+                let err = io::Error::last_os_error();
+                if err.kind() == io::ErrorKind::NotFound {
+                    return Ok(None);
+                }
+                return Err(err);
+            }
+            let mut text = String::new();
+            unsafe { std::fs::File::from_raw_fd(fd) }.read_to_string(&mut text)?;
+            Ok(Some(text))
+        }
+    }
+
+    impl Drop for Dir {
+        fn drop(&mut self) {
+            unsafe { libc::closedir(self.0) };
+        }
+    }
+
+    /// A directory still to list: its parent stays open until then, so it's
+    /// opened by name from there rather than by its full path
+    struct Pending {
+        parent: Option<Rc<Dir>>,
+        name: CString,
+        path: PathBuf,
+        ignores: Vec<Arc<Gitignore>>,
+        /// Whether a symlink led here
+        linked: bool,
+    }
+
+    /// What's left of the walk, in the order it's handed out
+    enum Next {
+        Dir(Pending),
+        File(PathBuf),
+    }
+
+    /// Hands every regular file under `root` to `found`, until it returns
+    /// false
+    pub fn walk(
+        root: &Path,
+        options: &Options,
+        mut found: impl FnMut(PathBuf) -> bool,
+    ) -> Result<(), eyre::Error> {
+        let ignore_file = options
+            .ignore_file
+            .as_deref()
+            .map(CString::new)
+            .transpose()?;
+        let nofollow = if options.follow_symlinks {
+            0
+        } else {
+            libc::O_NOFOLLOW
+        };
+        let mut visited = HashSet::new();
+        // the root's, with --one-file-system
+        let mut device = None;
+        let mut pending = vec![Next::Dir(Pending {
+            parent: None,
+            name: CString::new(root.as_os_str().as_bytes())?,
+            path: root.to_owned(),
+            ignores: Vec::new(),
+            linked: false,
+        })];
+        // directories reached through symlinks wait until the rest is
+        // walked, so one reachable both ways is listed under its own path
+        let mut linked = Vec::new();
+        while let Some(next) = pending.pop().or_else(|| linked.pop()) {
+            let Pending {
+                parent,
+                name,
+                path,
+                mut ignores,
+                linked: through_link,
+            } = match next {
+                Next::Dir(dir) => dir,
+                Next::File(file) => match found(file) {
+                    true => continue,
+                    false => return Ok(()),
+                },
+            };
+            let dir = match &parent {
+                // like any input, the root may itself be a symlink
+                None => Dir::open(libc::AT_FDCWD, &name, 0)?,
+                Some(parent) => match Dir::open(parent.fd(), &name, nofollow) {
+                    Ok(dir) => dir,
+                    Err(e) if matches!(e.raw_os_error(), Some(libc::ELOOP | libc::ENOTDIR)) => {
+                        tracing::warn!(path = %path.display(), "no longer a directory, skipping");
+                        continue;
+                    }
+                    Err(e) => return Err(e.into()),
+                },
+            };
+            drop(parent);
+            if options.one_file_system && device.is_none() {
+                device = Some(dir.id()?.0);
+            }
+            if options.follow_symlinks && !visited.insert(dir.id()?) {
+                tracing::warn!(path = %path.display(), "already walked, skipping symlink loop");
+                continue;
+            }
+            let dir = Rc::new(dir);
+
+            if let Some(ignore_file) = &ignore_file {
+                if let Some(text) = dir.read(ignore_file)? {
+                    let ignore_path = path.join(OsStr::from_bytes(ignore_file.to_bytes()));
+                    ignores.push(Arc::new(parse_ignore(&ignore_path, &text)?));
+                }
+            }
+
+            let mut entries = dir.entries()?;
+            entries.sort();
+            let mut children = Vec::new();
+            for (name, mut kind) in entries {
+                if kind == libc::DT_UNKNOWN {
+                    kind = dir.file_type(&name, false)?;
+                }
+                let child = path.join(OsStr::from_bytes(name.to_bytes()));
+                let is_link = kind == libc::DT_LNK;
+                if is_link && options.follow_symlinks {
+                    kind = match dir.file_type(&name, true) {
+                        Ok(kind) => kind,
+                        Err(e) if matches!(e.raw_os_error(), Some(libc::ENOENT | libc::ELOOP)) => {
+                            tracing::warn!(path = %child.display(), "dangling symlink, skipping");
+                            continue;
+                        }
+                        Err(e) => return Err(e.into()),
+                    };
+                }
+                let is_dir = kind == libc::DT_DIR;
+                if is_ignored(&ignores, &child, is_dir) || options.skips(&child, is_dir) {
+                    continue;
+                }
+                if kind == libc::DT_DIR {
+                    if let Some(device) = device {
+                        if dir.device(&name, options.follow_symlinks)? != device {
+                            tracing::debug!(path = %child.display(), "on another filesystem, skipping");
+                            continue;
+                        }
+                    }
+                    children.push(Next::Dir(Pending {
+                        parent: Some(dir.clone()),
+                        name,
+                        path: child,
+                        ignores: ignores.clone(),
+                        linked: through_link || is_link,
+                    }));
+                } else if kind == libc::DT_REG {
+                    children.push(Next::File(child));
+                }
+            }
+            // last in, first out, so the first name comes first
+            for child in children.into_iter().rev() {
+                match child {
+                    Next::Dir(dir) if dir.linked => linked.push(Next::Dir(dir)),
+                    child => pending.push(child),
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod by_path {
+    use super::*;
+    use async_std::{fs, path::PathBuf as AsyncPathBuf, prelude::*};
+
+    /// What's left of the walk, in the order it's handed out: directories
+    /// with the ignore files above them and whether a symlink led there
+    enum Next {
+        Dir(AsyncPathBuf, Vec<Arc<Gitignore>>, bool),
+        File(PathBuf),
+    }
+
+    /// Sends every regular file under `root` to `found`, until it's closed
+    pub async fn walk(
+        root: &Path,
+        options: &Options,
+        found: &async_std::channel::Sender<Result<PathBuf, eyre::Error>>,
+    ) -> Result<(), eyre::Error> {
+        let mut visited = HashSet::new();
+        // the root's, with --one-file-system
+        let device = match options.one_file_system {
+            true => Some(device(fs::metadata(root).await?)),
+            false => None,
+        };
+        let mut pending = vec![Next::Dir(AsyncPathBuf::from(root), Vec::new(), false)];
+        // directories reached through symlinks wait until the rest is
+        // walked, so one reachable both ways is listed under its own path
+        let mut linked = Vec::new();
+        while let Some(next) = pending.pop().or_else(|| linked.pop()) {
+            let (dir, mut ignores, through_link) = match next {
+                Next::Dir(dir, ignores, through_link) => (dir, ignores, through_link),
+                Next::File(file) => match found.send(Ok(file)).await {
+                    Ok(()) => continue,
+                    Err(_) => return Ok(()),
+                },
+            };
+            if options.follow_symlinks && !visited.insert(fs::canonicalize(&dir).await?) {
+                tracing::warn!(path = %dir.display(), "already walked, skipping symlink loop");
+                continue;
+            }
+            if let Some(name) = &options.ignore_file {
+                if let Some(ignore) = load_ignore(&dir.join(name)).await? {
+                    ignores.push(Arc::new(ignore));
+                }
+            }
+
+            let mut entries = Vec::new();
+            let mut read = fs::read_dir(&dir).await?;
+            while let Some(entry) = read.next().await {
+                entries.push(entry?);
+            }
+            entries.sort_by_key(|entry| entry.file_name());
+            let mut children = Vec::new();
+            for entry in entries {
+                let mut file_type = entry.file_type().await?;
+                let path: PathBuf = entry.path().into();
+                let is_link = file_type.is_symlink();
+                if is_link && options.follow_symlinks {
+                    file_type = match fs::metadata(entry.path()).await {
+                        Ok(metadata) => metadata.file_type(),
+                        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                            tracing::warn!(path = %path.display(), "dangling symlink, skipping");
+                            continue;
+                        }
+                        Err(e) => return Err(e.into()),
+                    };
+                }
+                let is_dir = file_type.is_dir();
+                if is_ignored(&ignores, &path, is_dir) || options.skips(&path, is_dir) {
+                    continue;
+                }
+                if let Some(device) = device.filter(|_| is_dir) {
+                    let metadata = if options.follow_symlinks {
+                        fs::metadata(entry.path()).await?
+                    } else {
+                        fs::symlink_metadata(entry.path()).await?
+                    };
+                    if self::device(metadata) != device {
+                        tracing::debug!(path = %path.display(), "on another filesystem, skipping");
+                        continue;
+                    }
+                }
+                if is_dir {
+                    children.push(Next::Dir(
+                        entry.path(),
+                        ignores.clone(),
+                        through_link || is_link,
+                    ));
+                } else if file_type.is_file() {
+                    children.push(Next::File(path));
+                }
+            }
+            // last in, first out, so the first name comes first
+            for child in children.into_iter().rev() {
+                match child {
+                    Next::Dir(_, _, true) => linked.push(child),
+                    child => pending.push(child),
+                }
+            }
+        }
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    fn device(metadata: std::fs::Metadata) -> u64 {
+        std::os::unix::fs::MetadataExt::dev(&metadata)
+    }
+
+    /// Filesystems can't be told apart here, so they're all one
+    #[cfg(not(unix))]
+    fn device(_metadata: std::fs::Metadata) -> u64 {
+        0
+    }
+
+    async fn load_ignore(path: &AsyncPathBuf) -> Result<Option<Gitignore>, eyre::Error> {
+        let text = match fs::read_to_string(path).await {
+            Ok(text) => text,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        parse_ignore(path.as_ref(), &text).map(Some)
+    }
+}
+
+/// Builds the exclusions of the ignore file at `path`, holding `text`
+fn parse_ignore(path: &Path, text: &str) -> Result<Gitignore, eyre::Error> {
+    let mut builder = ignore::gitignore::GitignoreBuilder::new(path.parent().unwrap());
+    for line in text.lines() {
+        builder
+            .add_line(Some(path.to_owned()), line)
+            .map_err(|e| eyre!("in {}: {}", path.display(), e))?;
+    }
+    Ok(builder.build()?)
+}
+
+fn is_ignored(ignores: &[Arc<Gitignore>], path: &Path, is_dir: bool) -> bool {
+    for ignore in ignores.iter().rev() {
+        match ignore.matched(path, is_dir) {
+            Match::None => continue,
+            Match::Ignore(_) => return true,
+            Match::Whitelist(_) => return false,
+        }
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An empty directory of its own for each test
+    fn scratch(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("surviving-walk-{}-{}", std::process::id(), name));
+        std::fs::remove_dir_all(&dir).ok();
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn streams_in_sorted_order() {
+        async_std::task::block_on(async {
+            let dir = scratch("sorted");
+            // `b/x` sorts before `b.txt`, `b` being shorter than `b.txt`
+            for file in ["top", "b/x", "b.txt", "c/d/y", "c/a", "a"] {
+                let path = dir.join(file);
+                std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+                std::fs::write(&path, file).unwrap();
+            }
+            let options = Options::default();
+            let found = stream(&dir, &options);
+            let mut streamed = Vec::new();
+            while let Ok(file) = found.recv().await {
+                streamed.push(file.unwrap());
+            }
+            let mut sorted = streamed.clone();
+            sorted.sort();
+            assert_eq!(streamed, sorted);
+            assert_eq!(streamed.len(), 6);
+            assert_eq!(files(&dir, &options).await.unwrap(), sorted);
+            std::fs::remove_dir_all(&dir).ok();
+        })
+    }
+
+    #[test]
+    fn stream_ends_with_the_walk_error() {
+        async_std::task::block_on(async {
+            let dir = scratch("missing");
+            let found = stream(&dir.join("nope"), &Options::default());
+            assert!(found.recv().await.unwrap().is_err());
+            assert!(found.recv().await.is_err());
+            std::fs::remove_dir_all(&dir).ok();
+        })
+    }
+}
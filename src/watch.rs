@@ -0,0 +1,278 @@
+//! Noticing changed files, for `--watch`, with inotify. Directories are
+//! watched with everything below them, as they're hashed. Files are watched
+//! through their directory, so a file that an editor saves by renaming a new
+//! copy over it is still seen.
+
+use color_eyre::eyre::{self, eyre};
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+#[cfg(target_os = "linux")]
+use std::{
+    ffi::{CString, OsStr, OsString},
+    io,
+    os::unix::{
+        ffi::OsStrExt,
+        io::{AsRawFd, FromRawFd, OwnedFd},
+    },
+};
+
+/// What's wanted from a watched directory
+#[cfg(target_os = "linux")]
+enum Watch {
+    /// Everything in it, and in new directories made there
+    All(PathBuf),
+    /// Only the inputs named, in a directory that isn't an input itself
+    Named(PathBuf, HashSet<OsString>),
+}
+
+pub struct Watcher {
+    #[cfg(target_os = "linux")]
+    fd: OwnedFd,
+    /// By watch descriptor
+    #[cfg(target_os = "linux")]
+    watches: HashMap<i32, Watch>,
+    roots: Vec<PathBuf>,
+}
+
+#[cfg(target_os = "linux")]
+const CHANGES: u32 = libc::IN_MODIFY
+    | libc::IN_CLOSE_WRITE
+    | libc::IN_CREATE
+    | libc::IN_DELETE
+    | libc::IN_MOVED_FROM
+    | libc::IN_MOVED_TO;
+
+impl Watcher {
+    /// Starts watching `roots`, the inputs as given
+    #[cfg(target_os = "linux")]
+    pub fn new(roots: &[PathBuf]) -> Result<Self, eyre::Error> {
+        let fd = unsafe { libc::inotify_init1(libc::IN_CLOEXEC) };
+        if fd < 0 {
+            return Err(eyre!(
+                "can't start watching: {}",
+                io::Error::last_os_error()
+            ));
+        }
+        let mut watcher = Self {
+            fd: unsafe { OwnedFd::from_raw_fd(fd) },
+            watches: HashMap::new(),
+            roots: roots.to_vec(),
+        };
+        // directories first, so a file that's also below one of them isn't
+        // watched for alone
+        let (dirs, files): (Vec<_>, Vec<_>) = roots.iter().partition(|root| root.is_dir());
+        for dir in dirs {
+            watcher.add_tree(dir)?;
+        }
+        for file in files {
+            if crate::open::is_stdin(file) || crate::remote::is_url(file) {
+                return Err(eyre!("--watch can't watch {}", file.display()));
+            }
+            let name = file
+                .file_name()
+                .ok_or_else(|| eyre!("--watch can't watch {}", file.display()))?;
+            // joined back onto the name as it was given
+            let dir = file.parent().unwrap_or_else(|| Path::new(""));
+            let wd = if dir.as_os_str().is_empty() {
+                watcher.add(Path::new("."))?
+            } else {
+                watcher.add(dir)?
+            };
+            match watcher.watches.get_mut(&wd) {
+                Some(Watch::All(_)) => {}
+                Some(Watch::Named(_, names)) => {
+                    names.insert(name.to_owned());
+                }
+                None => {
+                    let names = std::iter::once(name.to_owned()).collect();
+                    watcher
+                        .watches
+                        .insert(wd, Watch::Named(dir.to_owned(), names));
+                }
+            }
+        }
+        Ok(watcher)
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub fn new(_roots: &[PathBuf]) -> Result<Self, eyre::Error> {
+        Err(eyre!("--watch is only supported on Linux"))
+    }
+
+    /// Watches `dir`, returning its watch descriptor, which is the one it
+    /// already had if it was watched before
+    #[cfg(target_os = "linux")]
+    fn add(&mut self, dir: &Path) -> Result<i32, eyre::Error> {
+        let c_dir = CString::new(dir.as_os_str().as_bytes())?;
+        let wd = unsafe {
+            libc::inotify_add_watch(
+                self.fd.as_raw_fd(),
+                c_dir.as_ptr(),
+                CHANGES | libc::IN_ONLYDIR,
+            )
+        };
+        if wd < 0 {
+            return Err(eyre!(
+                "can't watch {}: {}",
+                dir.display(),
+                io::Error::last_os_error()
+            ));
+        }
+        Ok(wd)
+    }
+
+    /// Watches `dir` and every directory below it, without following
+    /// symlinks
+    #[cfg(target_os = "linux")]
+    fn add_tree(&mut self, dir: &Path) -> Result<(), eyre::Error> {
+        let mut pending = vec![dir.to_owned()];
+        while let Some(dir) = pending.pop() {
+            let wd = self.add(&dir)?;
+            self.watches.insert(wd, Watch::All(dir.clone()));
+            let entries = match std::fs::read_dir(&dir) {
+                Ok(entries) => entries,
+                // gone already, or unreadable, which hashing reports
+                Err(_) => continue,
+            };
+            for entry in entries.flatten() {
+                if entry.file_type().is_ok_and(|kind| kind.is_dir()) {
+                    pending.push(entry.path());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Waits for something to change, then until nothing has for
+    /// `debounce`, and returns every path that changed, in order. Every
+    /// root is returned if changes were lost, as they are when too many
+    /// come at once.
+    #[cfg(target_os = "linux")]
+    pub fn changes(&mut self, debounce: Duration) -> Result<Vec<PathBuf>, eyre::Error> {
+        let mut changed = std::collections::BTreeSet::new();
+        let mut timeout = -1;
+        // inotify_event is 4-byte aligned
+        let mut buf = vec![0u32; 4096];
+        loop {
+            let mut poll = libc::pollfd {
+                fd: self.fd.as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            };
+            match unsafe { libc::poll(&mut poll, 1, timeout) } {
+                0 => break,
+                n if n < 0 => {
+                    let err = io::Error::last_os_error();
+                    if err.kind() == io::ErrorKind::Interrupted {
+                        continue;
+                    }
+                    return Err(err.into());
+                }
+                _ => {}
+            }
+            let len = unsafe {
+                libc::read(
+                    self.fd.as_raw_fd(),
+                    buf.as_mut_ptr() as *mut libc::c_void,
+                    buf.len() * 4,
+                )
+            };
+            if len < 0 {
+                return Err(io::Error::last_os_error().into());
+            }
+            let bytes =
+                unsafe { std::slice::from_raw_parts(buf.as_ptr() as *const u8, len as usize) };
+            self.parse(bytes, &mut changed)?;
+            timeout = debounce.as_millis().min(i32::MAX as u128) as i32;
+        }
+        Ok(changed.into_iter().collect())
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub fn changes(&mut self, _debounce: Duration) -> Result<Vec<PathBuf>, eyre::Error> {
+        unreachable!("there's no watcher to ask")
+    }
+
+    /// Adds the paths the events in `bytes` are about to `changed`
+    #[cfg(target_os = "linux")]
+    fn parse(
+        &mut self,
+        mut bytes: &[u8],
+        changed: &mut std::collections::BTreeSet<PathBuf>,
+    ) -> Result<(), eyre::Error> {
+        const HEADER: usize = std::mem::size_of::<libc::inotify_event>();
+        while bytes.len() >= HEADER {
+            let event: libc::inotify_event =
+                unsafe { std::ptr::read_unaligned(bytes.as_ptr() as *const _) };
+            let end = HEADER + event.len as usize;
+            // the name is padded with NULs
+            let name = &bytes[HEADER..end];
+            let name =
+                OsStr::from_bytes(&name[..name.iter().position(|&b| b == 0).unwrap_or(name.len())]);
+            bytes = &bytes[end..];
+
+            if event.mask & libc::IN_Q_OVERFLOW != 0 {
+                changed.extend(self.roots.iter().cloned());
+                continue;
+            }
+            if event.mask & libc::IN_IGNORED != 0 {
+                // the directory is gone
+                self.watches.remove(&event.wd);
+                continue;
+            }
+            let path = match self.watches.get(&event.wd) {
+                Some(Watch::All(dir)) => dir.join(name),
+                Some(Watch::Named(dir, names)) if names.contains(name) => dir.join(name),
+                _ => continue,
+            };
+            let made_dir = event.mask & libc::IN_ISDIR != 0
+                && event.mask & (libc::IN_CREATE | libc::IN_MOVED_TO) != 0;
+            if made_dir {
+                // files can be made in it before it's watched, but they're
+                // found by walking it, under its own path
+                self.add_tree(&path)?;
+            }
+            changed.insert(path);
+        }
+        Ok(())
+    }
+}
+
+/// Of the files below `roots` now, and the `known` ones, those `changed`
+/// is about, directly or through a directory they're in. Paths are as
+/// walking the roots gives them.
+pub fn select(
+    roots: &[PathBuf],
+    below: &HashMap<PathBuf, Vec<PathBuf>>,
+    changed: &[PathBuf],
+    known: &HashSet<PathBuf>,
+) -> Vec<PathBuf> {
+    let changed: HashSet<&Path> = changed.iter().map(PathBuf::as_path).collect();
+    let is_changed = |file: &Path| file.ancestors().any(|path| changed.contains(path));
+    let mut wanted = Vec::new();
+    let mut seen = HashSet::new();
+    for root in roots {
+        let files = match below.get(root) {
+            Some(files) => files.as_slice(),
+            None => std::slice::from_ref(root),
+        };
+        for file in files {
+            if is_changed(file) && seen.insert(file.clone()) {
+                wanted.push(file.clone());
+            }
+        }
+    }
+    // removed since, so hashing them reports it
+    let mut removed: Vec<_> = known
+        .iter()
+        .filter(|file| changed.contains(file.as_path()) && !seen.contains(*file))
+        .cloned()
+        .collect();
+    removed.sort();
+    wanted.extend(removed);
+    wanted
+}
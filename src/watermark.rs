@@ -0,0 +1,128 @@
+//! Who made a manifest and how, for `--watermark`: a comment line ahead of
+//! the results that checking skips, and prints, so a manifest found months
+//! later still says which run, host, version and options produced it.
+
+use color_eyre::eyre::{self, eyre};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    time::{Duration, SystemTime},
+};
+
+/// How a watermark line starts. Specific enough that no path in a `lines`
+/// manifest is taken for one, and a comment `sha256sum -c` skips too.
+pub const PREFIX: &str = "# surviving-run ";
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Watermark {
+    /// A random UUID, telling this run from every other
+    pub run: String,
+    pub host: String,
+    pub version: String,
+    /// When the run started, as a Unix timestamp
+    pub started: u64,
+    pub algorithm: String,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub keyed: bool,
+    /// The command line, minus the program name
+    pub arguments: Vec<String>,
+}
+
+impl Watermark {
+    pub fn new(algorithm: crate::algo::Algorithm, keyed: bool, started: SystemTime) -> Self {
+        Self {
+            run: uuid(),
+            host: crate::runs::hostname(),
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            started: started
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0),
+            algorithm: algorithm.name().to_string(),
+            keyed,
+            arguments: redacted(std::env::args().skip(1)),
+        }
+    }
+
+    /// The comment line, without its newline
+    pub fn line(&self) -> String {
+        format!(
+            "{}{}",
+            PREFIX,
+            serde_json::to_string(self).expect("a watermark is always valid JSON")
+        )
+    }
+
+    /// The watermark in `manifest`, if it has one
+    pub fn find(manifest: &str) -> Result<Option<Self>, eyre::Error> {
+        match manifest.lines().find_map(|line| line.strip_prefix(PREFIX)) {
+            Some(json) => serde_json::from_str(json)
+                .map(Some)
+                .map_err(|e| eyre!("unreadable watermark: {}", e)),
+            None => Ok(None),
+        }
+    }
+
+    /// One line saying where the manifest came from
+    pub fn describe(&self) -> String {
+        format!(
+            "made by run {} of surviving {} on {}, {}, with: {}",
+            self.run,
+            self.version,
+            self.host,
+            crate::zsync::rfc2822(SystemTime::UNIX_EPOCH + Duration::from_secs(self.started)),
+            self.arguments.join(" ")
+        )
+    }
+}
+
+/// The arguments with `--hmac-key`'s left out, since the manifest is for
+/// others to read, and the key may be given in hex
+fn redacted(arguments: impl Iterator<Item = String>) -> Vec<String> {
+    let mut redacted = Vec::new();
+    let mut secret = false;
+    for argument in arguments {
+        if std::mem::take(&mut secret) {
+            redacted.push("<redacted>".to_string());
+        } else if argument.starts_with("--hmac-key=") {
+            redacted.push("--hmac-key=<redacted>".to_string());
+        } else {
+            secret = argument == "--hmac-key";
+            redacted.push(argument);
+        }
+    }
+    redacted
+}
+
+/// Whether `line` is a watermark, and not an entry
+pub fn is_watermark(line: &str) -> bool {
+    line.starts_with(PREFIX)
+}
+
+/// A version 4 UUID, from the random keys the standard library seeds its
+/// hash maps with
+fn uuid() -> String {
+    let mut bytes = [0u8; 16];
+    for half in bytes.chunks_mut(8) {
+        let mut hasher = RandomState::new().build_hasher();
+        hasher.write_u128(
+            SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .map_or(0, |d| d.as_nanos()),
+        );
+        hasher.write_u32(std::process::id());
+        half.copy_from_slice(&hasher.finish().to_le_bytes());
+    }
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+    let hex = crate::hex(&bytes);
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    )
+}
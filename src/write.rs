@@ -0,0 +1,286 @@
+//! The write direction of [`SimpleRead`](crate::SimpleRead): sinks written
+//! as async-trait methods, turned into an [`AsyncWrite`].
+
+use async_trait::async_trait;
+use futures::{io::AsyncWrite, Future};
+use std::{
+    io,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+#[async_trait]
+pub trait SimpleWrite {
+    async fn simple_write(&mut self, buf: &[u8]) -> io::Result<usize>;
+
+    async fn simple_flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Flushes by default
+    async fn simple_close(&mut self) -> io::Result<()> {
+        self.simple_flush().await
+    }
+}
+
+/// Turns a [`SimpleWrite`] into an [`AsyncWrite`]
+///
+/// Each write copies what it's given, since the future writing it outlives
+/// the caller's borrow. As with any [`AsyncWrite`], a write that returned
+/// [`Poll::Pending`] has to be polled again with the same data.
+pub struct SimpleAsyncWriter<W>
+where
+    W: SimpleWrite,
+{
+    state: State<W>,
+    /// A write that finished while a flush or close waited on it, for the
+    /// next [`AsyncWrite::poll_write`]
+    written: Option<io::Result<usize>>,
+}
+
+impl<W> SimpleAsyncWriter<W>
+where
+    W: SimpleWrite,
+{
+    pub fn new(inner: W) -> Self {
+        Self {
+            state: State::Idle(inner, Vec::new()),
+            written: None,
+        }
+    }
+
+    /// The sink, unless an operation is still in flight
+    pub fn into_inner(self) -> Option<W> {
+        match self.state {
+            State::Idle(inner, _) => Some(inner),
+            _ => None,
+        }
+    }
+}
+
+// the sink moves in and out of futures between operations, it's never
+// pinned in place
+impl<W: SimpleWrite> Unpin for SimpleAsyncWriter<W> {}
+
+type BoxFut<T> = Pin<Box<dyn Future<Output = T> + Send>>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Op {
+    Write,
+    Flush,
+    Close,
+}
+
+enum State<W> {
+    Idle(W, Vec<u8>),
+    /// Flushes and closes write nothing, and report zero bytes
+    Pending(Op, BoxFut<(W, Vec<u8>, io::Result<usize>)>),
+    Transitional,
+}
+
+impl<W> SimpleAsyncWriter<W>
+where
+    W: SimpleWrite + Send + 'static,
+{
+    /// Drives `op` to completion, after whatever other operation was still
+    /// in flight
+    fn poll_op(&mut self, cx: &mut Context<'_>, op: Op, buf: &[u8]) -> Poll<io::Result<usize>> {
+        loop {
+            match std::mem::replace(&mut self.state, State::Transitional) {
+                State::Idle(mut inner, mut data) => {
+                    let fut: BoxFut<_> = match op {
+                        Op::Write => {
+                            data.clear();
+                            data.extend_from_slice(buf);
+                            Box::pin(async move {
+                                let res = inner.simple_write(&data).await;
+                                (inner, data, res)
+                            })
+                        }
+                        Op::Flush => Box::pin(async move {
+                            let res = inner.simple_flush().await.map(|()| 0);
+                            (inner, data, res)
+                        }),
+                        Op::Close => Box::pin(async move {
+                            let res = inner.simple_close().await.map(|()| 0);
+                            (inner, data, res)
+                        }),
+                    };
+                    self.state = State::Pending(op, fut);
+                }
+                State::Pending(pending, mut fut) => match fut.as_mut().poll(cx) {
+                    Poll::Ready((inner, data, res)) => {
+                        self.state = State::Idle(inner, data);
+                        if pending == op {
+                            return Poll::Ready(res);
+                        }
+                        match pending {
+                            Op::Write => self.written = Some(res),
+                            // a flush that was given up on only matters if
+                            // it failed
+                            Op::Flush | Op::Close => res.map(|_| ())?,
+                        }
+                    }
+                    Poll::Pending => {
+                        self.state = State::Pending(pending, fut);
+                        return Poll::Pending;
+                    }
+                },
+                State::Transitional => unreachable!(),
+            }
+        }
+    }
+}
+
+impl<W> AsyncWrite for SimpleAsyncWriter<W>
+where
+    W: SimpleWrite + Send + 'static,
+{
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        if let Some(written) = this.written.take() {
+            return Poll::Ready(written);
+        }
+        if buf.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
+        this.poll_op(cx, Op::Write, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.get_mut()
+            .poll_op(cx, Op::Flush, &[])
+            .map(|res| res.map(|_| ()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.get_mut()
+            .poll_op(cx, Op::Close, &[])
+            .map(|res| res.map(|_| ()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::io::AsyncWriteExt;
+
+    /// Keeps what it's given, a few bytes at a time, failing when told to
+    #[derive(Default)]
+    struct Sink {
+        data: Vec<u8>,
+        /// At most this much per write
+        chunk: usize,
+        /// Which writes fail, counting from zero
+        failing: Vec<usize>,
+        writes: usize,
+        flushes: usize,
+        closes: usize,
+        /// Whether each operation waits once before it's done
+        hesitant: bool,
+    }
+
+    #[async_trait]
+    impl SimpleWrite for Sink {
+        async fn simple_write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            if self.hesitant {
+                async_std::task::yield_now().await;
+            }
+            let write = self.writes;
+            self.writes += 1;
+            if self.failing.contains(&write) {
+                return Err(io::Error::other("full"));
+            }
+            let n = buf.len().min(self.chunk);
+            self.data.extend_from_slice(&buf[..n]);
+            Ok(n)
+        }
+
+        async fn simple_flush(&mut self) -> io::Result<()> {
+            self.flushes += 1;
+            Ok(())
+        }
+
+        async fn simple_close(&mut self) -> io::Result<()> {
+            self.closes += 1;
+            Ok(())
+        }
+    }
+
+    fn writer(sink: Sink) -> SimpleAsyncWriter<Sink> {
+        SimpleAsyncWriter::new(sink)
+    }
+
+    #[test]
+    fn short_writes_add_up() {
+        async_std::task::block_on(async {
+            let mut writer = writer(Sink {
+                chunk: 3,
+                ..Default::default()
+            });
+            writer.write_all(b"hello world").await.unwrap();
+            writer.close().await.unwrap();
+            let sink = writer.into_inner().unwrap();
+            assert_eq!(sink.data, b"hello world");
+            assert_eq!(sink.writes, 4);
+            assert_eq!(sink.closes, 1);
+        });
+    }
+
+    #[test]
+    fn flushes_and_closes_reach_the_sink() {
+        async_std::task::block_on(async {
+            let mut writer = writer(Sink {
+                chunk: 64,
+                ..Default::default()
+            });
+            writer.write_all(b"ab").await.unwrap();
+            writer.flush().await.unwrap();
+            writer.flush().await.unwrap();
+            writer.close().await.unwrap();
+            let sink = writer.into_inner().unwrap();
+            assert_eq!((sink.flushes, sink.closes), (2, 1));
+        });
+    }
+
+    #[test]
+    fn errors_leave_the_writer_usable() {
+        async_std::task::block_on(async {
+            let mut writer = writer(Sink {
+                chunk: 2,
+                failing: vec![1],
+                ..Default::default()
+            });
+            let e = writer.write_all(b"abcd").await.unwrap_err();
+            assert_eq!(e.to_string(), "full");
+            writer.write_all(b"cd").await.unwrap();
+            assert_eq!(writer.into_inner().unwrap().data, b"abcd");
+        });
+    }
+
+    #[test]
+    fn flushing_finishes_a_pending_write_first() {
+        async_std::task::block_on(async {
+            let mut writer = writer(Sink {
+                chunk: 64,
+                hesitant: true,
+                ..Default::default()
+            });
+            let first = futures::future::poll_fn(|cx| {
+                Poll::Ready(Pin::new(&mut writer).poll_write(cx, b"abc"))
+            })
+            .await;
+            assert!(first.is_pending());
+            writer.flush().await.unwrap();
+            // the write that finished during the flush is reported now
+            assert_eq!(writer.write(b"abc").await.unwrap(), 3);
+            let sink = writer.into_inner().unwrap();
+            assert_eq!(sink.data, b"abc");
+            assert_eq!((sink.writes, sink.flushes), (1, 1));
+        });
+    }
+}
@@ -0,0 +1,166 @@
+//! zsync control files: a header plus a weak rolling checksum and a truncated
+//! MD4 for every block, so clients can fetch only the blocks they lack.
+//!
+//! Checksum sizes follow zsyncmake's own choices, so the output is what
+//! zsync 0.6.2 clients expect.
+
+use async_std::{fs::File, io::prelude::*};
+use color_eyre::eyre;
+use sha1::Sha1;
+use sha3::Digest;
+use std::{path::Path, time::SystemTime};
+
+const VERSION: &str = "0.6.2";
+
+/// Options written into the header
+pub struct Options {
+    /// Where clients download the full file from, relative to the control file
+    pub url: String,
+    pub block_size: Option<usize>,
+}
+
+/// zsyncmake's default: bigger blocks for bigger files
+fn default_block_size(len: u64) -> usize {
+    if len < 100_000_000 {
+        2048
+    } else {
+        4096
+    }
+}
+
+/// How many blocks must match in a row, and how many bytes of each checksum
+/// to keep, as zsyncmake computes them
+fn hash_lengths(len: u64, block_size: usize) -> (usize, usize, usize) {
+    // zsyncmake divides the two as integers
+    let blocks = (1 + len / block_size as u64) as f64;
+    let seq_matches = if len > block_size as u64 { 2 } else { 1 };
+    let len = len.max(1) as f64;
+    let block_size = block_size as f64;
+    let rsum = (((len.ln() + block_size.ln()) / 2f64.ln() - 8.6) / seq_matches as f64 / 8.0)
+        .ceil()
+        .clamp(2.0, 4.0) as usize;
+    let checksum =
+        ((20.0 + (len.ln() + blocks.ln()) / 2f64.ln()) / seq_matches as f64 / 8.0).ceil();
+    let floor = ((7.9 + (20.0 + blocks.ln() / 2f64.ln())) / 8.0).floor();
+    let checksum = checksum.max(floor).min(16.0) as usize;
+    (seq_matches, rsum, checksum)
+}
+
+/// rsync's weak checksum, as two big-endian 16-bit halves
+fn rsum(block: &[u8]) -> [u8; 4] {
+    let mut a: u16 = 0;
+    let mut b: u16 = 0;
+    let len = block.len();
+    for (i, &c) in block.iter().enumerate() {
+        a = a.wrapping_add(c as u16);
+        b = b.wrapping_add(((len - i) as u16).wrapping_mul(c as u16));
+    }
+    let [a0, a1] = a.to_be_bytes();
+    let [b0, b1] = b.to_be_bytes();
+    [a0, a1, b0, b1]
+}
+
+/// Writes the control file for `path` to `out`
+pub async fn make(
+    path: &Path,
+    options: &Options,
+    out: &mut (impl Write + Unpin),
+) -> Result<(), eyre::Error> {
+    let mut file = File::open(path).await?;
+    let metadata = file.metadata().await?;
+    let len = metadata.len();
+    let block_size = options
+        .block_size
+        .unwrap_or_else(|| default_block_size(len));
+    let (seq_matches, rsum_len, checksum_len) = hash_lengths(len, block_size);
+
+    let mut sha1 = Sha1::new();
+    let mut checksums = Vec::new();
+    let mut block = vec![0u8; block_size];
+    loop {
+        let mut filled = 0;
+        while filled < block_size {
+            match file.read(&mut block[filled..]).await? {
+                0 => break,
+                n => filled += n,
+            }
+        }
+        if filled == 0 {
+            break;
+        }
+        sha1.update(&block[..filled]);
+        // the last block is checksummed as if padded with zeroes
+        block[filled..].iter_mut().for_each(|b| *b = 0);
+        checksums.extend_from_slice(&rsum(&block)[4 - rsum_len..]);
+        checksums.extend_from_slice(&md4::Md4::digest(&block)[..checksum_len]);
+        if filled < block_size {
+            break;
+        }
+    }
+
+    let name = path
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let mut header = format!("zsync: {}\nFilename: {}\n", VERSION, name);
+    if let Ok(mtime) = metadata.modified() {
+        header += &format!("MTime: {}\n", rfc2822(mtime));
+    }
+    header += &format!(
+        "Blocksize: {}\nLength: {}\nHash-Lengths: {},{},{}\nURL: {}\nSHA-1: {}\n\n",
+        block_size,
+        len,
+        seq_matches,
+        rsum_len,
+        checksum_len,
+        options.url,
+        crate::hex(&sha1.finalize())
+    );
+
+    out.write_all(header.as_bytes()).await?;
+    out.write_all(&checksums).await?;
+    out.flush().await?;
+    Ok(())
+}
+
+/// Formats a time as an RFC 2822 date, in UTC
+pub fn rfc2822(time: SystemTime) -> String {
+    const DAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+
+    let secs = time
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0);
+    let days = secs.div_euclid(86400);
+    let rem = secs.rem_euclid(86400);
+    let (year, month, day) = civil(days);
+
+    format!(
+        "{}, {:02} {} {} {:02}:{:02}:{:02} +0000",
+        DAYS[days.rem_euclid(7) as usize],
+        day,
+        MONTHS[(month - 1) as usize],
+        year,
+        rem / 3600,
+        rem % 3600 / 60,
+        rem % 60
+    )
+}
+
+/// The year, month and day `days` after the Unix epoch, with Howard
+/// Hinnant's civil-from-days
+pub fn civil(days: i64) -> (i64, i64, i64) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}